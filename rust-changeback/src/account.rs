@@ -0,0 +1,327 @@
+//! Per-account records stored next to the shared Change value.
+//!
+//! Records are stored as a [`VersionedAccount`], so a field can be added
+//! without migrating every account at once: the new layout becomes a new
+//! variant, older variants are upgraded when read, and an account is written
+//! back in the current layout the next time it changes.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{AccountId, Balance, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default)]
+pub struct Account {
+    /// Change owed to the account in yoctoNEAR, see [`purchase`](crate::purchase).
+    pub balance: Balance,
+    /// Block timestamp (nanoseconds) at which the current rebate period started.
+    pub period_start: Timestamp,
+    /// Operations performed by the account since `period_start`.
+    pub period_ops: u32,
+    /// Rebate accrued and not claimed yet, in yoctoNEAR.
+    pub rebate: Balance,
+    /// Account that referred this one, fixed by its first referred `add`.
+    pub referrer: Option<AccountId>,
+    /// Number of accounts this one has referred.
+    pub referrals: u32,
+    /// Referral bonus credited to this account and not paid out yet.
+    pub referral_bonus: Balance,
+    /// Whether the account sits in the referral payout queue.
+    pub payout_queued: bool,
+    /// Whether token deposits get rounded up into savings, see [`savings`](crate::savings).
+    pub round_up: bool,
+    /// Whether purchase change goes to the charity, see [`charity`](crate::charity).
+    pub donate_change: bool,
+    /// Change donated to the charity so far, in yoctoNEAR.
+    pub donated: Balance,
+    /// Block timestamp until which the account has locked its own outflows,
+    /// see [`lock`](crate::lock).
+    pub locked_until: Timestamp,
+    /// Account that may claim the balance once this one goes inactive, see
+    /// [`beneficiary`](crate::beneficiary).
+    pub beneficiary: Option<AccountId>,
+    /// Nanoseconds without activity after which `beneficiary` may claim.
+    pub inactivity_period: u64,
+    /// Block timestamp of the last call by the account that changed its record.
+    pub last_active: Timestamp,
+    /// Notification categories the account opted out of, one bit each, see
+    /// [`notification`](crate::notification).
+    pub muted_notifications: u8,
+    /// Platform fees kept on the account's matured purchases that no rebate
+    /// has paid back yet, see [`rebate`](crate::rebate).
+    pub rebatable_fees: Balance,
+    /// Rebate accrued since `period_start`.
+    pub period_rebate: Balance,
+}
+
+/// Layout of [`Account`] before `locked_until`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountV1 {
+    pub balance: Balance,
+    pub period_start: Timestamp,
+    pub period_ops: u32,
+    pub rebate: Balance,
+    pub referrer: Option<AccountId>,
+    pub referrals: u32,
+    pub referral_bonus: Balance,
+    pub payout_queued: bool,
+    pub round_up: bool,
+    pub donate_change: bool,
+    pub donated: Balance,
+}
+
+/// Layout of [`Account`] before `beneficiary`, `inactivity_period` and `last_active`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountV2 {
+    pub balance: Balance,
+    pub period_start: Timestamp,
+    pub period_ops: u32,
+    pub rebate: Balance,
+    pub referrer: Option<AccountId>,
+    pub referrals: u32,
+    pub referral_bonus: Balance,
+    pub payout_queued: bool,
+    pub round_up: bool,
+    pub donate_change: bool,
+    pub donated: Balance,
+    pub locked_until: Timestamp,
+}
+
+/// Layout of [`Account`] before `muted_notifications`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountV3 {
+    pub balance: Balance,
+    pub period_start: Timestamp,
+    pub period_ops: u32,
+    pub rebate: Balance,
+    pub referrer: Option<AccountId>,
+    pub referrals: u32,
+    pub referral_bonus: Balance,
+    pub payout_queued: bool,
+    pub round_up: bool,
+    pub donate_change: bool,
+    pub donated: Balance,
+    pub locked_until: Timestamp,
+    pub beneficiary: Option<AccountId>,
+    pub inactivity_period: u64,
+    pub last_active: Timestamp,
+}
+
+/// Layout of [`Account`] before `rebatable_fees` and `period_rebate`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountV4 {
+    pub balance: Balance,
+    pub period_start: Timestamp,
+    pub period_ops: u32,
+    pub rebate: Balance,
+    pub referrer: Option<AccountId>,
+    pub referrals: u32,
+    pub referral_bonus: Balance,
+    pub payout_queued: bool,
+    pub round_up: bool,
+    pub donate_change: bool,
+    pub donated: Balance,
+    pub locked_until: Timestamp,
+    pub beneficiary: Option<AccountId>,
+    pub inactivity_period: u64,
+    pub last_active: Timestamp,
+    pub muted_notifications: u8,
+}
+
+/// Every layout an account record has been stored in.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedAccount {
+    V1(AccountV1),
+    V2(AccountV2),
+    V3(AccountV3),
+    V4(AccountV4),
+    V5(Account),
+}
+
+impl From<AccountV1> for AccountV2 {
+    fn from(account: AccountV1) -> Self {
+        AccountV2 {
+            balance: account.balance,
+            period_start: account.period_start,
+            period_ops: account.period_ops,
+            rebate: account.rebate,
+            referrer: account.referrer,
+            referrals: account.referrals,
+            referral_bonus: account.referral_bonus,
+            payout_queued: account.payout_queued,
+            round_up: account.round_up,
+            donate_change: account.donate_change,
+            donated: account.donated,
+            locked_until: 0,
+        }
+    }
+}
+
+impl From<AccountV2> for AccountV3 {
+    fn from(account: AccountV2) -> Self {
+        AccountV3 {
+            balance: account.balance,
+            period_start: account.period_start,
+            period_ops: account.period_ops,
+            rebate: account.rebate,
+            referrer: account.referrer,
+            referrals: account.referrals,
+            referral_bonus: account.referral_bonus,
+            payout_queued: account.payout_queued,
+            round_up: account.round_up,
+            donate_change: account.donate_change,
+            donated: account.donated,
+            locked_until: account.locked_until,
+            beneficiary: None,
+            inactivity_period: 0,
+            last_active: 0,
+        }
+    }
+}
+
+impl From<AccountV3> for AccountV4 {
+    fn from(account: AccountV3) -> Self {
+        AccountV4 {
+            balance: account.balance,
+            period_start: account.period_start,
+            period_ops: account.period_ops,
+            rebate: account.rebate,
+            referrer: account.referrer,
+            referrals: account.referrals,
+            referral_bonus: account.referral_bonus,
+            payout_queued: account.payout_queued,
+            round_up: account.round_up,
+            donate_change: account.donate_change,
+            donated: account.donated,
+            locked_until: account.locked_until,
+            beneficiary: account.beneficiary,
+            inactivity_period: account.inactivity_period,
+            last_active: account.last_active,
+            muted_notifications: 0,
+        }
+    }
+}
+
+impl From<AccountV4> for Account {
+    fn from(account: AccountV4) -> Self {
+        Account {
+            balance: account.balance,
+            period_start: account.period_start,
+            period_ops: account.period_ops,
+            rebate: account.rebate,
+            referrer: account.referrer,
+            referrals: account.referrals,
+            referral_bonus: account.referral_bonus,
+            payout_queued: account.payout_queued,
+            round_up: account.round_up,
+            donate_change: account.donate_change,
+            donated: account.donated,
+            locked_until: account.locked_until,
+            beneficiary: account.beneficiary,
+            inactivity_period: account.inactivity_period,
+            last_active: account.last_active,
+            muted_notifications: account.muted_notifications,
+            rebatable_fees: 0,
+            period_rebate: 0,
+        }
+    }
+}
+
+impl From<VersionedAccount> for Account {
+    /// Upgrades a stored record to the current layout.
+    fn from(account: VersionedAccount) -> Self {
+        match account {
+            VersionedAccount::V1(account) => {
+                AccountV4::from(AccountV3::from(AccountV2::from(account))).into()
+            }
+            VersionedAccount::V2(account) => AccountV4::from(AccountV3::from(account)).into(),
+            VersionedAccount::V3(account) => AccountV4::from(account).into(),
+            VersionedAccount::V4(account) => account.into(),
+            VersionedAccount::V5(account) => account,
+        }
+    }
+}
+
+impl From<Account> for VersionedAccount {
+    fn from(account: Account) -> Self {
+        VersionedAccount::V5(account)
+    }
+}
+
+impl Change {
+    /// Returns the record of `account_id`, or an empty one if it never interacted.
+    pub(crate) fn internal_get_account(&self, account_id: &AccountId) -> Account {
+        self.accounts
+            .get(account_id)
+            .map(Account::from)
+            .unwrap_or_default()
+    }
+
+    /// Stores the record of `account_id`, counting the write as activity of
+    /// the account when it made the call.
+    pub(crate) fn internal_set_account(&mut self, account_id: &AccountId, account: &Account) {
+        let mut account = account.clone();
+        if *account_id == env::predecessor_account_id() {
+            account.last_active = env::block_timestamp();
+        }
+        self.accounts
+            .insert(account_id, &VersionedAccount::from(account));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn accounts_are_stored_in_the_current_version() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        let account_id = "jane.testnet".to_string();
+        contract.internal_credit_balance(&account_id, 70, Book::Custody);
+        assert!(matches!(
+            contract.accounts.get(&account_id),
+            Some(VersionedAccount::V5(Account { balance: 70, .. }))
+        ));
+        assert_eq!(70, contract.internal_get_account(&account_id).balance);
+    }
+
+    #[test]
+    fn older_accounts_are_upgraded_when_read() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        let account_id = "jane.testnet".to_string();
+        let stored = AccountV1 {
+            balance: 70,
+            period_start: 0,
+            period_ops: 2,
+            rebate: 0,
+            referrer: None,
+            referrals: 0,
+            referral_bonus: 0,
+            payout_queued: false,
+            round_up: true,
+            donate_change: false,
+            donated: 0,
+        };
+        contract
+            .accounts
+            .insert(&account_id, &VersionedAccount::V1(stored));
+        let account = contract.internal_get_account(&account_id);
+        assert_eq!(
+            (70, 2, true, 0),
+            (
+                account.balance,
+                account.period_ops,
+                account.round_up,
+                account.locked_until
+            )
+        );
+        assert_eq!(None, account.beneficiary);
+        assert_eq!(0, account.muted_notifications);
+        assert_eq!(0, account.rebatable_fees);
+    }
+}
@@ -0,0 +1,443 @@
+//! Double-entry bookkeeping of the NEAR the contract holds.
+//!
+//! The NEAR held for each party is kept in a [`Book`], and every movement is
+//! a [`Posting`] debiting some books and crediting others by the same total.
+//! [`internal_post`](Change::internal_post) refuses postings that don't
+//! balance or would overdraw a book, so the books always add up.
+//!
+//! `Custody` is the asset side, the NEAR the contract holds: NEAR coming in
+//! is debited to it and credited to the book it is held for, NEAR going out
+//! the other way round. Movements between parties, such as a redeemed gift
+//! card turning into change, go straight from one book to the other. Fees
+//! are credited to the treasury, which is where the contract keeps them.
+//! [`get_trial_balance`](Change::get_trial_balance) lists every book.
+//!
+//! Every posting is also kept in a journal, and each book keeps a statement
+//! line for every posting that touched it, with the book's balance after it,
+//! so [`get_account_statement`](Change::get_account_statement) can page
+//! through a book's movements without replaying the journal.
+
+use std::collections::BTreeMap;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, Balance, Timestamp};
+
+use crate::*;
+
+#[derive(
+    BorshDeserialize,
+    BorshSerialize,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Book {
+    /// NEAR the contract holds for all the other books.
+    Custody,
+    /// Change stored for accounts, see [`purchase`](crate::purchase).
+    UserChange,
+    /// Partial payments held by open [intents](crate::intent).
+    Escrow,
+    /// Value of the outstanding [gift cards](crate::gift_card).
+    GiftCards,
+    /// Revenue, pending and frozen sales and rewards pools of the [merchants](crate::merchant).
+    Merchants,
+    /// Hot [treasury](crate::treasury), settlement fees included.
+    Treasury,
+    /// Treasury NEAR above `hot_balance_limit`.
+    ColdTreasury,
+    /// Storage deposits of registered accounts, see [`storage_management`](crate::storage_management).
+    StorageDeposits,
+}
+
+const BOOKS: [Book; 8] = [
+    Book::Custody,
+    Book::UserChange,
+    Book::Escrow,
+    Book::GiftCards,
+    Book::Merchants,
+    Book::Treasury,
+    Book::ColdTreasury,
+    Book::StorageDeposits,
+];
+
+impl Book {
+    /// Whether the book grows with debits; only `Custody`, the asset, does.
+    fn is_debit(self) -> bool {
+        self == Book::Custody
+    }
+}
+
+/// Everything ever debited and credited to a book.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Default)]
+pub struct BookTotals {
+    pub debits: Balance,
+    pub credits: Balance,
+}
+
+impl BookTotals {
+    /// Balance on the side the book grows on, negative if overdrawn.
+    fn balance(&self, book: Book) -> i128 {
+        let (grows, shrinks) = if book.is_debit() {
+            (self.debits, self.credits)
+        } else {
+            (self.credits, self.debits)
+        };
+        grows as i128 - shrinks as i128
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default)]
+pub struct Books {
+    totals: BTreeMap<Book, BookTotals>,
+}
+
+impl Books {
+    /// Returns the balance of `book`.
+    pub fn balance(&self, book: Book) -> Balance {
+        self.totals
+            .get(&book)
+            .map_or(0, |totals| totals.balance(book) as Balance)
+    }
+
+    /// Returns the balance of every book.
+    pub fn balances(&self) -> Vec<(Book, Balance)> {
+        BOOKS
+            .iter()
+            .map(|&book| (book, self.balance(book)))
+            .collect()
+    }
+}
+
+/// A posting as it was applied, indexed by entry id.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct JournalEntry {
+    pub timestamp: Timestamp,
+    pub debits: Vec<(Book, Balance)>,
+    pub credits: Vec<(Book, Balance)>,
+}
+
+/// What one journal entry did to one book.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StatementLine {
+    pub entry_id: u64,
+    pub debit: Balance,
+    pub credit: Balance,
+    /// Balance of the book after the entry.
+    pub balance: Balance,
+}
+
+/// A balanced set of debits and credits, see [`internal_post`](Change::internal_post).
+#[derive(Default)]
+pub(crate) struct Posting {
+    debits: Vec<(Book, Balance)>,
+    credits: Vec<(Book, Balance)>,
+}
+
+impl Posting {
+    /// NEAR coming in to be held in `book`.
+    pub fn receive(book: Book, amount: Balance) -> Self {
+        Self::default()
+            .debit(Book::Custody, amount)
+            .credit(book, amount)
+    }
+
+    /// NEAR held in `book` going out.
+    pub fn pay_out(book: Book, amount: Balance) -> Self {
+        Self::default()
+            .debit(book, amount)
+            .credit(Book::Custody, amount)
+    }
+
+    /// NEAR held in `from` moving to `to` without leaving the contract.
+    pub fn transfer(from: Book, to: Book, amount: Balance) -> Self {
+        Self::default().debit(from, amount).credit(to, amount)
+    }
+
+    pub fn debit(mut self, book: Book, amount: Balance) -> Self {
+        self.debits.push((book, amount));
+        self
+    }
+
+    pub fn credit(mut self, book: Book, amount: Balance) -> Self {
+        self.credits.push((book, amount));
+        self
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TrialBalanceLine {
+    pub book: Book,
+    /// Balance of a book that grows with debits, 0 otherwise.
+    pub debit: U128,
+    /// Balance of a book that grows with credits, 0 otherwise.
+    pub credit: U128,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatementLineView {
+    pub entry_id: U64,
+    pub timestamp: U64,
+    pub debit: U128,
+    pub credit: U128,
+    pub balance: U128,
+    /// The other books the entry moved NEAR from or to.
+    pub contra_books: Vec<Book>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TrialBalanceView {
+    pub lines: Vec<TrialBalanceLine>,
+    pub total_debit: U128,
+    pub total_credit: U128,
+}
+
+impl Change {
+    /// Returns the balance of `book`.
+    pub(crate) fn book(&self, book: Book) -> Balance {
+        self.books.balance(book)
+    }
+
+    /// Applies `posting` and journals it, panicking if its debits and credits
+    /// differ or it would overdraw a book.
+    pub(crate) fn internal_post(&mut self, posting: Posting) {
+        let debits: Balance = posting.debits.iter().map(|(_, amount)| amount).sum();
+        let credits: Balance = posting.credits.iter().map(|(_, amount)| amount).sum();
+        assert_eq!(debits, credits, "Unbalanced posting");
+        let mut moved: BTreeMap<Book, (Balance, Balance)> = BTreeMap::new();
+        for &(book, amount) in &posting.debits {
+            self.books.totals.entry(book).or_default().debits += amount;
+            moved.entry(book).or_default().0 += amount;
+        }
+        for &(book, amount) in &posting.credits {
+            self.books.totals.entry(book).or_default().credits += amount;
+            moved.entry(book).or_default().1 += amount;
+        }
+        for (book, totals) in &self.books.totals {
+            if totals.balance(*book) < 0 {
+                env::panic(format!("Posting overdraws the {:?} book", book).as_bytes());
+            }
+        }
+        let entry_id = self.journal.len();
+        self.journal.push(&JournalEntry {
+            timestamp: env::block_timestamp(),
+            debits: posting.debits,
+            credits: posting.credits,
+        });
+        for (book, (debit, credit)) in moved {
+            let line = StatementLine {
+                entry_id,
+                debit,
+                credit,
+                balance: self.book(book),
+            };
+            let mut lines = self
+                .book_statements
+                .get(&book)
+                .unwrap_or_else(|| Vector::new(StorageKey::BookStatement { book }));
+            lines.push(&line);
+            self.book_statements.insert(&book, &lines);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the balance of every book, debit and credit balances apart;
+    /// their totals are always equal.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_trial_balance
+    /// ```
+    pub fn get_trial_balance(&self) -> TrialBalanceView {
+        let lines: Vec<TrialBalanceLine> = BOOKS
+            .iter()
+            .map(|&book| {
+                let balance = self.book(book);
+                let (debit, credit) = if book.is_debit() {
+                    (balance, 0)
+                } else {
+                    (0, balance)
+                };
+                TrialBalanceLine {
+                    book,
+                    debit: U128(debit),
+                    credit: U128(credit),
+                }
+            })
+            .collect();
+        TrialBalanceView {
+            total_debit: U128(lines.iter().map(|line| line.debit.0).sum()),
+            total_credit: U128(lines.iter().map(|line| line.credit.0).sum()),
+            lines,
+        }
+    }
+
+    /// Returns up to `limit` movements of `book` starting at its `from_index`-th,
+    /// oldest first, as many as the gas allows, see [`pagination`](crate::pagination).
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_account_statement '{"book": "Treasury", "from_index": "0", "limit": 50}'
+    /// ```
+    pub fn get_account_statement(
+        &self,
+        book: Book,
+        from_index: U64,
+        limit: u64,
+    ) -> Page<StatementLineView> {
+        let lines = match self.book_statements.get(&book) {
+            Some(lines) => lines,
+            None => return paginate(limit, |_| None),
+        };
+        paginate(limit, |n| {
+            let line = lines.get(from_index.0.checked_add(n)?)?;
+            let entry = self
+                .journal
+                .get(line.entry_id)
+                .expect("Journal entry is missing");
+            let mut contra_books: Vec<Book> = entry
+                .debits
+                .iter()
+                .chain(&entry.credits)
+                .map(|&(contra, _)| contra)
+                .filter(|&contra| contra != book)
+                .collect();
+            contra_books.sort();
+            contra_books.dedup();
+            Some(StatementLineView {
+                entry_id: U64(line.entry_id),
+                timestamp: U64(entry.timestamp),
+                debit: U128(line.debit),
+                credit: U128(line.credit),
+                balance: U128(line.balance),
+                contra_books,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn postings_move_between_books() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_post(Posting::receive(Book::GiftCards, 100));
+        contract.internal_post(Posting::transfer(Book::GiftCards, Book::UserChange, 30));
+        contract.internal_post(Posting::pay_out(Book::UserChange, 10));
+        assert_eq!(90, contract.book(Book::Custody));
+        assert_eq!(70, contract.book(Book::GiftCards));
+        assert_eq!(20, contract.book(Book::UserChange));
+        let trial_balance = contract.get_trial_balance();
+        assert_eq!(90, trial_balance.total_debit.0);
+        assert_eq!(90, trial_balance.total_credit.0);
+    }
+
+    #[test]
+    fn purchases_post_to_the_books() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        testing_env!(context_for("jane.testnet", 100));
+        contract.purchase(U128(30), None, None, None, None, None);
+        assert_eq!(70, contract.book(Book::UserChange));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.purchase(U128(50), Some(true), None, None, None, None);
+        assert_eq!(20, contract.book(Book::UserChange));
+        assert_eq!(20, contract.book(Book::Custody));
+        let trial_balance = contract.get_trial_balance();
+        assert_eq!(trial_balance.total_debit, trial_balance.total_credit);
+    }
+
+    #[test]
+    fn statements_list_the_movements_of_a_book() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_post(Posting::receive(Book::GiftCards, 100));
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = 5;
+        testing_env!(context);
+        contract.internal_post(Posting::transfer(Book::GiftCards, Book::UserChange, 30));
+        contract.internal_post(Posting::pay_out(Book::UserChange, 10));
+
+        let statement = contract
+            .get_account_statement(Book::GiftCards, U64(0), 10)
+            .items;
+        assert_eq!(2, statement.len());
+        assert_eq!(
+            (100, 0, 100),
+            (
+                statement[0].credit.0,
+                statement[0].debit.0,
+                statement[0].balance.0
+            )
+        );
+        assert_eq!(vec![Book::Custody], statement[0].contra_books);
+        assert_eq!(1, statement[1].entry_id.0);
+        assert_eq!(5, statement[1].timestamp.0);
+        assert_eq!((30, 70), (statement[1].debit.0, statement[1].balance.0));
+        assert_eq!(vec![Book::UserChange], statement[1].contra_books);
+
+        let custody = contract
+            .get_account_statement(Book::Custody, U64(1), 10)
+            .items;
+        assert_eq!(1, custody.len());
+        assert_eq!(
+            (2, 10, 90),
+            (
+                custody[0].entry_id.0,
+                custody[0].credit.0,
+                custody[0].balance.0
+            )
+        );
+        assert!(contract
+            .get_account_statement(Book::ColdTreasury, U64(0), 10)
+            .items
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unbalanced posting")]
+    fn unbalanced_postings_are_refused() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_post(
+            Posting::default()
+                .debit(Book::Custody, 100)
+                .credit(Book::Treasury, 90),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Posting overdraws the Treasury book")]
+    fn books_cannot_be_overdrawn() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_post(Posting::receive(Book::UserChange, 100));
+        contract.internal_post(Posting::transfer(Book::Treasury, Book::UserChange, 1));
+    }
+}
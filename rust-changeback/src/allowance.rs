@@ -0,0 +1,185 @@
+//! Allowances, letting an account spend from the Change of another.
+//!
+//! Following ERC-20's `approve`, an owner gives a spender an allowance in one
+//! currency with [`approve_allowance`](Change::approve_allowance), which the
+//! spender uses up with [`change_from`](Change::change_from), a `change` on
+//! the owner's Change. Approving again replaces the allowance rather than
+//! adding to it, and [`revoke_allowance`](Change::revoke_allowance) drops it.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ApprovalEvent<'a> {
+    owner_id: &'a AccountId,
+    spender_id: &'a AccountId,
+    currency: &'a str,
+    amount: U128,
+}
+
+impl Change {
+    fn internal_set_allowance(
+        &mut self,
+        owner_id: &AccountId,
+        spender_id: &AccountId,
+        currency: String,
+        amount: Balance,
+    ) {
+        self.emit_event(
+            "approval",
+            &ApprovalEvent {
+                owner_id,
+                spender_id,
+                currency: &currency,
+                amount: U128(amount),
+            },
+        );
+        let key = (owner_id.clone(), spender_id.clone(), currency);
+        if amount == 0 {
+            self.allowances.remove(&key);
+        } else {
+            self.allowances.insert(&key, &amount);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Lets `spender` take up to `amount` from the caller's Change in
+    /// `currency`, `"NEAR"` when omitted, replacing any allowance it had.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet approve_allowance '{"spender": "pos.testnet", "amount": "500"}' --accountId jane.testnet
+    /// ```
+    pub fn approve_allowance(
+        &mut self,
+        spender: ValidAccountId,
+        amount: U128,
+        currency: Option<String>,
+    ) {
+        self.assert_access("approve_allowance");
+        self.assert_writable();
+        let owner_id = env::predecessor_account_id();
+        let spender_id: AccountId = spender.into();
+        require!(owner_id != spender_id, messages::SELF_APPROVAL);
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        self.internal_set_allowance(&owner_id, &spender_id, currency, amount.0);
+    }
+
+    /// Drops the allowance of `spender` on the caller's Change in `currency`.
+    pub fn revoke_allowance(&mut self, spender: ValidAccountId, currency: Option<String>) {
+        self.assert_access("revoke_allowance");
+        let owner_id = env::predecessor_account_id();
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        self.internal_set_allowance(&owner_id, spender.as_ref(), currency, 0);
+    }
+
+    /// Subtracts `amount`, the configured `sub_step` when omitted, from the
+    /// Change of `owner_id` in `currency`, out of the caller's allowance.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet change_from '{"owner_id": "jane.testnet", "amount": "20"}' --accountId pos.testnet
+    /// ```
+    pub fn change_from(
+        &mut self,
+        owner_id: ValidAccountId,
+        amount: Option<U128>,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_access("change_from");
+        self.assert_writable();
+        let owner_id: AccountId = owner_id.into();
+        let spender_id = env::predecessor_account_id();
+        let amount = counter_amount(amount.unwrap_or(self.config().sub_step))
+            .unwrap_or_else(|error| fail!(error));
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        let key = (owner_id.clone(), spender_id.clone(), currency.clone());
+        let allowance = self.allowances.get(&key).unwrap_or(0);
+        require!(
+            amount as Balance <= allowance,
+            messages::AMOUNT_EXCEEDS_ALLOWANCE
+        );
+        let val = self.internal_change(&owner_id, &currency, amount);
+        self.internal_set_allowance(
+            &owner_id,
+            &spender_id,
+            currency,
+            allowance - amount as Balance,
+        );
+        self.internal_outcome(val)
+    }
+
+    /// Returns what `spender_id` may still take from the Change of `owner_id` in `currency`.
+    pub fn get_allowance(
+        &self,
+        owner_id: AccountId,
+        spender_id: AccountId,
+        currency: Option<String>,
+    ) -> U128 {
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        U128(
+            self.allowances
+                .get(&(owner_id, spender_id, currency))
+                .unwrap_or(0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn allowance(contract: &Change) -> u128 {
+        contract
+            .get_allowance("jane.testnet".to_string(), "pos.testnet".to_string(), None)
+            .0
+    }
+
+    #[test]
+    fn spenders_use_up_their_allowance() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        contract.approve_allowance(account("pos.testnet"), U128(50), None);
+        testing_env!(context_for("pos.testnet", 0));
+        let outcome = contract.change_from(account("jane.testnet"), Some(U128(30)), None);
+        assert_eq!(970, outcome.new_balance.0);
+        assert_eq!(20, allowance(&contract));
+        assert_eq!(0, contract.get_num_for("pos.testnet".to_string(), None).0);
+        contract.change_from(account("jane.testnet"), None, None);
+        assert_eq!(10, allowance(&contract));
+    }
+
+    #[test]
+    fn approving_replaces_and_revoking_drops() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.approve_allowance(account("pos.testnet"), U128(50), None);
+        contract.approve_allowance(account("pos.testnet"), U128(5), None);
+        assert_eq!(5, allowance(&contract));
+        contract.revoke_allowance(account("pos.testnet"), None);
+        assert_eq!(0, allowance(&contract));
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount exceeds the allowance")]
+    fn spending_past_the_allowance_is_refused() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.approve_allowance(account("pos.testnet"), U128(50), Some("USDC".to_string()));
+        testing_env!(context_for("pos.testnet", 0));
+        contract.change_from(account("jane.testnet"), Some(U128(30)), None);
+    }
+}
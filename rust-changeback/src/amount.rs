@@ -0,0 +1,147 @@
+//! Arithmetic helpers for token amounts.
+//!
+//! Every division that can leave a remainder (fees, bonuses, discounts,
+//! points, USD valuations) rounds the way the `rounding_mode` config says.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::Balance;
+
+/// 100% expressed in basis points.
+pub(crate) const BPS_DENOMINATOR: u128 = 10_000;
+
+/// 1 NEAR in yoctoNEAR.
+pub(crate) const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+/// How a division with a remainder is rounded.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RoundingMode {
+    /// Towards zero.
+    Floor,
+    /// Away from zero.
+    Ceil,
+    /// To the nearest integer, halves to the even one.
+    Bankers,
+}
+
+/// Rounds `quotient + remainder / denominator` with `mode`.
+fn round(quotient: u128, remainder: u128, denominator: u128, mode: RoundingMode) -> u128 {
+    let round_up = match mode {
+        RoundingMode::Floor => false,
+        RoundingMode::Ceil => remainder > 0,
+        RoundingMode::Bankers => {
+            let twice = remainder * 2;
+            twice > denominator || (twice == denominator && quotient % 2 == 1)
+        }
+    };
+    quotient + u128::from(round_up)
+}
+
+/// Returns `bps` basis points of `amount`.
+pub(crate) fn bps_of(amount: Balance, bps: u16, mode: RoundingMode) -> Balance {
+    let product = amount * u128::from(bps);
+    round(
+        product / BPS_DENOMINATOR,
+        product % BPS_DENOMINATOR,
+        BPS_DENOMINATOR,
+        mode,
+    )
+}
+
+/// Returns `amount / denominator`.
+pub(crate) fn div_rounded(amount: u128, denominator: u128, mode: RoundingMode) -> u128 {
+    round(
+        amount / denominator,
+        amount % denominator,
+        denominator,
+        mode,
+    )
+}
+
+/// Returns `amount` yoctoNEAR times a per-NEAR `rate`, without overflowing
+/// for any realistic amount.
+pub(crate) fn per_near(amount: Balance, rate: u32, mode: RoundingMode) -> u128 {
+    let rate = u128::from(rate);
+    // whole NEAR multiply exactly, only the fraction leaves a remainder
+    let fraction = amount % ONE_NEAR * rate;
+    round(
+        amount / ONE_NEAR * rate + fraction / ONE_NEAR,
+        fraction % ONE_NEAR,
+        ONE_NEAR,
+        mode,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RoundingMode::*;
+
+    #[test]
+    fn exact_results_are_the_same_in_every_mode() {
+        for mode in [Floor, Ceil, Bankers] {
+            assert_eq!(0, bps_of(0, 250, mode));
+            assert_eq!(25, bps_of(1_000, 250, mode));
+            assert_eq!(1_000, bps_of(1_000, 10_000, mode));
+            assert_eq!(0, bps_of(1_000, 0, mode));
+            assert_eq!(30, per_near(3 * ONE_NEAR, 10, mode));
+        }
+    }
+
+    #[test]
+    fn remainders_round_by_mode() {
+        // 1% of 150 is 1.5, of 149 is 1.49, of 151 is 1.51, of 250 is 2.5
+        let cases: [(u128, [u128; 3]); 5] = [
+            (150, [1, 2, 2]),
+            (149, [1, 2, 1]),
+            (151, [1, 2, 2]),
+            (250, [2, 3, 2]),
+            (1, [0, 1, 0]),
+        ];
+        for (amount, [floor, ceil, bankers]) in cases {
+            assert_eq!(floor, bps_of(amount, 100, Floor), "floor of {}", amount);
+            assert_eq!(ceil, bps_of(amount, 100, Ceil), "ceil of {}", amount);
+            assert_eq!(
+                bankers,
+                bps_of(amount, 100, Bankers),
+                "bankers of {}",
+                amount
+            );
+        }
+    }
+
+    #[test]
+    fn quotients_round_by_mode() {
+        assert_eq!(2, div_rounded(25, 10, Floor));
+        assert_eq!(3, div_rounded(25, 10, Ceil));
+        assert_eq!(2, div_rounded(25, 10, Bankers));
+        assert_eq!(4, div_rounded(35, 10, Bankers));
+        assert_eq!(3, div_rounded(30, 10, Ceil));
+    }
+
+    #[test]
+    fn fractions_of_a_near_round_by_mode() {
+        let half = ONE_NEAR / 2;
+        assert_eq!(0, per_near(half, 1, Floor));
+        assert_eq!(1, per_near(half, 1, Ceil));
+        assert_eq!(0, per_near(half, 1, Bankers));
+        assert_eq!(2, per_near(ONE_NEAR + half, 1, Bankers));
+        assert_eq!(1, per_near(1, 1, Ceil));
+        assert_eq!(0, per_near(ONE_NEAR - 1, 1, Floor));
+        assert_eq!(1, per_near(ONE_NEAR - 1, 1, Bankers));
+    }
+
+    #[test]
+    fn large_amounts_do_not_overflow() {
+        let floor = per_near(u128::MAX, u32::MAX, Floor);
+        assert!(floor >= u128::MAX / ONE_NEAR * u128::from(u32::MAX));
+        assert_eq!(floor + 1, per_near(u128::MAX, u32::MAX, Ceil));
+        assert_eq!(
+            u128::MAX / BPS_DENOMINATOR,
+            bps_of(u128::MAX / BPS_DENOMINATOR, 10_000, Ceil)
+        );
+    }
+}
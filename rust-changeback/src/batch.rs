@@ -0,0 +1,207 @@
+//! Several counter operations in one call.
+//!
+//! [`batch_apply`](Change::batch_apply) runs a list of operations on the
+//! caller's Change in a single transaction: either every one of them applies
+//! or, when one is refused, none does and the call fails naming the index of
+//! the operation refused. Each still lands in the
+//! [history](crate::history), but a single `batch_apply` event announces them
+//! all, and the batch counts as one operation toward
+//! [rebates](crate::rebate): it is one transaction to pay gas for. Nothing is attached
+//! to a batch, so its adds earn no [referral](crate::referral) bonus.
+
+use near_sdk::json_types::{I128, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Most operations one batch may hold, bounding its gas.
+const MAX_BATCH_OPERATIONS: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Operation {
+    /// Adds `amount`, as [`add`](Change::add) does.
+    Add { amount: U128 },
+    /// Subtracts `amount`, as [`change`](Change::change) does.
+    Sub { amount: U128 },
+    /// Sets the Change to zero. Owner only, as [`reset`](Change::reset).
+    Reset,
+}
+
+/// Payload of the `batch_apply` event.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct BatchEvent<'a> {
+    account_id: &'a AccountId,
+    currency: &'a str,
+    operations: U64,
+    old_value: I128,
+    new_value: I128,
+}
+
+impl Change {
+    /// Applies `operation` by the caller to `value`. Returns the kind of
+    /// operation, its delta and the value it leaves.
+    fn internal_batch_step(
+        &self,
+        operation: &Operation,
+        value: i128,
+    ) -> Result<(CounterOperation, i128, i128), String> {
+        let (kind, delta) = match operation {
+            Operation::Add { amount } => (CounterOperation::Add, counter_amount(*amount)?),
+            Operation::Sub { amount } => (CounterOperation::Change, -counter_amount(*amount)?),
+            Operation::Reset => {
+                if env::predecessor_account_id() != self.owner_id {
+                    return Err(messages::OWNER_ONLY.to_string());
+                }
+                return Ok((CounterOperation::Reset, value.saturating_neg(), 0));
+            }
+        };
+        self.internal_check_not_dust(delta.abs())?;
+        Ok((kind, delta, self.internal_apply_delta(value, delta)?))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Applies `ops` in order to the caller's Change in `currency`, `"NEAR"`
+    /// when omitted, all or none of them. Returns the value they leave, see
+    /// [`outcome`](crate::outcome).
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet batch_apply '{"ops": [{"Add": {"amount": "250"}}, {"Sub": {"amount": "10"}}]}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn batch_apply(
+        &mut self,
+        ops: Vec<Operation>,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_access("batch_apply");
+        self.assert_writable();
+        require!(!ops.is_empty(), messages::NO_OPERATIONS);
+        require!(
+            ops.len() <= MAX_BATCH_OPERATIONS,
+            messages::TOO_MANY_OPERATIONS
+        );
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        let account_id = env::predecessor_account_id();
+        self.assert_served_here(&account_id);
+        self.assert_registered(&account_id);
+        self.internal_throttle(&account_id);
+        let old_value = self.internal_get_num(&account_id, &currency);
+        let mut value = old_value;
+        for (index, operation) in ops.iter().enumerate() {
+            let (kind, delta, new_value) = self
+                .internal_batch_step(operation, value)
+                .unwrap_or_else(|error| fail!(messages::BATCH_OPERATION_FAILED, index, error));
+            self.internal_record_history(kind, &account_id, &currency, delta, new_value);
+            self.internal_record_stats(kind, delta);
+            value = new_value;
+        }
+        if ops.iter().any(|operation| operation != &Operation::Reset) {
+            self.internal_record_operation(&account_id);
+        }
+        self.internal_set_num(&account_id, &currency, value);
+        self.emit_event(
+            "batch_apply",
+            &BatchEvent {
+                account_id: &account_id,
+                currency: &currency,
+                operations: U64(ops.len() as u64),
+                old_value: I128(old_value),
+                new_value: I128(value),
+            },
+        );
+        self.internal_outcome(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn add(amount: u128) -> Operation {
+        Operation::Add {
+            amount: U128(amount),
+        }
+    }
+
+    fn sub(amount: u128) -> Operation {
+        Operation::Sub {
+            amount: U128(amount),
+        }
+    }
+
+    #[test]
+    fn operations_apply_in_order_with_one_event() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(100)), None);
+        testing_env!(context_for("jane.testnet", 0));
+        let value = contract.batch_apply(vec![add(250), sub(10), add(5)], None);
+        assert_eq!(345, value.new_balance.0);
+        assert_eq!(
+            345,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        assert_eq!(4, contract.get_history(U64(0), 10).items.len());
+        let events: Vec<_> = get_logs()
+            .into_iter()
+            .filter(|log| log.starts_with("EVENT_JSON:"))
+            .collect();
+        assert_eq!(
+            vec!["EVENT_JSON:{\"data\":[{\"account_id\":\"jane.testnet\",\"currency\":\"NEAR\",\"new_value\":\"345\",\"old_value\":\"100\",\"operations\":\"3\"}],\"event\":\"batch_apply\",\"standard\":\"changeback\",\"version\":\"1.0.0\"}"],
+            events
+        );
+    }
+
+    #[test]
+    fn a_batch_is_one_operation_for_rebates() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_credit_rebatable_fee(&"jane.testnet".to_string(), 10u128.pow(24));
+        let threshold = contract.get_config().rebate_threshold;
+        for _ in 0..threshold {
+            contract.add(None, None, None, None);
+        }
+        contract.batch_apply(vec![add(1); MAX_BATCH_OPERATIONS], None);
+        assert_eq!(
+            contract.get_config().rebate_per_op.0,
+            contract.get_rebate("jane.testnet".to_string()).0
+        );
+    }
+
+    #[test]
+    fn owner_resets_within_a_batch() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let value = contract.batch_apply(
+            vec![add(250), Operation::Reset, sub(10)],
+            Some("USDC".to_string()),
+        );
+        assert_eq!(-10, value.new_balance.0);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "[BATCH_OPERATION_FAILED] Operation 1: [OWNER_ONLY] Only the owner can call this method"
+    )]
+    fn reset_in_a_batch_is_owner_only() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.batch_apply(vec![sub(10), Operation::Reset], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Operation 1: [AMOUNT_NOT_POSITIVE] Amount must be positive")]
+    fn one_bad_operation_refuses_the_batch() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.batch_apply(vec![add(250), add(0)], None);
+    }
+}
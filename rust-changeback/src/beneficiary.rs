@@ -0,0 +1,222 @@
+//! Beneficiaries who inherit the balance of inactive accounts.
+//!
+//! An account can [name a beneficiary](Change::set_beneficiary) and an
+//! inactivity period. Each call by the account that changes its record
+//! counts as activity; once none has happened for the whole period, the
+//! beneficiary can [claim](Change::claim_inheritance) the account's change,
+//! which moves into the beneficiary's own balance.
+
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Timestamp};
+
+use crate::account::Account;
+use crate::config::DAY;
+use crate::*;
+
+/// Shortest inactivity period an account may choose, so a beneficiary can't
+/// claim from an account that is merely on holiday.
+const MIN_INACTIVITY_PERIOD: u64 = 30 * DAY;
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BeneficiaryView {
+    pub beneficiary_id: AccountId,
+    pub inactivity_period: U64,
+    /// When the beneficiary may claim, unless the account is active before.
+    pub claimable_at: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct BeneficiaryEvent<'a> {
+    account_id: &'a AccountId,
+    beneficiary_id: Option<&'a AccountId>,
+    inactivity_period: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct InheritanceClaimedEvent<'a> {
+    account_id: &'a AccountId,
+    beneficiary_id: &'a AccountId,
+    amount: U128,
+}
+
+fn claimable_at(account: &Account) -> Timestamp {
+    account
+        .last_active
+        .saturating_add(account.inactivity_period)
+}
+
+#[near_bindgen]
+impl Change {
+    /// Names `beneficiary_id` as the caller's beneficiary, able to claim their
+    /// change after `inactivity_period` nanoseconds without activity, or
+    /// removes the beneficiary when it is `None`.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_beneficiary '{"beneficiary_id": "heir.testnet", "inactivity_period": "31536000000000000"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn set_beneficiary(
+        &mut self,
+        beneficiary_id: Option<ValidAccountId>,
+        inactivity_period: U64,
+    ) {
+        self.assert_access("set_beneficiary");
+        let account_id = env::predecessor_account_id();
+        let beneficiary_id: Option<AccountId> = beneficiary_id.map(Into::into);
+        if beneficiary_id.is_some() {
+            require!(
+                inactivity_period.0 >= MIN_INACTIVITY_PERIOD,
+                messages::INACTIVITY_PERIOD_TOO_SHORT
+            );
+        }
+        require!(
+            beneficiary_id.as_ref() != Some(&account_id),
+            messages::SELF_BENEFICIARY
+        );
+        let mut account = self.internal_get_account(&account_id);
+        account.beneficiary = beneficiary_id;
+        account.inactivity_period = account
+            .beneficiary
+            .as_ref()
+            .map_or(0, |_| inactivity_period.0);
+        self.internal_set_account(&account_id, &account);
+        self.emit_event(
+            "beneficiary_set",
+            &BeneficiaryEvent {
+                account_id: &account_id,
+                beneficiary_id: account.beneficiary.as_ref(),
+                inactivity_period: U64(account.inactivity_period),
+            },
+        );
+    }
+
+    /// Moves the change of `account_id` into the caller's balance. Only its
+    /// beneficiary, once it has been inactive for its inactivity period.
+    /// Returns the amount inherited.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet claim_inheritance '{"account_id": "donation.YOU.testnet"}' --accountId heir.testnet
+    /// ```
+    pub fn claim_inheritance(&mut self, account_id: ValidAccountId) -> U128 {
+        self.assert_access("claim_inheritance");
+        self.assert_writable();
+        let account_id: AccountId = account_id.into();
+        let beneficiary_id = env::predecessor_account_id();
+        let mut account = self.internal_get_account(&account_id);
+        require!(
+            account.beneficiary.as_ref() == Some(&beneficiary_id),
+            messages::BENEFICIARY_ONLY
+        );
+        require!(
+            env::block_timestamp() >= claimable_at(&account),
+            messages::ACCOUNT_STILL_ACTIVE
+        );
+        self.assert_unlocked(&account_id);
+        let amount = account.balance;
+        require!(amount > 0, messages::NOTHING_TO_INHERIT);
+        account.balance = 0;
+        self.internal_set_account(&account_id, &account);
+        self.internal_credit_balance(&beneficiary_id, amount, Book::UserChange);
+        self.emit_event(
+            "inheritance_claimed",
+            &InheritanceClaimedEvent {
+                account_id: &account_id,
+                beneficiary_id: &beneficiary_id,
+                amount: U128(amount),
+            },
+        );
+        U128(amount)
+    }
+
+    /// Returns the beneficiary of `account_id`, if it named one.
+    pub fn get_beneficiary(&self, account_id: AccountId) -> Option<BeneficiaryView> {
+        let account = self.internal_get_account(&account_id);
+        let beneficiary_id = account.beneficiary.clone()?;
+        Some(BeneficiaryView {
+            beneficiary_id,
+            inactivity_period: U64(account.inactivity_period),
+            claimable_at: U64(claimable_at(&account)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    const PERIOD: u64 = 30 * DAY;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn at(predecessor: &str, timestamp: u64) -> near_sdk::VMContext {
+        let mut context = context_for(predecessor, 0);
+        context.block_timestamp = timestamp;
+        context
+    }
+
+    fn jane_with_heir() -> Change {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_credit_balance(&"jane.testnet".to_string(), 70, Book::Custody);
+        contract.set_beneficiary(Some(account("heir.testnet")), U64(PERIOD));
+        contract
+    }
+
+    #[test]
+    fn beneficiary_inherits_after_the_period() {
+        let mut contract = jane_with_heir();
+        assert_eq!(
+            Some(U64(PERIOD)),
+            contract
+                .get_beneficiary("jane.testnet".to_string())
+                .map(|view| view.claimable_at)
+        );
+        testing_env!(at("heir.testnet", PERIOD));
+        assert_eq!(70, contract.claim_inheritance(account("jane.testnet")).0);
+        assert_eq!(0, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(70, contract.get_balance("heir.testnet".to_string()).0);
+        assert_eq!(70, contract.book(Book::UserChange));
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is still active")]
+    fn activity_restarts_the_period() {
+        let mut contract = jane_with_heir();
+        testing_env!(at("jane.testnet", PERIOD - 1));
+        contract.set_round_up(true);
+        testing_env!(at("heir.testnet", PERIOD));
+        contract.claim_inheritance(account("jane.testnet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the beneficiary can claim the balance")]
+    fn only_the_beneficiary_can_claim() {
+        let mut contract = jane_with_heir();
+        testing_env!(at("bob.testnet", PERIOD));
+        contract.claim_inheritance(account("jane.testnet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Inactivity period must be at least 30 days")]
+    fn inactivity_period_has_a_minimum() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_beneficiary(Some(account("heir.testnet")), U64(DAY));
+    }
+
+    #[test]
+    fn beneficiary_can_be_removed() {
+        let mut contract = jane_with_heir();
+        contract.set_beneficiary(None, U64(0));
+        assert_eq!(None, contract.get_beneficiary("jane.testnet".to_string()));
+    }
+}
@@ -0,0 +1,79 @@
+//! Compact bitmaps for claimed / processed flags over sequential ids.
+//!
+//! Keeping one set entry per flagged id costs a full storage record each; a
+//! bitmap keeps one bit per id instead, in chunks of `CHUNK_BITS` stored under
+//! the bitmap's prefix. A chunk only grows up to the highest byte set in it,
+//! so sparse low ids stay cheap too.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::IntoStorageKey;
+
+/// Bits stored per chunk.
+const CHUNK_BITS: u64 = 8 * 256;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Bitmap {
+    chunks: LookupMap<u64, Vec<u8>>,
+}
+
+/// Chunk, byte within the chunk and mask within the byte of bit `index`.
+fn locate(index: u64) -> (u64, usize, u8) {
+    let offset = index % CHUNK_BITS;
+    (index / CHUNK_BITS, (offset / 8) as usize, 1 << (offset % 8))
+}
+
+impl Bitmap {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            chunks: LookupMap::new(prefix),
+        }
+    }
+
+    pub fn contains(&self, index: u64) -> bool {
+        let (chunk, byte, mask) = locate(index);
+        self.chunks
+            .get(&chunk)
+            .and_then(|bits| bits.get(byte).copied())
+            .is_some_and(|bits| bits & mask != 0)
+    }
+
+    /// Sets bit `index`. Returns whether it wasn't set yet.
+    pub fn insert(&mut self, index: u64) -> bool {
+        let (chunk, byte, mask) = locate(index);
+        let mut bits = self.chunks.get(&chunk).unwrap_or_default();
+        if bits.len() <= byte {
+            bits.resize(byte + 1, 0);
+        } else if bits[byte] & mask != 0 {
+            return false;
+        }
+        bits[byte] |= mask;
+        self.chunks.insert(&chunk, &bits);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn bits_are_set_once() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut bitmap = Bitmap::new(b"b".to_vec());
+        assert!(!bitmap.contains(3));
+        assert!(bitmap.insert(3));
+        assert!(!bitmap.insert(3));
+        assert!(bitmap.contains(3));
+        assert!(!bitmap.contains(2));
+        assert!(!bitmap.contains(11));
+        assert!(bitmap.insert(CHUNK_BITS + 3));
+        assert!(bitmap.contains(CHUNK_BITS + 3));
+        assert!(bitmap.insert(CHUNK_BITS - 1));
+        assert!(bitmap.contains(3));
+        assert_eq!(Some(1), bitmap.chunks.get(&1).map(|bits| bits.len()));
+    }
+}
@@ -0,0 +1,85 @@
+//! Typed results of the promises callbacks resolve.
+//!
+//! Every callback of this contract resolves a single promise. Instead of
+//! matching on [`PromiseResult`] each time, callbacks read its outcome with
+//! [`promise_result`], which deserializes the value returned on success, or
+//! with [`promise_succeeded`] when the call returns nothing, as transfers do.
+
+use std::fmt;
+
+use near_sdk::serde::de::DeserializeOwned;
+use near_sdk::serde_json;
+use near_sdk::{env, PromiseResult};
+
+/// Why the promise a callback resolves didn't yield a value.
+#[derive(Debug, PartialEq)]
+pub enum CallbackError {
+    /// The call failed, or a promise it returned did.
+    Failed,
+    /// The result isn't available yet, which the runtime shouldn't let happen.
+    NotReady,
+    /// The call succeeded but returned something other than the expected value.
+    InvalidValue,
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CallbackError::Failed => "call failed",
+            CallbackError::NotReady => "result not ready",
+            CallbackError::InvalidValue => "unexpected return value",
+        })
+    }
+}
+
+fn raw_promise_result() -> Result<Vec<u8>, CallbackError> {
+    match env::promise_result(0) {
+        PromiseResult::Successful(value) => Ok(value),
+        PromiseResult::Failed => Err(CallbackError::Failed),
+        PromiseResult::NotReady => Err(CallbackError::NotReady),
+    }
+}
+
+/// Returns the JSON value returned by the resolved promise.
+pub(crate) fn promise_result<T: DeserializeOwned>() -> Result<T, CallbackError> {
+    let value = raw_promise_result()?;
+    serde_json::from_slice(&value).map_err(|_| CallbackError::InvalidValue)
+}
+
+/// Returns whether the resolved promise succeeded, ignoring what it returned.
+pub(crate) fn promise_succeeded() -> Result<(), CallbackError> {
+    raw_promise_result().map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn resolving(result: PromiseResult) {
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![result]
+        );
+    }
+
+    #[test]
+    fn results_are_typed() {
+        resolving(PromiseResult::Successful(b"\"42\"".to_vec()));
+        assert_eq!(Ok(U128(42)), promise_result::<U128>());
+        assert_eq!(Ok(()), promise_succeeded());
+        resolving(PromiseResult::Successful(b"{}".to_vec()));
+        assert_eq!(Err(CallbackError::InvalidValue), promise_result::<U128>());
+        resolving(PromiseResult::Failed);
+        assert_eq!(Err(CallbackError::Failed), promise_result::<U128>());
+        assert_eq!(Err(CallbackError::Failed), promise_succeeded());
+        resolving(PromiseResult::NotReady);
+        assert_eq!(Err(CallbackError::NotReady), promise_succeeded());
+    }
+}
@@ -0,0 +1,379 @@
+//! Time-windowed promotional campaigns.
+//!
+//! The owner runs campaigns between a start and an end timestamp, for all
+//! merchants or for a single one, each capped at `max_uses` purchases. Every
+//! purchase at a merchant is checked against the running campaigns, which
+//! apply by themselves, without the buyer doing anything:
+//!
+//! - [`PointsMultiplier`](CampaignEffect::PointsMultiplier) multiplies the
+//!   loyalty points awarded; of several running, the largest applies.
+//! - [`FeeWaiver`](CampaignEffect::FeeWaiver) waives the platform fee on the
+//!   purchase, credited against the fee of the merchant's next settlement.
+//!
+//! Each campaign keeps the numbers marketers need to judge it, exposed by
+//! [`get_campaign_stats`](Change::get_campaign_stats).
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::amount::{bps_of, per_near};
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CampaignEffect {
+    PointsMultiplier { multiplier: u32 },
+    FeeWaiver,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Campaign {
+    pub name: String,
+    /// Merchant the campaign runs at; `None` for all merchants.
+    pub merchant_id: Option<AccountId>,
+    pub effect: CampaignEffect,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub max_uses: u32,
+    /// Purchases the campaign applied to.
+    pub uses: u32,
+    /// Distinct buyers among those purchases.
+    pub participants: u32,
+    /// Platform fees waived, in yoctoNEAR.
+    pub fees_waived: Balance,
+    /// Loyalty points awarded on top of the regular ones.
+    pub bonus_points: u128,
+}
+
+impl Campaign {
+    fn applies(&self, merchant_id: &AccountId, now: Timestamp) -> bool {
+        self.start <= now
+            && now < self.end
+            && self.uses < self.max_uses
+            && self.merchant_id.as_ref().is_none_or(|id| id == merchant_id)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignView {
+    pub id: U64,
+    pub name: String,
+    pub merchant_id: Option<AccountId>,
+    pub effect: CampaignEffect,
+    pub start: U64,
+    pub end: U64,
+    pub max_uses: u32,
+    pub uses: u32,
+}
+
+impl CampaignView {
+    fn new(id: u64, campaign: Campaign) -> Self {
+        Self {
+            id: U64(id),
+            name: campaign.name,
+            merchant_id: campaign.merchant_id,
+            effect: campaign.effect,
+            start: U64(campaign.start),
+            end: U64(campaign.end),
+            max_uses: campaign.max_uses,
+            uses: campaign.uses,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignStats {
+    pub redemptions: u32,
+    pub unique_participants: u32,
+    /// Fees waived by the campaign, in yoctoNEAR.
+    pub total_discount: U128,
+    pub bonus_points: U128,
+    /// Whether the campaign still applies to purchases as of now.
+    pub running: bool,
+}
+
+impl Change {
+    /// Applies the running campaigns to a purchase of `price` by `buyer_id` at
+    /// `merchant_id` and returns the loyalty points multiplier to award with.
+    pub(crate) fn internal_apply_campaigns(
+        &mut self,
+        buyer_id: &AccountId,
+        merchant_id: &AccountId,
+        price: Balance,
+    ) -> u32 {
+        let now = env::block_timestamp();
+        let mut merchant = self
+            .merchants
+            .get(merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        // the largest multiplier wins and only that campaign counts the purchase
+        let mut best_multiplier: Option<(u64, u32)> = None;
+        for campaign_id in self.active_campaigns.to_vec() {
+            let campaign = self.campaigns.get(campaign_id).unwrap();
+            if now >= campaign.end || campaign.uses >= campaign.max_uses {
+                self.active_campaigns.remove(&campaign_id);
+                continue;
+            }
+            if !campaign.applies(merchant_id, now) {
+                continue;
+            }
+            match campaign.effect {
+                CampaignEffect::PointsMultiplier { multiplier } => {
+                    if best_multiplier.is_none_or(|(_, best)| multiplier > best) {
+                        best_multiplier = Some((campaign_id, multiplier));
+                    }
+                }
+                CampaignEffect::FeeWaiver => {
+                    let waived = bps_of(price, merchant.fee_bps, self.config().rounding_mode);
+                    merchant.fee_credit += waived;
+                    self.internal_count_campaign_use(campaign_id, campaign, buyer_id, waived, 0);
+                }
+            }
+        }
+        self.merchants.insert(merchant_id, &merchant);
+        match best_multiplier {
+            Some((campaign_id, multiplier)) => {
+                let campaign = self.campaigns.get(campaign_id).unwrap();
+                let bonus = per_near(price, merchant.points_per_near, self.config().rounding_mode)
+                    * u128::from(multiplier - 1);
+                self.internal_count_campaign_use(campaign_id, campaign, buyer_id, 0, bonus);
+                multiplier
+            }
+            None => 1,
+        }
+    }
+
+    fn internal_count_campaign_use(
+        &mut self,
+        campaign_id: u64,
+        mut campaign: Campaign,
+        buyer_id: &AccountId,
+        fee_waived: Balance,
+        bonus_points: u128,
+    ) {
+        campaign.uses += 1;
+        let buyer = self.internal_intern(buyer_id);
+        let mut participants = self
+            .campaign_participants
+            .get(&campaign_id)
+            .unwrap_or_else(|| Bitmap::new(StorageKey::CampaignParticipantBits { campaign_id }));
+        if participants.insert(u64::from(buyer)) {
+            campaign.participants += 1;
+            self.campaign_participants
+                .insert(&campaign_id, &participants);
+        }
+        campaign.fees_waived += fee_waived;
+        campaign.bonus_points += bonus_points;
+        self.campaigns.replace(campaign_id, &campaign);
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Starts a campaign between `start` and `end`, for purchases at `merchant_id`
+    /// or at every merchant, applying to at most `max_uses` purchases. Owner only.
+    /// Returns the campaign id.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet create_campaign '{"name": "double points weekend", "effect": {"PointsMultiplier": {"multiplier": 2}}, "start": "1700000000000000000", "end": "1700172800000000000", "max_uses": 1000}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn create_campaign(
+        &mut self,
+        name: String,
+        merchant_id: Option<AccountId>,
+        effect: CampaignEffect,
+        start: U64,
+        end: U64,
+        max_uses: u32,
+    ) -> U64 {
+        self.assert_access("create_campaign");
+        require!(start.0 < end.0, messages::CAMPAIGN_ENDS_BEFORE_START);
+        if let Some(merchant_id) = &merchant_id {
+            require!(
+                self.merchants.get(merchant_id).is_some(),
+                messages::UNKNOWN_MERCHANT
+            );
+        }
+        if let CampaignEffect::PointsMultiplier { multiplier } = effect {
+            require!(multiplier > 0, messages::MULTIPLIER_NOT_POSITIVE);
+        }
+        let campaign = Campaign {
+            name,
+            merchant_id,
+            effect,
+            start: start.0,
+            end: end.0,
+            max_uses,
+            uses: 0,
+            participants: 0,
+            fees_waived: 0,
+            bonus_points: 0,
+        };
+        let id = self.campaigns.len();
+        self.campaigns.push(&campaign);
+        self.active_campaigns.insert(&id);
+        self.emit_event("campaign_created", &CampaignView::new(id, campaign));
+        U64(id)
+    }
+
+    /// Returns how campaign `campaign_id` has performed so far.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_campaign_stats '{"campaign_id": "0"}'
+    /// ```
+    pub fn get_campaign_stats(&self, campaign_id: U64) -> Option<CampaignStats> {
+        let now = env::block_timestamp();
+        self.campaigns
+            .get(campaign_id.0)
+            .map(|campaign| CampaignStats {
+                redemptions: campaign.uses,
+                unique_participants: campaign.participants,
+                total_discount: U128(campaign.fees_waived),
+                bonus_points: U128(campaign.bonus_points),
+                running: campaign.start <= now
+                    && now < campaign.end
+                    && campaign.uses < campaign.max_uses,
+            })
+    }
+
+    pub fn get_campaign(&self, campaign_id: U64) -> Option<CampaignView> {
+        self.campaigns
+            .get(campaign_id.0)
+            .map(|campaign| CampaignView::new(campaign_id.0, campaign))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::ONE_NEAR;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn market() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(account("shop.testnet"), account("shop.testnet"), 500);
+        contract.register_merchant(account("cafe.testnet"), account("cafe.testnet"), 500);
+        contract.set_points_rate("shop.testnet".to_string(), 10, U128(0));
+        contract.set_points_rate("cafe.testnet".to_string(), 10, U128(0));
+        contract
+    }
+
+    fn buy_at(contract: &mut Change, merchant: &str, timestamp: Timestamp) {
+        let mut context = context_for("jane.testnet", ONE_NEAR);
+        context.block_timestamp = timestamp;
+        testing_env!(context);
+        contract.purchase(
+            U128(ONE_NEAR),
+            None,
+            Some(merchant.to_string()),
+            None,
+            None,
+            None,
+        );
+    }
+
+    fn points(contract: &Change, merchant: &str) -> u128 {
+        contract
+            .get_points("jane.testnet".to_string(), merchant.to_string())
+            .0
+    }
+
+    #[test]
+    fn multiplier_applies_within_the_window_only() {
+        let mut contract = market();
+        let id = contract.create_campaign(
+            "double points".to_string(),
+            Some("shop.testnet".to_string()),
+            CampaignEffect::PointsMultiplier { multiplier: 2 },
+            U64(100),
+            U64(200),
+            10,
+        );
+        buy_at(&mut contract, "shop.testnet", 50);
+        buy_at(&mut contract, "shop.testnet", 150);
+        buy_at(&mut contract, "cafe.testnet", 150);
+        buy_at(&mut contract, "shop.testnet", 200);
+        assert_eq!(40, points(&contract, "shop.testnet"));
+        assert_eq!(10, points(&contract, "cafe.testnet"));
+        assert_eq!(1, contract.get_campaign(id).unwrap().uses);
+    }
+
+    #[test]
+    fn fee_waiver_stops_at_its_cap() {
+        let mut contract = market();
+        contract.create_campaign(
+            "zero fees".to_string(),
+            None,
+            CampaignEffect::FeeWaiver,
+            U64(0),
+            U64(1000),
+            2,
+        );
+        buy_at(&mut contract, "shop.testnet", 10);
+        buy_at(&mut contract, "cafe.testnet", 10);
+        buy_at(&mut contract, "shop.testnet", 10);
+        let fee = ONE_NEAR / 20;
+        let shop = contract.merchants.get(&"shop.testnet".to_string()).unwrap();
+        assert_eq!(fee, shop.fee_credit);
+        let cafe = contract.merchants.get(&"cafe.testnet".to_string()).unwrap();
+        assert_eq!(fee, cafe.fee_credit);
+        assert!(contract.active_campaigns.is_empty());
+    }
+
+    #[test]
+    fn stats_count_unique_buyers_and_what_was_granted() {
+        let mut contract = market();
+        let double = contract.create_campaign(
+            "double points".to_string(),
+            None,
+            CampaignEffect::PointsMultiplier { multiplier: 2 },
+            U64(0),
+            U64(1000),
+            10,
+        );
+        let triple = contract.create_campaign(
+            "triple points at the shop".to_string(),
+            Some("shop.testnet".to_string()),
+            CampaignEffect::PointsMultiplier { multiplier: 3 },
+            U64(0),
+            U64(1000),
+            10,
+        );
+        let zero_fees = contract.create_campaign(
+            "zero fees".to_string(),
+            None,
+            CampaignEffect::FeeWaiver,
+            U64(0),
+            U64(1000),
+            10,
+        );
+        buy_at(&mut contract, "shop.testnet", 10);
+        buy_at(&mut contract, "cafe.testnet", 10);
+        buy_at(&mut contract, "cafe.testnet", 10);
+
+        let stats = contract.get_campaign_stats(double).unwrap();
+        assert_eq!((2, 1), (stats.redemptions, stats.unique_participants));
+        assert_eq!(20, stats.bonus_points.0);
+        let stats = contract.get_campaign_stats(triple).unwrap();
+        assert_eq!(1, stats.redemptions);
+        assert_eq!(20, stats.bonus_points.0);
+        let stats = contract.get_campaign_stats(zero_fees).unwrap();
+        assert_eq!(3, stats.redemptions);
+        assert_eq!(3 * ONE_NEAR / 20, stats.total_discount.0);
+        assert!(stats.running);
+        assert_eq!(30, points(&contract, "shop.testnet"));
+    }
+}
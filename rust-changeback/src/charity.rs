@@ -0,0 +1,214 @@
+//! Donating change to charity instead of keeping it.
+//!
+//! An account that opts in has the change of each of its purchases sent to
+//! the configured `charity_id` rather than credited to its balance. Every
+//! account's cumulative donations are tracked, and the biggest donors are
+//! kept on a public leaderboard. A donation the charity can't receive is
+//! credited back as change.
+
+use near_sdk::json_types::U128;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::account::Account;
+use crate::*;
+
+/// Donors kept on the leaderboard.
+pub(crate) const LEADERBOARD_SIZE: usize = 20;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct DonationEvent<'a> {
+    account_id: &'a AccountId,
+    charity_id: &'a AccountId,
+    amount: U128,
+    total: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonorView {
+    pub account_id: AccountId,
+    pub donated: U128,
+}
+
+impl Change {
+    /// Credits `change` to `buyer`, or donates it if they opted in and a charity
+    /// is configured. The caller stores `buyer` afterwards.
+    pub(crate) fn internal_keep_change(
+        &mut self,
+        buyer_id: &AccountId,
+        buyer: &mut Account,
+        change: Balance,
+    ) {
+        let charity_id = match &self.config().charity_id {
+            Some(charity_id) if buyer.donate_change && change > 0 => charity_id.clone(),
+            _ => {
+                buyer.balance += change;
+                self.internal_post(Posting::receive(Book::UserChange, change));
+                return;
+            }
+        };
+        buyer.donated += change;
+        self.internal_rank_donor(buyer_id, buyer.donated);
+        self.emit_event(
+            "change_donated",
+            &DonationEvent {
+                account_id: buyer_id,
+                charity_id: &charity_id,
+                amount: U128(change),
+                total: U128(buyer.donated),
+            },
+        );
+        self.internal_transfer(charity_id, change)
+            .then(ext_self::on_change_donated(
+                buyer_id.clone(),
+                U128(change),
+                &env::current_account_id(),
+                0,
+                self.config().gas.callback.0,
+            ));
+    }
+
+    /// Puts `account_id` on the leaderboard with `donated`, if that's enough to make it.
+    fn internal_rank_donor(&mut self, account_id: &AccountId, donated: Balance) {
+        let leaderboard = &mut self.donation_leaderboard;
+        leaderboard.retain(|(donor_id, _)| donor_id != account_id);
+        let rank = leaderboard.partition_point(|(_, other)| *other >= donated);
+        leaderboard.insert(rank, (account_id.clone(), donated));
+        leaderboard.truncate(LEADERBOARD_SIZE);
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Opts the caller in or out of donating their change to the charity.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_donate_change '{"enabled": true}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn set_donate_change(&mut self, enabled: bool) {
+        self.assert_access("set_donate_change");
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_get_account(&account_id);
+        account.donate_change = enabled;
+        self.internal_set_account(&account_id, &account);
+    }
+
+    /// Credits a donation back as change if the charity couldn't receive it.
+    pub fn on_change_donated(&mut self, account_id: AccountId, amount: U128) {
+        self.assert_access("on_change_donated");
+        if promise_succeeded().is_ok() {
+            return;
+        }
+        let mut account = self.internal_get_account(&account_id);
+        account.donated -= amount.0;
+        account.balance += amount.0;
+        self.internal_set_account(&account_id, &account);
+        self.internal_post(Posting::receive(Book::UserChange, amount.0));
+        self.donation_leaderboard
+            .retain(|(donor_id, _)| *donor_id != account_id);
+        if account.donated > 0 {
+            self.internal_rank_donor(&account_id, account.donated);
+        }
+        self.log(LogLevel::Info, messages::DONATION_FAILED, &[]);
+    }
+
+    /// Returns the change `account_id` has donated so far, in yoctoNEAR.
+    pub fn get_donated(&self, account_id: AccountId) -> U128 {
+        U128(self.internal_get_account(&account_id).donated)
+    }
+
+    /// Returns up to `limit` of the biggest donors, biggest first.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_donations_leaderboard '{"limit": 10}'
+    /// ```
+    pub fn get_donations_leaderboard(&self, limit: u64) -> Vec<DonorView> {
+        self.donation_leaderboard
+            .iter()
+            .take(limit as usize)
+            .map(|(account_id, donated)| DonorView {
+                account_id: account_id.clone(),
+                donated: U128(*donated),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn charity_shop() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                charity_id: Some("charity.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        contract
+    }
+
+    fn buy(contract: &mut Change, buyer: &str, price: u128) {
+        testing_env!(context_for(buyer, 100));
+        contract.purchase(U128(price), None, None, None, None, None);
+    }
+
+    #[test]
+    fn donors_give_their_change_away() {
+        let mut contract = charity_shop();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_donate_change(true);
+        buy(&mut contract, "jane.testnet", 30);
+        buy(&mut contract, "bob.testnet", 30);
+        assert_eq!(0, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(70, contract.get_donated("jane.testnet".to_string()).0);
+        assert_eq!(70, contract.get_balance("bob.testnet".to_string()).0);
+        assert_eq!(0, contract.get_donated("bob.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn leaderboard_ranks_the_biggest_donors() {
+        let mut contract = charity_shop();
+        for donor in ["jane.testnet", "bob.testnet"] {
+            testing_env!(context_for(donor, 0));
+            contract.set_donate_change(true);
+        }
+        buy(&mut contract, "jane.testnet", 90);
+        buy(&mut contract, "bob.testnet", 50);
+        buy(&mut contract, "jane.testnet", 50);
+        let leaderboard: Vec<(AccountId, u128)> = contract
+            .get_donations_leaderboard(10)
+            .into_iter()
+            .map(|donor| (donor.account_id, donor.donated.0))
+            .collect();
+        assert_eq!(
+            vec![
+                ("jane.testnet".to_string(), 60),
+                ("bob.testnet".to_string(), 50)
+            ],
+            leaderboard
+        );
+        assert_eq!(1, contract.get_donations_leaderboard(1).len());
+    }
+
+    #[test]
+    fn leaderboard_keeps_only_the_top_donors() {
+        let mut contract = charity_shop();
+        for donor in 0..LEADERBOARD_SIZE as u128 + 1 {
+            contract.internal_rank_donor(&format!("donor{}.testnet", donor), donor + 1);
+        }
+        let leaderboard = contract.get_donations_leaderboard(100);
+        assert_eq!(LEADERBOARD_SIZE, leaderboard.len());
+        assert_eq!(LEADERBOARD_SIZE as u128 + 1, leaderboard[0].donated.0);
+        assert_eq!(2, leaderboard[LEADERBOARD_SIZE - 1].donated.0);
+    }
+}
@@ -0,0 +1,744 @@
+//! Tunable parameters, kept in state so the owner can adjust them without a redeploy.
+//!
+//! A new configuration can also be scheduled for a later `effective_at`, so
+//! integrators get notice of a parameter change. It takes over for every call
+//! from that moment on, all fields at once; [`Change::config`] is what the rest
+//! of the contract reads.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, ValidAccountId, I128, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Gas, Timestamp};
+
+use crate::amount::{RoundingMode, BPS_DENOMINATOR};
+use crate::display::MAX_DECIMALS;
+use crate::dust::MAX_DUST_BALANCE_THRESHOLD;
+use crate::voucher::assert_valid_voucher_key;
+use crate::*;
+
+/// One minute in nanoseconds, the unit of `env::block_timestamp()`.
+pub(crate) const MINUTE: u64 = 60 * 1_000_000_000;
+pub(crate) const HOUR: u64 = 60 * MINUTE;
+pub(crate) const DAY: u64 = 24 * HOUR;
+
+/// One teragas, the unit gas budgets are usually given in.
+pub(crate) const TGAS: Gas = 1_000_000_000_000;
+/// Most gas a transaction can be given, which bounds each budget.
+const MAX_GAS_BUDGET: Gas = 300 * TGAS;
+
+/// Gas attached to each kind of cross-contract call, so a downstream contract
+/// needing more doesn't take a redeploy.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GasBudgets {
+    /// Oracle `get_price`, see [`oracle`](crate::oracle).
+    pub get_price: U64,
+    /// NEP-141 `ft_transfer`, see [`savings`](crate::savings).
+    pub ft_transfer: U64,
+    /// NEP-141 `ft_transfer_call` into the AMM, its `ft_on_transfer` included.
+    pub ft_transfer_call: U64,
+    /// AMM `swap`, see [`swap`](crate::swap).
+    pub amm_swap: U64,
+    /// AMM `withdraw`, its `ft_transfer` back here included.
+    pub amm_withdraw: U64,
+    /// Each callback of this contract checking the outcome of a call or transfer.
+    pub callback: U64,
+    /// Most gas a merchant notification gets, see [`webhook`](crate::webhook).
+    pub webhook: U64,
+    /// Listener `on_change_updated`, see [`listener`](crate::listener).
+    pub listener: U64,
+    /// `new` of a child contract, see [`factory`](crate::factory).
+    pub child_init: U64,
+    /// Instance `receive_remote_transfer`, see [`remote_transfer`](crate::remote_transfer).
+    pub remote_transfer: U64,
+}
+
+impl Default for GasBudgets {
+    fn default() -> Self {
+        Self {
+            get_price: U64(10 * TGAS),
+            ft_transfer: U64(10 * TGAS),
+            ft_transfer_call: U64(35 * TGAS),
+            amm_swap: U64(20 * TGAS),
+            amm_withdraw: U64(30 * TGAS),
+            callback: U64(10 * TGAS),
+            webhook: U64(50 * TGAS),
+            listener: U64(10 * TGAS),
+            child_init: U64(20 * TGAS),
+            remote_transfer: U64(20 * TGAS),
+        }
+    }
+}
+
+impl GasBudgets {
+    fn all(&self) -> [Gas; 10] {
+        [
+            self.get_price.0,
+            self.ft_transfer.0,
+            self.ft_transfer_call.0,
+            self.amm_swap.0,
+            self.amm_withdraw.0,
+            self.callback.0,
+            self.webhook.0,
+            self.listener.0,
+            self.child_init.0,
+            self.remote_transfer.0,
+        ]
+    }
+}
+
+/// Range every counter must stay within; `add`, `change` and `batch_apply`
+/// refuse operations that would leave it.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CounterLimits {
+    pub min_value: I128,
+    pub max_value: I128,
+}
+
+impl Default for CounterLimits {
+    fn default() -> Self {
+        Self {
+            min_value: I128(i128::MIN),
+            max_value: I128(i128::MAX),
+        }
+    }
+}
+
+/// Calls to `add` and `change` each account may make per window, see
+/// [`throttle`](crate::throttle).
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateLimit {
+    /// 0 disables the limit.
+    pub max_calls: u32,
+    /// Length of a window in nanoseconds.
+    pub window: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+    /// Operations an account can perform per period before it starts earning rebates.
+    pub rebate_threshold: u32,
+    /// Length of a rebate period in nanoseconds.
+    pub rebate_period: U64,
+    /// Rebate earned for each operation above the threshold, in yoctoNEAR.
+    pub rebate_per_op: U128,
+    /// Most rebate an account accrues in one period, in yoctoNEAR.
+    pub rebate_cap_per_period: U128,
+    /// Share of the platform fee kept on each matured purchase of a referred
+    /// account credited to its referrer, in basis points, see [`referral`](crate::referral).
+    pub referral_bonus_bps: u16,
+    /// Referral bonus at which a referrer gets paid out automatically, in yoctoNEAR.
+    pub referral_payout_threshold: U128,
+    /// Maximum number of queued payouts processed in one call, bounding its gas.
+    pub payout_batch_size: u32,
+    /// ref.finance-style AMM used to consolidate tokens, see [`swap`](crate::swap).
+    pub amm_contract: Option<AccountId>,
+    /// Whitelisted token everything else gets swapped into.
+    pub base_token: Option<AccountId>,
+    /// Price oracle queried by [`refresh_price`](Change::refresh_price).
+    pub oracle_contract: Option<AccountId>,
+    /// Oldest cached price, in nanoseconds, still trusted for conversions.
+    pub price_max_age: U64,
+    /// Merchant account receiving the price of every purchase.
+    pub beneficiary_id: Option<AccountId>,
+    /// Smallest merchant revenue worth a settlement transfer, in yoctoNEAR.
+    pub min_settlement: U128,
+    /// Dispute window newly registered merchants start with, in nanoseconds.
+    pub dispute_window: U64,
+    /// Age, in nanoseconds, at which loyalty points expire; 0 keeps them forever.
+    pub points_max_age: U64,
+    /// Most the owner can withdraw from the treasury per day without the
+    /// trustees, in yoctoNEAR. Once there are trustees, only they change it.
+    pub treasury_multisig_threshold: U128,
+    /// Most the hot treasury holds, in yoctoNEAR; the rest is kept cold. `None`
+    /// keeps it all hot. Once there are trustees, only they change it.
+    pub hot_balance_limit: Option<U128>,
+    /// Hours the outflow of the current hour is compared against, see [`velocity`](crate::velocity).
+    pub velocity_window_hours: u32,
+    /// How many times the trailing hourly average an hour's outflow may reach
+    /// before it is reported; 0 disables the check.
+    pub velocity_alert_multiple: u32,
+    /// Hourly outflow, in yoctoNEAR, reported while the trailing average is 0,
+    /// when no multiple of it means anything.
+    pub velocity_quiet_floor: U128,
+    /// Whether a reported outflow also pauses withdrawals.
+    pub velocity_auto_pause: bool,
+    /// How much gets logged; below `Audit` even events are dropped.
+    pub log_level: LogLevel,
+    /// Time, in nanoseconds, a requested admin key rotation waits before it can run.
+    pub key_rotation_delay: U64,
+    /// How long, in nanoseconds, the outcome of a call with an idempotency key is kept.
+    pub idempotency_ttl: U64,
+    /// How fees, bonuses, discounts and points are rounded, see [`amount`](crate::amount).
+    pub rounding_mode: RoundingMode,
+    /// Smallest amount `add` / `change` accept, see [`dust`](crate::dust); 0 accepts any.
+    pub min_operation_amount: U128,
+    /// Stored change below which the owner can sweep it into the treasury, in yoctoNEAR.
+    pub dust_balance_threshold: U128,
+    /// Account receiving the change of accounts donating it, see [`charity`](crate::charity).
+    pub charity_id: Option<AccountId>,
+    /// Contract notified of every `add` and `change`, see [`listener`](crate::listener).
+    pub listener_account_id: Option<AccountId>,
+    /// Ed25519 key signing change vouchers, see [`voucher`](crate::voucher).
+    pub voucher_public_key: Option<Base58PublicKey>,
+    /// NEAR kept on top of the storage cost, see [`storage`](crate::storage), in yoctoNEAR.
+    pub storage_reserve_margin: U128,
+    /// Whether `add`, `change` and `batch_apply` refuse accounts without a storage deposit,
+    /// see [`storage_management`](crate::storage_management).
+    pub storage_registration_required: bool,
+    /// Per-item events a batch call logs one by one; past it they are coalesced
+    /// into a single summary, see [`events`](crate::events).
+    pub max_batch_events: u32,
+    /// Gas attached to cross-contract calls.
+    pub gas: GasBudgets,
+    /// What `add` adds when called without an amount.
+    pub add_step: U128,
+    /// What `change` subtracts when called without an amount.
+    pub sub_step: U128,
+    /// Range counters must stay within.
+    pub limits: CounterLimits,
+    /// Decimal places counter amounts are displayed with, see
+    /// [`display`](crate::display); `None` leaves them raw.
+    pub decimals: Option<u8>,
+    /// How often each account may call `add` and `change`.
+    pub rate_limit: RateLimit,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rebate_threshold: 10,
+            rebate_period: U64(DAY),
+            // 0.001 NEAR
+            rebate_per_op: U128(10u128.pow(21)),
+            // 0.01 NEAR
+            rebate_cap_per_period: U128(10u128.pow(22)),
+            // 1%
+            referral_bonus_bps: 100,
+            // 0.1 NEAR
+            referral_payout_threshold: U128(10u128.pow(23)),
+            payout_batch_size: 5,
+            amm_contract: None,
+            base_token: None,
+            oracle_contract: None,
+            price_max_age: U64(5 * MINUTE),
+            beneficiary_id: None,
+            // 1 NEAR
+            min_settlement: U128(10u128.pow(24)),
+            dispute_window: U64(7 * DAY),
+            points_max_age: U64(365 * DAY),
+            // 10 NEAR
+            treasury_multisig_threshold: U128(10 * 10u128.pow(24)),
+            hot_balance_limit: None,
+            velocity_window_hours: 24,
+            velocity_alert_multiple: 0,
+            velocity_quiet_floor: U128(10 * crate::amount::ONE_NEAR),
+            velocity_auto_pause: false,
+            log_level: LogLevel::Debug,
+            key_rotation_delay: U64(2 * DAY),
+            idempotency_ttl: U64(DAY),
+            rounding_mode: RoundingMode::Floor,
+            min_operation_amount: U128(0),
+            dust_balance_threshold: U128(0),
+            charity_id: None,
+            listener_account_id: None,
+            voucher_public_key: None,
+            // 1 NEAR
+            storage_reserve_margin: U128(10u128.pow(24)),
+            storage_registration_required: false,
+            max_batch_events: 20,
+            gas: GasBudgets::default(),
+            add_step: U128(1000),
+            sub_step: U128(10),
+            limits: CounterLimits::default(),
+            decimals: None,
+            rate_limit: RateLimit {
+                max_calls: 0,
+                window: U64(MINUTE),
+            },
+        }
+    }
+}
+
+/// Configuration set to replace the current one at `effective_at`.
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct ScheduledConfig {
+    pub config: Config,
+    pub effective_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ScheduledConfigView {
+    pub config: Config,
+    pub effective_at: U64,
+}
+
+impl Change {
+    /// Returns the configuration in effect: the scheduled one once its time has come.
+    pub(crate) fn config(&self) -> &Config {
+        match &self.scheduled_config {
+            Some(scheduled) if env::block_timestamp() >= scheduled.effective_at => {
+                &scheduled.config
+            }
+            _ => &self.config,
+        }
+    }
+
+    /// Replaces the whole configuration, right away or from `effective_at` on.
+    pub(crate) fn internal_set_config(&mut self, config: Config, effective_at: Option<U64>) {
+        assert_valid_config(&config);
+        if !self.trustees.is_empty() {
+            let current = self.config();
+            require!(
+                config.treasury_multisig_threshold == current.treasury_multisig_threshold
+                    && config.hot_balance_limit == current.hot_balance_limit,
+                messages::TREASURY_LIMITS_NEED_APPROVAL
+            );
+        }
+        self.internal_apply_scheduled_config();
+        match effective_at {
+            Some(effective_at) => {
+                require!(
+                    effective_at.0 > env::block_timestamp(),
+                    messages::EFFECTIVE_TIME_IN_PAST
+                );
+                self.scheduled_config = Some(ScheduledConfig {
+                    config,
+                    effective_at: effective_at.0,
+                });
+                self.log(
+                    LogLevel::Info,
+                    messages::CONFIG_SCHEDULED,
+                    &[&effective_at.0],
+                );
+            }
+            None => {
+                self.config = config;
+                self.internal_rebalance_treasury();
+                self.log(LogLevel::Info, messages::CONFIG_UPDATED, &[]);
+            }
+        }
+    }
+
+    /// Sets the treasury limits, in the current configuration and in any
+    /// scheduled one, so it can't bring the old ones back.
+    pub(crate) fn internal_set_treasury_limits(
+        &mut self,
+        multisig_threshold: U128,
+        hot_balance_limit: Option<U128>,
+    ) {
+        self.internal_apply_scheduled_config();
+        self.config.treasury_multisig_threshold = multisig_threshold;
+        self.config.hot_balance_limit = hot_balance_limit;
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.treasury_multisig_threshold = multisig_threshold;
+            scheduled.config.hot_balance_limit = hot_balance_limit;
+        }
+        self.internal_rebalance_treasury();
+        self.log(LogLevel::Info, messages::CONFIG_UPDATED, &[]);
+    }
+
+    /// Makes a scheduled configuration whose time has come the current one.
+    fn internal_apply_scheduled_config(&mut self) {
+        if let Some(scheduled) = self.scheduled_config.take() {
+            if env::block_timestamp() >= scheduled.effective_at {
+                self.config = scheduled.config;
+            } else {
+                self.scheduled_config = Some(scheduled);
+            }
+        }
+    }
+}
+
+fn assert_valid_config(config: &Config) {
+    require!(
+        u128::from(config.referral_bonus_bps) <= BPS_DENOMINATOR,
+        messages::REFERRAL_BONUS_TOO_HIGH
+    );
+    assert_valid_gas_budgets(&config.gas);
+    require!(
+        counter_amount(config.add_step).is_ok() && counter_amount(config.sub_step).is_ok(),
+        messages::INVALID_STEPS
+    );
+    assert_valid_limits(&config.limits);
+    assert_valid_rate_limit(&config.rate_limit);
+    assert_valid_decimals(config.decimals);
+    require!(
+        config.dust_balance_threshold.0 <= MAX_DUST_BALANCE_THRESHOLD,
+        messages::DUST_THRESHOLD_TOO_HIGH,
+        MAX_DUST_BALANCE_THRESHOLD
+    );
+    if let Some(key) = &config.voucher_public_key {
+        assert_valid_voucher_key(key);
+    }
+}
+
+/// Limits must keep 0 in range, where counters start and `reset` leaves them.
+fn assert_valid_limits(limits: &CounterLimits) {
+    require!(
+        limits.min_value.0 <= 0 && limits.max_value.0 >= 0,
+        messages::LIMITS_EXCLUDE_ZERO
+    );
+}
+
+fn assert_valid_decimals(decimals: Option<u8>) {
+    require!(
+        decimals.unwrap_or(0) <= MAX_DECIMALS,
+        messages::TOO_MANY_DECIMALS,
+        MAX_DECIMALS
+    );
+}
+
+fn assert_valid_rate_limit(rate_limit: &RateLimit) {
+    require!(
+        rate_limit.max_calls == 0 || rate_limit.window.0 > 0,
+        messages::RATE_LIMIT_WINDOW_NOT_POSITIVE
+    );
+}
+
+fn assert_valid_gas_budgets(gas: &GasBudgets) {
+    require!(
+        gas.all()
+            .iter()
+            .all(|&budget| budget > 0 && budget <= MAX_GAS_BUDGET),
+        messages::INVALID_GAS_BUDGETS,
+        MAX_GAS_BUDGET
+    );
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the configuration in effect.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_config
+    /// ```
+    pub fn get_config(&self) -> Config {
+        self.config().clone()
+    }
+
+    /// Returns the configuration scheduled to take over, if it hasn't yet.
+    pub fn get_pending_config(&self) -> Option<ScheduledConfigView> {
+        self.scheduled_config
+            .as_ref()
+            .filter(|scheduled| env::block_timestamp() < scheduled.effective_at)
+            .map(|scheduled| ScheduledConfigView {
+                config: scheduled.config.clone(),
+                effective_at: U64(scheduled.effective_at),
+            })
+    }
+
+    /// Replaces the whole configuration, right away or from `effective_at` on,
+    /// replacing any configuration still pending. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_config '{"config": {...}, "effective_at": "1700000000000000000"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_config(&mut self, config: Config, effective_at: Option<U64>) {
+        self.assert_access("set_config");
+        self.internal_set_config(config, effective_at);
+    }
+
+    /// Replaces the gas budgets of the configuration in effect, and of the
+    /// pending one if any, right away. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_gas_budgets '{"gas": {"get_price": "20000000000000", ...}}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_gas_budgets(&mut self, gas: GasBudgets) {
+        self.assert_access("set_gas_budgets");
+        assert_valid_gas_budgets(&gas);
+        self.internal_apply_scheduled_config();
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.gas = gas.clone();
+        }
+        self.config.gas = gas;
+        self.log(LogLevel::Info, messages::GAS_BUDGETS_UPDATED, &[]);
+    }
+
+    /// Replaces the counter limits of the configuration in effect, and of the
+    /// pending one if any, right away. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_limits '{"min_value": "-1000", "max_value": "1000000"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_limits(&mut self, min_value: I128, max_value: I128) {
+        self.assert_access("set_limits");
+        let limits = CounterLimits {
+            min_value,
+            max_value,
+        };
+        assert_valid_limits(&limits);
+        self.internal_apply_scheduled_config();
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.limits = limits.clone();
+        }
+        self.config.limits = limits;
+        self.log(LogLevel::Info, messages::COUNTER_LIMITS_UPDATED, &[]);
+    }
+
+    /// Returns the range counters must stay within.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_limits
+    /// ```
+    pub fn get_limits(&self) -> CounterLimits {
+        self.config().limits.clone()
+    }
+
+    /// Sets the decimal places counter amounts are displayed with in the
+    /// configuration in effect, and in the pending one if any, right away,
+    /// or stops formatting them with `null`. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_decimals '{"decimals": 2}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_decimals(&mut self, decimals: Option<u8>) {
+        self.assert_access("set_decimals");
+        assert_valid_decimals(decimals);
+        self.internal_apply_scheduled_config();
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.decimals = decimals;
+        }
+        self.config.decimals = decimals;
+        self.log(LogLevel::Info, messages::DECIMALS_UPDATED, &[]);
+    }
+
+    /// Replaces the rate limit of the configuration in effect, and of the
+    /// pending one if any, right away. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_rate_limit '{"max_calls": 10, "window": "60000000000"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_rate_limit(&mut self, max_calls: u32, window: U64) {
+        self.assert_access("set_rate_limit");
+        let rate_limit = RateLimit { max_calls, window };
+        assert_valid_rate_limit(&rate_limit);
+        self.internal_apply_scheduled_config();
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.rate_limit = rate_limit.clone();
+        }
+        self.config.rate_limit = rate_limit;
+        self.log(LogLevel::Info, messages::RATE_LIMIT_UPDATED, &[]);
+    }
+
+    pub fn get_rate_limit(&self) -> RateLimit {
+        self.config().rate_limit.clone()
+    }
+
+    /// Sets the contract notified of every `add` and `change` of the
+    /// configuration in effect, and of the pending one if any, right away,
+    /// or removes it with `null`. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_listener '{"listener_account_id": "books.YOU.testnet"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_listener(&mut self, listener_account_id: Option<ValidAccountId>) {
+        self.assert_access("set_listener");
+        let listener_account_id: Option<AccountId> = listener_account_id.map(Into::into);
+        self.internal_apply_scheduled_config();
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.listener_account_id = listener_account_id.clone();
+        }
+        self.config.listener_account_id = listener_account_id;
+        self.log(LogLevel::Info, messages::LISTENER_UPDATED, &[]);
+    }
+
+    /// Sets the key signing vouchers of the configuration in effect, and of
+    /// the pending one if any, right away, or removes it with `null`, which
+    /// stops every outstanding voucher from being redeemed. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_voucher_key '{"public_key": "ed25519:<base58 public key>"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_voucher_key(&mut self, public_key: Option<Base58PublicKey>) {
+        self.assert_access("set_voucher_key");
+        if let Some(key) = &public_key {
+            assert_valid_voucher_key(key);
+        }
+        self.internal_apply_scheduled_config();
+        if let Some(scheduled) = &mut self.scheduled_config {
+            scheduled.config.voucher_public_key = public_key.clone();
+        }
+        self.config.voucher_public_key = public_key;
+        self.log(LogLevel::Info, messages::VOUCHER_KEY_UPDATED, &[]);
+    }
+
+    /// Drops the pending configuration. Owner only.
+    pub fn cancel_pending_config(&mut self) {
+        self.assert_access("cancel_pending_config");
+        self.internal_apply_scheduled_config();
+        require!(
+            self.scheduled_config.take().is_some(),
+            messages::NO_CONFIG_PENDING
+        );
+        self.log(LogLevel::Info, messages::PENDING_CONFIG_CANCELLED, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn owner_sets_config() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let config = Config {
+            rebate_threshold: 3,
+            ..Config::default()
+        };
+        contract.set_config(config.clone(), None);
+        assert_eq!(config, contract.get_config());
+    }
+
+    #[test]
+    fn scheduled_config_takes_over_at_its_time() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let config = Config {
+            rebate_threshold: 3,
+            ..Config::default()
+        };
+        contract.set_config(config.clone(), Some(U64(DAY)));
+        assert_eq!(Config::default(), contract.get_config());
+        let pending = contract.get_pending_config().unwrap();
+        assert_eq!(config, pending.config);
+        assert_eq!(DAY, pending.effective_at.0);
+
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = DAY;
+        testing_env!(context);
+        assert_eq!(config, contract.get_config());
+        assert!(contract.get_pending_config().is_none());
+        // what took over stays when something else gets scheduled
+        contract.set_config(Config::default(), Some(U64(2 * DAY)));
+        assert_eq!(config, contract.get_config());
+    }
+
+    #[test]
+    #[should_panic(expected = "No config pending")]
+    fn pending_config_can_be_cancelled() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(Config::default(), Some(U64(DAY)));
+        contract.cancel_pending_config();
+        assert!(contract.get_pending_config().is_none());
+        contract.cancel_pending_config();
+    }
+
+    #[test]
+    fn counter_steps_are_configurable() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                add_step: U128(5),
+                sub_step: U128(2),
+                ..Config::default()
+            },
+            None,
+        );
+        contract.add(None, None, None, None);
+        contract.change(None, None, None);
+        assert_eq!(3, contract.get_num_for("alice.testnet".to_string(), None).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Steps must be positive and fit the counter")]
+    fn counter_steps_must_be_positive() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                sub_step: U128(0),
+                ..Config::default()
+            },
+            None,
+        );
+    }
+
+    #[test]
+    fn owner_sets_gas_budgets() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(Config::default(), Some(U64(DAY)));
+        let gas = GasBudgets {
+            get_price: U64(25 * TGAS),
+            ..GasBudgets::default()
+        };
+        contract.set_gas_budgets(gas.clone());
+        assert_eq!(gas, contract.get_config().gas);
+        assert_eq!(gas, contract.get_pending_config().unwrap().config.gas);
+    }
+
+    #[test]
+    #[should_panic(expected = "Gas budgets must be positive")]
+    fn gas_budgets_are_bounded() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_gas_budgets(GasBudgets {
+            callback: U64(0),
+            ..GasBudgets::default()
+        });
+    }
+
+    #[test]
+    fn counters_stay_within_the_limits() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_limits(I128(-20), I128(1500));
+        assert_eq!(-20, contract.get_limits().min_value.0);
+        contract.add(None, None, None, None);
+        let add = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.add(None, None, None, None);
+        }));
+        assert!(add.is_err());
+        assert_eq!(
+            1000,
+            contract.get_num_for("alice.testnet".to_string(), None).0
+        );
+        assert_eq!(
+            Err("[ABOVE_MAXIMUM] Change would exceed the maximum of 1500".to_string()),
+            contract.internal_apply_delta(1000, 501)
+        );
+        assert_eq!(
+            Err("[BELOW_MINIMUM] Change would fall below the minimum of -20".to_string()),
+            contract.internal_apply_delta(0, -21)
+        );
+        assert_eq!(Ok(-20), contract.internal_apply_delta(0, -20));
+    }
+
+    #[test]
+    #[should_panic(expected = "Change would fall below the minimum of -15")]
+    fn change_is_refused_below_the_minimum() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_limits(I128(-15), I128(i128::MAX));
+        contract.change(None, None, None);
+        contract.change(None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Limits must contain zero")]
+    fn limits_must_contain_zero() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_limits(I128(10), I128(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn set_config_rejects_non_owner() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(Config::default(), None);
+    }
+}
@@ -0,0 +1,352 @@
+//! Discount coupons applied at checkout.
+//!
+//! Like gift cards, coupons are registered by the sha256 hash of their code.
+//! A coupon created by a merchant only applies to purchases at that merchant;
+//! one created by the owner applies to any purchase. The discount lowers the
+//! price the buyer pays and the merchant is booked. A purchase needs a positive
+//! price, so coupons taking off all of it are refused: 100% ones when created,
+//! fixed ones at or above the price when used.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance, CryptoHash, Timestamp};
+
+use crate::amount::{bps_of, RoundingMode, BPS_DENOMINATOR};
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Discount {
+    /// A share of the price, in basis points.
+    Percent { bps: u16 },
+    /// A fixed amount off the price, in yoctoNEAR.
+    Fixed { amount: U128 },
+}
+
+impl Discount {
+    /// Returns the discount on `price`, never more than the price itself.
+    fn of(&self, price: Balance, mode: RoundingMode) -> Balance {
+        match self {
+            Discount::Percent { bps } => bps_of(price, *bps, mode),
+            Discount::Fixed { amount } => amount.0.min(price),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Coupon {
+    pub creator_id: AccountId,
+    /// Merchant the coupon is valid at; `None` for coupons valid anywhere.
+    pub merchant_id: Option<AccountId>,
+    pub discount: Discount,
+    pub max_uses: u32,
+    pub uses: u32,
+    /// Block timestamp after which the coupon can no longer be used.
+    pub expiry: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponView {
+    pub code_hash: Base58CryptoHash,
+    pub creator_id: AccountId,
+    pub merchant_id: Option<AccountId>,
+    pub discount: Discount,
+    pub max_uses: u32,
+    pub remaining_uses: u32,
+    pub expiry: U64,
+}
+
+impl CouponView {
+    fn new(code_hash: CryptoHash, coupon: Coupon) -> Self {
+        Self {
+            code_hash: code_hash.into(),
+            creator_id: coupon.creator_id,
+            merchant_id: coupon.merchant_id,
+            discount: coupon.discount,
+            max_uses: coupon.max_uses,
+            remaining_uses: coupon.max_uses - coupon.uses,
+            expiry: U64(coupon.expiry),
+        }
+    }
+}
+
+/// Returns the hash coupons with `code` are registered by.
+pub(crate) fn coupon_code_hash(code: &str) -> CryptoHash {
+    let mut code_hash = CryptoHash::default();
+    code_hash.copy_from_slice(&env::sha256(code.as_bytes()));
+    code_hash
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct CouponReleasedEvent {
+    code_hash: Base58CryptoHash,
+    remaining_uses: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct CouponUsedEvent {
+    code_hash: Base58CryptoHash,
+    price: U128,
+    discount: U128,
+    remaining_uses: u32,
+}
+
+impl Change {
+    /// Uses up one use of the coupon with `code` on a purchase of `price` at
+    /// `merchant_id`, panicking if it isn't valid there. Returns the discount.
+    pub(crate) fn internal_use_coupon(
+        &mut self,
+        code: &str,
+        merchant_id: Option<&AccountId>,
+        price: Balance,
+    ) -> Balance {
+        let code_hash = coupon_code_hash(code);
+        let mut coupon = self
+            .coupons
+            .get(&code_hash)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_COUPON));
+        require!(
+            env::block_timestamp() <= coupon.expiry,
+            messages::COUPON_EXPIRED
+        );
+        require!(coupon.uses < coupon.max_uses, messages::COUPON_USED_UP);
+        if let Some(coupon_merchant) = &coupon.merchant_id {
+            require!(
+                Some(coupon_merchant) == merchant_id,
+                messages::COUPON_NOT_VALID_AT_MERCHANT
+            );
+        }
+        let discount = coupon.discount.of(price, self.config().rounding_mode);
+        require!(discount < price, messages::COUPON_COVERS_PRICE);
+        coupon.uses += 1;
+        self.coupons.insert(&code_hash, &coupon);
+        self.emit_event(
+            "coupon_used",
+            &CouponUsedEvent {
+                code_hash: code_hash.into(),
+                price: U128(price),
+                discount: U128(discount),
+                remaining_uses: coupon.max_uses - coupon.uses,
+            },
+        );
+        discount
+    }
+
+    /// Gives back a use of the coupon hashing to `code_hash`, taken by a
+    /// payment that didn't go through.
+    pub(crate) fn internal_release_coupon(&mut self, code_hash: &CryptoHash) {
+        let mut coupon = match self.coupons.get(code_hash) {
+            Some(coupon) => coupon,
+            None => return,
+        };
+        coupon.uses = coupon.uses.saturating_sub(1);
+        self.coupons.insert(code_hash, &coupon);
+        self.emit_event(
+            "coupon_released",
+            &CouponReleasedEvent {
+                code_hash: (*code_hash).into(),
+                remaining_uses: coupon.max_uses - coupon.uses,
+            },
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Creates a coupon usable `max_uses` times until `expiry`, redeemed with the
+    /// code hashing to `code_hash`. A merchant's coupons are valid only at that
+    /// merchant, the owner's everywhere.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet create_coupon '{"code_hash": "<base58 sha256 of the code>", "discount": {"Percent": {"bps": 1000}}, "max_uses": 100, "expiry": "1700000000000000000"}' --accountId shop.testnet
+    /// ```
+    pub fn create_coupon(
+        &mut self,
+        code_hash: Base58CryptoHash,
+        discount: Discount,
+        max_uses: u32,
+        expiry: U64,
+    ) {
+        self.assert_access("create_coupon");
+        self.assert_writable();
+        let creator_id = env::predecessor_account_id();
+        let merchant_id = if creator_id == self.owner_id {
+            None
+        } else {
+            require!(
+                self.merchants.get(&creator_id).is_some(),
+                messages::COUPON_ISSUER_ONLY
+            );
+            Some(creator_id.clone())
+        };
+        if let Discount::Percent { bps } = discount {
+            require!(
+                u128::from(bps) < BPS_DENOMINATOR,
+                messages::DISCOUNT_TOO_HIGH
+            );
+        }
+        require!(max_uses > 0, messages::COUPON_WITHOUT_USES);
+        let code_hash: CryptoHash = code_hash.into();
+        require!(
+            self.coupons.get(&code_hash).is_none(),
+            messages::COUPON_EXISTS
+        );
+        let coupon = Coupon {
+            creator_id,
+            merchant_id,
+            discount,
+            max_uses,
+            uses: 0,
+            expiry: expiry.0,
+        };
+        self.coupons.insert(&code_hash, &coupon);
+        self.emit_event("coupon_created", &CouponView::new(code_hash, coupon));
+    }
+
+    /// Returns the coupon with `code_hash`.
+    pub fn get_coupon(&self, code_hash: Base58CryptoHash) -> Option<CouponView> {
+        let code_hash: CryptoHash = code_hash.into();
+        self.coupons
+            .get(&code_hash)
+            .map(|coupon| CouponView::new(code_hash, coupon))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn hash(code: &str) -> Base58CryptoHash {
+        let mut hash = CryptoHash::default();
+        hash.copy_from_slice(&env::sha256(code.as_bytes()));
+        hash.into()
+    }
+
+    /// shop.testnet with a 10% coupon `TEN` usable twice until 1000, and cafe.testnet.
+    fn market() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(account("shop.testnet"), account("shop.testnet"), 0);
+        contract.register_merchant(account("cafe.testnet"), account("cafe.testnet"), 0);
+        testing_env!(context_for("shop.testnet", 0));
+        contract.create_coupon(hash("TEN"), Discount::Percent { bps: 1000 }, 2, U64(1000));
+        contract
+    }
+
+    fn buy(contract: &mut Change, merchant: &str, coupon: &str) -> u128 {
+        testing_env!(context_for("jane.testnet", 500));
+        contract
+            .purchase(
+                U128(400),
+                None,
+                Some(merchant.to_string()),
+                None,
+                Some(coupon.to_string()),
+                None,
+            )
+            .0
+    }
+
+    #[test]
+    fn coupons_discount_the_price_until_used_up() {
+        let mut contract = market();
+        assert_eq!(140, buy(&mut contract, "shop.testnet", "TEN"));
+        let receipt = contract.get_receipt(U64(0)).unwrap();
+        assert_eq!(360, receipt.price.0);
+        assert_eq!(40, receipt.discount.0);
+        assert_eq!(1, contract.get_coupon(hash("TEN")).unwrap().remaining_uses);
+        buy(&mut contract, "shop.testnet", "TEN");
+        assert_eq!(
+            720,
+            contract
+                .get_merchant("shop.testnet".to_string())
+                .unwrap()
+                .pending
+                .0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Coupon is used up")]
+    fn coupons_have_limited_uses() {
+        let mut contract = market();
+        buy(&mut contract, "shop.testnet", "TEN");
+        buy(&mut contract, "shop.testnet", "TEN");
+        buy(&mut contract, "shop.testnet", "TEN");
+    }
+
+    #[test]
+    #[should_panic(expected = "Coupon isn't valid at this merchant")]
+    fn merchant_coupons_stay_at_their_merchant() {
+        let mut contract = market();
+        buy(&mut contract, "cafe.testnet", "TEN");
+    }
+
+    #[test]
+    fn owner_coupons_apply_anywhere() {
+        let mut contract = market();
+        testing_env!(context_for("alice.testnet", 0));
+        contract.create_coupon(
+            hash("FIFTY-OFF"),
+            Discount::Fixed { amount: U128(50) },
+            10,
+            U64(1000),
+        );
+        assert_eq!(150, buy(&mut contract, "cafe.testnet", "FIFTY-OFF"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Discount must stay below 100%")]
+    fn coupons_cannot_take_everything_off() {
+        let mut contract = market();
+        contract.create_coupon(
+            hash("FREE"),
+            Discount::Percent { bps: 10_000 },
+            1,
+            U64(1000),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Coupon can't cover the whole price")]
+    fn fixed_coupons_cannot_cover_the_price() {
+        let mut contract = market();
+        contract.create_coupon(
+            hash("FOUR-HUNDRED-OFF"),
+            Discount::Fixed { amount: U128(400) },
+            1,
+            U64(1000),
+        );
+        buy(&mut contract, "shop.testnet", "FOUR-HUNDRED-OFF");
+    }
+
+    #[test]
+    #[should_panic(expected = "Coupon has expired")]
+    fn expired_coupons_are_rejected() {
+        let mut contract = market();
+        let mut context = context_for("jane.testnet", 500);
+        context.block_timestamp = 1001;
+        testing_env!(context);
+        contract.purchase(
+            U128(400),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            Some("TEN".to_string()),
+            None,
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Change kept per currency.
+//!
+//! Every account holds a separate Change in each currency it uses, named by
+//! its symbol: `"NEAR"`, `"USDC"`, `"ARS"`. `add`, `change`, `reset` and
+//! `get_num_for` take the currency, [`DEFAULT_CURRENCY`] when omitted, and
+//! [`get_all_balances`](Change::get_all_balances) returns an account's Change
+//! in all of them. An account can use at most `MAX_CURRENCIES`, which keeps
+//! that view bounded.
+
+use std::collections::BTreeMap;
+
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::I128;
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+/// Currency of the Change of calls that don't name one.
+pub(crate) const DEFAULT_CURRENCY: &str = "NEAR";
+/// Longest currency symbol accepted.
+const MAX_SYMBOL_LEN: usize = 12;
+/// Most currencies a single account can hold Change in.
+const MAX_CURRENCIES: u64 = 16;
+
+/// Returns the currency named by `currency`, the default one if `None`.
+///
+/// Symbols are 1 to `MAX_SYMBOL_LEN` uppercase ASCII letters or digits.
+pub(crate) fn currency_symbol(currency: Option<String>) -> Result<String, Message> {
+    let currency = currency.unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+    let valid = !currency.is_empty()
+        && currency.len() <= MAX_SYMBOL_LEN
+        && currency
+            .bytes()
+            .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+    if valid {
+        Ok(currency)
+    } else {
+        Err(messages::INVALID_CURRENCY_SYMBOL)
+    }
+}
+
+impl Change {
+    /// Sets the Change of `account_id` in `currency` to `value`.
+    pub(crate) fn internal_set_num(&mut self, account_id: &AccountId, currency: &str, value: i128) {
+        self.assert_not_rolling_back();
+        self.internal_write_num(account_id, currency, value);
+    }
+
+    /// Sets the Change of `account_id` in `currency` to `value`, even while
+    /// rolling back.
+    pub(crate) fn internal_write_num(
+        &mut self,
+        account_id: &AccountId,
+        currency: &str,
+        value: i128,
+    ) {
+        self.internal_save_for_snapshot(account_id, currency);
+        let mut balances = self.ledger.get(account_id).unwrap_or_else(|| {
+            UnorderedMap::new(StorageKey::AccountLedger {
+                account_hash: env::sha256(account_id.as_bytes()),
+            })
+        });
+        let currency = currency.to_string();
+        if balances.get(&currency).is_none() {
+            require!(
+                balances.len() < MAX_CURRENCIES,
+                messages::TOO_MANY_CURRENCIES
+            );
+        }
+        balances.insert(&currency, &value);
+        self.ledger.insert(account_id, &balances);
+    }
+
+    /// Drops the Change of `account_id` in `currency`. Returns what it was.
+    pub(crate) fn internal_remove_num(&mut self, account_id: &AccountId, currency: &str) -> i128 {
+        self.assert_not_rolling_back();
+        self.internal_drop_num(account_id, currency)
+    }
+
+    /// Drops the Change of `account_id` in `currency`, even while rolling back.
+    /// Returns what it was.
+    pub(crate) fn internal_drop_num(&mut self, account_id: &AccountId, currency: &str) -> i128 {
+        self.internal_save_for_snapshot(account_id, currency);
+        let mut balances = match self.ledger.get(account_id) {
+            Some(balances) => balances,
+            None => return 0,
+        };
+        let old_value = balances.remove(&currency.to_string()).unwrap_or(0);
+        self.ledger.insert(account_id, &balances);
+        old_value
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the Change of `account_id` in every currency it has used.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_all_balances '{"account_id": "donation.YOU.testnet"}'
+    /// ```
+    pub fn get_all_balances(&self, account_id: AccountId) -> BTreeMap<String, I128> {
+        self.ledger
+            .get(&account_id)
+            .map(|balances| {
+                balances
+                    .iter()
+                    .map(|(currency, value)| (currency, I128(value)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn currency(symbol: &str) -> Option<String> {
+        Some(symbol.to_string())
+    }
+
+    #[test]
+    fn change_is_kept_per_currency() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        let jane = "jane.testnet".to_string();
+        contract.add(None, None, None, None);
+        contract.add(None, None, Some(U128(500)), currency("USDC"));
+        contract.change(None, None, currency("ARS"));
+        assert_eq!(1000, contract.get_num_for(jane.clone(), None).0);
+        assert_eq!(1000, contract.get_num_for(jane.clone(), currency("NEAR")).0);
+        assert_eq!(500, contract.get_num_for(jane.clone(), currency("USDC")).0);
+        assert_eq!(0, contract.get_num_for(jane.clone(), currency("EUR")).0);
+        let balances: Vec<_> = contract
+            .get_all_balances(jane)
+            .into_iter()
+            .map(|(currency, value)| (currency, value.0))
+            .collect();
+        assert_eq!(
+            vec![
+                ("ARS".to_string(), -10),
+                ("NEAR".to_string(), 1000),
+                ("USDC".to_string(), 500)
+            ],
+            balances
+        );
+        assert!(contract
+            .get_all_balances("bob.testnet".to_string())
+            .is_empty());
+    }
+
+    #[test]
+    fn reset_clears_one_currency() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        contract.add(None, None, None, currency("USDC"));
+        contract.reset(
+            ValidAccountId::try_from("alice.testnet").unwrap(),
+            currency("USDC"),
+        );
+        let alice = "alice.testnet".to_string();
+        assert_eq!(1000, contract.get_num_for(alice.clone(), None).0);
+        assert_eq!(1, contract.get_all_balances(alice).len());
+    }
+
+    #[test]
+    fn symbols_are_validated() {
+        assert_eq!(Ok("NEAR".to_string()), currency_symbol(None));
+        assert_eq!(Ok("USDT0".to_string()), currency_symbol(currency("USDT0")));
+        assert!(currency_symbol(currency("")).is_err());
+        assert!(currency_symbol(currency("usdc")).is_err());
+        assert!(currency_symbol(currency("LONGERTHAN12C")).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many currencies")]
+    fn currencies_per_account_are_capped() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        for n in 0..=MAX_CURRENCIES {
+            contract.add(None, None, None, currency(&format!("C{}", n)));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Counter amounts formatted for display, so thin clients don't have to.
+//!
+//! Counters are integers. When the owner sets `decimals`, the views of
+//! counter amounts (outcomes, history, series, stats) return next to each
+//! raw amount a `*_formatted` string with that many decimal places:
+//! `-1234` with 2 decimals is `"-12.34"`. Without `decimals` the strings are
+//! left out. NEAR amounts, always in yoctoNEAR, aren't formatted.
+
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+/// Most decimal places an i128 amount has.
+pub(crate) const MAX_DECIMALS: u8 = 38;
+
+/// Formats `raw` with `decimals` decimal places.
+pub(crate) fn format_decimal(raw: i128, decimals: u8) -> String {
+    let digits = raw.unsigned_abs().to_string();
+    let decimals = usize::from(decimals);
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let sign = if raw < 0 { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+impl Change {
+    /// Returns `raw` formatted with the configured decimals, if any.
+    pub(crate) fn internal_format(&self, raw: i128) -> Option<String> {
+        self.config()
+            .decimals
+            .map(|decimals| format_decimal(raw, decimals))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the Change of `account_id` in `currency` formatted with the
+    /// configured decimals, the raw amount when there are none.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_formatted_num_for '{"account_id": "donation.YOU.testnet"}'
+    /// ```
+    pub fn get_formatted_num_for(&self, account_id: AccountId, currency: Option<String>) -> String {
+        let raw = self.get_num_for(account_id, currency).0;
+        self.internal_format(raw).unwrap_or_else(|| raw.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{U128, U64};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn amounts_are_formatted_with_the_decimals() {
+        assert_eq!("-12.34", format_decimal(-1234, 2));
+        assert_eq!("0.05", format_decimal(5, 2));
+        assert_eq!("-0.005", format_decimal(-5, 3));
+        assert_eq!("7", format_decimal(7, 0));
+        assert_eq!("0.00", format_decimal(0, 2));
+        assert_eq!(
+            "-1.70141183460469231731687303715884105728",
+            format_decimal(i128::MIN, MAX_DECIMALS)
+        );
+    }
+
+    #[test]
+    fn views_return_formatted_amounts_once_decimals_are_set() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let outcome = contract.add(None, None, Some(U128(1250)), None);
+        assert_eq!(None, outcome.new_balance_formatted);
+        assert_eq!(
+            "1250",
+            contract.get_formatted_num_for("alice.testnet".to_string(), None)
+        );
+
+        contract.set_decimals(Some(2));
+        let outcome = contract.change(None, None, None);
+        assert_eq!(Some("12.40".to_string()), outcome.new_balance_formatted);
+        assert_eq!(
+            "12.40",
+            contract.get_formatted_num_for("alice.testnet".to_string(), None)
+        );
+        let record = &contract.get_history(U64(1), 1).items[0];
+        assert_eq!(Some("-0.10".to_string()), record.delta_formatted);
+        assert_eq!(Some("12.40".to_string()), record.value_formatted);
+        assert_eq!(
+            Some("12.50".to_string()),
+            contract.get_stats().largest_delta_formatted
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "At most 38 decimals")]
+    fn decimals_are_bounded() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_decimals(Some(39));
+    }
+}
@@ -0,0 +1,442 @@
+//! Disputes over purchases made at a merchant.
+//!
+//! Within the merchant's dispute window after a purchase the buyer can dispute it with
+//! [`open_dispute`](Change::open_dispute). The price is frozen out of the
+//! merchant's pending revenue, so it can't be settled while the dispute
+//! lasts, and the owner assigns the dispute to an account holding the
+//! [`Arbiter`](Role::Arbiter) role to resolve it.
+//!
+//! The arbiter settles it with [`resolve_dispute`](Change::resolve_dispute):
+//! the refunded part of the frozen amount goes to the buyer's change balance
+//! and the rest back to the merchant's revenue. A resolution is final.
+//!
+//! A purchase paid by several accounts, like a layaway intent, can be
+//! disputed by any of them, and refunds are shared among them all in
+//! proportion to what each paid.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::roles::Role;
+use crate::split::pro_rata;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DisputeStatus {
+    /// Waiting for the owner to assign an arbiter.
+    Open,
+    /// Assigned to an arbiter.
+    Assigned,
+    /// Decided by the arbiter; see the dispute's outcome.
+    Resolved,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DisputeOutcome {
+    /// The whole frozen amount goes back to the buyer.
+    FullRefund,
+    /// `amount` goes back to the buyer, the rest to the merchant.
+    PartialRefund { amount: U128 },
+    /// The frozen amount goes back to the merchant.
+    Dismissed,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Dispute {
+    pub receipt_id: u64,
+    pub buyer_id: AccountId,
+    pub merchant_id: AccountId,
+    pub reason: String,
+    pub opened_at: Timestamp,
+    /// Merchant revenue frozen by the dispute, in yoctoNEAR.
+    pub frozen: Balance,
+    pub arbiter_id: Option<AccountId>,
+    pub status: DisputeStatus,
+    pub outcome: Option<DisputeOutcome>,
+    /// Part of `frozen` refunded to the buyer by the outcome.
+    pub refunded: Balance,
+    pub resolved_at: Option<Timestamp>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DisputeView {
+    pub id: U64,
+    pub receipt_id: U64,
+    pub buyer_id: AccountId,
+    pub merchant_id: AccountId,
+    pub reason: String,
+    pub opened_at: U64,
+    pub frozen: U128,
+    pub arbiter_id: Option<AccountId>,
+    pub status: DisputeStatus,
+    pub outcome: Option<DisputeOutcome>,
+    pub refunded: U128,
+    pub resolved_at: Option<U64>,
+}
+
+impl DisputeView {
+    fn new(id: u64, dispute: Dispute) -> Self {
+        Self {
+            id: U64(id),
+            receipt_id: U64(dispute.receipt_id),
+            buyer_id: dispute.buyer_id,
+            merchant_id: dispute.merchant_id,
+            reason: dispute.reason,
+            opened_at: U64(dispute.opened_at),
+            frozen: U128(dispute.frozen),
+            arbiter_id: dispute.arbiter_id,
+            status: dispute.status,
+            outcome: dispute.outcome,
+            refunded: U128(dispute.refunded),
+            resolved_at: dispute.resolved_at.map(U64),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct DisputeAssignedEvent<'a> {
+    dispute_id: U64,
+    arbiter_id: &'a AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct DisputeResolvedEvent<'a> {
+    dispute_id: U64,
+    receipt_id: U64,
+    arbiter_id: &'a AccountId,
+    outcome: &'a DisputeOutcome,
+    refunded: U128,
+    released: U128,
+}
+
+#[near_bindgen]
+impl Change {
+    /// Disputes the purchase `receipt_id`, freezing its price in the merchant's
+    /// revenue. Only the buyer can, within the merchant's dispute window.
+    /// Returns the dispute id.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet open_dispute '{"receipt_id": "0", "reason": "never delivered"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn open_dispute(&mut self, receipt_id: U64, reason: String) -> U64 {
+        self.assert_access("open_dispute");
+        let receipt = self
+            .receipts
+            .get(receipt_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_RECEIPT));
+        let buyer_id = env::predecessor_account_id();
+        require!(
+            self.internal_receipt_payers(receipt_id.0, &receipt)
+                .iter()
+                .any(|(payer_id, _)| *payer_id == buyer_id),
+            messages::BUYER_ONLY
+        );
+        let merchant_id = self.internal_account_id(
+            receipt
+                .merchant
+                .unwrap_or_else(|| fail!(messages::NOT_A_MERCHANT_PURCHASE)),
+        );
+        require!(
+            receipt
+                .dispute_until
+                .is_some_and(|until| env::block_timestamp() <= until),
+            messages::DISPUTE_WINDOW_CLOSED
+        );
+        require!(
+            self.receipt_disputes.get(&receipt_id.0).is_none(),
+            messages::PURCHASE_ALREADY_DISPUTED
+        );
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        // still pending, as the window is open
+        let frozen = receipt.price;
+        merchant.pending -= frozen;
+        merchant.frozen += frozen;
+        self.merchants.insert(&merchant_id, &merchant);
+
+        let dispute = Dispute {
+            receipt_id: receipt_id.0,
+            buyer_id,
+            merchant_id,
+            reason,
+            opened_at: env::block_timestamp(),
+            frozen,
+            arbiter_id: None,
+            status: DisputeStatus::Open,
+            outcome: None,
+            refunded: 0,
+            resolved_at: None,
+        };
+        let id = self.disputes.len();
+        self.disputes.push(&dispute);
+        self.receipt_disputes.insert(&receipt_id.0, &id);
+        self.emit_event("dispute_opened", &DisputeView::new(id, dispute));
+        U64(id)
+    }
+
+    /// Assigns the dispute `dispute_id` to `arbiter_id`, which must hold the
+    /// arbiter role. Owner only; reassigning is allowed.
+    pub fn assign_dispute(&mut self, dispute_id: U64, arbiter_id: ValidAccountId) {
+        self.assert_access("assign_dispute");
+        let arbiter_id: AccountId = arbiter_id.into();
+        require!(
+            self.internal_has_role(Role::Arbiter, &arbiter_id),
+            messages::ACCOUNT_NOT_ARBITER
+        );
+        let mut dispute = self
+            .disputes
+            .get(dispute_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_DISPUTE));
+        require!(
+            dispute.status != DisputeStatus::Resolved,
+            messages::DISPUTE_ALREADY_RESOLVED
+        );
+        dispute.arbiter_id = Some(arbiter_id.clone());
+        dispute.status = DisputeStatus::Assigned;
+        self.disputes.replace(dispute_id.0, &dispute);
+        self.emit_event(
+            "dispute_assigned",
+            &DisputeAssignedEvent {
+                dispute_id,
+                arbiter_id: &arbiter_id,
+            },
+        );
+    }
+
+    /// Decides the dispute `dispute_id`, moving its frozen amount according to
+    /// `outcome`. Only the arbiter it is assigned to can, and only once.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet resolve_dispute '{"dispute_id": "0", "outcome": {"PartialRefund": {"amount": "100"}}}' --accountId arbiter.testnet
+    /// ```
+    pub fn resolve_dispute(&mut self, dispute_id: U64, outcome: DisputeOutcome) {
+        self.assert_access("resolve_dispute");
+        let mut dispute = self
+            .disputes
+            .get(dispute_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_DISPUTE));
+        require!(
+            dispute.status != DisputeStatus::Resolved,
+            messages::DISPUTE_ALREADY_RESOLVED
+        );
+        let arbiter_id = env::predecessor_account_id();
+        require!(
+            Some(&arbiter_id) == dispute.arbiter_id.as_ref(),
+            messages::ASSIGNED_ARBITER_ONLY
+        );
+        // an arbiter who lost the role can't decide anymore
+        require!(
+            self.internal_has_role(Role::Arbiter, &arbiter_id),
+            messages::ACCOUNT_NOT_ARBITER
+        );
+        let refunded = match &outcome {
+            DisputeOutcome::FullRefund => dispute.frozen,
+            DisputeOutcome::PartialRefund { amount } => {
+                require!(amount.0 <= dispute.frozen, messages::REFUND_ABOVE_DISPUTED);
+                amount.0
+            }
+            DisputeOutcome::Dismissed => 0,
+        };
+        let released = dispute.frozen - refunded;
+        let mut merchant = self
+            .merchants
+            .get(&dispute.merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        merchant.frozen -= dispute.frozen;
+        merchant.revenue += released;
+        self.merchants.insert(&dispute.merchant_id, &merchant);
+        if refunded > 0 {
+            let receipt = self.receipts.get(dispute.receipt_id).unwrap();
+            let payers = self.internal_receipt_payers(dispute.receipt_id, &receipt);
+            let refunds = pro_rata(refunded, &payers, self.config().rounding_mode);
+            for ((payer_id, _), refund) in payers.iter().zip(refunds) {
+                if refund > 0 {
+                    self.internal_credit_balance(payer_id, refund, Book::Merchants);
+                }
+            }
+            self.internal_notify_merchant(
+                &dispute.merchant_id,
+                "refund",
+                dispute.receipt_id,
+                refunded,
+            );
+        }
+
+        self.emit_event(
+            "dispute_resolved",
+            &DisputeResolvedEvent {
+                dispute_id,
+                receipt_id: U64(dispute.receipt_id),
+                arbiter_id: &arbiter_id,
+                outcome: &outcome,
+                refunded: U128(refunded),
+                released: U128(released),
+            },
+        );
+        dispute.status = DisputeStatus::Resolved;
+        dispute.outcome = Some(outcome);
+        dispute.refunded = refunded;
+        dispute.resolved_at = Some(env::block_timestamp());
+        self.disputes.replace(dispute_id.0, &dispute);
+    }
+
+    pub fn get_dispute(&self, dispute_id: U64) -> Option<DisputeView> {
+        self.disputes
+            .get(dispute_id.0)
+            .map(|dispute| DisputeView::new(dispute_id.0, dispute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    /// A contract where jane.testnet bought for 430 at shop.testnet (receipt 0).
+    fn contract_with_purchase() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(account("shop.testnet"), account("shop-payouts.testnet"), 0);
+        contract.grant_role(Role::Arbiter, account("arbiter.testnet"));
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(
+            U128(430),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        contract
+    }
+
+    #[test]
+    fn dispute_freezes_the_merchant_revenue() {
+        let mut contract = contract_with_purchase();
+        let dispute_id = contract.open_dispute(U64(0), "never delivered".to_string());
+        let shop = contract.merchants.get(&"shop.testnet".to_string()).unwrap();
+        assert_eq!(0, shop.pending);
+        assert_eq!(430, shop.frozen);
+
+        testing_env!(context_for("alice.testnet", 0));
+        contract.assign_dispute(dispute_id, account("arbiter.testnet"));
+        let dispute = contract.get_dispute(dispute_id).unwrap();
+        assert_eq!(DisputeStatus::Assigned, dispute.status);
+        assert_eq!(Some("arbiter.testnet".to_string()), dispute.arbiter_id);
+        assert_eq!(430, dispute.frozen.0);
+    }
+
+    /// Opens a dispute on receipt 0 and assigns it to arbiter.testnet, leaving the arbiter as caller.
+    fn assigned_dispute(contract: &mut Change) -> U64 {
+        let dispute_id = contract.open_dispute(U64(0), "never delivered".to_string());
+        testing_env!(context_for("alice.testnet", 0));
+        contract.assign_dispute(dispute_id, account("arbiter.testnet"));
+        testing_env!(context_for("arbiter.testnet", 0));
+        dispute_id
+    }
+
+    #[test]
+    fn partial_refund_splits_the_frozen_amount() {
+        let mut contract = contract_with_purchase();
+        let dispute_id = assigned_dispute(&mut contract);
+        contract.resolve_dispute(
+            dispute_id,
+            DisputeOutcome::PartialRefund { amount: U128(100) },
+        );
+        // 70 of change plus the refund
+        assert_eq!(170, contract.get_balance("jane.testnet".to_string()).0);
+        let shop = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(330, shop.revenue.0);
+        assert_eq!(0, shop.frozen.0);
+        let dispute = contract.get_dispute(dispute_id).unwrap();
+        assert_eq!(DisputeStatus::Resolved, dispute.status);
+        assert_eq!(100, dispute.refunded.0);
+    }
+
+    #[test]
+    fn full_refund_and_dismissal_move_everything() {
+        let mut contract = contract_with_purchase();
+        let dispute_id = assigned_dispute(&mut contract);
+        contract.resolve_dispute(dispute_id, DisputeOutcome::FullRefund);
+        assert_eq!(500, contract.get_balance("jane.testnet".to_string()).0);
+
+        testing_env!(context_for("jane.testnet", 430));
+        contract.purchase(
+            U128(430),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        let dispute_id = contract.open_dispute(U64(1), String::new());
+        testing_env!(context_for("alice.testnet", 0));
+        contract.assign_dispute(dispute_id, account("arbiter.testnet"));
+        testing_env!(context_for("arbiter.testnet", 0));
+        contract.resolve_dispute(dispute_id, DisputeOutcome::Dismissed);
+        assert_eq!(500, contract.get_balance("jane.testnet".to_string()).0);
+        let shop = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(430, shop.revenue.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dispute is already resolved")]
+    fn resolutions_are_final() {
+        let mut contract = contract_with_purchase();
+        let dispute_id = assigned_dispute(&mut contract);
+        contract.resolve_dispute(dispute_id, DisputeOutcome::Dismissed);
+        contract.resolve_dispute(dispute_id, DisputeOutcome::FullRefund);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the assigned arbiter can resolve the dispute")]
+    fn only_the_assigned_arbiter_resolves() {
+        let mut contract = contract_with_purchase();
+        let dispute_id = assigned_dispute(&mut contract);
+        testing_env!(context_for("alice.testnet", 0));
+        contract.resolve_dispute(dispute_id, DisputeOutcome::FullRefund);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dispute window has closed")]
+    fn disputes_must_be_opened_within_the_window() {
+        let mut contract = contract_with_purchase();
+        let mut context = context_for("jane.testnet", 0);
+        context.block_timestamp = contract.config.dispute_window.0 + 1;
+        testing_env!(context);
+        contract.open_dispute(U64(0), String::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the buyer can dispute a purchase")]
+    fn only_the_buyer_disputes() {
+        let mut contract = contract_with_purchase();
+        testing_env!(context_for("bob.testnet", 0));
+        contract.open_dispute(U64(0), String::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not an arbiter")]
+    fn disputes_are_assigned_to_arbiters_only() {
+        let mut contract = contract_with_purchase();
+        let dispute_id = contract.open_dispute(U64(0), String::new());
+        testing_env!(context_for("alice.testnet", 0));
+        contract.assign_dispute(dispute_id, account("bob.testnet"));
+    }
+}
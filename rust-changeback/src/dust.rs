@@ -0,0 +1,201 @@
+//! Dust: operations and balances too small to be worth their storage.
+//!
+//! `add` / `change` by less than `min_operation_amount` are refused, so nobody
+//! bloats per-account records with micro-operations. Stored change left below
+//! `dust_balance_threshold` can be swept into the treasury by the owner; every
+//! swept account gets a `dust_swept` event as notice. The threshold is capped
+//! at `MAX_DUST_BALANCE_THRESHOLD`, so a sweep only ever takes what isn't worth
+//! its storage, never balances users would withdraw.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// Highest `dust_balance_threshold` the owner can set, 0.01 NEAR: about what
+/// an account record costs to store.
+pub(crate) const MAX_DUST_BALANCE_THRESHOLD: Balance = 10u128.pow(22);
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct DustSweptEvent {
+    account_id: AccountId,
+    amount: U128,
+}
+
+impl Change {
+    /// Returns an error if `amount` is below the minimum operation amount.
+    pub(crate) fn internal_check_not_dust(&self, amount: i128) -> Result<(), Message> {
+        if amount.unsigned_abs() < self.config().min_operation_amount.0 {
+            return Err(messages::DUST_AMOUNT);
+        }
+        Ok(())
+    }
+
+    /// Panics if `amount` is below the minimum operation amount.
+    pub(crate) fn assert_not_dust(&self, amount: i128) {
+        self.internal_check_not_dust(amount)
+            .unwrap_or_else(|error| fail!(error));
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Moves the stored change of each of `account_ids` holding less than
+    /// `dust_balance_threshold` into the treasury. Owner only. Returns the total swept.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet sweep_dust '{"account_ids": ["donation.YOU.testnet"]}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn sweep_dust(&mut self, account_ids: Vec<ValidAccountId>) -> U128 {
+        self.assert_access("sweep_dust");
+        self.assert_writable();
+        let mut events = EventBuffer::new("dust_swept");
+        for account_id in account_ids {
+            let account_id: AccountId = account_id.into();
+            let mut account = self.internal_get_account(&account_id);
+            if account.balance == 0 || account.balance >= self.config().dust_balance_threshold.0 {
+                continue;
+            }
+            let amount = std::mem::take(&mut account.balance);
+            self.internal_set_account(&account_id, &account);
+            events.push(
+                DustSweptEvent {
+                    account_id,
+                    amount: U128(amount),
+                },
+                amount,
+            );
+        }
+        let total = events.total();
+        self.internal_flush_events(events);
+        self.internal_credit_treasury(total, Book::UserChange);
+        U128(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn dusty_contract() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                min_operation_amount: U128(100),
+                dust_balance_threshold: U128(50),
+                ..Config::default()
+            },
+            None,
+        );
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount is below the dust threshold")]
+    fn small_operations_are_refused() {
+        let mut contract = dusty_contract();
+        assert_eq!(1000, contract.add(None, None, None, None).new_balance.0);
+        // `change` only subtracts 10
+        contract.change(None, None, None);
+    }
+
+    #[test]
+    fn minimum_goes_past_u32() {
+        let mut contract = dusty_contract();
+        contract.set_config(
+            Config {
+                min_operation_amount: U128(u64::MAX as u128),
+                ..Config::default()
+            },
+            None,
+        );
+        assert_eq!(
+            Err(messages::DUST_AMOUNT),
+            contract.internal_check_not_dust(i128::from(u32::MAX) + 1)
+        );
+        assert_eq!(
+            Ok(()),
+            contract.internal_check_not_dust(-(u64::MAX as i128))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Dust balance threshold can be at most 10000000000000000000000")]
+    fn dust_threshold_is_capped() {
+        let mut contract = dusty_contract();
+        contract.set_config(
+            Config {
+                dust_balance_threshold: U128(u128::MAX),
+                ..Config::default()
+            },
+            None,
+        );
+    }
+
+    #[test]
+    fn dust_balances_are_swept_into_the_treasury() {
+        let mut contract = dusty_contract();
+        contract.internal_credit_balance(&"jane.testnet".to_string(), 30, Book::Custody);
+        contract.internal_credit_balance(&"bob.testnet".to_string(), 70, Book::Custody);
+        let swept = contract.sweep_dust(vec![
+            ValidAccountId::try_from("jane.testnet").unwrap(),
+            ValidAccountId::try_from("bob.testnet").unwrap(),
+            ValidAccountId::try_from("nobody.testnet").unwrap(),
+        ]);
+        assert_eq!(30, swept.0);
+        assert_eq!(30, contract.book(Book::Treasury));
+        assert_eq!(0, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(70, contract.get_balance("bob.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn large_sweeps_log_a_summary() {
+        let mut contract = dusty_contract();
+        let max_batch_events = contract.config().max_batch_events;
+        let account_ids: Vec<ValidAccountId> = (0..=max_batch_events)
+            .map(|n| {
+                let account_id = format!("user{}.testnet", n);
+                contract.internal_credit_balance(&account_id, 10, Book::Custody);
+                ValidAccountId::try_from(account_id).unwrap()
+            })
+            .collect();
+        testing_env!(context_for("alice.testnet", 0));
+        contract.sweep_dust(account_ids[1..].to_vec());
+        let logs = get_logs();
+        assert_eq!(max_batch_events as usize, logs.len());
+        assert!(logs
+            .iter()
+            .all(|log| log.contains("\"event\":\"dust_swept\"")));
+
+        testing_env!(context_for("alice.testnet", 0));
+        for account_id in account_ids[1..].iter() {
+            contract.internal_credit_balance(account_id.as_ref(), 10, Book::Custody);
+        }
+        contract.sweep_dust(account_ids);
+        let logs = get_logs();
+        assert_eq!(1, logs.len());
+        assert!(logs[0].contains("\"event\":\"batch_summary\""));
+        let summary = format!(
+            "\"count\":\"{}\",\"event\":\"dust_swept\",\"total\":\"{}\"",
+            max_batch_events + 1,
+            10 * (max_batch_events + 1)
+        );
+        assert!(logs[0].contains(&summary));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_owner_sweeps_dust() {
+        let mut contract = dusty_contract();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.sweep_dust(vec![]);
+    }
+}
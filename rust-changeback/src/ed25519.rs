@@ -0,0 +1,86 @@
+//! Ed25519 signature verification, which the host of near-sdk 3 doesn't offer.
+//!
+//! Verification is left to `ed25519-dalek`, built without its default
+//! features: no precomputed tables to bloat the wasm, no signing. Checks are
+//! strict, as RFC 8032 requires: signatures whose `S` isn't reduced mod the
+//! group order are refused, and so are keys and `R`s of small order, which
+//! would let one signature pass for several messages.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+
+/// Whether `signature` is `public_key`'s ed25519 signature of `message`.
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    VerifyingKey::from_bytes(public_key)
+        .and_then(|key| key.verify_strict(message, &Signature::from_bytes(signature)))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wycheproof::eddsa::{TestName, TestSet};
+    use wycheproof::TestResult;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn check(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        match (public_key.try_into(), signature.try_into()) {
+            (Ok(key), Ok(sig)) => verify(key, message, sig),
+            _ => false,
+        }
+    }
+
+    /// RFC 8032 section 7.1, but for TEST 1024, whose message is a kilobyte:
+    /// it is Wycheproof's test 83.
+    const RFC_8032: [(&str, &str, &str); 4] = [
+        (
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+        (
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        ),
+        (
+            "ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf",
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            "dc2a4459e7369633a52b1bf277839a00201009a3efbf3ecb69bea2186c26b58909351fc9ac90b3ecfdfbc7c66431e0303dca179c138ac17ad9bef1177331a704",
+        ),
+    ];
+
+    #[test]
+    fn rfc_8032_vectors_verify() {
+        for (public_key, message, signature) in RFC_8032 {
+            assert!(check(&hex(public_key), &hex(message), &hex(signature)));
+            assert!(!check(&hex(public_key), b"tampered", &hex(signature)));
+        }
+    }
+
+    #[test]
+    fn wycheproof_vectors_verify() {
+        let set = TestSet::load(TestName::Ed25519).unwrap();
+        for group in set.test_groups {
+            for test in group.tests {
+                assert_eq!(
+                    test.result == TestResult::Valid,
+                    check(&group.key.pk, &test.msg, &test.sig),
+                    "Wycheproof test {}: {}",
+                    test.tc_id,
+                    test.comment
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Logging: structured events following [NEP-297](https://nomicon.io/Standards/EventsFormat)
+//! and plain human-readable messages.
+//!
+//! Each event is a single log line `EVENT_JSON:{...}` so indexers can pick
+//! them out of the regular human-readable logs. The `log_level` config decides
+//! which of them are written at all.
+//!
+//! Batch calls collect their per-item events in an [`EventBuffer`] and flush
+//! it when done, so a receipt's logs stay bounded: past `max_batch_events`
+//! items, a single `batch_summary` event carries their count and total instead.
+
+use std::fmt::Display;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, json};
+
+use crate::*;
+
+pub(crate) const EVENT_STANDARD: &str = "changeback";
+pub(crate) const EVENT_VERSION: &str = "1.0.0";
+
+/// How much the contract logs, each level including the ones before it.
+#[derive(
+    BorshDeserialize,
+    BorshSerialize,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    PartialOrd,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LogLevel {
+    /// Nothing at all.
+    Off,
+    /// Structured events only, the audit trail indexers rely on.
+    Audit,
+    /// Human-readable messages about what a call did.
+    Info,
+    /// Chatty diagnostics such as the overflow warning.
+    Debug,
+}
+
+/// Per-item `event` events of a batch call, held back until it is done.
+pub(crate) struct EventBuffer<T> {
+    event: &'static str,
+    entries: Vec<T>,
+    /// Sum of the amounts of `entries`.
+    total: Balance,
+}
+
+impl<T: Serialize> EventBuffer<T> {
+    pub(crate) fn new(event: &'static str) -> Self {
+        Self {
+            event,
+            entries: vec![],
+            total: 0,
+        }
+    }
+
+    /// Adds the event `data` about `amount`.
+    pub(crate) fn push(&mut self, data: T, amount: Balance) {
+        self.entries.push(data);
+        self.total += amount;
+    }
+
+    pub(crate) fn total(&self) -> Balance {
+        self.total
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct BatchSummaryEvent<'a> {
+    event: &'a str,
+    count: U64,
+    total: U128,
+}
+
+impl Change {
+    /// Logs the events in `buffer`, or a summary of them if there are more than
+    /// `max_batch_events`.
+    pub(crate) fn internal_flush_events<T: Serialize>(&self, buffer: EventBuffer<T>) {
+        if buffer.entries.len() as u64 <= u64::from(self.config().max_batch_events) {
+            for data in buffer.entries.iter() {
+                self.emit_event(buffer.event, data);
+            }
+            return;
+        }
+        self.emit_event(
+            "batch_summary",
+            &BatchSummaryEvent {
+                event: buffer.event,
+                count: U64(buffer.entries.len() as u64),
+                total: U128(buffer.total),
+            },
+        );
+    }
+
+    /// Logs `data` as the payload of a `event` event.
+    pub(crate) fn emit_event<T: Serialize>(&self, event: &str, data: &T) {
+        if self.config().log_level < LogLevel::Audit {
+            return;
+        }
+        let event = json!({
+            "standard": EVENT_STANDARD,
+            "version": EVENT_VERSION,
+            "event": event,
+            "data": [data],
+        });
+        let log_message = format!("EVENT_JSON:{}", serde_json::to_string(&event).unwrap());
+        env::log(log_message.as_bytes());
+        self.events_emitted.set(self.events_emitted.get() + 1);
+    }
+
+    /// Logs `message`, formatted with `args`, if the configured level goes
+    /// down to `level`.
+    pub(crate) fn log(&self, level: LogLevel, message: Message, args: &[&dyn Display]) {
+        if level <= self.config().log_level {
+            env::log(message.format(args).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn logs_at(log_level: LogLevel) -> Vec<String> {
+        testing_env!(context_for("alice.testnet", 10u128.pow(24)));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                log_level,
+                ..Config::default()
+            },
+            None,
+        );
+        contract.fund_treasury();
+        testing_env!(context_for("alice.testnet", 0));
+        contract.add(None, None, None, None);
+        contract.withdraw_treasury(ValidAccountId::try_from("ops.testnet").unwrap(), U128(1));
+        get_logs()
+    }
+
+    #[test]
+    fn debug_logs_everything() {
+        let logs = logs_at(LogLevel::Debug);
+        assert!(logs
+            .contains(&"[OVERFLOW_WARNING] Make sure you don't overflow, my friend.".to_string()));
+        assert!(logs.contains(&"[MONEY_ADDED] Added money to 1000".to_string()));
+        assert!(logs.iter().any(|log| log.starts_with("EVENT_JSON:")));
+    }
+
+    #[test]
+    fn audit_keeps_only_events() {
+        let logs = logs_at(LogLevel::Audit);
+        assert_eq!(2, logs.len());
+        assert!(logs[0].contains("\"event\":\"add\""));
+        assert!(logs[1].contains("\"event\":\"treasury_withdrawal\""));
+    }
+
+    #[test]
+    fn off_is_silent() {
+        assert!(logs_at(LogLevel::Off).is_empty());
+    }
+}
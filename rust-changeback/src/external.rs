@@ -0,0 +1,87 @@
+//! Interfaces of the contracts this one calls, itself included for callbacks,
+//! so the arguments of every cross-contract call are checked at compile time.
+//!
+//! Merchant [webhooks](crate::webhook) are the exception: their method name is
+//! configured per merchant, so they are called without an interface.
+
+use near_sdk::ext_contract;
+use near_sdk::json_types::{ValidAccountId, I128, U128, U64};
+
+use crate::remote_transfer::RemoteTransfer;
+use crate::swap::SwapAction;
+
+/// NEP-141 fungible token.
+#[ext_contract(ext_ft)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> U128;
+}
+
+/// Price oracle, see [`oracle`](crate::oracle).
+#[ext_contract(ext_oracle)]
+pub trait PriceOracle {
+    fn get_price(&self, asset_id: AccountId);
+}
+
+/// ref.finance-style AMM, see [`swap`](crate::swap).
+#[ext_contract(ext_amm)]
+pub trait Amm {
+    fn swap(&mut self, actions: Vec<SwapAction>) -> U128;
+    fn withdraw(&mut self, token_id: AccountId, amount: U128, unregister: Option<bool>);
+}
+
+/// Contract notified of counter moves, see [`listener`](crate::listener).
+#[ext_contract(ext_listener)]
+pub trait ChangeListener {
+    fn on_change_updated(&mut self, new_value: I128, delta: I128, caller: AccountId);
+}
+
+/// Another instance of this contract, see [`remote_transfer`](crate::remote_transfer).
+#[ext_contract(ext_instance)]
+pub trait ChangeInstance {
+    fn receive_remote_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        currency: String,
+    ) -> U128;
+}
+
+/// Callbacks of this contract.
+#[ext_contract(ext_self)]
+pub trait ChangeCallbacks {
+    fn on_amm_withdrawn(&mut self, token_id: AccountId, amount: U128);
+    fn on_change_donated(&mut self, account_id: AccountId, amount: U128);
+    fn on_listener_notified(
+        &mut self,
+        listener_id: AccountId,
+        caller: AccountId,
+        new_value: I128,
+        delta: I128,
+    );
+    fn on_merchant_contract_created(&mut self, merchant_id: AccountId, deposit: U128);
+    fn on_price_fetched(&mut self, token_id: AccountId);
+    fn on_purchase_forwarded(&mut self, buyer_id: AccountId, amount: U128);
+    fn on_referral_payout(&mut self, account_id: AccountId, amount: U128);
+    fn on_remote_transfer(&mut self, transfer: RemoteTransfer);
+    fn on_savings_withdrawn(&mut self, account_id: AccountId, token_id: AccountId, amount: U128);
+    fn on_settled(&mut self, settlement_id: U64);
+    fn on_shard_deployed(&mut self, index: u64, deposit: U128);
+    fn on_token_withdrawn(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        currency: Option<String>,
+    );
+    fn on_swap_to_base(&mut self, token_id: AccountId, amount_in: U128, min_amount_out: U128);
+    fn on_swap_reverted(&mut self, token_id: AccountId, amount_out: U128);
+    fn on_treasury_withdrawn(&mut self, receiver_id: AccountId, amount: U128);
+}
@@ -0,0 +1,211 @@
+//! Deploying this contract to subaccounts of its own, for
+//! [shards](crate::sharding) and for merchants running their own change-back
+//! instance.
+//!
+//! The owner uploads this contract's wasm once with
+//! [`set_contract_code`](Change::set_contract_code). A registered merchant
+//! can then have it deployed to `<prefix>.<this contract>` with
+//! [`create_merchant_contract`](Change::create_merchant_contract), owning the
+//! new instance, funded with the deposit it attaches. If the deployment
+//! fails, the deposit goes back to the merchant.
+
+use near_sdk::collections::LazyOption;
+use near_sdk::json_types::{Base64VecU8, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{near_bindgen, Promise};
+
+use crate::*;
+
+/// Least NEAR a child contract is funded with, covering the storage of its
+/// code, in yoctoNEAR.
+pub(crate) const MIN_CHILD_DEPOSIT: Balance = 5 * 10u128.pow(24);
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct MerchantContractEvent<'a> {
+    merchant_id: &'a AccountId,
+    contract_id: &'a AccountId,
+    created: bool,
+}
+
+/// Storage of the uploaded contract code, which is only loaded to deploy it.
+fn contract_code() -> LazyOption<Vec<u8>> {
+    LazyOption::new(StorageKey::ContractCode, None)
+}
+
+impl Change {
+    /// Creates `account_id` with `deposit`, deploys the uploaded code to it
+    /// and initializes it with `owner_id` as its owner.
+    pub(crate) fn internal_deploy_child(
+        &self,
+        account_id: AccountId,
+        owner_id: &AccountId,
+        deposit: Balance,
+    ) -> Promise {
+        let code = contract_code()
+            .get()
+            .unwrap_or_else(|| fail!(messages::NO_CONTRACT_CODE_UPLOADED));
+        let init_args = json!({ "owner_id": owner_id }).to_string().into_bytes();
+        Promise::new(account_id)
+            .create_account()
+            .transfer(deposit)
+            .deploy_contract(code)
+            .function_call(
+                b"new".to_vec(),
+                init_args,
+                0,
+                self.config().gas.child_init.0,
+            )
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Uploads the wasm deployed to child contracts. Owner only.
+    pub fn set_contract_code(&mut self, code: Base64VecU8) {
+        self.assert_access("set_contract_code");
+        require!(!code.0.is_empty(), messages::EMPTY_CONTRACT_CODE);
+        contract_code().set(&code.0);
+        self.log(LogLevel::Info, messages::CONTRACT_CODE_UPDATED, &[]);
+    }
+
+    /// Deploys this contract to `<prefix>.<this contract>`, owned by the
+    /// calling merchant and funded with the attached deposit.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet create_merchant_contract '{"prefix": "shop"}' --accountId shop.testnet --amount 5 --gas 100000000000000
+    /// ```
+    #[payable]
+    pub fn create_merchant_contract(&mut self, prefix: String) -> Promise {
+        self.assert_access("create_merchant_contract");
+        let merchant_id = env::predecessor_account_id();
+        require!(
+            self.merchants.get(&merchant_id).is_some(),
+            messages::REGISTERED_MERCHANT_ONLY
+        );
+        require!(
+            self.merchant_contracts.get(&merchant_id).is_none(),
+            messages::MERCHANT_HAS_CONTRACT
+        );
+        require!(!prefix.contains('.'), messages::PREFIX_WITH_DOTS);
+        let contract_id = format!("{}.{}", prefix, env::current_account_id());
+        require!(
+            env::is_valid_account_id(contract_id.as_bytes()),
+            messages::INVALID_CONTRACT_ACCOUNT,
+            contract_id
+        );
+        let deposit = env::attached_deposit();
+        require!(
+            deposit >= MIN_CHILD_DEPOSIT,
+            messages::DEPOSIT_TOO_SMALL,
+            MIN_CHILD_DEPOSIT
+        );
+        self.merchant_contracts.insert(&merchant_id, &contract_id);
+        self.internal_deploy_child(contract_id, &merchant_id, deposit)
+            .then(ext_self::on_merchant_contract_created(
+                merchant_id,
+                U128(deposit),
+                &env::current_account_id(),
+                0,
+                self.config().gas.callback.0,
+            ))
+    }
+
+    /// Forgets the contract of `merchant_id` and refunds its deposit, which
+    /// came back, if the deployment failed.
+    pub fn on_merchant_contract_created(&mut self, merchant_id: AccountId, deposit: U128) {
+        self.assert_access("on_merchant_contract_created");
+        let created = promise_succeeded().is_ok();
+        let contract_id = if created {
+            self.merchant_contracts.get(&merchant_id)
+        } else {
+            self.merchant_contracts.remove(&merchant_id)
+        }
+        .unwrap_or_else(|| fail!(messages::MERCHANT_NO_CONTRACT));
+        self.emit_event(
+            "merchant_contract_created",
+            &MerchantContractEvent {
+                merchant_id: &merchant_id,
+                contract_id: &contract_id,
+                created,
+            },
+        );
+        if !created {
+            Promise::new(merchant_id).transfer(deposit.0);
+        }
+    }
+
+    /// Returns the contract created for `merchant_id`, if any.
+    pub fn get_merchant_contract(&self, merchant_id: AccountId) -> Option<AccountId> {
+        self.merchant_contracts.get(&merchant_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult};
+    use std::convert::TryFrom;
+
+    fn platform() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_contract_code(Base64VecU8(vec![0, 97, 115, 109]));
+        let shop = ValidAccountId::try_from("shop.testnet").unwrap();
+        contract.register_merchant(shop.clone(), shop, 0);
+        contract
+    }
+
+    #[test]
+    fn merchants_get_their_own_contract() {
+        let mut contract = platform();
+        testing_env!(context_for("shop.testnet", MIN_CHILD_DEPOSIT));
+        contract.create_merchant_contract("shop".to_string());
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("shop.alice.testnet"));
+        assert_eq!(
+            Some("shop.alice.testnet".to_string()),
+            contract.get_merchant_contract("shop.testnet".to_string())
+        );
+    }
+
+    #[test]
+    fn failed_contracts_are_forgotten() {
+        let mut contract = platform();
+        testing_env!(context_for("shop.testnet", MIN_CHILD_DEPOSIT));
+        contract.create_merchant_contract("shop".to_string());
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_merchant_contract_created("shop.testnet".to_string(), U128(MIN_CHILD_DEPOSIT));
+        assert_eq!(
+            None,
+            contract.get_merchant_contract("shop.testnet".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only registered merchants can create a contract")]
+    fn only_merchants_create_contracts() {
+        let mut contract = platform();
+        testing_env!(context_for("jane.testnet", MIN_CHILD_DEPOSIT));
+        contract.create_merchant_contract("jane".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Prefix can't contain dots")]
+    fn prefixes_are_one_label() {
+        let mut contract = platform();
+        testing_env!(context_for("shop.testnet", MIN_CHILD_DEPOSIT));
+        contract.create_merchant_contract("a.b".to_string());
+    }
+}
@@ -0,0 +1,258 @@
+//! Change in cash at a point of sale, broken into the denominations of the
+//! float.
+//!
+//! The owner sets the denominations registers hand change out in, each with
+//! how many pieces of it the float holds. [`compute_change`](Change::compute_change)
+//! breaks the change owed on a sale into them, largest first and as far as
+//! the float goes; what it can't make is left as the remainder, which the
+//! cashier can credit as stored change instead. [`settle_change`](Change::settle_change)
+//! does the same for a cashier with an open [POS session](crate::pos) and
+//! takes the pieces out of the float. Amounts are in the smallest unit of the
+//! cash currency, cents for instance.
+
+use std::cmp::Reverse;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Most denominations the float can have, bounding the work of breaking
+/// change down.
+const MAX_DENOMINATIONS: usize = 20;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Denomination {
+    pub value: U128,
+    /// Pieces of it, in the float or in a breakdown.
+    pub count: u32,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChangeBreakdown {
+    /// `paid` minus `price`.
+    pub change: U128,
+    /// Pieces making up the change, largest denomination first.
+    pub pieces: Vec<Denomination>,
+    /// Part of the change the float can't make.
+    pub remainder: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ChangeSettledEvent<'a> {
+    cashier_id: &'a AccountId,
+    session_id: U64,
+    change: U128,
+    remainder: U128,
+}
+
+impl Change {
+    /// Breaks the change on `price` paid with `paid` into the float.
+    fn internal_break_change(&self, paid: Balance, price: Balance) -> ChangeBreakdown {
+        require!(paid >= price, messages::PAID_BELOW_PRICE);
+        let change = paid - price;
+        let mut remainder = change;
+        let mut pieces = vec![];
+        for denomination in &self.denominations {
+            let wanted = remainder / denomination.value.0;
+            let count = wanted.min(Balance::from(denomination.count)) as u32;
+            if count > 0 {
+                remainder -= Balance::from(count) * denomination.value.0;
+                pieces.push(Denomination {
+                    value: denomination.value,
+                    count,
+                });
+            }
+        }
+        ChangeBreakdown {
+            change: U128(change),
+            pieces,
+            remainder: U128(remainder),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Replaces the denominations of the float and how many pieces of each it
+    /// holds. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_denominations '{"denominations": [{"value": "500", "count": 10}, {"value": "100", "count": 40}]}' --accountId YOU.testnet
+    /// ```
+    pub fn set_denominations(&mut self, denominations: Vec<Denomination>) {
+        self.assert_access("set_denominations");
+        require!(
+            denominations.len() <= MAX_DENOMINATIONS,
+            messages::TOO_MANY_DENOMINATIONS,
+            MAX_DENOMINATIONS
+        );
+        let mut denominations = denominations;
+        denominations.sort_by_key(|denomination| Reverse(denomination.value.0));
+        require!(
+            denominations
+                .iter()
+                .all(|denomination| denomination.value.0 > 0)
+                && denominations
+                    .windows(2)
+                    .all(|pair| pair[0].value.0 != pair[1].value.0),
+            messages::INVALID_DENOMINATIONS
+        );
+        self.denominations = denominations;
+        self.emit_event("denominations_set", &self.denominations);
+    }
+
+    /// Returns the denominations of the float, largest first, with how many
+    /// pieces of each it holds.
+    pub fn get_denominations(&self) -> Vec<Denomination> {
+        self.denominations.clone()
+    }
+
+    /// Returns the change owed on `price` paid with `paid`, broken into the
+    /// denominations the float holds.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet compute_change '{"paid": "2000", "price": "1350"}'
+    /// ```
+    pub fn compute_change(&self, paid: U128, price: U128) -> ChangeBreakdown {
+        self.internal_break_change(paid.0, price.0)
+    }
+
+    /// Breaks the change like `compute_change` and hands it out of the float.
+    /// The caller must have an open POS session.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet settle_change '{"paid": "2000", "price": "1350"}' --accountId cashier.YOU.testnet
+    /// ```
+    pub fn settle_change(&mut self, paid: U128, price: U128) -> ChangeBreakdown {
+        self.assert_access("settle_change");
+        self.assert_writable();
+        let cashier_id = env::predecessor_account_id();
+        let session_id = self
+            .open_pos_sessions
+            .get(&cashier_id)
+            .unwrap_or_else(|| fail!(messages::NO_OPEN_POS_SESSION));
+        let breakdown = self.internal_break_change(paid.0, price.0);
+        for piece in &breakdown.pieces {
+            let denomination = self
+                .denominations
+                .iter_mut()
+                .find(|denomination| denomination.value == piece.value)
+                .unwrap();
+            denomination.count -= piece.count;
+        }
+        self.emit_event(
+            "change_settled",
+            &ChangeSettledEvent {
+                cashier_id: &cashier_id,
+                session_id: U64(session_id),
+                change: breakdown.change,
+                remainder: breakdown.remainder,
+            },
+        );
+        breakdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn denomination(value: u128, count: u32) -> Denomination {
+        Denomination {
+            value: U128(value),
+            count,
+        }
+    }
+
+    fn stocked() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_denominations(vec![
+            denomination(100, 10),
+            denomination(500, 1),
+            denomination(25, 2),
+        ]);
+        contract
+    }
+
+    #[test]
+    fn change_is_broken_into_the_float() {
+        let contract = stocked();
+        assert_eq!(
+            vec![500, 100, 25],
+            contract
+                .get_denominations()
+                .iter()
+                .map(|denomination| denomination.value.0)
+                .collect::<Vec<_>>()
+        );
+        let breakdown = contract.compute_change(U128(2000), U128(1340));
+        assert_eq!(660, breakdown.change.0);
+        assert_eq!(
+            vec![
+                denomination(500, 1),
+                denomination(100, 1),
+                denomination(25, 2)
+            ],
+            breakdown.pieces
+        );
+        assert_eq!(10, breakdown.remainder.0);
+        assert_eq!(0, contract.compute_change(U128(5), U128(5)).change.0);
+    }
+
+    #[test]
+    fn settling_takes_the_pieces_out_of_the_float() {
+        let mut contract = stocked();
+        testing_env!(context_for("cashier.testnet", 0));
+        contract.open_pos_session("front-1".to_string());
+        let breakdown = contract.settle_change(U128(2000), U128(1000));
+        assert_eq!(
+            vec![denomination(500, 1), denomination(100, 5)],
+            breakdown.pieces
+        );
+        assert_eq!(
+            vec![
+                denomination(500, 0),
+                denomination(100, 5),
+                denomination(25, 2)
+            ],
+            contract.get_denominations()
+        );
+        let breakdown = contract.settle_change(U128(2000), U128(1000));
+        assert_eq!(
+            vec![denomination(100, 5), denomination(25, 2)],
+            breakdown.pieces
+        );
+        assert_eq!(450, breakdown.remainder.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "No open POS session")]
+    fn settling_takes_an_open_session() {
+        let mut contract = stocked();
+        testing_env!(context_for("cashier.testnet", 0));
+        contract.settle_change(U128(2000), U128(1000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Paid amount doesn't cover the price")]
+    fn paying_less_than_the_price_is_refused() {
+        stocked().compute_change(U128(100), U128(101));
+    }
+
+    #[test]
+    #[should_panic(expected = "Denominations must be positive and distinct")]
+    fn denominations_are_distinct() {
+        let mut contract = stocked();
+        contract.set_denominations(vec![denomination(100, 1), denomination(100, 2)]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Gift cards redeemable for change balance.
+//!
+//! The issuer attaches the card value and registers only the sha256 hash of
+//! the card's secret code, so the code itself never appears on chain until it
+//! is redeemed. The value stays locked in the contract until then.
+//!
+//! Whoever presents the code to [`redeem_gift_card`](Change::redeem_gift_card)
+//! gets the value credited to their change balance. Each code works once,
+//! and a redeemed code can't be issued again.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, CryptoHash, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct GiftCard {
+    pub issuer_id: AccountId,
+    pub amount: Balance,
+    pub issued_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GiftCardView {
+    pub code_hash: Base58CryptoHash,
+    pub issuer_id: AccountId,
+    pub amount: U128,
+    pub issued_at: U64,
+}
+
+impl GiftCardView {
+    fn new(code_hash: CryptoHash, card: GiftCard) -> Self {
+        Self {
+            code_hash: code_hash.into(),
+            issuer_id: card.issuer_id,
+            amount: U128(card.amount),
+            issued_at: U64(card.issued_at),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct GiftCardRedeemedEvent<'a> {
+    code_hash: Base58CryptoHash,
+    account_id: &'a AccountId,
+    amount: U128,
+}
+
+#[near_bindgen]
+impl Change {
+    /// Issues a gift card worth `amount`, which the caller must attach, redeemable
+    /// with the code hashing to `code_hash`.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet issue_gift_card '{"amount": "5000000000000000000000000", "code_hash": "<base58 sha256 of the code>"}' --accountId shop.testnet --deposit 5
+    /// ```
+    #[payable]
+    pub fn issue_gift_card(&mut self, amount: U128, code_hash: Base58CryptoHash) {
+        self.assert_access("issue_gift_card");
+        self.assert_writable();
+        require!(amount.0 > 0, messages::AMOUNT_NOT_POSITIVE);
+        require!(
+            env::attached_deposit() == amount.0,
+            messages::GIFT_CARD_DEPOSIT_MISMATCH
+        );
+        let code_hash: CryptoHash = code_hash.into();
+        require!(
+            self.gift_cards.get(&code_hash).is_none()
+                && !self.redeemed_gift_cards.contains(&code_hash),
+            messages::GIFT_CARD_EXISTS
+        );
+        let card = GiftCard {
+            issuer_id: env::predecessor_account_id(),
+            amount: amount.0,
+            issued_at: env::block_timestamp(),
+        };
+        self.gift_cards.insert(&code_hash, &card);
+        self.internal_post(Posting::receive(Book::GiftCards, amount.0));
+        self.emit_event("gift_card_issued", &GiftCardView::new(code_hash, card));
+    }
+
+    /// Redeems the gift card with `code`, crediting its value to the caller's
+    /// change balance. Returns the amount credited.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet redeem_gift_card '{"code": "SUMMER-1"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn redeem_gift_card(&mut self, code: String) -> U128 {
+        self.assert_access("redeem_gift_card");
+        self.assert_writable();
+        let mut code_hash = CryptoHash::default();
+        code_hash.copy_from_slice(&env::sha256(code.as_bytes()));
+        if self.redeemed_gift_cards.contains(&code_hash) {
+            fail!(messages::GIFT_CARD_ALREADY_REDEEMED);
+        }
+        let card = self
+            .gift_cards
+            .remove(&code_hash)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_GIFT_CARD));
+        self.redeemed_gift_cards.insert(&code_hash);
+        let account_id = env::predecessor_account_id();
+        self.internal_credit_balance(&account_id, card.amount, Book::GiftCards);
+        self.emit_event(
+            "gift_card_redeemed",
+            &GiftCardRedeemedEvent {
+                code_hash: code_hash.into(),
+                account_id: &account_id,
+                amount: U128(card.amount),
+            },
+        );
+        U128(card.amount)
+    }
+
+    /// Returns up to `limit` outstanding gift cards starting at `from_index`,
+    /// as many as the gas allows, see [`pagination`](crate::pagination).
+    pub fn get_gift_cards(&self, from_index: U64, limit: u64) -> Page<GiftCardView> {
+        let keys = self.gift_cards.keys_as_vector();
+        let values = self.gift_cards.values_as_vector();
+        paginate(limit, |n| {
+            let index = from_index.0.checked_add(n)?;
+            Some(GiftCardView::new(keys.get(index)?, values.get(index)?))
+        })
+    }
+
+    /// Returns the value of all outstanding gift cards.
+    pub fn get_gift_card_liability(&self) -> U128 {
+        U128(self.book(Book::GiftCards))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn hash(code: &str) -> Base58CryptoHash {
+        let mut hash = CryptoHash::default();
+        hash.copy_from_slice(&env::sha256(code.as_bytes()));
+        hash.into()
+    }
+
+    #[test]
+    fn issued_cards_are_listed_as_liabilities() {
+        testing_env!(context_for("shop.testnet", 500));
+        let mut contract = Change::default();
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+        testing_env!(context_for("cafe.testnet", 200));
+        contract.issue_gift_card(U128(200), hash("SUMMER-2"));
+        assert_eq!(700, contract.get_gift_card_liability().0);
+        let cards = contract.get_gift_cards(U64(1), 10).items;
+        assert_eq!(1, cards.len());
+        assert_eq!("cafe.testnet", cards[0].issuer_id);
+        assert_eq!(200, cards[0].amount.0);
+        assert_eq!(hash("SUMMER-2"), cards[0].code_hash);
+    }
+
+    #[test]
+    fn redeeming_credits_the_redeemer() {
+        testing_env!(context_for("shop.testnet", 500));
+        let mut contract = Change::default();
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+        testing_env!(context_for("jane.testnet", 0));
+        assert_eq!(500, contract.redeem_gift_card("SUMMER-1".to_string()).0);
+        assert_eq!(500, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(0, contract.get_gift_card_liability().0);
+        assert!(contract.get_gift_cards(U64(0), 10).items.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Gift card was already redeemed")]
+    fn cards_are_redeemed_once() {
+        testing_env!(context_for("shop.testnet", 500));
+        let mut contract = Change::default();
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.redeem_gift_card("SUMMER-1".to_string());
+        contract.redeem_gift_card("SUMMER-1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "No gift card with this code")]
+    fn wrong_codes_are_rejected() {
+        testing_env!(context_for("shop.testnet", 500));
+        let mut contract = Change::default();
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+        contract.redeem_gift_card("SUMMER-2".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach exactly the card amount")]
+    fn cards_are_fully_funded() {
+        testing_env!(context_for("shop.testnet", 499));
+        let mut contract = Change::default();
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "A gift card with this code already exists")]
+    fn codes_are_unique() {
+        testing_env!(context_for("shop.testnet", 500));
+        let mut contract = Change::default();
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+        contract.issue_gift_card(U128(500), hash("SUMMER-1"));
+    }
+}
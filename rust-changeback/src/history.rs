@@ -0,0 +1,215 @@
+//! History of every `add`, `change` and `reset`, so dapps can render activity
+//! feeds without running an indexer.
+//!
+//! Records are appended in the order the operations ran and refer to the
+//! account whose Change moved by its [interned](crate::interning) id.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{I128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, Timestamp};
+
+use crate::*;
+
+/// Most records one `get_records` call returns, bounding its gas.
+const MAX_RECORDS_PER_CALL: usize = 100;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CounterOperation {
+    Add,
+    Change,
+    Reset,
+    /// Restore by [`rollback_to`](crate::snapshot).
+    Rollback,
+    /// Sent to another instance, see [`remote_transfer`](crate::remote_transfer).
+    TransferOut,
+    /// Received from another instance, or a failed send put back.
+    TransferIn,
+}
+
+impl CounterOperation {
+    /// Name of the event announcing the operation.
+    pub(crate) fn event(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Change => "change",
+            Self::Reset => "reset",
+            Self::Rollback => "rollback",
+            Self::TransferOut => "transfer_out",
+            Self::TransferIn => "transfer_in",
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ChangeRecord {
+    pub timestamp: Timestamp,
+    /// Interned id of the account whose Change moved.
+    pub account: u32,
+    pub operation: CounterOperation,
+    /// Currency of the Change that moved, see [`currency`](crate::currency).
+    pub currency: String,
+    pub delta: i128,
+    /// Change of the account after the operation.
+    pub value: i128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChangeRecordView {
+    pub id: U64,
+    pub timestamp: U64,
+    pub account_id: AccountId,
+    pub operation: CounterOperation,
+    pub currency: String,
+    pub delta: I128,
+    pub value: I128,
+    /// `delta` and `value` formatted for display, see [`display`](crate::display).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_formatted: Option<String>,
+}
+
+impl Change {
+    /// Appends the record of `operation` moving the Change of `account_id` in
+    /// `currency` by `delta` to `value`.
+    pub(crate) fn internal_record_history(
+        &mut self,
+        operation: CounterOperation,
+        account_id: &AccountId,
+        currency: &str,
+        delta: i128,
+        value: i128,
+    ) {
+        let record = ChangeRecord {
+            timestamp: env::block_timestamp(),
+            account: self.internal_intern(account_id),
+            operation,
+            currency: currency.to_string(),
+            delta,
+            value,
+        };
+        self.history.push(&record);
+        self.internal_checkpoint(currency, delta);
+    }
+
+    fn internal_record_view(&self, id: u64) -> Option<ChangeRecordView> {
+        let record = self.history.get(id)?;
+        Some(ChangeRecordView {
+            id: U64(id),
+            timestamp: U64(record.timestamp),
+            account_id: self.internal_account_id(record.account),
+            operation: record.operation,
+            currency: record.currency,
+            delta: I128(record.delta),
+            value: I128(record.value),
+            delta_formatted: self.internal_format(record.delta),
+            value_formatted: self.internal_format(record.value),
+        })
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns up to `limit` history records starting at `from_index`, oldest
+    /// first, as many as the gas allows, see [`pagination`](crate::pagination).
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_history '{"from_index": "0", "limit": 50}'
+    /// ```
+    pub fn get_history(&self, from_index: U64, limit: u64) -> Page<ChangeRecordView> {
+        paginate(limit, |n| {
+            self.internal_record_view(from_index.0.checked_add(n)?)
+        })
+    }
+
+    /// Returns the history records `ids`, in the order asked for, `null` for
+    /// the ids with no record.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_records '{"ids": ["4", "17"]}'
+    /// ```
+    pub fn get_records(&self, ids: Vec<U64>) -> Vec<Option<ChangeRecordView>> {
+        require!(
+            ids.len() <= MAX_RECORDS_PER_CALL,
+            messages::TOO_MANY_RECORDS,
+            MAX_RECORDS_PER_CALL
+        );
+        ids.into_iter()
+            .map(|id| self.internal_record_view(id.0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn operations_are_recorded_in_order() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(250)), None);
+        let mut context = context_for("bob.testnet", 0);
+        context.block_timestamp = 7;
+        testing_env!(context);
+        contract.change(None, None, None);
+        testing_env!(context_for("alice.testnet", 0));
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+
+        let history = contract.get_history(U64(0), 10).items;
+        let summary: Vec<_> = history
+            .iter()
+            .map(|record| {
+                (
+                    record.account_id.as_str(),
+                    record.operation,
+                    record.delta.0,
+                    record.value.0,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("jane.testnet", CounterOperation::Add, 250, 250),
+                ("bob.testnet", CounterOperation::Change, -10, -10),
+                ("jane.testnet", CounterOperation::Reset, -250, 0),
+            ],
+            summary
+        );
+        assert_eq!(7, history[1].timestamp.0);
+        assert_eq!(1, contract.get_history(U64(1), 1).items[0].id.0);
+    }
+
+    #[test]
+    fn records_are_read_by_id() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(250)), None);
+        contract.change(None, None, None);
+        contract.add(None, None, None, None);
+
+        let records = contract.get_records(vec![U64(2), U64(9), U64(0)]);
+        assert_eq!(3, records.len());
+        assert_eq!(Some(1240), records[0].as_ref().map(|record| record.value.0));
+        assert!(records[1].is_none());
+        assert_eq!(Some(0), records[2].as_ref().map(|record| record.id.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "At most 100 records per call")]
+    fn record_reads_are_bounded() {
+        testing_env!(context_for("jane.testnet", 0));
+        let contract = Change::default();
+        contract.get_records((0..101).map(U64).collect());
+    }
+}
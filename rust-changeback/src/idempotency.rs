@@ -0,0 +1,172 @@
+//! Idempotency keys, so a wallet retrying a call doesn't apply it twice.
+//!
+//! `add`, `change` and `purchase` take an optional client-chosen
+//! `idempotency_key`. The outcome of the first call with a key is kept, per
+//! caller, for `idempotency_ttl`; a repeat within that time returns the same
+//! outcome without touching any state, and a repeated purchase gets its
+//! deposit refunded. Expired keys can be used again.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{Balance, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum IdempotentOutcome {
+    /// Value of the counter the `add` or `change` left, and the history record it wrote.
+    Counter {
+        val: i128,
+        record_id: u64,
+    },
+    Purchase {
+        receipt_id: u64,
+        change: Balance,
+    },
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct IdempotencyRecord {
+    /// Method the key was first used with.
+    pub method: String,
+    pub outcome: IdempotentOutcome,
+    pub expires_at: Timestamp,
+}
+
+impl Change {
+    /// Returns the outcome of an earlier `method` call by the caller with `key`,
+    /// if it hasn't expired yet.
+    pub(crate) fn internal_replay(
+        &self,
+        key: &Option<String>,
+        method: &str,
+    ) -> Option<IdempotentOutcome> {
+        let key = key.as_ref()?;
+        let record = self
+            .idempotency_keys
+            .get(&(env::predecessor_account_id(), key.clone()))
+            .filter(|record| env::block_timestamp() < record.expires_at)?;
+        require!(record.method == method, messages::IDEMPOTENCY_KEY_REUSED);
+        self.log(
+            LogLevel::Info,
+            messages::IDEMPOTENT_REPLAY,
+            &[&method, &key],
+        );
+        Some(record.outcome)
+    }
+
+    /// Keeps the outcome of a `method` call by the caller with `key` for `idempotency_ttl`.
+    pub(crate) fn internal_remember(
+        &mut self,
+        key: Option<String>,
+        method: &str,
+        outcome: IdempotentOutcome,
+    ) {
+        if let Some(key) = key {
+            let record = IdempotencyRecord {
+                method: method.to_string(),
+                outcome,
+                expires_at: env::block_timestamp() + self.config().idempotency_ttl.0,
+            };
+            self.idempotency_keys
+                .insert(&(env::predecessor_account_id(), key), &record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DAY;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn key(key: &str) -> Option<String> {
+        Some(key.to_string())
+    }
+
+    #[test]
+    fn retried_add_is_applied_once() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        assert_eq!(
+            1000,
+            contract.add(None, key("tx-1"), None, None).new_balance.0
+        );
+        assert_eq!(
+            1000,
+            contract.add(None, key("tx-1"), None, None).new_balance.0
+        );
+        assert_eq!(
+            1000,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        assert_eq!(
+            2000,
+            contract.add(None, key("tx-2"), None, None).new_balance.0
+        );
+        assert_eq!(3000, contract.add(None, None, None, None).new_balance.0);
+    }
+
+    #[test]
+    fn keys_expire_after_the_ttl() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.change(key("tx-1"), None, None);
+        let mut context = context_for("jane.testnet", 0);
+        context.block_timestamp = DAY;
+        testing_env!(context);
+        assert_eq!(-20, contract.change(key("tx-1"), None, None).new_balance.0);
+    }
+
+    #[test]
+    fn keys_are_scoped_to_the_caller() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, key("tx-1"), None, None);
+        testing_env!(context_for("bob.testnet", 0));
+        assert_eq!(
+            1000,
+            contract.add(None, key("tx-1"), None, None).new_balance.0
+        );
+    }
+
+    #[test]
+    fn retried_purchase_returns_the_original_change() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        testing_env!(context_for("jane.testnet", 100));
+        let change = contract.purchase(U128(30), None, None, None, None, key("order-7"));
+        assert_eq!(70, change.0);
+        assert_eq!(
+            70,
+            contract
+                .purchase(U128(30), None, None, None, None, key("order-7"))
+                .0
+        );
+        assert_eq!(1, contract.receipts.len());
+        assert_eq!(
+            70,
+            contract
+                .internal_get_account(&"jane.testnet".to_string())
+                .balance
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Idempotency key was used for another method")]
+    fn keys_belong_to_one_method() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, key("tx-1"), None, None);
+        contract.change(key("tx-1"), None, None);
+    }
+}
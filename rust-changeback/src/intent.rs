@@ -0,0 +1,614 @@
+//! Payment intents for QR checkout.
+//!
+//! A registered merchant creates an intent for an amount, shows its id to the
+//! customer (typically as a QR code), and the customer pays it with
+//! [`pay_intent`](Change::pay_intent). Paying books the purchase like
+//! [`purchase`](Change::purchase) does, at the merchant that created the intent.
+//!
+//! An unpaid intent expires on its own once its deadline passes: it can no
+//! longer be paid and shows as expired. Anyone may then call
+//! [`expire_intent`](Change::expire_intent) to record it. Until then the
+//! merchant can cancel it.
+//!
+//! An intent can be paid in several deposits, layaway style. Deposits that
+//! don't cover the rest of the amount are held by the contract; the one that
+//! completes it books the purchase. At most `MAX_SPLITS` accounts can pay an
+//! intent, each earning the points of what it paid, sharing any refund of
+//! the purchase and able to dispute it. Held deposits go back to the change
+//! balances of their payers if the intent is cancelled or expires, and the
+//! coupon applied to it is released.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, CryptoHash, Timestamp};
+
+use crate::coupon::coupon_code_hash;
+use crate::split::MAX_SPLITS;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum IntentStatus {
+    Open,
+    Paid,
+    /// Cancelled by the merchant before it was paid.
+    Cancelled,
+    /// The deadline passed before it was paid.
+    Expired,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Intent {
+    pub merchant_id: AccountId,
+    pub amount: Balance,
+    pub memo: String,
+    /// Block timestamp after which the intent can no longer be paid.
+    pub expiry: Timestamp,
+    pub status: IntentStatus,
+    /// Receipt of the purchase that paid the intent.
+    pub receipt_id: Option<u64>,
+    /// Taken off the amount by a coupon.
+    pub discount: Balance,
+    /// Code hash of that coupon, released if the intent is never paid.
+    pub coupon: Option<CryptoHash>,
+    /// Partial payments held toward the amount, per payer.
+    pub payments: Vec<(AccountId, Balance)>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IntentView {
+    pub id: U64,
+    pub merchant_id: AccountId,
+    pub amount: U128,
+    pub memo: String,
+    pub expiry: U64,
+    pub status: IntentStatus,
+    pub receipt_id: Option<U64>,
+    pub discount: U128,
+    /// Paid so far, including the payment that completed the intent.
+    pub paid: U128,
+    pub remaining: U128,
+}
+
+impl IntentView {
+    fn new(id: u64, intent: Intent) -> Self {
+        let status = intent.status_at(env::block_timestamp());
+        let paid = match status {
+            IntentStatus::Paid => intent.amount - intent.discount,
+            _ => intent.held(),
+        };
+        Self {
+            id: U64(id),
+            merchant_id: intent.merchant_id,
+            amount: U128(intent.amount),
+            memo: intent.memo,
+            expiry: U64(intent.expiry),
+            status,
+            receipt_id: intent.receipt_id.map(U64),
+            discount: U128(intent.discount),
+            paid: U128(paid),
+            remaining: U128(intent.amount - intent.discount - paid),
+        }
+    }
+}
+
+impl Intent {
+    /// Status at `now`, reporting an open intent past its deadline as expired.
+    fn status_at(&self, now: Timestamp) -> IntentStatus {
+        match self.status {
+            IntentStatus::Open if now > self.expiry => IntentStatus::Expired,
+            status => status,
+        }
+    }
+
+    /// Sum of the partial payments held toward the amount.
+    fn held(&self) -> Balance {
+        self.payments.iter().map(|(_, amount)| amount).sum()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct IntentStatusEvent<'a> {
+    intent_id: U64,
+    merchant_id: &'a AccountId,
+    status: IntentStatus,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct IntentPartialPaymentEvent<'a> {
+    intent_id: U64,
+    payer_id: &'a AccountId,
+    amount: U128,
+    remaining: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct IntentPaidEvent<'a> {
+    intent_id: U64,
+    buyer_id: &'a AccountId,
+    receipt_id: U64,
+}
+
+impl Change {
+    /// Moves an open intent to the final `status`, returning the partial payments
+    /// it held to their payers, and announces it.
+    fn internal_close_intent(&mut self, intent_id: u64, intent: &mut Intent, status: IntentStatus) {
+        for (payer_id, amount) in intent.payments.drain(..) {
+            self.internal_credit_balance(&payer_id, amount, Book::Escrow);
+        }
+        if let Some(code_hash) = intent.coupon.take() {
+            self.internal_release_coupon(&code_hash);
+        }
+        intent.status = status;
+        self.intents.replace(intent_id, intent);
+        self.emit_event(
+            "intent_status",
+            &IntentStatusEvent {
+                intent_id: U64(intent_id),
+                merchant_id: &intent.merchant_id,
+                status,
+            },
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Creates an intent to be paid `amount` by `expiry` (a block timestamp in
+    /// nanoseconds) and returns its id. The caller must be a registered merchant.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet create_intent '{"amount": "4300000000000000000000000", "memo": "table 4", "expiry": "1700000000000000000"}' --accountId shop.testnet
+    /// ```
+    pub fn create_intent(&mut self, amount: U128, memo: String, expiry: U64) -> U64 {
+        self.assert_access("create_intent");
+        self.assert_writable();
+        let merchant_id = env::predecessor_account_id();
+        require!(
+            self.merchants.get(&merchant_id).is_some(),
+            messages::UNKNOWN_MERCHANT
+        );
+        require!(amount.0 > 0, messages::AMOUNT_NOT_POSITIVE);
+        require!(expiry.0 > env::block_timestamp(), messages::EXPIRY_IN_PAST);
+        let intent = Intent {
+            merchant_id,
+            amount: amount.0,
+            memo,
+            expiry: expiry.0,
+            status: IntentStatus::Open,
+            receipt_id: None,
+            discount: 0,
+            coupon: None,
+            payments: Vec::new(),
+        };
+        let id = self.intents.len();
+        self.intents.push(&intent);
+        self.emit_event("intent_created", &IntentView::new(id, intent));
+        U64(id)
+    }
+
+    /// Pays the intent `intent_id` with the attached deposit, crediting any
+    /// excess as the caller's change. Returns the change credited.
+    ///
+    /// A deposit short of the remaining amount is held as a partial payment.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet pay_intent '{"intent_id": "0"}' --accountId donation.YOU.testnet --deposit 5
+    /// ```
+    ///
+    /// With `use_balance` the caller's stored change pays first, as in `purchase`.
+    /// A `coupon` can be applied once per intent, by any of its payments.
+    #[payable]
+    pub fn pay_intent(
+        &mut self,
+        intent_id: U64,
+        use_balance: Option<bool>,
+        coupon: Option<String>,
+    ) -> U128 {
+        self.assert_access("pay_intent");
+        self.assert_writable();
+        let mut intent = self
+            .intents
+            .get(intent_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_INTENT));
+        match intent.status_at(env::block_timestamp()) {
+            IntentStatus::Open => {}
+            IntentStatus::Paid => fail!(messages::INTENT_ALREADY_PAID),
+            IntentStatus::Cancelled => fail!(messages::INTENT_CANCELLED),
+            IntentStatus::Expired => fail!(messages::INTENT_EXPIRED),
+        }
+        let buyer_id = env::predecessor_account_id();
+        let use_balance = use_balance.unwrap_or(false);
+        if use_balance {
+            self.assert_unlocked(&buyer_id);
+        }
+        if !intent
+            .payments
+            .iter()
+            .any(|(payer_id, _)| *payer_id == buyer_id)
+        {
+            require!(
+                intent.payments.len() < MAX_SPLITS,
+                messages::TOO_MANY_INTENT_PAYERS,
+                MAX_SPLITS
+            );
+        }
+        let deposit = env::attached_deposit();
+        let held = intent.held();
+        if let Some(code) = coupon {
+            require!(intent.coupon.is_none(), messages::INTENT_HAS_COUPON);
+            intent.discount =
+                self.internal_use_coupon(&code, Some(&intent.merchant_id), intent.amount);
+            intent.coupon = Some(coupon_code_hash(&code));
+            require!(
+                held <= intent.amount - intent.discount,
+                messages::COUPON_BELOW_PAID
+            );
+        }
+        let price = intent.amount - intent.discount;
+        let remaining = price - held;
+        let mut buyer = self.internal_get_account(&buyer_id);
+        let from_balance = if use_balance {
+            buyer.balance.min(remaining)
+        } else {
+            0
+        };
+        if deposit + from_balance < remaining {
+            let amount = deposit + from_balance;
+            require!(amount > 0, messages::NOTHING_TO_PAY_WITH);
+            buyer.balance -= from_balance;
+            self.internal_set_account(&buyer_id, &buyer);
+            self.internal_post(
+                Posting::default()
+                    .debit(Book::UserChange, from_balance)
+                    .debit(Book::Custody, deposit)
+                    .credit(Book::Escrow, amount),
+            );
+            match intent
+                .payments
+                .iter_mut()
+                .find(|(payer_id, _)| *payer_id == buyer_id)
+            {
+                Some((_, paid)) => *paid += amount,
+                None => intent.payments.push((buyer_id.clone(), amount)),
+            }
+            self.intents.replace(intent_id.0, &intent);
+            self.emit_event(
+                "intent_partial_payment",
+                &IntentPartialPaymentEvent {
+                    intent_id,
+                    payer_id: &buyer_id,
+                    amount: U128(amount),
+                    remaining: U128(remaining - amount),
+                },
+            );
+            return U128(0);
+        }
+        let (receipt_id, change) = self.internal_purchase(
+            &buyer_id,
+            price,
+            intent.discount,
+            &intent.payments,
+            deposit,
+            use_balance,
+            Some(intent.merchant_id.clone()),
+            None,
+        );
+        intent.payments.clear();
+        intent.status = IntentStatus::Paid;
+        intent.receipt_id = Some(receipt_id);
+        self.intents.replace(intent_id.0, &intent);
+        self.emit_event(
+            "intent_paid",
+            &IntentPaidEvent {
+                intent_id,
+                buyer_id: &buyer_id,
+                receipt_id: U64(receipt_id),
+            },
+        );
+        U128(change)
+    }
+
+    /// Cancels the unpaid intent `intent_id`. Only the merchant that created it can.
+    pub fn cancel_intent(&mut self, intent_id: U64) {
+        self.assert_access("cancel_intent");
+        let mut intent = self
+            .intents
+            .get(intent_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_INTENT));
+        require!(
+            env::predecessor_account_id() == intent.merchant_id,
+            messages::INTENT_MERCHANT_ONLY
+        );
+        require!(
+            intent.status_at(env::block_timestamp()) == IntentStatus::Open,
+            messages::INTENT_NOT_CANCELLABLE
+        );
+        self.internal_close_intent(intent_id.0, &mut intent, IntentStatus::Cancelled);
+    }
+
+    /// Records that the unpaid intent `intent_id` is past its deadline. Callable by anyone.
+    pub fn expire_intent(&mut self, intent_id: U64) {
+        self.assert_access("expire_intent");
+        let mut intent = self
+            .intents
+            .get(intent_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_INTENT));
+        require!(
+            intent.status == IntentStatus::Open,
+            messages::INTENT_NOT_OPEN
+        );
+        require!(
+            env::block_timestamp() > intent.expiry,
+            messages::INTENT_NOT_EXPIRED
+        );
+        self.internal_close_intent(intent_id.0, &mut intent, IntentStatus::Expired);
+    }
+
+    /// Returns the intent `intent_id`.
+    pub fn get_intent(&self, intent_id: U64) -> Option<IntentView> {
+        self.intents
+            .get(intent_id.0)
+            .map(|intent| IntentView::new(intent_id.0, intent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::ONE_NEAR;
+    use crate::coupon::Discount;
+    use crate::dispute::DisputeOutcome;
+    use crate::roles::Role;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    /// A contract with `shop.testnet` registered and an intent of 430 expiring at 1000.
+    fn contract_with_intent() -> (Change, U64) {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(account("shop.testnet"), account("shop-payouts.testnet"), 0);
+        testing_env!(context_for("shop.testnet", 0));
+        let intent_id = contract.create_intent(U128(430), "table 4".to_string(), U64(1000));
+        (contract, intent_id)
+    }
+
+    #[test]
+    fn paying_an_intent_books_the_purchase() {
+        let (mut contract, intent_id) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 500));
+        assert_eq!(70, contract.pay_intent(intent_id, None, None).0);
+        let intent = contract.get_intent(intent_id).unwrap();
+        assert_eq!(IntentStatus::Paid, intent.status);
+        let receipt = contract.get_receipt(intent.receipt_id.unwrap()).unwrap();
+        assert_eq!(Some("shop.testnet".to_string()), receipt.merchant_id);
+        assert_eq!(430, receipt.price.0);
+        let merchant = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(430, merchant.pending.0);
+        assert_eq!(70, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intent is already paid")]
+    fn intents_are_paid_once() {
+        let (mut contract, intent_id) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.pay_intent(intent_id, None, None);
+        contract.pay_intent(intent_id, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intent has expired")]
+    fn expired_intents_cannot_be_paid() {
+        let (mut contract, intent_id) = contract_with_intent();
+        let mut context = context_for("jane.testnet", 500);
+        context.block_timestamp = 1001;
+        testing_env!(context);
+        contract.pay_intent(intent_id, None, None);
+    }
+
+    #[test]
+    fn partial_payments_accumulate_until_the_amount_is_covered() {
+        let (mut contract, intent_id) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 200));
+        assert_eq!(0, contract.pay_intent(intent_id, None, None).0);
+        testing_env!(context_for("jane.testnet", 100));
+        contract.pay_intent(intent_id, None, None);
+        let intent = contract.get_intent(intent_id).unwrap();
+        assert_eq!(IntentStatus::Open, intent.status);
+        assert_eq!(300, intent.paid.0);
+        assert_eq!(130, intent.remaining.0);
+        assert_eq!(
+            0,
+            contract
+                .get_merchant("shop.testnet".to_string())
+                .unwrap()
+                .pending
+                .0
+        );
+
+        testing_env!(context_for("jane.testnet", 150));
+        assert_eq!(20, contract.pay_intent(intent_id, None, None).0);
+        let intent = contract.get_intent(intent_id).unwrap();
+        assert_eq!(IntentStatus::Paid, intent.status);
+        assert_eq!(0, intent.remaining.0);
+        let receipt = contract.get_receipt(intent.receipt_id.unwrap()).unwrap();
+        assert_eq!(430, receipt.price.0);
+        assert_eq!(20, receipt.change.0);
+        assert_eq!(
+            430,
+            contract
+                .get_merchant("shop.testnet".to_string())
+                .unwrap()
+                .pending
+                .0
+        );
+        assert_eq!(20, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn partial_payments_are_returned_when_the_intent_is_cancelled() {
+        let (mut contract, intent_id) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 200));
+        contract.pay_intent(intent_id, None, None);
+        testing_env!(context_for("bob.testnet", 100));
+        contract.pay_intent(intent_id, None, None);
+        testing_env!(context_for("shop.testnet", 0));
+        contract.cancel_intent(intent_id);
+        assert_eq!(200, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(100, contract.get_balance("bob.testnet".to_string()).0);
+        assert_eq!(0, contract.get_intent(intent_id).unwrap().paid.0);
+    }
+
+    #[test]
+    fn every_payer_shares_the_purchase() {
+        let (mut contract, intent_id) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 300));
+        contract.pay_intent(intent_id, None, None);
+        testing_env!(context_for("bob.testnet", 200));
+        assert_eq!(70, contract.pay_intent(intent_id, None, None).0);
+        let receipt_id = contract.get_intent(intent_id).unwrap().receipt_id.unwrap();
+        let receipt = contract.get_receipt(receipt_id).unwrap();
+        let payers: Vec<_> = receipt
+            .payers
+            .iter()
+            .map(|(payer_id, share)| (payer_id.as_str(), share.0))
+            .collect();
+        assert_eq!(vec![("jane.testnet", 300), ("bob.testnet", 130)], payers);
+        assert_eq!(
+            1,
+            contract
+                .get_receipts_by_payer("jane.testnet".to_string(), U64(0), 10)
+                .items
+                .len()
+        );
+
+        // jane didn't make the last payment but can still dispute it
+        testing_env!(context_for("alice.testnet", 0));
+        contract.grant_role(Role::Arbiter, account("arbiter.testnet"));
+        testing_env!(context_for("jane.testnet", 0));
+        let dispute_id = contract.open_dispute(receipt_id, "never delivered".to_string());
+        testing_env!(context_for("alice.testnet", 0));
+        contract.assign_dispute(dispute_id, account("arbiter.testnet"));
+        testing_env!(context_for("arbiter.testnet", 0));
+        contract.resolve_dispute(dispute_id, DisputeOutcome::FullRefund);
+        assert_eq!(300, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(70 + 130, contract.get_balance("bob.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn every_payer_earns_the_points_of_its_share() {
+        let (mut contract, _) = contract_with_intent();
+        contract.set_points_rate("shop.testnet".to_string(), 10, U128(1));
+        let intent_id = contract.create_intent(U128(3 * ONE_NEAR), String::new(), U64(1000));
+        testing_env!(context_for("jane.testnet", 2 * ONE_NEAR));
+        contract.pay_intent(intent_id, None, None);
+        testing_env!(context_for("bob.testnet", ONE_NEAR));
+        contract.pay_intent(intent_id, None, None);
+        let points = |account_id: &str| {
+            contract
+                .get_points(account_id.to_string(), "shop.testnet".to_string())
+                .0
+        };
+        assert_eq!((20, 10), (points("jane.testnet"), points("bob.testnet")));
+    }
+
+    #[test]
+    #[should_panic(expected = "An intent can be paid by at most 10 accounts")]
+    fn intents_take_a_bounded_number_of_payers() {
+        let (mut contract, intent_id) = contract_with_intent();
+        for n in 0..=MAX_SPLITS {
+            testing_env!(context_for(&format!("payer{}.testnet", n), 1));
+            contract.pay_intent(intent_id, None, None);
+        }
+    }
+
+    #[test]
+    fn coupons_are_released_when_the_intent_is_cancelled() {
+        let (mut contract, intent_id) = contract_with_intent();
+        let code_hash = coupon_code_hash("TEN");
+        contract.create_coupon(
+            code_hash.into(),
+            Discount::Percent { bps: 1000 },
+            1,
+            U64(1000),
+        );
+        testing_env!(context_for("jane.testnet", 100));
+        contract.pay_intent(intent_id, None, Some("TEN".to_string()));
+        assert_eq!(
+            0,
+            contract
+                .get_coupon(code_hash.into())
+                .unwrap()
+                .remaining_uses
+        );
+        testing_env!(context_for("shop.testnet", 0));
+        contract.cancel_intent(intent_id);
+        assert_eq!(
+            1,
+            contract
+                .get_coupon(code_hash.into())
+                .unwrap()
+                .remaining_uses
+        );
+        assert_eq!(100, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn open_intents_expire_after_the_deadline() {
+        let (mut contract, intent_id) = contract_with_intent();
+        let mut context = context_for("jane.testnet", 0);
+        context.block_timestamp = 1001;
+        testing_env!(context);
+        assert_eq!(
+            IntentStatus::Expired,
+            contract.get_intent(intent_id).unwrap().status
+        );
+        contract.expire_intent(intent_id);
+        assert_eq!(
+            IntentStatus::Expired,
+            contract.intents.get(intent_id.0).unwrap().status
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Intent was cancelled")]
+    fn cancelled_intents_cannot_be_paid() {
+        let (mut contract, intent_id) = contract_with_intent();
+        contract.cancel_intent(intent_id);
+        assert_eq!(
+            IntentStatus::Cancelled,
+            contract.get_intent(intent_id).unwrap().status
+        );
+        testing_env!(context_for("jane.testnet", 500));
+        contract.pay_intent(intent_id, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the merchant can cancel the intent")]
+    fn only_the_merchant_cancels() {
+        let (mut contract, intent_id) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.cancel_intent(intent_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown merchant")]
+    fn only_merchants_create_intents() {
+        let (mut contract, _) = contract_with_intent();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.create_intent(U128(430), String::new(), U64(1000));
+    }
+}
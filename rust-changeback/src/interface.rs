@@ -0,0 +1,397 @@
+//! The registry of the contract's public methods.
+//!
+//! `METHODS` lists every method with whether it changes state, who may call
+//! it and what deposit it needs. [`get_interface`](Change::get_interface)
+//! returns it so wallets and explorers can tell how to call each method, and
+//! the methods check their callers and deposits through
+//! [`assert_access`](Change::assert_access), which reads the same table, so
+//! the two can't disagree.
+
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen};
+
+use crate::roles::Role;
+use crate::*;
+
+/// Who may call a method.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Access {
+    Anyone,
+    Owner,
+    /// Accounts holding the role, see [`roles`](crate::roles).
+    Role(Role),
+    Trustee,
+    /// The contract itself, resolving its own promises.
+    Contract,
+    /// Accounts the call concerns, such as the merchant or the buyer, which
+    /// the method checks against its arguments.
+    Restricted,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Method {
+    pub name: &'static str,
+    /// Whether the method changes state, and so must be sent as a transaction.
+    pub mutable: bool,
+    pub access: Access,
+    /// Whether the method accepts an attached deposit.
+    pub payable: bool,
+    /// Whether the method requires exactly 1 yoctoNEAR attached, confirming
+    /// the call with a full access key.
+    pub one_yocto: bool,
+    /// Whether the method changes state even while the contract is
+    /// [paused](crate::pause): the controls an emergency or a maintenance
+    /// needs, and the callbacks resolving promises already sent.
+    pub while_paused: bool,
+}
+
+const fn view(name: &'static str) -> Method {
+    Method {
+        name,
+        mutable: false,
+        access: Access::Anyone,
+        payable: false,
+        one_yocto: false,
+        while_paused: false,
+    }
+}
+
+const fn call(name: &'static str, access: Access) -> Method {
+    Method {
+        mutable: true,
+        access,
+        while_paused: matches!(access, Access::Contract),
+        ..view(name)
+    }
+}
+
+impl Method {
+    const fn access(self, access: Access) -> Self {
+        Method { access, ..self }
+    }
+
+    const fn payable(self) -> Self {
+        Method {
+            payable: true,
+            ..self
+        }
+    }
+
+    const fn one_yocto(self) -> Self {
+        Method {
+            one_yocto: true,
+            ..self.payable()
+        }
+    }
+
+    const fn while_paused(self) -> Self {
+        Method {
+            while_paused: true,
+            ..self
+        }
+    }
+}
+
+pub(crate) const METHODS: &[Method] = &[
+    call("new", Access::Anyone).while_paused(),
+    view("get_num_for"),
+    call("add", Access::Anyone).payable(),
+    call("change", Access::Anyone),
+    call("withdraw", Access::Anyone),
+    call("reset", Access::Owner),
+    view("get_trial_balance"),
+    view("get_account_statement"),
+    call("approve_allowance", Access::Anyone),
+    call("revoke_allowance", Access::Anyone),
+    call("change_from", Access::Restricted),
+    view("get_allowance"),
+    call("batch_apply", Access::Anyone),
+    call("set_beneficiary", Access::Anyone),
+    call("claim_inheritance", Access::Restricted),
+    view("get_beneficiary"),
+    call("create_campaign", Access::Owner),
+    view("get_campaign_stats"),
+    view("get_campaign"),
+    call("set_donate_change", Access::Anyone),
+    call("on_change_donated", Access::Contract),
+    view("get_donated"),
+    view("get_donations_leaderboard"),
+    view("get_config"),
+    view("get_pending_config"),
+    call("set_config", Access::Owner),
+    call("set_gas_budgets", Access::Owner),
+    call("set_limits", Access::Owner),
+    view("get_limits"),
+    call("set_decimals", Access::Owner),
+    call("set_rate_limit", Access::Owner),
+    view("get_rate_limit"),
+    call("set_listener", Access::Owner),
+    call("set_voucher_key", Access::Owner),
+    call("cancel_pending_config", Access::Owner),
+    call("create_coupon", Access::Restricted),
+    view("get_coupon"),
+    view("get_all_balances"),
+    view("get_formatted_num_for"),
+    call("open_dispute", Access::Restricted),
+    call("assign_dispute", Access::Owner),
+    call("resolve_dispute", Access::Restricted),
+    view("get_dispute"),
+    call("sweep_dust", Access::Owner),
+    call("set_contract_code", Access::Owner),
+    call("create_merchant_contract", Access::Restricted).payable(),
+    call("on_merchant_contract_created", Access::Contract),
+    view("get_merchant_contract"),
+    call("set_denominations", Access::Owner),
+    view("get_denominations"),
+    view("compute_change"),
+    call("settle_change", Access::Anyone),
+    call("issue_gift_card", Access::Anyone).payable(),
+    call("redeem_gift_card", Access::Anyone),
+    view("get_gift_cards"),
+    view("get_gift_card_liability"),
+    view("get_history"),
+    view("get_records"),
+    call("create_intent", Access::Anyone),
+    call("pay_intent", Access::Anyone).payable(),
+    call("cancel_intent", Access::Restricted),
+    call("expire_intent", Access::Anyone),
+    view("get_intent"),
+    call("rotate_admin_key", Access::Owner).while_paused(),
+    call("cancel_key_rotation", Access::Restricted).while_paused(),
+    call("execute_key_rotation", Access::Anyone).while_paused(),
+    view("get_pending_key_rotation"),
+    call("on_listener_notified", Access::Contract),
+    call("lock_account", Access::Anyone),
+    view("get_locked_until"),
+    call("set_points_rate", Access::Restricted),
+    call("fund_rewards", Access::Anyone).payable(),
+    call("redeem_points", Access::Anyone),
+    view("get_points"),
+    call("register_merchant", Access::Owner),
+    call("set_merchant_dispute_window", Access::Owner),
+    view("get_merchant"),
+    view("get_messages"),
+    call("migrate", Access::Owner).while_paused(),
+    view("get_state_version"),
+    call("set_notification_preferences", Access::Anyone),
+    view("get_notification_preferences"),
+    call("refresh_price", Access::Anyone),
+    call("on_price_fetched", Access::Contract),
+    view("get_price"),
+    view("get_value_usd"),
+    view("get_balance_usd"),
+    view("get_total_usd"),
+    call("transfer_ownership", Access::Owner).while_paused(),
+    view("get_owner"),
+    call("pause", Access::Owner).while_paused(),
+    call("unpause", Access::Owner).while_paused(),
+    view("is_paused"),
+    call("enter_read_only", Access::Role(Role::Guardian)).while_paused(),
+    call("exit_read_only", Access::Role(Role::Guardian)).while_paused(),
+    view("is_read_only"),
+    call("open_pos_session", Access::Anyone),
+    call("close_pos_session", Access::Anyone),
+    view("get_pos_session"),
+    call("purchase", Access::Anyone).payable(),
+    call("on_purchase_forwarded", Access::Contract),
+    view("get_balance"),
+    call("withdraw_change", Access::Anyone),
+    view("get_rebate"),
+    call("claim_rebate", Access::Anyone),
+    view("get_receipt"),
+    view("get_receipts_by_payer"),
+    view("get_merchant_receipts"),
+    call("process_payouts", Access::Anyone),
+    call("on_referral_payout", Access::Contract),
+    view("get_referral_info"),
+    call("set_relay_key", Access::Anyone),
+    view("get_relay_key"),
+    call("relay", Access::Role(Role::Relayer)),
+    view("get_nonce"),
+    call("send_to_instance", Access::Anyone),
+    call("receive_remote_transfer", Access::Role(Role::Instance)),
+    call("on_remote_transfer", Access::Contract),
+    call("grant_role", Access::Owner).while_paused(),
+    call("revoke_role", Access::Owner).while_paused(),
+    view("has_role"),
+    view("get_role_members"),
+    call("set_round_up_unit", Access::Owner),
+    call("set_round_up", Access::Anyone),
+    view("get_savings"),
+    call("withdraw_savings", Access::Anyone).one_yocto(),
+    call("on_savings_withdrawn", Access::Contract),
+    view("get_series"),
+    call("settle", Access::Restricted),
+    call("on_settled", Access::Contract),
+    view("get_settlements"),
+    call("deploy_shards", Access::Owner).payable(),
+    call("on_shard_deployed", Access::Contract),
+    view("get_shard_for"),
+    view("get_shards"),
+    view("simulate_add"),
+    view("simulate_change"),
+    view("simulate_purchase"),
+    call("create_snapshot", Access::Owner),
+    call("rollback_to", Access::Owner).while_paused(),
+    call("continue_rollback", Access::Anyone).while_paused(),
+    view("get_rollback"),
+    view("get_snapshots"),
+    view("get_solvency"),
+    call("purchase_split", Access::Anyone).payable(),
+    view("export_full_state").access(Access::Owner),
+    call("import_state", Access::Owner).while_paused(),
+    view("get_state_hash"),
+    view("get_stats"),
+    view("get_storage_reserve"),
+    call("storage_deposit", Access::Anyone).payable(),
+    call("storage_withdraw", Access::Anyone).one_yocto(),
+    call("storage_unregister", Access::Anyone).one_yocto(),
+    view("storage_balance_bounds"),
+    view("storage_balance_of"),
+    call("set_swap_pool", Access::Owner),
+    call("swap_to_base", Access::Owner),
+    call("on_swap_to_base", Access::Contract),
+    call("on_swap_reverted", Access::Contract),
+    call("withdraw_from_amm", Access::Owner),
+    call("on_amm_withdrawn", Access::Contract),
+    call("schedule", Access::Owner),
+    call("execute", Access::Anyone),
+    call("cancel", Access::Owner),
+    view("get_scheduled_operation"),
+    call("add_token", Access::Owner),
+    call("remove_token", Access::Owner),
+    call("set_token_currency", Access::Owner),
+    view("list_tokens"),
+    view("get_token_balance"),
+    view("get_token_totals"),
+    call("withdraw_token", Access::Anyone).one_yocto(),
+    call("on_token_withdrawn", Access::Contract),
+    // refunds what it is sent while paused
+    call("ft_on_transfer", Access::Anyone).while_paused(),
+    call("fund_treasury", Access::Anyone).payable(),
+    view("get_treasury"),
+    view("get_cold_treasury"),
+    call("withdraw_treasury", Access::Owner),
+    call("on_treasury_withdrawn", Access::Contract),
+    call("init_trustees", Access::Owner),
+    call("propose_action", Access::Trustee).while_paused(),
+    call("confirm_action", Access::Trustee).while_paused(),
+    call("cancel_action", Access::Trustee).while_paused(),
+    view("get_proposal"),
+    view("get_pending_proposals"),
+    view("get_trustees"),
+    call("resume_withdrawals", Access::Owner),
+    view("get_outflow_velocity"),
+    call("redeem_voucher", Access::Anyone),
+    view("is_voucher_redeemed"),
+    call("set_merchant_webhook", Access::Restricted),
+    view("get_interface"),
+];
+
+/// Returns the registry entry of method `name`.
+fn method(name: &str) -> &'static Method {
+    METHODS
+        .iter()
+        .find(|method| method.name == name)
+        .unwrap_or_else(|| fail!(messages::UNREGISTERED_METHOD, name))
+}
+
+impl Change {
+    /// Panics unless the predecessor may call method `name` with the deposit
+    /// attached, as listed in `METHODS`, and, for a method changing state,
+    /// unless the contract is unpaused or the method runs while paused.
+    /// Accounts a `Restricted` method concerns are left to the method to check.
+    pub(crate) fn assert_access(&self, name: &str) {
+        let method = method(name);
+        if method.mutable && !method.while_paused {
+            self.assert_not_paused();
+        }
+        let caller = env::predecessor_account_id();
+        match method.access {
+            Access::Anyone | Access::Restricted => {}
+            Access::Owner => self.assert_owner(),
+            Access::Contract => self.assert_callback(),
+            Access::Role(role) => require!(
+                self.internal_has_role(role, &caller),
+                missing_role_message(role)
+            ),
+            Access::Trustee => require!(self.trustees.contains(&caller), messages::TRUSTEE_ONLY),
+        }
+        if method.one_yocto {
+            require!(env::attached_deposit() == 1, messages::ONE_YOCTO_REQUIRED);
+        }
+    }
+}
+
+fn missing_role_message(role: Role) -> Message {
+    match role {
+        Role::Arbiter => messages::ARBITER_ONLY,
+        Role::Relayer => messages::RELAYER_ONLY,
+        Role::Guardian => messages::GUARDIAN_ONLY,
+        Role::Instance => messages::INSTANCE_ONLY,
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns every public method with whether it changes state, who may
+    /// call it and the deposit it needs.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_interface
+    /// ```
+    pub fn get_interface(&self) -> Vec<Method> {
+        METHODS.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::collections::HashSet;
+
+    #[test]
+    fn methods_are_registered_once() {
+        let mut names = HashSet::new();
+        assert!(METHODS.iter().all(|method| names.insert(method.name)));
+        assert!(METHODS
+            .iter()
+            .all(|method| method.payable || !method.one_yocto));
+        assert!(METHODS
+            .iter()
+            .all(|method| method.mutable || !method.payable));
+    }
+
+    #[test]
+    fn interface_describes_the_methods() {
+        testing_env!(context_for("jane.testnet", 0));
+        let contract = Change::default();
+        let interface = contract.get_interface();
+        let find = |name| *interface.iter().find(|method| method.name == name).unwrap();
+        assert_eq!(call("reset", Access::Owner), find("reset"));
+        assert_eq!(call("add", Access::Anyone).payable(), find("add"));
+        assert_eq!(view("get_num_for"), find("get_num_for"));
+        assert!(find("withdraw_savings").one_yocto);
+        assert_eq!(Access::Role(Role::Relayer), find("relay").access);
+        assert_eq!(Access::Contract, find("on_price_fetched").access);
+        assert_eq!(
+            r#"{"name":"relay","mutable":true,"access":{"Role":"Relayer"},"payable":false,"one_yocto":false,"while_paused":false}"#,
+            near_sdk::serde_json::to_string(&find("relay")).unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only trustees can call this method")]
+    fn access_follows_the_registry() {
+        testing_env!(context_for("alice.testnet", 0));
+        let contract = Change::default();
+        contract.assert_access("get_num_for");
+        contract.assert_access("reset");
+        contract.assert_access("propose_action");
+    }
+}
@@ -0,0 +1,96 @@
+//! Interned account ids, so records that name accounts over and over don't
+//! repeat their full ids.
+//!
+//! The first time a receipt, a campaign or the [history](crate::history)
+//! names an account it gets the next compact `u32` id. Receipts store those
+//! ids for their buyer and merchant, the payer and merchant receipt indexes
+//! are keyed by them, history records name accounts by them and campaigns flag
+//! their participants by them in a [bitmap](crate::bitmap); views turn them
+//! back into account ids. Ids are never reused or reassigned.
+
+use crate::*;
+
+impl Change {
+    /// Returns the compact id of `account_id`, assigning the next one on first use.
+    pub(crate) fn internal_intern(&mut self, account_id: &AccountId) -> u32 {
+        if let Some(id) = self.interned_ids.get(account_id) {
+            return id;
+        }
+        let id = u32::try_from(self.interned_accounts.len())
+            .unwrap_or_else(|_| fail!(messages::TOO_MANY_INTERNED_ACCOUNTS));
+        self.interned_accounts.push(account_id);
+        self.interned_ids.insert(account_id, &id);
+        id
+    }
+
+    /// Returns the compact id of `account_id`, if it was ever interned.
+    pub(crate) fn internal_interned_id(&self, account_id: &AccountId) -> Option<u32> {
+        self.interned_ids.get(account_id)
+    }
+
+    /// Returns the account the compact `id` was assigned to.
+    pub(crate) fn internal_account_id(&self, id: u32) -> AccountId {
+        self.interned_accounts
+            .get(u64::from(id))
+            .expect("Unknown interned account")
+    }
+
+    /// Appends `account_ids` to the interned accounts, which must hold exactly
+    /// `from_index` so far, keeping the ids they had where they were exported.
+    pub(crate) fn internal_import_interned(
+        &mut self,
+        from_index: u64,
+        account_ids: Vec<AccountId>,
+    ) {
+        require!(
+            from_index == self.interned_accounts.len(),
+            messages::CHUNK_OUT_OF_ORDER
+        );
+        for account_id in account_ids {
+            self.internal_intern(&account_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{U128, U64};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn accounts_are_interned_once() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let jane = "jane.testnet".to_string();
+        let bob = "bob.testnet".to_string();
+        assert_eq!(None, contract.internal_interned_id(&jane));
+        assert_eq!(0, contract.internal_intern(&jane));
+        assert_eq!(1, contract.internal_intern(&bob));
+        assert_eq!(0, contract.internal_intern(&jane));
+        assert_eq!(Some(1), contract.internal_interned_id(&bob));
+        assert_eq!(bob, contract.internal_account_id(1));
+    }
+
+    #[test]
+    fn receipts_store_interned_accounts() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        testing_env!(context_for("jane.testnet", 100));
+        contract.purchase(U128(30), None, None, None, None, None);
+        contract.purchase(U128(30), None, None, None, None, None);
+        assert_eq!(1, contract.interned_accounts.len());
+        assert_eq!(0, contract.receipts.get(1).unwrap().buyer);
+        let receipt = contract.get_receipt(U64(1)).unwrap();
+        assert_eq!("jane.testnet", receipt.buyer_id);
+    }
+}
@@ -0,0 +1,203 @@
+//! Rotating the contract account's full-access key behind a delay.
+//!
+//! The owner asks for a rotation from the key being retired; once
+//! `key_rotation_delay` has passed without the owner or a trustee cancelling
+//! it, anyone can execute it, which adds the new key to the contract account
+//! and deletes the old one in a single batch. The delay leaves time to notice
+//! a rotation requested with a leaked key.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, Promise, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct KeyRotation {
+    pub old_public_key: Base58PublicKey,
+    pub new_public_key: Base58PublicKey,
+    pub requested_at: Timestamp,
+    /// Time from which the rotation can be executed.
+    pub executable_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeyRotationView {
+    pub old_public_key: Base58PublicKey,
+    pub new_public_key: Base58PublicKey,
+    pub requested_at: U64,
+    pub executable_at: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct KeyRotationEvent<'a> {
+    old_public_key: &'a Base58PublicKey,
+    new_public_key: &'a Base58PublicKey,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    executable_at: Option<U64>,
+}
+
+impl Change {
+    fn internal_emit_key_rotation(&self, event: &str, rotation: &KeyRotation, with_time: bool) {
+        self.emit_event(
+            event,
+            &KeyRotationEvent {
+                old_public_key: &rotation.old_public_key,
+                new_public_key: &rotation.new_public_key,
+                executable_at: if with_time {
+                    Some(U64(rotation.executable_at))
+                } else {
+                    None
+                },
+            },
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Schedules replacing the full-access key signing this call with `new_public_key`.
+    /// Owner only; a pending rotation has to be executed or cancelled first.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet rotate_admin_key '{"new_public_key": "ed25519:3tysLvy7KGoE8pznUgXvSHa4vYyGvrDZFcT8jgb8PEQ6"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn rotate_admin_key(&mut self, new_public_key: Base58PublicKey) {
+        self.assert_access("rotate_admin_key");
+        require!(
+            self.pending_key_rotation.is_none(),
+            messages::KEY_ROTATION_PENDING
+        );
+        let old_public_key = Base58PublicKey(env::signer_account_pk());
+        require!(
+            old_public_key != new_public_key,
+            messages::NEW_KEY_IS_SIGNING_KEY
+        );
+        let now = env::block_timestamp();
+        let rotation = KeyRotation {
+            old_public_key,
+            new_public_key,
+            requested_at: now,
+            executable_at: now + self.config().key_rotation_delay.0,
+        };
+        self.internal_emit_key_rotation("key_rotation_requested", &rotation, true);
+        self.pending_key_rotation = Some(rotation);
+    }
+
+    /// Drops the pending key rotation. Callable by the owner or any trustee.
+    pub fn cancel_key_rotation(&mut self) {
+        self.assert_access("cancel_key_rotation");
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == self.owner_id || self.trustees.contains(&caller),
+            messages::KEY_ROTATION_CANCELLER_ONLY
+        );
+        let rotation = self
+            .pending_key_rotation
+            .take()
+            .unwrap_or_else(|| fail!(messages::NO_KEY_ROTATION_PENDING));
+        self.internal_emit_key_rotation("key_rotation_cancelled", &rotation, false);
+    }
+
+    /// Adds the new key to the contract account and deletes the old one, once the
+    /// delay has passed. Anyone can call this.
+    pub fn execute_key_rotation(&mut self) -> Promise {
+        self.assert_access("execute_key_rotation");
+        let rotation = self
+            .pending_key_rotation
+            .take()
+            .unwrap_or_else(|| fail!(messages::NO_KEY_ROTATION_PENDING));
+        require!(
+            env::block_timestamp() >= rotation.executable_at,
+            messages::KEY_ROTATION_DELAYED
+        );
+        self.internal_emit_key_rotation("key_rotation_executed", &rotation, false);
+        Promise::new(env::current_account_id())
+            .add_full_access_key(rotation.new_public_key.into())
+            .delete_key(rotation.old_public_key.into())
+    }
+
+    pub fn get_pending_key_rotation(&self) -> Option<KeyRotationView> {
+        self.pending_key_rotation
+            .as_ref()
+            .map(|rotation| KeyRotationView {
+                old_public_key: rotation.old_public_key.clone(),
+                new_public_key: rotation.new_public_key.clone(),
+                requested_at: U64(rotation.requested_at),
+                executable_at: U64(rotation.executable_at),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DAY;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, VMContext};
+    use std::convert::TryFrom;
+
+    fn key(byte: u8) -> Base58PublicKey {
+        let mut bytes = vec![byte; 33];
+        // ed25519
+        bytes[0] = 0;
+        Base58PublicKey(bytes)
+    }
+
+    fn signed_at(predecessor: &str, timestamp: u64) -> VMContext {
+        let mut context = context_for(predecessor, 0);
+        context.signer_account_pk = key(1).0;
+        context.block_timestamp = timestamp;
+        context
+    }
+
+    fn rotating_contract() -> Change {
+        testing_env!(signed_at("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.rotate_admin_key(key(2));
+        contract
+    }
+
+    #[test]
+    fn rotation_runs_after_the_delay() {
+        let mut contract = rotating_contract();
+        let rotation = contract.get_pending_key_rotation().unwrap();
+        assert_eq!(key(1), rotation.old_public_key);
+        assert_eq!(2 * DAY, rotation.executable_at.0);
+        testing_env!(signed_at("jane.testnet", 2 * DAY));
+        contract.execute_key_rotation();
+        assert!(contract.get_pending_key_rotation().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Key rotation delay hasn't passed yet")]
+    fn rotation_waits_for_the_delay() {
+        let mut contract = rotating_contract();
+        testing_env!(signed_at("jane.testnet", 2 * DAY - 1));
+        contract.execute_key_rotation();
+    }
+
+    #[test]
+    #[should_panic(expected = "No key rotation pending")]
+    fn trustees_can_cancel_a_rotation() {
+        let mut contract = rotating_contract();
+        contract.init_trustees(vec![ValidAccountId::try_from("t1.testnet").unwrap()], 1);
+        testing_env!(signed_at("t1.testnet", DAY));
+        contract.cancel_key_rotation();
+        testing_env!(signed_at("jane.testnet", 2 * DAY));
+        contract.execute_key_rotation();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a trustee can cancel a key rotation")]
+    fn strangers_cannot_cancel_a_rotation() {
+        let mut contract = rotating_contract();
+        testing_env!(signed_at("jane.testnet", DAY));
+        contract.cancel_key_rotation();
+    }
+}
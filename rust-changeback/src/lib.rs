@@ -1,45 +1,455 @@
 //! This contract implements simple Change backed by storage on blockchain.
 //!
-//! The contract provides methods to [add] / [change] Change and
-//! [get it's current value][get_num] or [reset].
+//! Every account keeps its own Change: the contract provides methods to
+//! [add] / [change] the caller's Change and [get an account's current
+//! value][get_num_for]; the owner can [reset] it.
 //!
 //! [add]: struct.Change.html#method.add
 //! [change]: struct.Change.html#method.change
-//! [get_num]: struct.Change.html#method.get_num
+//! [get_num_for]: struct.Change.html#method.get_num_for
 //! [reset]: struct.Change.html#method.reset
 
+use std::cell::Cell;
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{env, near_bindgen};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::{Base58PublicKey, ValidAccountId, I128, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen, AccountId, Balance, BorshStorageKey, CryptoHash, Promise};
+
+mod account;
+mod accounting;
+mod allowance;
+mod amount;
+mod batch;
+mod beneficiary;
+mod bitmap;
+mod callback;
+mod campaign;
+mod charity;
+mod config;
+mod coupon;
+mod currency;
+mod display;
+mod dispute;
+mod dust;
+mod ed25519;
+mod events;
+mod external;
+mod factory;
+mod float;
+mod gift_card;
+mod history;
+mod idempotency;
+mod intent;
+mod interface;
+mod interning;
+mod key_rotation;
+mod listener;
+mod lock;
+mod loyalty;
+mod merchant;
+mod messages;
+mod migration;
+mod notification;
+mod oracle;
+mod outcome;
+mod ownership;
+mod pagination;
+mod pause;
+mod pos;
+mod purchase;
+mod rebate;
+mod receipt;
+mod referral;
+mod relay;
+mod remote_transfer;
+mod roles;
+mod savings;
+mod series;
+mod settlement;
+mod sharding;
+mod simulate;
+mod snapshot;
+mod solvency;
+mod split;
+mod state_export;
+mod state_hash;
+mod stats;
+mod storage;
+mod storage_management;
+mod swap;
+#[cfg(test)]
+mod test_utils;
+mod throttle;
+mod timelock;
+mod token;
+mod treasury;
+mod trustee;
+mod velocity;
+mod voucher;
+mod webhook;
+
+use crate::account::VersionedAccount;
+use crate::accounting::{Book, Books, JournalEntry, Posting, StatementLine};
+use crate::bitmap::Bitmap;
+use crate::callback::{promise_result, promise_succeeded};
+use crate::campaign::Campaign;
+pub use crate::config::{Config, ScheduledConfig};
+use crate::coupon::Coupon;
+use crate::currency::{currency_symbol, DEFAULT_CURRENCY};
+use crate::dispute::Dispute;
+use crate::events::{EventBuffer, LogLevel};
+use crate::external::{ext_amm, ext_ft, ext_instance, ext_listener, ext_oracle, ext_self};
+use crate::float::Denomination;
+use crate::gift_card::GiftCard;
+use crate::history::{ChangeRecord, CounterOperation};
+use crate::idempotency::{IdempotencyRecord, IdempotentOutcome};
+use crate::intent::Intent;
+use crate::key_rotation::KeyRotation;
+use crate::loyalty::PointsBatch;
+use crate::merchant::Merchant;
+use crate::messages::{fail, require, Message};
+use crate::notification::NotificationCategory;
+use crate::oracle::CachedPrice;
+use crate::outcome::OperationOutcome;
+use crate::pagination::{paginate, Page};
+use crate::pos::PosSession;
+use crate::receipt::Receipt;
+use crate::roles::Role;
+use crate::series::Checkpoint;
+use crate::settlement::Settlement;
+use crate::sharding::Shard;
+use crate::snapshot::{Rollback, Snapshot};
+use crate::stats::UsageStats;
+use crate::throttle::CallWindow;
+use crate::timelock::ScheduledOperation;
+use crate::token::TokenInfo;
+use crate::trustee::TrusteeProposal;
+use crate::velocity::OutflowVelocity;
 
 near_sdk::setup_alloc!();
 
+/// Prefixes of the persistent collections kept in contract state.
+#[derive(BorshStorageKey, BorshSerialize)]
+pub(crate) enum StorageKey {
+    Accounts,
+    Ledger,
+    AccountLedger { account_hash: Vec<u8> },
+    PayoutQueue,
+    Tokens,
+    TokenBalances,
+    Prices,
+    Savings,
+    Merchants,
+    Settlements,
+    Receipts,
+    PosSessions,
+    OpenPosSessions,
+    Intents,
+    Roles,
+    Disputes,
+    ReceiptDisputes,
+    MaturingRevenue { merchant_hash: Vec<u8> },
+    Points,
+    GiftCards,
+    RedeemedGiftCards,
+    Coupons,
+    Campaigns,
+    ActiveCampaigns,
+    CampaignParticipants,
+    CampaignParticipantBits { campaign_id: u64 },
+    Trustees,
+    TrusteeProposals,
+    IdempotencyKeys,
+    Nonces,
+    ReceiptsByPayer,
+    PayerReceipts { payer: u32 },
+    ReceiptsByMerchant,
+    MerchantReceipts { merchant: u32 },
+    InternedIds,
+    InternedAccounts,
+    History,
+    Snapshots,
+    Journal,
+    BookStatements,
+    BookStatement { book: Book },
+    Shards,
+    ContractCode,
+    CallWindows,
+    Allowances,
+    MerchantContracts,
+    Timelock,
+    RedeemedVouchers,
+    Checkpoints,
+    CurrencyCheckpoints { currency: String },
+    StatsCallers,
+    StorageDeposits,
+    RelayKeys,
+    PendingProposals,
+    SnapshotValues { snapshot_id: u64 },
+    SnapshotKeys { snapshot_id: u64 },
+    ReceiptPayers,
+}
+
 // add the following attributes to prepare your code for serialization and invocation on the blockchain
 // More built-in Rust attributes here: https://doc.rust-lang.org/reference/attributes.html#built-in-attributes-index
 #[near_bindgen]
-#[derive(Default, BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize)]
 pub struct Change {
     // See more data types at https://doc.rust-lang.org/book/ch03-02-data-types.html
-    val: i32, // i32 is signed. unsigned integers are also available: u8, i32, u32, u64, u128
+    /// Change of each account by currency, see [`currency`](crate::currency);
+    /// i128 is signed, unsigned integers are also available: u8, u32, u64, u128
+    ledger: LookupMap<AccountId, UnorderedMap<String, i128>>,
+    /// Every move of the ledger, oldest first, see [`history`](crate::history).
+    history: Vector<ChangeRecord>,
+    /// Points of the history the ledger can be rolled back to, see [`snapshot`](crate::snapshot).
+    snapshots: UnorderedMap<u64, Snapshot>,
+    /// Rollback restoring the ledger, see [`snapshot`](crate::snapshot).
+    rollback: Option<Rollback>,
+    /// Net change per minute of each currency, see [`series`](crate::series).
+    checkpoints: LookupMap<String, Vector<Checkpoint>>,
+    /// Usage counters, see [`stats`](crate::stats).
+    stats: UsageStats,
+    /// Interned ids of every account that moved a counter.
+    stats_callers: Bitmap,
+    /// Account allowed to call the administrative methods.
+    owner_id: AccountId,
+    config: Config,
+    /// Configuration waiting to take over, see [`config`](crate::config).
+    scheduled_config: Option<ScheduledConfig>,
+    /// What each spender may take from an account's Change, keyed by
+    /// `(owner, spender, currency)`, see [`allowance`](crate::allowance).
+    allowances: LookupMap<(AccountId, AccountId, String), Balance>,
+    /// Per-account bookkeeping, see [`account`](crate::account).
+    accounts: LookupMap<AccountId, VersionedAccount>,
+    /// Storage deposit of each registered account, see
+    /// [`storage_management`](crate::storage_management).
+    storage_deposits: LookupMap<AccountId, Balance>,
+    /// NEAR held for each party, in yoctoNEAR, see [`accounting`](crate::accounting).
+    books: Books,
+    /// Every posting to the books, indexed by entry id.
+    journal: Vector<JournalEntry>,
+    /// Movements of each book, oldest first.
+    book_statements: LookupMap<Book, Vector<StatementLine>>,
+    /// Referrers whose bonus reached the payout threshold, oldest first.
+    payout_queue: Vector<AccountId>,
+    /// Index in `payout_queue` of the next referrer to pay; earlier entries were paid.
+    payout_cursor: u64,
+    /// Whitelisted NEP-141 tokens accepted by `ft_on_transfer`.
+    tokens: UnorderedMap<AccountId, TokenInfo>,
+    /// Token balances keyed by `(account, token)`.
+    token_balances: LookupMap<(AccountId, AccountId), Balance>,
+    /// Last price fetched from the oracle for each token.
+    prices: LookupMap<AccountId, CachedPrice>,
+    /// Round-up savings keyed by `(account, token)`, kept apart from spendable balances.
+    savings: LookupMap<(AccountId, AccountId), Balance>,
+    /// Merchants selling through this contract, keyed by their account.
+    merchants: UnorderedMap<AccountId, Merchant>,
+    /// Contract created for each merchant, see [`factory`](crate::factory).
+    merchant_contracts: LookupMap<AccountId, AccountId>,
+    /// Every merchant settlement, in the order they were made.
+    settlements: Vector<Settlement>,
+    /// One receipt per purchase, indexed by receipt id.
+    receipts: Vector<Receipt>,
+    /// Ids of the receipts paid by each interned account, oldest first.
+    receipts_by_payer: LookupMap<u32, Vector<u64>>,
+    /// Interned payers and their shares of the price of each receipt paid by
+    /// several accounts, see [`intent`](crate::intent).
+    receipt_payers: LookupMap<u64, Vec<(u32, Balance)>>,
+    /// Ids of the receipts booked to each interned merchant, oldest first.
+    receipts_by_merchant: LookupMap<u32, Vector<u64>>,
+    /// Compact id of every interned account, see [`interning`](crate::interning).
+    interned_ids: LookupMap<AccountId, u32>,
+    /// Interned accounts, indexed by their compact id.
+    interned_accounts: Vector<AccountId>,
+    /// Point-of-sale sessions, indexed by session id.
+    pos_sessions: Vector<PosSession>,
+    /// Open session id of each cashier.
+    open_pos_sessions: LookupMap<AccountId, u64>,
+    /// Payment intents created by merchants, indexed by intent id.
+    intents: Vector<Intent>,
+    /// Roles granted by the owner, as `(role, account)` pairs.
+    roles: UnorderedSet<(Role, AccountId)>,
+    /// Purchase disputes, indexed by dispute id.
+    disputes: Vector<Dispute>,
+    /// Dispute id of each disputed receipt.
+    receipt_disputes: LookupMap<u64, u64>,
+    /// Loyalty points keyed by `(customer, merchant)`, in batches by award day.
+    points: LookupMap<(AccountId, AccountId), Vec<PointsBatch>>,
+    /// Outstanding gift cards keyed by the sha256 hash of their code.
+    gift_cards: UnorderedMap<CryptoHash, GiftCard>,
+    /// Code hashes of redeemed gift cards, which can't be issued again.
+    redeemed_gift_cards: LookupSet<CryptoHash>,
+    /// Nonces of redeemed vouchers, see [`voucher`](crate::voucher).
+    redeemed_vouchers: Bitmap,
+    /// Discount coupons keyed by the sha256 hash of their code.
+    coupons: LookupMap<CryptoHash, Coupon>,
+    /// Promotional campaigns, indexed by campaign id.
+    campaigns: Vector<Campaign>,
+    /// Ids of the campaigns that haven't ended or been used up yet.
+    active_campaigns: UnorderedSet<u64>,
+    /// Interned ids of each campaign's buyers, to count its distinct participants.
+    campaign_participants: LookupMap<u64, Bitmap>,
+    /// Accounts approving large treasury withdrawals, see [`trustee`](crate::trustee).
+    trustees: UnorderedSet<AccountId>,
+    /// Trustee approvals a proposal needs to run.
+    trustee_threshold: u32,
+    /// Trustee proposals, indexed by proposal id.
+    trustee_proposals: Vector<TrusteeProposal>,
+    /// Ids of the trustee proposals not executed yet.
+    pending_proposals: UnorderedSet<u64>,
+    /// Hourly NEAR outflow, see [`velocity`](crate::velocity).
+    outflow_velocity: OutflowVelocity,
+    /// Day, counted from the epoch, and how much the owner withdrew from the
+    /// treasury in it, see [`treasury`](crate::treasury).
+    owner_withdrawals: (u64, Balance),
+    /// Set when an outflow anomaly paused every outbound transfer.
+    withdrawals_paused: bool,
+    /// Set by the owner to refuse every balance-moving call, see [`pause`](crate::pause).
+    paused: bool,
+    /// Set by a guardian to refuse every call but withdrawals, see [`pause`](crate::pause).
+    read_only: bool,
+    /// Operations waiting out their timelock, by id, see [`timelock`](crate::timelock).
+    timelock: UnorderedMap<u64, ScheduledOperation>,
+    /// Id the next scheduled operation gets.
+    next_timelock_id: u64,
+    /// Full-access key replacement waiting out its delay, see [`key_rotation`](crate::key_rotation).
+    pending_key_rotation: Option<KeyRotation>,
+    /// Outcomes of calls made with an idempotency key, per caller and key,
+    /// see [`idempotency`](crate::idempotency).
+    idempotency_keys: LookupMap<(AccountId, String), IdempotencyRecord>,
+    /// Current rate limit window of each account, see [`throttle`](crate::throttle).
+    call_windows: LookupMap<AccountId, CallWindow>,
+    /// Last nonce used by each account for relayed operations.
+    nonces: LookupMap<AccountId, u64>,
+    /// Key each account signs its relayed operations with, see [`relay`](crate::relay).
+    relay_keys: LookupMap<AccountId, Base58PublicKey>,
+    /// Biggest donors of change with their totals, biggest first.
+    donation_leaderboard: Vec<(AccountId, Balance)>,
+    /// Cash denominations of the float, largest first, see [`float`](crate::float).
+    denominations: Vec<Denomination>,
+    /// Shard contracts the ledger is spread over, see [`sharding`](crate::sharding).
+    shards: Vector<Shard>,
+    /// Events emitted by the current call, see [`outcome`](crate::outcome); not stored.
+    #[borsh_skip]
+    events_emitted: Cell<u32>,
+}
+
+// when the contract is deployed without calling `new`, the first call lands here
+// and the contract account itself is the owner until it hands ownership over.
+impl Default for Change {
+    fn default() -> Self {
+        Self {
+            ledger: LookupMap::new(StorageKey::Ledger),
+            history: Vector::new(StorageKey::History),
+            snapshots: UnorderedMap::new(StorageKey::Snapshots),
+            rollback: None,
+            checkpoints: LookupMap::new(StorageKey::Checkpoints),
+            stats: UsageStats::default(),
+            stats_callers: Bitmap::new(StorageKey::StatsCallers),
+            owner_id: env::current_account_id(),
+            config: Config::default(),
+            scheduled_config: None,
+            allowances: LookupMap::new(StorageKey::Allowances),
+            accounts: LookupMap::new(StorageKey::Accounts),
+            storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
+            books: Books::default(),
+            journal: Vector::new(StorageKey::Journal),
+            book_statements: LookupMap::new(StorageKey::BookStatements),
+            payout_queue: Vector::new(StorageKey::PayoutQueue),
+            payout_cursor: 0,
+            tokens: UnorderedMap::new(StorageKey::Tokens),
+            token_balances: LookupMap::new(StorageKey::TokenBalances),
+            prices: LookupMap::new(StorageKey::Prices),
+            savings: LookupMap::new(StorageKey::Savings),
+            merchants: UnorderedMap::new(StorageKey::Merchants),
+            merchant_contracts: LookupMap::new(StorageKey::MerchantContracts),
+            settlements: Vector::new(StorageKey::Settlements),
+            receipts: Vector::new(StorageKey::Receipts),
+            receipts_by_payer: LookupMap::new(StorageKey::ReceiptsByPayer),
+            receipt_payers: LookupMap::new(StorageKey::ReceiptPayers),
+            receipts_by_merchant: LookupMap::new(StorageKey::ReceiptsByMerchant),
+            interned_ids: LookupMap::new(StorageKey::InternedIds),
+            interned_accounts: Vector::new(StorageKey::InternedAccounts),
+            pos_sessions: Vector::new(StorageKey::PosSessions),
+            open_pos_sessions: LookupMap::new(StorageKey::OpenPosSessions),
+            intents: Vector::new(StorageKey::Intents),
+            roles: UnorderedSet::new(StorageKey::Roles),
+            disputes: Vector::new(StorageKey::Disputes),
+            receipt_disputes: LookupMap::new(StorageKey::ReceiptDisputes),
+            points: LookupMap::new(StorageKey::Points),
+            gift_cards: UnorderedMap::new(StorageKey::GiftCards),
+            redeemed_gift_cards: LookupSet::new(StorageKey::RedeemedGiftCards),
+            redeemed_vouchers: Bitmap::new(StorageKey::RedeemedVouchers),
+            coupons: LookupMap::new(StorageKey::Coupons),
+            campaigns: Vector::new(StorageKey::Campaigns),
+            active_campaigns: UnorderedSet::new(StorageKey::ActiveCampaigns),
+            campaign_participants: LookupMap::new(StorageKey::CampaignParticipants),
+            trustees: UnorderedSet::new(StorageKey::Trustees),
+            trustee_threshold: 0,
+            trustee_proposals: Vector::new(StorageKey::TrusteeProposals),
+            pending_proposals: UnorderedSet::new(StorageKey::PendingProposals),
+            outflow_velocity: OutflowVelocity::default(),
+            owner_withdrawals: (0, 0),
+            withdrawals_paused: false,
+            paused: false,
+            read_only: false,
+            timelock: UnorderedMap::new(StorageKey::Timelock),
+            next_timelock_id: 0,
+            pending_key_rotation: None,
+            idempotency_keys: LookupMap::new(StorageKey::IdempotencyKeys),
+            call_windows: LookupMap::new(StorageKey::CallWindows),
+            nonces: LookupMap::new(StorageKey::Nonces),
+            relay_keys: LookupMap::new(StorageKey::RelayKeys),
+            donation_leaderboard: Vec::new(),
+            denominations: Vec::new(),
+            shards: Vector::new(StorageKey::Shards),
+            events_emitted: Cell::new(0),
+        }
+    }
 }
 
 #[near_bindgen]
 impl Change {
-    /// Returns 32-bit signed integer of the Change value.
+    /// Initializes the contract with `owner_id` as its owner, see [`ownership`](crate::ownership).
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet new '{"owner_id": "YOU.testnet"}' --accountId Change.YOU.testnet
+    /// ```
+    #[init]
+    pub fn new(owner_id: ValidAccountId) -> Self {
+        require!(!env::state_exists(), messages::ALREADY_INITIALIZED);
+        migration::internal_write_state_version();
+        Self {
+            owner_id: owner_id.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the Change value of `account_id` in `currency`, a 128-bit signed integer.
     ///
-    /// This must match the type from our struct's 'val' defined above.
+    /// This must match the type of the values in our struct's `ledger` defined above.
     ///
     /// Note, the parameter is `&self` (without being mutable) meaning it doesn't modify state.
     /// In the frontend (/src/main.js) this is added to the "viewMethods" array
     /// using near-cli we can call this by:
     ///
     /// ```bash
-    /// near view Change.YOU.testnet get_num
+    /// near view Change.YOU.testnet get_num_for '{"account_id": "donation.YOU.testnet"}'
     /// ```
-    pub fn get_num(&self) -> i32 {
-        return self.val;
+    ///
+    /// `currency` picks the currency, `"NEAR"` when omitted, see [`currency`](crate::currency):
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_num_for '{"account_id": "donation.YOU.testnet", "currency": "USDC"}'
+    /// ```
+    pub fn get_num_for(&self, account_id: AccountId, currency: Option<String>) -> I128 {
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        I128(self.internal_get_num(&account_id, &currency))
     }
 
-    /// add the Change.
+    /// add to the caller's Change.
     ///
     /// Note, the parameter is "&mut self" as this function modifies state.
     /// In the frontend (/src/main.js) this is added to the "changeMethods" array
@@ -48,18 +458,90 @@ impl Change {
     /// ```bash
     /// near call Change.YOU.testnet add --accountId donation.YOU.testnet
     /// ```
-    pub fn add(&mut self) {
-        // note: adding one like this is an easy way to accidentally overflow
-        // real smart contracts will want to have safety checks
-        // e.g. self.val = i32::wrapping_add(self.val, 1);
-        // https://doc.rust-lang.org/std/primitive.i32.html#method.wrapping_add
-        self.val += 1000;
-        let log_message = format!("Added money to {}", self.val);
-        env::log(log_message.as_bytes());
-        after_counter_change();
+    ///
+    /// An optional `referrer` links the caller to the account that brought them in,
+    /// see [`referral`](crate::referral):
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet add '{"referrer": "friend.testnet"}' --accountId donation.YOU.testnet
+    /// ```
+    ///
+    /// `amount` sets how much is added, the configured `add_step` (1000 unless changed)
+    /// when omitted:
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet add '{"amount": "250"}' --accountId donation.YOU.testnet
+    /// ```
+    ///
+    /// and `currency` which Change it goes to, `"NEAR"` when omitted:
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet add '{"amount": "250", "currency": "USDC"}' --accountId donation.YOU.testnet
+    /// ```
+    ///
+    /// NEAR attached to the call backs the Change: it moves by the deposit, in
+    /// yoctoNEAR, and the deposit is credited to the caller's stored change until
+    /// [`withdraw`](Change::withdraw) sends it back. `amount`, if given, must match
+    /// the deposit, and `currency` be `"NEAR"`:
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet add --accountId donation.YOU.testnet --deposit 1
+    /// ```
+    ///
+    /// Returns the caller's new value, see [`outcome`](crate::outcome). A retry with the
+    /// same `idempotency_key` refunds its deposit and returns the outcome of the first call
+    /// instead of adding again, see [`idempotency`](crate::idempotency).
+    #[payable]
+    pub fn add(
+        &mut self,
+        referrer: Option<AccountId>,
+        idempotency_key: Option<String>,
+        amount: Option<U128>,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_access("add");
+        self.assert_writable();
+        let deposit = env::attached_deposit();
+        let amount = match amount {
+            _ if deposit == 0 => amount.unwrap_or(self.config().add_step),
+            Some(amount) => {
+                require!(amount.0 == deposit, messages::AMOUNT_NOT_DEPOSIT, deposit);
+                amount
+            }
+            None => U128(deposit),
+        };
+        let amount = counter_amount(amount).unwrap_or_else(|error| fail!(error));
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        require!(
+            deposit == 0 || currency == DEFAULT_CURRENCY,
+            messages::DEPOSIT_NOT_NEAR
+        );
+        if let Some(IdempotentOutcome::Counter { val, record_id }) =
+            self.internal_replay(&idempotency_key, "add")
+        {
+            if deposit > 0 {
+                self.internal_transfer(env::predecessor_account_id(), deposit);
+            }
+            return self.internal_replayed_outcome(record_id, val);
+        }
+        if deposit > 0 {
+            self.internal_credit_balance(&env::predecessor_account_id(), deposit, Book::Custody);
+            self.log(LogLevel::Info, messages::DEPOSITED_AS_CHANGE, &[&deposit]);
+        }
+        let val = self.internal_add(&env::predecessor_account_id(), &currency, referrer, amount);
+        let outcome = self.internal_outcome(val);
+        self.internal_remember(
+            idempotency_key,
+            "add",
+            IdempotentOutcome::Counter {
+                val,
+                record_id: outcome.receipt_id.0,
+            },
+        );
+        outcome
     }
 
-    /// change (subtract from) the Change.
+    /// change (subtract from) the caller's Change.
     ///
     /// In (/src/main.js) this is also added to the "changeMethods" array
     /// using near-cli we can call this by:
@@ -67,31 +549,254 @@ impl Change {
     /// ```bash
     /// near call Change.YOU.testnet change --accountId donation.YOU.testnet
     /// ```
-    pub fn change(&mut self) {
-        // note: subtracting one like this is an easy way to accidentally overflow
-        // real smart contracts will want to have safety checks
-        // e.g. self.val = i32::wrapping_sub(self.val, 1);
-        // https://doc.rust-lang.org/std/primitive.i32.html#method.wrapping_sub
-        self.val -= 10;
-        let log_message = format!("Value after change {}", self.val);
-        env::log(log_message.as_bytes());
-        after_counter_change();
-    }
-
-    /// Reset to zero.
-    pub fn reset(&mut self) {
-        self.val = 0;
-        // Another way to log is to cast a string into bytes, hence "b" below:
-        env::log(b"Reset Change to zero");
+    ///
+    /// Returns the new value; `amount` (the configured `sub_step`, 10 unless changed,
+    /// when omitted), `currency` and
+    /// `idempotency_key` work as for [`add`](Change::add).
+    pub fn change(
+        &mut self,
+        idempotency_key: Option<String>,
+        amount: Option<U128>,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_access("change");
+        self.assert_writable();
+        let amount = counter_amount(amount.unwrap_or(self.config().sub_step))
+            .unwrap_or_else(|error| fail!(error));
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        if let Some(IdempotentOutcome::Counter { val, record_id }) =
+            self.internal_replay(&idempotency_key, "change")
+        {
+            return self.internal_replayed_outcome(record_id, val);
+        }
+        let val = self.internal_change(&env::predecessor_account_id(), &currency, amount);
+        let outcome = self.internal_outcome(val);
+        self.internal_remember(
+            idempotency_key,
+            "change",
+            IdempotentOutcome::Counter {
+                val,
+                record_id: outcome.receipt_id.0,
+            },
+        );
+        outcome
+    }
+
+    /// Sends `amount` yoctoNEAR deposited with [`add`](Change::add) back to the
+    /// caller, taking it off their NEAR Change like [`change`](Change::change).
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet withdraw '{"amount": "1000000000000000000000000"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn withdraw(&mut self, amount: U128) -> Promise {
+        self.assert_access("withdraw");
+        self.assert_writable();
+        let account_id = env::predecessor_account_id();
+        let delta = counter_amount(amount).unwrap_or_else(|error| fail!(error));
+        self.internal_change(&account_id, DEFAULT_CURRENCY, delta);
+        self.internal_withdraw_change(account_id, amount.0)
+    }
+
+    /// Reset the Change of `account_id` in `currency`, `"NEAR"` when omitted, to zero. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet reset '{"account_id": "donation.YOU.testnet"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn reset(
+        &mut self,
+        account_id: ValidAccountId,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_access("reset");
+        self.assert_reset_unguarded();
+        self.internal_reset(account_id.into(), currency)
+    }
+}
+
+/// Payload of the `add`, `change` and `reset` events.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct CounterEvent<'a> {
+    caller_id: AccountId,
+    /// Account whose Change moved; the caller unless relayed or reset by the owner.
+    account_id: &'a AccountId,
+    currency: &'a str,
+    delta: I128,
+    old_value: I128,
+    new_value: I128,
+}
+
+impl Change {
+    /// Resets the Change of `account_id` in `currency` to zero.
+    pub(crate) fn internal_reset(
+        &mut self,
+        account_id: AccountId,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_writable();
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        let old_value = self.internal_remove_num(&account_id, &currency);
+        self.internal_record_counter(
+            CounterOperation::Reset,
+            &account_id,
+            &currency,
+            -old_value,
+            old_value,
+            0,
+        );
+        self.log(LogLevel::Info, messages::CHANGE_RESET, &[]);
+        self.internal_outcome(0)
+    }
+
+    /// Returns the Change of `account_id` in `currency`, 0 if it never had any.
+    pub(crate) fn internal_get_num(&self, account_id: &AccountId, currency: &str) -> i128 {
+        self.ledger
+            .get(account_id)
+            .and_then(|balances| balances.get(&currency.to_string()))
+            .unwrap_or(0)
+    }
+
+    /// Moves the Change of `account_id` in `currency` by `delta` for `operation`.
+    /// Returns its new value.
+    fn internal_move_num(
+        &mut self,
+        operation: CounterOperation,
+        account_id: &AccountId,
+        currency: &str,
+        delta: i128,
+    ) -> i128 {
+        self.assert_served_here(account_id);
+        let old_value = self.internal_get_num(account_id, currency);
+        let new_value = self
+            .internal_apply_delta(old_value, delta)
+            .unwrap_or_else(|e| env::panic(e.as_bytes()));
+        self.internal_set_num(account_id, currency, new_value);
+        self.internal_record_counter(operation, account_id, currency, delta, old_value, new_value);
+        new_value
+    }
+
+    /// Returns `current` moved by `delta`, or an error if the Change would
+    /// overflow or leave the configured limits.
+    pub(crate) fn internal_apply_delta(&self, current: i128, delta: i128) -> Result<i128, String> {
+        let new_value = apply_delta(current, delta)?;
+        let limits = &self.config().limits;
+        if new_value < limits.min_value.0 {
+            return Err(messages::BELOW_MINIMUM.format(&[&limits.min_value.0]));
+        }
+        if new_value > limits.max_value.0 {
+            return Err(messages::ABOVE_MAXIMUM.format(&[&limits.max_value.0]));
+        }
+        Ok(new_value)
+    }
+
+    /// Announces `operation` and adds it to the history.
+    pub(crate) fn internal_record_counter(
+        &mut self,
+        operation: CounterOperation,
+        account_id: &AccountId,
+        currency: &str,
+        delta: i128,
+        old_value: i128,
+        new_value: i128,
+    ) {
+        self.internal_record_history(operation, account_id, currency, delta, new_value);
+        self.internal_record_stats(operation, delta);
+        self.emit_event(
+            operation.event(),
+            &CounterEvent {
+                caller_id: env::predecessor_account_id(),
+                account_id,
+                currency,
+                delta: I128(delta),
+                old_value: I128(old_value),
+                new_value: I128(new_value),
+            },
+        );
+    }
+
+    /// Adds `amount` to the Change of `account_id`, the caller or a relayed
+    /// account, in `currency`. Returns its new value.
+    pub(crate) fn internal_add(
+        &mut self,
+        account_id: &AccountId,
+        currency: &str,
+        referrer: Option<AccountId>,
+        amount: i128,
+    ) -> i128 {
+        self.assert_not_dust(amount);
+        self.assert_registered(account_id);
+        self.internal_throttle(account_id);
+        let val = self.internal_move_num(CounterOperation::Add, account_id, currency, amount);
+        self.log(LogLevel::Info, messages::MONEY_ADDED, &[&val]);
+        self.internal_record_operation(account_id);
+        self.internal_bind_referrer(account_id, referrer);
+        self.internal_notify_listener(account_id, val, amount);
+        self.internal_process_payouts();
+        after_counter_change(self.config().log_level);
+        val
+    }
+
+    /// Subtracts `amount` from the Change of `account_id` in `currency`. Returns its new value.
+    pub(crate) fn internal_change(
+        &mut self,
+        account_id: &AccountId,
+        currency: &str,
+        amount: i128,
+    ) -> i128 {
+        self.assert_not_dust(amount);
+        self.assert_registered(account_id);
+        self.internal_throttle(account_id);
+        let val = self.internal_move_num(CounterOperation::Change, account_id, currency, -amount);
+        self.log(LogLevel::Info, messages::VALUE_CHANGED, &[&val]);
+        self.internal_record_operation(account_id);
+        self.internal_notify_listener(account_id, val, -amount);
+        after_counter_change(self.config().log_level);
+        val
     }
+
+    /// Panics unless the predecessor is the contract owner.
+    pub(crate) fn assert_owner(&self) {
+        require!(
+            env::predecessor_account_id() == self.owner_id,
+            messages::OWNER_ONLY
+        );
+    }
+
+    /// Panics unless the contract called itself, as it does to resolve its
+    /// own promises. Every callback starts with this, so nobody else can
+    /// hand one a made-up promise result, such as a failed transfer to be
+    /// credited back.
+    pub(crate) fn assert_callback(&self) {
+        require!(
+            env::predecessor_account_id() == env::current_account_id(),
+            messages::CALLBACK_ONLY
+        );
+    }
+}
+
+/// Checks an `amount` passed to `add` / `change`, or a step they default to.
+pub(crate) fn counter_amount(amount: U128) -> Result<i128, Message> {
+    match amount {
+        U128(0) => Err(messages::AMOUNT_NOT_POSITIVE),
+        U128(amount) => i128::try_from(amount).map_err(|_| messages::AMOUNT_TOO_LARGE),
+    }
+}
+
+/// Returns `current` moved by `delta`, or an error if the Change would overflow.
+pub(crate) fn apply_delta(current: i128, delta: i128) -> Result<i128, String> {
+    current
+        .checked_add(delta)
+        .ok_or_else(|| messages::CHANGE_OVERFLOW.format(&[&current, &delta]))
 }
 
 // unlike the struct's functions above, this function cannot use attributes #[derive(…)] or #[near_bindgen]
 // any attempts will throw helpful warnings upon 'cargo build'
 // while this function cannot be invoked directly on the blockchain, it can be called from an invoked function
-fn after_counter_change() {
-    // show helpful warning that i32 (8-bit signed integer) will overflow above 127 or below -128
-    env::log("Make sure you don't overflow, my friend.".as_bytes());
+fn after_counter_change(log_level: LogLevel) {
+    // show helpful warning that i128 overflows above i128::MAX or below i128::MIN
+    if log_level >= LogLevel::Debug {
+        env::log(messages::OVERFLOW_WARNING.to_string().as_bytes());
+    }
 }
 
 /*
@@ -105,32 +810,11 @@ fn after_counter_change() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::{context_for, get_context};
+    use near_sdk::json_types::U64;
+    use near_sdk::testing_env;
     use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
-
-    // part of writing unit tests is setting up a mock context
-    // in this example, this is only needed for env::log in the contract
-    // this is also a useful list to peek at when wondering what's available in env::*
-    fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
-        VMContext {
-            current_account_id: "alice.testnet".to_string(),
-            signer_account_id: "robert.testnet".to_string(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id: "jane.testnet".to_string(),
-            input,
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 0,
-            account_locked_balance: 0,
-            storage_usage: 0,
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view,
-            output_data_receivers: vec![],
-            epoch_height: 19,
-        }
-    }
+    use near_sdk::PromiseResult;
 
     // mark individual unit tests with #[test] for them to be registered and fired
     #[test]
@@ -139,33 +823,271 @@ mod tests {
         let context = get_context(vec![], false);
         testing_env!(context);
         // instantiate a contract variable with the Change at zero
-        let mut contract = Change { val: 0 };
-        contract.add();
-        println!("Value after add: {}", contract.get_num());
-        // confirm that we received 1 when calling get_num
-        assert_eq!(1000, contract.get_num());
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        println!(
+            "Value after add: {}",
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        // confirm that we received 1000 when calling get_num_for
+        assert_eq!(
+            1000,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
     }
 
     #[test]
     fn change() {
         let context = get_context(vec![], false);
         testing_env!(context);
-        let mut contract = Change { val: 0 };
-        contract.change();
-        println!("Value after change: {}", contract.get_num());
-        // confirm that we received -1 when calling get_num
-        assert_eq!(-10, contract.get_num());
+        let mut contract = Change::default();
+        contract.change(None, None, None);
+        println!(
+            "Value after change: {}",
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        // confirm that we received -10 when calling get_num_for
+        assert_eq!(
+            -10,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
     }
 
     #[test]
     fn add_and_reset() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        // alice.testnet is the contract account, so the owner
+        testing_env!(context_for("alice.testnet", 0));
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+        println!(
+            "Value after reset: {}",
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        // confirm that we received 0 when calling get_num_for
+        assert_eq!(0, contract.get_num_for("jane.testnet".to_string(), None).0);
+    }
+
+    #[test]
+    fn add_and_change_by_an_amount() {
         let context = get_context(vec![], false);
         testing_env!(context);
-        let mut contract = Change { val: 0 };
-        contract.add();
-        contract.reset();
-        println!("Value after reset: {}", contract.get_num());
-        // confirm that we received -1 when calling get_num
-        assert_eq!(0, contract.get_num());
-    }
-}
\ No newline at end of file
+        let mut contract = Change::default();
+        assert_eq!(
+            250,
+            contract
+                .add(None, None, Some(U128(250)), None)
+                .new_balance
+                .0
+        );
+        assert_eq!(
+            200,
+            contract.change(None, Some(U128(50)), None).new_balance.0
+        );
+    }
+
+    #[test]
+    fn deposits_back_the_stored_change() {
+        testing_env!(context_for("jane.testnet", 500));
+        let mut contract = Change::default();
+        contract.add(None, Some("tx-1".to_string()), None, None);
+        assert_eq!(500, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(
+            500,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        // the retry's deposit is refunded, not credited
+        contract.add(None, Some("tx-1".to_string()), None, None);
+        assert_eq!(500, contract.get_balance("jane.testnet".to_string()).0);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.withdraw(U128(200));
+        assert_eq!(300, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(
+            300,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        assert_eq!(300, contract.book(Book::UserChange));
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount must be the attached deposit of 500")]
+    fn deposits_set_the_amount_added() {
+        testing_env!(context_for("jane.testnet", 500));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(250)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposits only back the NEAR Change")]
+    fn deposits_back_only_the_near_change() {
+        testing_env!(context_for("jane.testnet", 500));
+        let mut contract = Change::default();
+        contract.add(None, None, None, Some("USDC".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough change")]
+    fn withdrawals_need_the_deposit() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(500)), None);
+        contract.withdraw(U128(200));
+    }
+
+    #[test]
+    fn each_account_keeps_its_own_change() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        let mut context = get_context(vec![], false);
+        context.predecessor_account_id = "bob.testnet".to_string();
+        testing_env!(context);
+        assert_eq!(-10, contract.change(None, None, None).new_balance.0);
+        assert_eq!(
+            1000,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        assert_eq!(-10, contract.get_num_for("bob.testnet".to_string(), None).0);
+    }
+
+    #[test]
+    fn counter_changes_are_announced() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(250)), None);
+        contract.change(None, None, None);
+        testing_env!(context_for("alice.testnet", 0));
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+        let events: Vec<_> = near_sdk::test_utils::get_logs()
+            .into_iter()
+            .filter(|log| log.starts_with("EVENT_JSON:"))
+            .collect();
+        assert_eq!(1, events.len());
+        assert_eq!(
+            "EVENT_JSON:{\"data\":[{\"account_id\":\"jane.testnet\",\"caller_id\":\"alice.testnet\",\"currency\":\"NEAR\",\"delta\":\"-240\",\"new_value\":\"0\",\"old_value\":\"240\"}],\"event\":\"reset\",\"standard\":\"changeback\",\"version\":\"1.0.0\"}",
+            events[0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_resets() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+    }
+
+    #[test]
+    fn amounts_are_validated() {
+        assert_eq!(Ok(1000), counter_amount(U128(1000)));
+        assert_eq!(Err(messages::AMOUNT_NOT_POSITIVE), counter_amount(U128(0)));
+        assert_eq!(Ok(i128::MAX), counter_amount(U128(i128::MAX as u128)));
+        assert_eq!(
+            Err(messages::AMOUNT_TOO_LARGE),
+            counter_amount(U128(i128::MAX as u128 + 1))
+        );
+    }
+
+    #[test]
+    fn deltas_stop_at_the_boundaries() {
+        assert_eq!(Ok(i128::MAX), apply_delta(i128::MAX - 1, 1));
+        assert_eq!(Ok(i128::MIN), apply_delta(i128::MIN + 10, -10));
+        assert_eq!(Ok(-1), apply_delta(i128::MAX, i128::MIN));
+        assert_eq!(
+            Err(format!(
+                "[CHANGE_OVERFLOW] Change overflow: current {}, delta 1",
+                i128::MAX
+            )),
+            apply_delta(i128::MAX, 1)
+        );
+        assert_eq!(
+            Err(format!(
+                "[CHANGE_OVERFLOW] Change overflow: current {}, delta -10",
+                i128::MIN
+            )),
+            apply_delta(i128::MIN, -10)
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Change overflow: current 170141183460469231731687303715884105727, delta 1000"
+    )]
+    fn add_panics_on_overflow() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Change::default();
+        contract.internal_set_num(&"jane.testnet".to_string(), DEFAULT_CURRENCY, i128::MAX);
+        contract.add(None, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Change overflow: current -170141183460469231731687303715884105728, delta -10"
+    )]
+    fn change_panics_on_underflow() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Change::default();
+        contract.internal_set_num(&"jane.testnet".to_string(), DEFAULT_CURRENCY, i128::MIN);
+        contract.change(None, None, None);
+    }
+
+    #[test]
+    fn callbacks_reject_other_callers() {
+        type Callback = fn(&mut Change);
+        let callbacks: [(&str, Callback); 9] = [
+            ("on_amm_withdrawn", |c| {
+                c.on_amm_withdrawn("usdc.testnet".to_string(), U128(1));
+            }),
+            ("on_change_donated", |c| {
+                c.on_change_donated("jane.testnet".to_string(), U128(1))
+            }),
+            ("on_price_fetched", |c| {
+                c.on_price_fetched("usdc.testnet".to_string());
+            }),
+            ("on_purchase_forwarded", |c| {
+                c.on_purchase_forwarded("jane.testnet".to_string(), U128(1))
+            }),
+            ("on_referral_payout", |c| {
+                c.on_referral_payout("jane.testnet".to_string(), U128(1))
+            }),
+            ("on_savings_withdrawn", |c| {
+                c.on_savings_withdrawn(
+                    "jane.testnet".to_string(),
+                    "usdc.testnet".to_string(),
+                    U128(1),
+                )
+            }),
+            ("on_settled", |c| c.on_settled(U64(0))),
+            ("on_swap_to_base", |c| {
+                c.on_swap_to_base("usdc.testnet".to_string(), U128(1), U128(1));
+            }),
+            ("on_treasury_withdrawn", |c| {
+                c.on_treasury_withdrawn("jane.testnet".to_string(), U128(1))
+            }),
+        ];
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        for (name, callback) in callbacks {
+            testing_env!(
+                context_for("jane.testnet", 0),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                vec![PromiseResult::Failed]
+            );
+            let spoofed =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| callback(&mut contract)));
+            let message = spoofed.expect_err(name);
+            let message = message.downcast_ref::<String>().expect(name);
+            assert!(
+                message.contains("Only the contract can call its callbacks"),
+                "{}: {}",
+                name,
+                message
+            );
+        }
+        assert_eq!(0, contract.book(Book::UserChange));
+    }
+}
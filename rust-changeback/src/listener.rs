@@ -0,0 +1,151 @@
+//! Notifications of every `add` and `change` to an external contract.
+//!
+//! When the owner sets `listener_account_id`, each `add` and `change` calls
+//! `on_change_updated(new_value, delta, caller)` on it, `caller` being the
+//! account whose Change moved. The counter has moved by then: a listener that
+//! fails or runs out of gas doesn't undo it, the callback just reports it with
+//! a `listener_failed` event. Accounts that muted alerts aren't notified
+//! about, see [`notification`](crate::notification).
+
+use near_sdk::json_types::I128;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ListenerFailedEvent<'a> {
+    listener_id: &'a AccountId,
+    caller: AccountId,
+    new_value: I128,
+    delta: I128,
+}
+
+impl Change {
+    /// Tells the listener, if there is one, that the Change of `caller` moved
+    /// by `delta` to `new_value`.
+    pub(crate) fn internal_notify_listener(
+        &self,
+        caller: &AccountId,
+        new_value: i128,
+        delta: i128,
+    ) {
+        let config = self.config();
+        let listener_id = match &config.listener_account_id {
+            Some(listener_id) => listener_id,
+            None => return,
+        };
+        if !self.internal_wants_notification(caller, NotificationCategory::Alerts) {
+            return;
+        }
+        ext_listener::on_change_updated(
+            I128(new_value),
+            I128(delta),
+            caller.clone(),
+            listener_id,
+            0,
+            config.gas.listener.0,
+        )
+        .then(ext_self::on_listener_notified(
+            listener_id.clone(),
+            caller.clone(),
+            I128(new_value),
+            I128(delta),
+            &env::current_account_id(),
+            0,
+            config.gas.callback.0,
+        ));
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Reports a notification the listener didn't take.
+    pub fn on_listener_notified(
+        &mut self,
+        listener_id: AccountId,
+        caller: AccountId,
+        new_value: I128,
+        delta: I128,
+    ) {
+        self.assert_access("on_listener_notified");
+        if promise_succeeded().is_ok() {
+            return;
+        }
+        self.emit_event(
+            "listener_failed",
+            &ListenerFailedEvent {
+                listener_id: &listener_id,
+                caller,
+                new_value,
+                delta,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::test_utils::{get_created_receipts, get_logs};
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult};
+    use std::convert::TryFrom;
+
+    fn notified() -> bool {
+        near_sdk::serde_json::to_string(&get_created_receipts())
+            .unwrap()
+            .contains("on_change_updated")
+    }
+
+    #[test]
+    fn counter_moves_notify_the_listener() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        assert!(!notified());
+        contract.set_listener(Some(ValidAccountId::try_from("books.testnet").unwrap()));
+        assert_eq!(
+            Some("books.testnet".to_string()),
+            contract.get_config().listener_account_id
+        );
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, Some(U128(250)), None);
+        assert!(notified());
+        testing_env!(context_for("jane.testnet", 0));
+        contract.change(None, None, None);
+        assert!(notified());
+    }
+
+    #[test]
+    fn failed_notifications_are_reported() {
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let mut contract = Change::default();
+        contract.on_listener_notified(
+            "books.testnet".to_string(),
+            "jane.testnet".to_string(),
+            I128(240),
+            I128(-10),
+        );
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.contains("listener_failed") && log.contains("books.testnet")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_sets_the_listener() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::new(ValidAccountId::try_from("alice.testnet").unwrap());
+        contract.set_listener(None);
+    }
+}
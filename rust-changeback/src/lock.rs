@@ -0,0 +1,152 @@
+//! Locks accounts put on themselves.
+//!
+//! An account can [lock](Change::lock_account) itself for a while, to keep
+//! its savings out of reach or to limit the damage of a leaked key. Until the
+//! lock ends it can't withdraw its change, rebate or savings, nor spend its
+//! change on a purchase; everything paying into the account keeps working.
+//! A lock can be extended but never shortened.
+
+use near_sdk::json_types::U64;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId};
+
+use crate::config::DAY;
+use crate::*;
+
+/// Longest a single lock may run for.
+const MAX_LOCK_DURATION: u64 = 365 * DAY;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AccountLockedEvent<'a> {
+    account_id: &'a AccountId,
+    locked_until: U64,
+}
+
+impl Change {
+    pub(crate) fn internal_is_locked(&self, account_id: &AccountId) -> bool {
+        env::block_timestamp() < self.internal_get_account(account_id).locked_until
+    }
+
+    /// Panics while `account_id` is locked.
+    pub(crate) fn assert_unlocked(&self, account_id: &AccountId) {
+        require!(
+            !self.internal_is_locked(account_id),
+            messages::ACCOUNT_LOCKED
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Locks the caller's outflows for `duration` nanoseconds from now, or
+    /// until their current lock ends if that's later. Returns when the lock ends.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet lock_account '{"duration": "2592000000000000"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn lock_account(&mut self, duration: U64) -> U64 {
+        self.assert_access("lock_account");
+        require!(
+            duration.0 > 0 && duration.0 <= MAX_LOCK_DURATION,
+            messages::INVALID_LOCK_DURATION
+        );
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_get_account(&account_id);
+        account.locked_until = account
+            .locked_until
+            .max(env::block_timestamp() + duration.0);
+        self.internal_set_account(&account_id, &account);
+        let locked_until = U64(account.locked_until);
+        self.emit_event(
+            "account_locked",
+            &AccountLockedEvent {
+                account_id: &account_id,
+                locked_until,
+            },
+        );
+        locked_until
+    }
+
+    /// Returns when the lock of `account_id` ends, if it's locked.
+    pub fn get_locked_until(&self, account_id: AccountId) -> Option<U64> {
+        self.internal_is_locked(&account_id)
+            .then(|| U64(self.internal_get_account(&account_id).locked_until))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn at(predecessor: &str, deposit: u128, timestamp: u64) -> near_sdk::VMContext {
+        let mut context = context_for(predecessor, deposit);
+        context.block_timestamp = timestamp;
+        context
+    }
+
+    fn locked_jane() -> Change {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_credit_balance(&"jane.testnet".to_string(), 70, Book::Custody);
+        assert_eq!(DAY, contract.lock_account(U64(DAY)).0);
+        contract
+    }
+
+    #[test]
+    fn locks_only_grow() {
+        let mut contract = locked_jane();
+        testing_env!(at("jane.testnet", 0, DAY / 2));
+        assert_eq!(DAY, contract.lock_account(U64(DAY / 4)).0);
+        assert_eq!(2 * DAY, contract.lock_account(U64(3 * DAY / 2)).0);
+        assert_eq!(
+            Some(U64(2 * DAY)),
+            contract.get_locked_until("jane.testnet".to_string())
+        );
+        testing_env!(at("jane.testnet", 0, 2 * DAY));
+        assert_eq!(None, contract.get_locked_until("jane.testnet".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is locked")]
+    fn locked_accounts_cannot_withdraw() {
+        let mut contract = locked_jane();
+        contract.withdraw_change(U128(70));
+    }
+
+    #[test]
+    fn withdrawals_resume_once_unlocked() {
+        let mut contract = locked_jane();
+        testing_env!(at("jane.testnet", 0, DAY));
+        contract.withdraw_change(U128(70));
+        assert_eq!(0, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is locked")]
+    fn locked_accounts_cannot_spend_their_change() {
+        let mut contract = locked_jane();
+        testing_env!(context_for("alice.testnet", 0));
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        testing_env!(context_for("jane.testnet", 0));
+        contract.purchase(U128(30), Some(true), None, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Lock duration must be positive and at most a year")]
+    fn locks_are_bounded() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.lock_account(U64(MAX_LOCK_DURATION + 1));
+    }
+}
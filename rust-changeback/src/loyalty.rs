@@ -0,0 +1,459 @@
+//! Loyalty points earned by buying at a merchant.
+//!
+//! Points are a separate tally from the change balance: every purchase at a
+//! merchant awards the buyer `points_per_near` points per NEAR of the price,
+//! at the rate the merchant has set. Points are kept per customer and merchant.
+//!
+//! Customers turn points into change balance with
+//! [`redeem_points`](Change::redeem_points), at the merchant's `point_value`.
+//! Redemptions are paid from a rewards pool the merchant funds in advance, so
+//! every credited point is backed.
+//!
+//! Points expire `points_max_age` after they were awarded. They are kept in
+//! batches, one per day they were awarded on, and expired batches are dropped
+//! whenever the points are touched; views simply leave them out. Redemptions
+//! spend the oldest points first.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::amount::per_near;
+use crate::config::DAY;
+use crate::*;
+
+/// Points awarded on one day.
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct PointsBatch {
+    pub points: u128,
+    /// Start of the day the points were awarded on.
+    pub awarded_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PointsEvent<'a> {
+    account_id: &'a AccountId,
+    merchant_id: &'a AccountId,
+    points: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PointsRedeemedEvent<'a> {
+    account_id: &'a AccountId,
+    merchant_id: &'a AccountId,
+    points: U128,
+    amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RewardsFundedEvent<'a> {
+    merchant_id: &'a AccountId,
+    amount: U128,
+    rewards_pool: U128,
+}
+
+impl Change {
+    /// Whether points awarded at `awarded_at` are expired by `now`.
+    fn internal_points_expired(&self, awarded_at: Timestamp, now: Timestamp) -> bool {
+        let max_age = self.config().points_max_age.0;
+        max_age > 0 && now >= awarded_at + max_age
+    }
+
+    /// Returns the unexpired points of `account_id` at `merchant_id`.
+    pub(crate) fn internal_points(&self, account_id: &AccountId, merchant_id: &AccountId) -> u128 {
+        let now = env::block_timestamp();
+        self.points
+            .get(&(account_id.clone(), merchant_id.clone()))
+            .unwrap_or_default()
+            .iter()
+            .filter(|batch| !self.internal_points_expired(batch.awarded_at, now))
+            .map(|batch| batch.points)
+            .sum()
+    }
+
+    /// Returns the point batches of `account_id` at `merchant_id`, oldest first,
+    /// dropping the expired ones and announcing what was forfeited.
+    fn internal_live_batches(
+        &self,
+        account_id: &AccountId,
+        merchant_id: &AccountId,
+    ) -> Vec<PointsBatch> {
+        let now = env::block_timestamp();
+        let (expired, live): (Vec<PointsBatch>, Vec<PointsBatch>) = self
+            .points
+            .get(&(account_id.clone(), merchant_id.clone()))
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|batch| self.internal_points_expired(batch.awarded_at, now));
+        let forfeited: u128 = expired.iter().map(|batch| batch.points).sum();
+        if forfeited > 0 {
+            self.emit_event(
+                "points_expired",
+                &PointsEvent {
+                    account_id,
+                    merchant_id,
+                    points: U128(forfeited),
+                },
+            );
+        }
+        live
+    }
+
+    fn internal_set_batches(
+        &mut self,
+        account_id: &AccountId,
+        merchant_id: &AccountId,
+        batches: &[PointsBatch],
+    ) {
+        let key = (account_id.clone(), merchant_id.clone());
+        if batches.is_empty() {
+            self.points.remove(&key);
+        } else {
+            self.points.insert(&key, &batches.to_vec());
+        }
+    }
+
+    /// Awards `account_id` the points for spending `price` at `merchant_id`,
+    /// times the `multiplier` of any running [campaign](crate::campaign).
+    pub(crate) fn internal_award_points(
+        &mut self,
+        account_id: &AccountId,
+        merchant_id: &AccountId,
+        price: Balance,
+        multiplier: u32,
+    ) {
+        let merchant = self
+            .merchants
+            .get(merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        let points = per_near(price, merchant.points_per_near, self.config().rounding_mode)
+            * u128::from(multiplier);
+        if points == 0 {
+            return;
+        }
+        let today = env::block_timestamp() / DAY * DAY;
+        let mut batches = self.internal_live_batches(account_id, merchant_id);
+        match batches.last_mut() {
+            Some(batch) if batch.awarded_at == today => batch.points += points,
+            _ => batches.push(PointsBatch {
+                points,
+                awarded_at: today,
+            }),
+        }
+        self.internal_set_batches(account_id, merchant_id, &batches);
+        self.emit_event(
+            "points_awarded",
+            &PointsEvent {
+                account_id,
+                merchant_id,
+                points: U128(points),
+            },
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sets the points `merchant_id` awards per NEAR spent and the yoctoNEAR each
+    /// point is worth when redeemed. Callable by the merchant or the owner.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_points_rate '{"merchant_id": "shop.testnet", "points_per_near": 10, "point_value": "1000000000000000000000"}' --accountId shop.testnet
+    /// ```
+    pub fn set_points_rate(
+        &mut self,
+        merchant_id: AccountId,
+        points_per_near: u32,
+        point_value: U128,
+    ) {
+        self.assert_access("set_points_rate");
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == merchant_id || caller == self.owner_id,
+            messages::POINTS_RATE_SETTER_ONLY
+        );
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        merchant.points_per_near = points_per_near;
+        merchant.point_value = point_value.0;
+        self.merchants.insert(&merchant_id, &merchant);
+    }
+
+    /// Adds the attached deposit to the rewards pool of `merchant_id`.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet fund_rewards '{"merchant_id": "shop.testnet"}' --accountId shop.testnet --deposit 10
+    /// ```
+    #[payable]
+    pub fn fund_rewards(&mut self, merchant_id: AccountId) -> U128 {
+        self.assert_access("fund_rewards");
+        self.assert_writable();
+        let amount = env::attached_deposit();
+        require!(amount > 0, messages::REWARDS_DEPOSIT_REQUIRED);
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        merchant.rewards_pool += amount;
+        self.merchants.insert(&merchant_id, &merchant);
+        self.internal_post(Posting::receive(Book::Merchants, amount));
+        self.emit_event(
+            "rewards_funded",
+            &RewardsFundedEvent {
+                merchant_id: &merchant_id,
+                amount: U128(amount),
+                rewards_pool: U128(merchant.rewards_pool),
+            },
+        );
+        U128(merchant.rewards_pool)
+    }
+
+    /// Redeems `points` earned at `merchant_id` for change balance, paid from the
+    /// merchant's rewards pool. Returns the amount credited.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet redeem_points '{"merchant_id": "shop.testnet", "points": "50"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn redeem_points(&mut self, merchant_id: AccountId, points: U128) -> U128 {
+        self.assert_access("redeem_points");
+        self.assert_writable();
+        let account_id = env::predecessor_account_id();
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        require!(merchant.point_value > 0, messages::POINTS_NOT_REDEEMABLE);
+        let mut batches = self.internal_live_batches(&account_id, &merchant_id);
+        let held: u128 = batches.iter().map(|batch| batch.points).sum();
+        require!(points.0 <= held, messages::NOT_ENOUGH_POINTS);
+        let amount = points
+            .0
+            .checked_mul(merchant.point_value)
+            .unwrap_or_else(|| fail!(messages::REDEMPTION_OVERFLOWS));
+        require!(
+            amount <= merchant.rewards_pool,
+            messages::REWARDS_POOL_SHORT
+        );
+        merchant.rewards_pool -= amount;
+        self.merchants.insert(&merchant_id, &merchant);
+        let mut to_spend = points.0;
+        for batch in batches.iter_mut() {
+            let spent = batch.points.min(to_spend);
+            batch.points -= spent;
+            to_spend -= spent;
+        }
+        batches.retain(|batch| batch.points > 0);
+        self.internal_set_batches(&account_id, &merchant_id, &batches);
+        self.internal_credit_balance(&account_id, amount, Book::Merchants);
+        self.emit_event(
+            "points_redeemed",
+            &PointsRedeemedEvent {
+                account_id: &account_id,
+                merchant_id: &merchant_id,
+                points,
+                amount: U128(amount),
+            },
+        );
+        U128(amount)
+    }
+
+    /// Returns the points `account_id` has earned at `merchant_id`.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_points '{"account_id": "donation.YOU.testnet", "merchant_id": "shop.testnet"}'
+    /// ```
+    pub fn get_points(&self, account_id: AccountId, merchant_id: AccountId) -> U128 {
+        U128(self.internal_points(&account_id, &merchant_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::ONE_NEAR;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn market() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(account("shop.testnet"), account("shop.testnet"), 0);
+        contract.register_merchant(account("cafe.testnet"), account("cafe.testnet"), 0);
+        testing_env!(context_for("shop.testnet", 0));
+        contract.set_points_rate("shop.testnet".to_string(), 10, U128(1000));
+        contract
+    }
+
+    #[test]
+    fn points_accrue_per_customer_and_merchant() {
+        let mut contract = market();
+        testing_env!(context_for("jane.testnet", 3 * ONE_NEAR));
+        contract.purchase(
+            U128(ONE_NEAR * 5 / 2),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        testing_env!(context_for("jane.testnet", ONE_NEAR / 2));
+        contract.purchase(
+            U128(ONE_NEAR),
+            Some(true),
+            Some("cafe.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        testing_env!(context_for("bob.testnet", ONE_NEAR));
+        contract.purchase(
+            U128(ONE_NEAR),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+
+        let points = |account: &str, merchant: &str| {
+            contract
+                .get_points(account.to_string(), merchant.to_string())
+                .0
+        };
+        assert_eq!(25, points("jane.testnet", "shop.testnet"));
+        assert_eq!(0, points("jane.testnet", "cafe.testnet"));
+        assert_eq!(10, points("bob.testnet", "shop.testnet"));
+        // points don't touch the monetary balance
+        assert_eq!(0, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn redeemed_points_are_paid_from_the_rewards_pool() {
+        let mut contract = market();
+        testing_env!(context_for("shop.testnet", 30_000));
+        contract.fund_rewards("shop.testnet".to_string());
+        testing_env!(context_for("jane.testnet", 3 * ONE_NEAR));
+        contract.purchase(
+            U128(3 * ONE_NEAR),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(
+            20_000,
+            contract
+                .redeem_points("shop.testnet".to_string(), U128(20))
+                .0
+        );
+        assert_eq!(20_000, contract.get_balance("jane.testnet".to_string()).0);
+        assert_eq!(
+            10,
+            contract
+                .get_points("jane.testnet".to_string(), "shop.testnet".to_string())
+                .0
+        );
+        let shop = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(10_000, shop.rewards_pool.0);
+    }
+
+    #[test]
+    fn points_expire_oldest_first() {
+        let mut contract = market();
+        testing_env!(context_for("shop.testnet", 30_000));
+        contract.fund_rewards("shop.testnet".to_string());
+        testing_env!(context_for("jane.testnet", ONE_NEAR));
+        contract.purchase(
+            U128(ONE_NEAR),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        let mut context = context_for("jane.testnet", 2 * ONE_NEAR);
+        context.block_timestamp = 10 * DAY;
+        testing_env!(context.clone());
+        contract.purchase(
+            U128(2 * ONE_NEAR),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        // spends the 10 points of day 0 and 5 of day 10
+        contract.redeem_points("shop.testnet".to_string(), U128(15));
+
+        context.block_timestamp = 366 * DAY;
+        context.attached_deposit = ONE_NEAR;
+        testing_env!(context.clone());
+        assert_eq!(
+            15,
+            contract
+                .get_points("jane.testnet".to_string(), "shop.testnet".to_string())
+                .0
+        );
+        contract.purchase(
+            U128(ONE_NEAR),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+
+        context.block_timestamp = 375 * DAY;
+        testing_env!(context);
+        assert_eq!(
+            10,
+            contract
+                .get_points("jane.testnet".to_string(), "shop.testnet".to_string())
+                .0
+        );
+        contract.redeem_points("shop.testnet".to_string(), U128(10));
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.contains("\"event\":\"points_expired\"")
+                && log.contains("\"points\":\"15\"")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rewards pool can't cover the redemption")]
+    fn redemptions_need_a_funded_pool() {
+        let mut contract = market();
+        testing_env!(context_for("jane.testnet", 3 * ONE_NEAR));
+        contract.purchase(
+            U128(3 * ONE_NEAR),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        contract.redeem_points("shop.testnet".to_string(), U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the merchant or the owner can set the points rate")]
+    fn others_cannot_set_the_points_rate() {
+        let mut contract = market();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_points_rate("shop.testnet".to_string(), 1000, U128(0));
+    }
+}
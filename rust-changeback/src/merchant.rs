@@ -0,0 +1,451 @@
+//! Registry of the merchants selling through this contract.
+//!
+//! Each merchant is identified by its account and keeps its own revenue: the
+//! price of every purchase routed to it, waiting to be paid out to its
+//! `payout_account` minus the platform fee of `fee_bps`.
+//!
+//! The price of a purchase stays pending for the merchant's `dispute_window`,
+//! during which the buyer can dispute it. Pending revenue matures into
+//! settleable revenue lazily, when the merchant is settled, each purchase by
+//! its own window: one made after the window was shortened can mature before
+//! older ones. Every settlement checks at most `MAX_MATURITY_CHECKS` pending
+//! purchases, picking up where the last one stopped, so a merchant with many
+//! of them settles its revenue over several calls.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::amount::{bps_of, BPS_DENOMINATOR};
+use crate::split::pro_rata;
+use crate::webhook::Webhook;
+use crate::*;
+
+/// Most pending purchases one call checks for maturity, bounding its gas.
+const MAX_MATURITY_CHECKS: u64 = 50;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Merchant {
+    /// Account the revenue is paid out to.
+    pub payout_account: AccountId,
+    /// Platform fee kept from the revenue, in basis points.
+    pub fee_bps: u16,
+    /// Settleable revenue not paid out yet, in yoctoNEAR.
+    pub revenue: Balance,
+    /// Revenue of purchases still within the dispute window, in yoctoNEAR.
+    pub pending: Balance,
+    /// Revenue held back by open disputes, in yoctoNEAR.
+    pub frozen: Balance,
+    /// Revenue booked since registration, in yoctoNEAR.
+    pub total_revenue: Balance,
+    /// Number of purchases made at this merchant.
+    pub purchases: u64,
+    /// Time after a purchase during which it can be disputed, in nanoseconds.
+    pub dispute_window: u64,
+    /// Receipts whose price is pending are those from `maturing_head` on, in
+    /// no particular order; the ones before it have matured.
+    pub maturing: Vector<u64>,
+    pub maturing_head: u64,
+    /// Where in `maturing` the next maturity check starts.
+    pub maturing_cursor: u64,
+    /// Loyalty points awarded per NEAR spent, see [`loyalty`](crate::loyalty).
+    pub points_per_near: u32,
+    /// yoctoNEAR credited per point redeemed; 0 means points can't be redeemed.
+    pub point_value: Balance,
+    /// NEAR set aside by the merchant to back point redemptions, in yoctoNEAR.
+    pub rewards_pool: Balance,
+    /// Platform fees waived by campaigns, taken off the next settlement fees.
+    pub fee_credit: Balance,
+    /// Contract notified of purchases and refunds, see [`webhook`](crate::webhook).
+    pub webhook: Option<Webhook>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MerchantView {
+    pub merchant_id: AccountId,
+    pub payout_account: AccountId,
+    pub fee_bps: u16,
+    pub revenue: U128,
+    pub pending: U128,
+    pub frozen: U128,
+    pub total_revenue: U128,
+    pub purchases: U64,
+    pub dispute_window: U64,
+    pub points_per_near: u32,
+    pub point_value: U128,
+    pub rewards_pool: U128,
+    pub webhook: Option<Webhook>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct MerchantRegisteredEvent<'a> {
+    merchant_id: &'a AccountId,
+    payout_account: &'a AccountId,
+    fee_bps: u16,
+}
+
+impl Change {
+    /// Books `amount` of the purchase `receipt_id` as pending revenue of
+    /// `merchant_id`, panicking if it isn't registered. Returns the end of the
+    /// purchase's dispute window.
+    pub(crate) fn internal_record_revenue(
+        &mut self,
+        merchant_id: &AccountId,
+        receipt_id: u64,
+        amount: Balance,
+    ) -> Timestamp {
+        let mut merchant = self
+            .merchants
+            .get(merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        merchant.pending += amount;
+        merchant.total_revenue += amount;
+        merchant.purchases += 1;
+        merchant.maturing.push(&receipt_id);
+        self.merchants.insert(merchant_id, &merchant);
+        env::block_timestamp() + merchant.dispute_window
+    }
+
+    /// Checks up to `MAX_MATURITY_CHECKS` pending purchases of `merchant`
+    /// from its cursor on. Returns the positions in `maturing` of those past
+    /// their dispute window, in order, the price of the undisputed ones among
+    /// them, whose revenue matures, and where the next check starts.
+    fn internal_find_matured(&self, merchant: &Merchant) -> (Vec<u64>, Balance, u64) {
+        let now = env::block_timestamp();
+        let head = merchant.maturing_head;
+        let pending = merchant.maturing.len() - head;
+        if pending == 0 {
+            return (vec![], 0, head);
+        }
+        let start = merchant.maturing_cursor.clamp(head, head + pending - 1) - head;
+        let checks = pending.min(MAX_MATURITY_CHECKS);
+        let mut positions = vec![];
+        let mut price = 0;
+        for offset in 0..checks {
+            let position = head + (start + offset) % pending;
+            let receipt_id = merchant.maturing.get(position).unwrap();
+            let receipt = self
+                .receipts
+                .get(receipt_id)
+                .unwrap_or_else(|| fail!(messages::UNKNOWN_RECEIPT));
+            if receipt.dispute_until.is_some_and(|until| now > until) {
+                positions.push(position);
+                // disputed purchases were already frozen
+                if self.receipt_disputes.get(&receipt_id).is_none() {
+                    price += receipt.price;
+                }
+            }
+        }
+        positions.sort_unstable();
+        (positions, price, head + (start + checks) % pending)
+    }
+
+    /// Moves the pending revenue of purchases past their dispute window into
+    /// `merchant.revenue`, checking up to `MAX_MATURITY_CHECKS` of them, and
+    /// credits the fee kept on them to their buyers' rebates and referrers.
+    pub(crate) fn internal_mature_revenue(&mut self, merchant: &mut Merchant) {
+        let (positions, price, cursor) = self.internal_find_matured(merchant);
+        merchant.pending -= price;
+        merchant.revenue += price;
+        merchant.maturing_cursor = cursor;
+        // swap each matured receipt to the head; the ones before it in the
+        // pending range have been checked, so only pending ones move back
+        for position in positions {
+            let head = merchant.maturing_head;
+            let receipt_id = merchant.maturing.get(position).unwrap();
+            if position != head {
+                let first = merchant.maturing.get(head).unwrap();
+                merchant.maturing.replace(position, &first);
+                merchant.maturing.replace(head, &receipt_id);
+            }
+            merchant.maturing_head += 1;
+            if self.receipt_disputes.get(&receipt_id).is_none() {
+                let receipt = self.receipts.get(receipt_id).unwrap();
+                let fee = bps_of(receipt.price, merchant.fee_bps, self.config().rounding_mode);
+                let payers = self.internal_receipt_payers(receipt_id, &receipt);
+                let fees = pro_rata(fee, &payers, self.config().rounding_mode);
+                for ((payer_id, _), fee) in payers.iter().zip(fees) {
+                    self.internal_credit_referrer(payer_id, fee);
+                    self.internal_credit_rebatable_fee(payer_id, fee);
+                }
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Registers `merchant_id`, paying its revenue to `payout_account` minus `fee_bps`. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet register_merchant '{"merchant_id": "shop.testnet", "payout_account": "shop-payouts.testnet", "fee_bps": 150}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn register_merchant(
+        &mut self,
+        merchant_id: ValidAccountId,
+        payout_account: ValidAccountId,
+        fee_bps: u16,
+    ) {
+        self.assert_access("register_merchant");
+        require!(
+            u128::from(fee_bps) <= BPS_DENOMINATOR,
+            messages::FEE_TOO_HIGH
+        );
+        let merchant_id: AccountId = merchant_id.into();
+        require!(
+            self.merchants.get(&merchant_id).is_none(),
+            messages::MERCHANT_ALREADY_REGISTERED
+        );
+        let merchant = Merchant {
+            payout_account: payout_account.into(),
+            fee_bps,
+            revenue: 0,
+            pending: 0,
+            frozen: 0,
+            total_revenue: 0,
+            purchases: 0,
+            dispute_window: self.config().dispute_window.0,
+            maturing: Vector::new(StorageKey::MaturingRevenue {
+                merchant_hash: env::sha256(merchant_id.as_bytes()),
+            }),
+            maturing_head: 0,
+            maturing_cursor: 0,
+            points_per_near: 0,
+            point_value: 0,
+            rewards_pool: 0,
+            fee_credit: 0,
+            webhook: None,
+        };
+        self.emit_event(
+            "merchant_registered",
+            &MerchantRegisteredEvent {
+                merchant_id: &merchant_id,
+                payout_account: &merchant.payout_account,
+                fee_bps,
+            },
+        );
+        self.merchants.insert(&merchant_id, &merchant);
+    }
+
+    /// Sets how long purchases at `merchant_id` can be disputed, in nanoseconds.
+    /// Applies to purchases made from now on. Owner only.
+    pub fn set_merchant_dispute_window(&mut self, merchant_id: AccountId, dispute_window: U64) {
+        self.assert_access("set_merchant_dispute_window");
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        merchant.dispute_window = dispute_window.0;
+        self.merchants.insert(&merchant_id, &merchant);
+    }
+
+    /// Returns the registration and revenue of `merchant_id`, counting revenue
+    /// the next settlement would find past its dispute window as settleable.
+    pub fn get_merchant(&self, merchant_id: AccountId) -> Option<MerchantView> {
+        self.merchants.get(&merchant_id).map(|merchant| {
+            let (_, matured, _) = self.internal_find_matured(&merchant);
+            MerchantView {
+                merchant_id,
+                payout_account: merchant.payout_account,
+                fee_bps: merchant.fee_bps,
+                revenue: U128(merchant.revenue + matured),
+                pending: U128(merchant.pending - matured),
+                frozen: U128(merchant.frozen),
+                total_revenue: U128(merchant.total_revenue),
+                purchases: U64(merchant.purchases),
+                dispute_window: U64(merchant.dispute_window),
+                points_per_near: merchant.points_per_near,
+                point_value: U128(merchant.point_value),
+                rewards_pool: U128(merchant.rewards_pool),
+                webhook: merchant.webhook,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn market() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(
+            account("shop.testnet"),
+            account("shop-payouts.testnet"),
+            150,
+        );
+        contract.register_merchant(account("cafe.testnet"), account("cafe.testnet"), 0);
+        contract
+    }
+
+    #[test]
+    fn revenue_is_tracked_per_merchant() {
+        let mut contract = market();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(
+            U128(430),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        testing_env!(context_for("bob.testnet", 100));
+        contract.purchase(
+            U128(100),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        contract.purchase(
+            U128(20),
+            Some(true),
+            Some("cafe.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        let shop = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(530, shop.pending.0);
+        assert_eq!(2, shop.purchases.0);
+        assert_eq!("shop-payouts.testnet", shop.payout_account);
+        assert_eq!(
+            20,
+            contract
+                .get_merchant("cafe.testnet".to_string())
+                .unwrap()
+                .pending
+                .0
+        );
+        assert_eq!(70, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn revenue_matures_after_the_merchant_dispute_window() {
+        let mut contract = market();
+        contract.set_merchant_dispute_window("cafe.testnet".to_string(), U64(100));
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(
+            U128(430),
+            None,
+            Some("cafe.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(
+            Some(U64(100)),
+            contract.get_receipt(U64(0)).unwrap().dispute_until
+        );
+
+        let mut context = context_for("jane.testnet", 0);
+        context.block_timestamp = 100;
+        testing_env!(context.clone());
+        let cafe = contract.get_merchant("cafe.testnet".to_string()).unwrap();
+        assert_eq!((0, 430), (cafe.revenue.0, cafe.pending.0));
+        context.block_timestamp = 101;
+        testing_env!(context);
+        let cafe = contract.get_merchant("cafe.testnet".to_string()).unwrap();
+        assert_eq!((430, 0), (cafe.revenue.0, cafe.pending.0));
+    }
+
+    fn buy_at_cafe(contract: &mut Change, price: u128) {
+        testing_env!(context_for("jane.testnet", price));
+        contract.purchase(
+            U128(price),
+            None,
+            Some("cafe.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+    }
+
+    fn at(timestamp: u64) {
+        let mut context = context_for("cafe.testnet", 0);
+        context.block_timestamp = timestamp;
+        testing_env!(context);
+    }
+
+    #[test]
+    fn each_purchase_matures_by_its_own_window() {
+        let mut contract = market();
+        contract.set_merchant_dispute_window("cafe.testnet".to_string(), U64(1000));
+        buy_at_cafe(&mut contract, 400);
+        testing_env!(context_for("alice.testnet", 0));
+        contract.set_merchant_dispute_window("cafe.testnet".to_string(), U64(10));
+        buy_at_cafe(&mut contract, 30);
+
+        at(11);
+        let cafe = contract.get_merchant("cafe.testnet".to_string()).unwrap();
+        assert_eq!((30, 400), (cafe.revenue.0, cafe.pending.0));
+        let mut merchant = contract.merchants.get(&"cafe.testnet".to_string()).unwrap();
+        contract.internal_mature_revenue(&mut merchant);
+        assert_eq!((30, 400), (merchant.revenue, merchant.pending));
+        assert_eq!(1, merchant.maturing_head);
+        at(1001);
+        contract.internal_mature_revenue(&mut merchant);
+        assert_eq!((430, 0), (merchant.revenue, merchant.pending));
+    }
+
+    #[test]
+    fn maturity_checks_are_bounded() {
+        let mut contract = market();
+        contract.set_merchant_dispute_window("cafe.testnet".to_string(), U64(10));
+        for _ in 0..MAX_MATURITY_CHECKS + 10 {
+            buy_at_cafe(&mut contract, 1);
+        }
+        at(11);
+        let cafe = contract.get_merchant("cafe.testnet".to_string()).unwrap();
+        assert_eq!((50, 10), (cafe.revenue.0, cafe.pending.0));
+        let mut merchant = contract.merchants.get(&"cafe.testnet".to_string()).unwrap();
+        contract.internal_mature_revenue(&mut merchant);
+        contract.internal_mature_revenue(&mut merchant);
+        assert_eq!((60, 0), (merchant.revenue, merchant.pending));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown merchant")]
+    fn purchase_at_unknown_merchant_fails() {
+        let mut contract = market();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(
+            U128(430),
+            None,
+            Some("nobody.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Merchant is already registered")]
+    fn merchants_register_once() {
+        let mut contract = market();
+        contract.register_merchant(account("shop.testnet"), account("shop.testnet"), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_owner_registers_merchants() {
+        let mut contract = market();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.register_merchant(account("jane.testnet"), account("jane.testnet"), 0);
+    }
+}
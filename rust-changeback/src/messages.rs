@@ -0,0 +1,461 @@
+//! Catalog of the user-facing messages: every panic and human-readable log
+//! line, keyed by a stable code.
+//!
+//! Messages are written as `[CODE] text`, e.g.
+//! `[OWNER_ONLY] Only the owner can call this method`, so frontends can match
+//! the code and show their own translation, while the English text keeps logs
+//! readable. Codes never change once released; texts may be reworded.
+//! [`get_messages`](Change::get_messages) lists the catalog to translate from.
+//! Texts fill their `{}` placeholders with the arguments in order.
+//!
+//! Panics go through [`require!`] and [`fail!`], log lines through
+//! [`log`](Change::log). Events carry no message code: their `event` name is
+//! the stable key frontends match on, and their payload is data rather than
+//! text to translate. Messages that only a bug can trigger, such as missing
+//! collection entries, stay plain strings.
+
+use std::fmt::{self, Display};
+
+use near_sdk::json_types::U64;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Message {
+    pub code: &'static str,
+    pub text: &'static str,
+}
+
+impl Message {
+    /// Returns `[CODE] text` with the placeholders filled with `args`.
+    pub(crate) fn format(self, args: &[&dyn Display]) -> String {
+        let mut message = format!("[{}] ", self.code);
+        let mut args = args.iter();
+        for (index, part) in self.text.split("{}").enumerate() {
+            if index > 0 {
+                match args.next() {
+                    Some(arg) => message.push_str(&arg.to_string()),
+                    None => message.push_str("{}"),
+                }
+            }
+            message.push_str(part);
+        }
+        message
+    }
+
+    pub(crate) fn panic(self, args: &[&dyn Display]) -> ! {
+        env::panic(self.format(args).as_bytes())
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.format(&[]))
+    }
+}
+
+/// Lets `?` turn a message into the `String` errors of the counter.
+impl From<Message> for String {
+    fn from(message: Message) -> Self {
+        message.to_string()
+    }
+}
+
+/// Panics with `message`, formatted with the arguments after it, unless
+/// `condition` holds.
+macro_rules! require {
+    ($condition:expr, $message:expr $(, $arg:expr)* $(,)?) => {
+        if !$condition {
+            $crate::messages::Message::panic($message, &[$(&$arg),*])
+        }
+    };
+}
+
+/// Panics with `message`, formatted with the arguments after it.
+macro_rules! fail {
+    ($message:expr $(, $arg:expr)* $(,)?) => {
+        $crate::messages::Message::panic($message, &[$(&$arg),*])
+    };
+}
+
+pub(crate) use {fail, require};
+
+macro_rules! catalog {
+    ($($code:ident: $text:literal,)*) => {
+        $(pub(crate) const $code: Message = Message {
+            code: stringify!($code),
+            text: $text,
+        };)*
+
+        /// Every message, in catalog order.
+        pub(crate) const MESSAGES: &[Message] = &[$($code),*];
+    };
+}
+
+catalog! {
+    // allowance
+    SELF_APPROVAL: "An account can't approve itself",
+    AMOUNT_EXCEEDS_ALLOWANCE: "Amount exceeds the allowance",
+    // batch
+    OWNER_ONLY: "Only the owner can call this method",
+    NO_OPERATIONS: "No operations",
+    TOO_MANY_OPERATIONS: "Too many operations",
+    BATCH_OPERATION_FAILED: "Operation {}: {}",
+    // beneficiary
+    INACTIVITY_PERIOD_TOO_SHORT: "Inactivity period must be at least 30 days",
+    SELF_BENEFICIARY: "An account can't be its own beneficiary",
+    BENEFICIARY_ONLY: "Only the beneficiary can claim the balance",
+    ACCOUNT_STILL_ACTIVE: "The account is still active",
+    NOTHING_TO_INHERIT: "Nothing to inherit",
+    // campaign
+    UNKNOWN_MERCHANT: "Unknown merchant",
+    CAMPAIGN_ENDS_BEFORE_START: "Campaign must end after it starts",
+    MULTIPLIER_NOT_POSITIVE: "Multiplier must be positive",
+    // charity
+    DONATION_FAILED: "Donation failed, credited back as change",
+    // config
+    CONFIG_SCHEDULED: "Config scheduled for {}",
+    EFFECTIVE_TIME_IN_PAST: "Effective time must be in the future",
+    CONFIG_UPDATED: "Config updated",
+    REFERRAL_BONUS_TOO_HIGH: "Referral bonus can't exceed 100%",
+    INVALID_STEPS: "Steps must be positive and fit the counter",
+    LIMITS_EXCLUDE_ZERO: "Limits must contain zero",
+    TOO_MANY_DECIMALS: "At most {} decimals",
+    RATE_LIMIT_WINDOW_NOT_POSITIVE: "Rate limit window must be positive",
+    INVALID_GAS_BUDGETS: "Gas budgets must be positive and at most {}",
+    GAS_BUDGETS_UPDATED: "Gas budgets updated",
+    COUNTER_LIMITS_UPDATED: "Counter limits updated",
+    DECIMALS_UPDATED: "Decimals updated",
+    RATE_LIMIT_UPDATED: "Rate limit updated",
+    LISTENER_UPDATED: "Listener updated",
+    VOUCHER_KEY_UPDATED: "Voucher key updated",
+    NO_CONFIG_PENDING: "No config pending",
+    PENDING_CONFIG_CANCELLED: "Pending config cancelled",
+    // coupon
+    UNKNOWN_COUPON: "Unknown coupon",
+    COUPON_EXPIRED: "Coupon has expired",
+    COUPON_USED_UP: "Coupon is used up",
+    COUPON_NOT_VALID_AT_MERCHANT: "Coupon isn't valid at this merchant",
+    COUPON_ISSUER_ONLY: "Only merchants and the owner can create coupons",
+    DISCOUNT_TOO_HIGH: "Discount must stay below 100%",
+    COUPON_COVERS_PRICE: "Coupon can't cover the whole price",
+    COUPON_WITHOUT_USES: "Coupon needs at least one use",
+    COUPON_EXISTS: "A coupon with this code already exists",
+    // currency
+    INVALID_CURRENCY_SYMBOL: "Invalid currency symbol",
+    TOO_MANY_CURRENCIES: "Too many currencies",
+    // dispute
+    UNKNOWN_RECEIPT: "Unknown receipt",
+    BUYER_ONLY: "Only the buyer can dispute a purchase",
+    NOT_A_MERCHANT_PURCHASE: "Only purchases at a merchant can be disputed",
+    DISPUTE_WINDOW_CLOSED: "Dispute window has closed",
+    PURCHASE_ALREADY_DISPUTED: "Purchase is already disputed",
+    ACCOUNT_NOT_ARBITER: "Account is not an arbiter",
+    UNKNOWN_DISPUTE: "Unknown dispute",
+    DISPUTE_ALREADY_RESOLVED: "Dispute is already resolved",
+    ASSIGNED_ARBITER_ONLY: "Only the assigned arbiter can resolve the dispute",
+    REFUND_ABOVE_DISPUTED: "Refund exceeds the disputed amount",
+    // dust
+    DUST_AMOUNT: "Amount is below the dust threshold",
+    DUST_THRESHOLD_TOO_HIGH: "Dust balance threshold can be at most {}",
+    // factory
+    NO_CONTRACT_CODE_UPLOADED: "No contract code uploaded",
+    EMPTY_CONTRACT_CODE: "Contract code is empty",
+    CONTRACT_CODE_UPDATED: "Contract code updated",
+    REGISTERED_MERCHANT_ONLY: "Only registered merchants can create a contract",
+    MERCHANT_HAS_CONTRACT: "Merchant already has a contract",
+    PREFIX_WITH_DOTS: "Prefix can't contain dots",
+    INVALID_CONTRACT_ACCOUNT: "Invalid contract account {}",
+    DEPOSIT_TOO_SMALL: "Attach at least {} yoctoNEAR",
+    MERCHANT_NO_CONTRACT: "Merchant has no contract",
+    // float
+    TOO_MANY_DENOMINATIONS: "At most {} denominations",
+    INVALID_DENOMINATIONS: "Denominations must be positive and distinct",
+    PAID_BELOW_PRICE: "Paid amount doesn't cover the price",
+    // gift_card
+    AMOUNT_NOT_POSITIVE: "Amount must be positive",
+    GIFT_CARD_DEPOSIT_MISMATCH: "Attach exactly the card amount",
+    GIFT_CARD_EXISTS: "A gift card with this code already exists",
+    GIFT_CARD_ALREADY_REDEEMED: "Gift card was already redeemed",
+    UNKNOWN_GIFT_CARD: "No gift card with this code",
+    // history
+    TOO_MANY_RECORDS: "At most {} records per call",
+    // idempotency
+    IDEMPOTENT_REPLAY: "Replaying {} for idempotency key {}",
+    IDEMPOTENCY_KEY_REUSED: "Idempotency key was used for another method",
+    // intent
+    EXPIRY_IN_PAST: "Expiry must be in the future",
+    UNKNOWN_INTENT: "Unknown intent",
+    INTENT_ALREADY_PAID: "Intent is already paid",
+    INTENT_CANCELLED: "Intent was cancelled",
+    INTENT_EXPIRED: "Intent has expired",
+    INTENT_HAS_COUPON: "Intent already has a coupon",
+    TOO_MANY_INTENT_PAYERS: "An intent can be paid by at most {} accounts",
+    COUPON_BELOW_PAID: "Coupon discounts below what was already paid",
+    NOTHING_TO_PAY_WITH: "Nothing to pay with",
+    INTENT_MERCHANT_ONLY: "Only the merchant can cancel the intent",
+    INTENT_NOT_CANCELLABLE: "Only open intents can be cancelled",
+    INTENT_NOT_OPEN: "Intent is not open",
+    INTENT_NOT_EXPIRED: "Intent has not expired yet",
+    // interface
+    ARBITER_ONLY: "Only arbiters can call this method",
+    RELAYER_ONLY: "Only relayers can relay operations",
+    GUARDIAN_ONLY: "Only guardians can change the read-only mode",
+    INSTANCE_ONLY: "Only trusted instances can send transfers",
+    UNREGISTERED_METHOD: "Unregistered method {}",
+    TRUSTEE_ONLY: "Only trustees can call this method",
+    ONE_YOCTO_REQUIRED: "Requires attached deposit of exactly 1 yoctoNEAR",
+    // interning
+    TOO_MANY_INTERNED_ACCOUNTS: "Too many interned accounts",
+    CHUNK_OUT_OF_ORDER: "Chunks must be imported in order",
+    // key_rotation
+    KEY_ROTATION_PENDING: "A key rotation is already pending",
+    NEW_KEY_IS_SIGNING_KEY: "New key is the signing key",
+    KEY_ROTATION_CANCELLER_ONLY: "Only the owner or a trustee can cancel a key rotation",
+    NO_KEY_ROTATION_PENDING: "No key rotation pending",
+    KEY_ROTATION_DELAYED: "Key rotation delay hasn't passed yet",
+    // lib
+    AMOUNT_TOO_LARGE: "Amount doesn't fit the counter",
+    OVERFLOW_WARNING: "Make sure you don't overflow, my friend.",
+    DEPOSITED_AS_CHANGE: "Deposited {} as change",
+    AMOUNT_NOT_DEPOSIT: "Amount must be the attached deposit of {}",
+    DEPOSIT_NOT_NEAR: "Deposits only back the NEAR Change",
+    MONEY_ADDED: "Added money to {}",
+    VALUE_CHANGED: "Value after change {}",
+    ALREADY_INITIALIZED: "Already initialized",
+    CHANGE_RESET: "Reset Change to zero",
+    BELOW_MINIMUM: "Change would fall below the minimum of {}",
+    ABOVE_MAXIMUM: "Change would exceed the maximum of {}",
+    CALLBACK_ONLY: "Only the contract can call its callbacks",
+    CHANGE_OVERFLOW: "Change overflow: current {}, delta {}",
+    // lock
+    ACCOUNT_LOCKED: "Account is locked",
+    INVALID_LOCK_DURATION: "Lock duration must be positive and at most a year",
+    // loyalty
+    POINTS_RATE_SETTER_ONLY: "Only the merchant or the owner can set the points rate",
+    REWARDS_DEPOSIT_REQUIRED: "Attach the NEAR to fund the rewards with",
+    POINTS_NOT_REDEEMABLE: "Merchant doesn't redeem points",
+    NOT_ENOUGH_POINTS: "Not enough points",
+    REDEMPTION_OVERFLOWS: "Redemption overflows",
+    REWARDS_POOL_SHORT: "Rewards pool can't cover the redemption",
+    // merchant
+    FEE_TOO_HIGH: "Fee can't exceed 100%",
+    MERCHANT_ALREADY_REGISTERED: "Merchant is already registered",
+    // migration
+    UNKNOWN_STATE_VERSION: "Unknown state version",
+    INVALID_STATE_VERSION: "Invalid state version",
+    // oracle
+    PRICE_FETCH_FAILED: "Could not fetch the price of {}: {}",
+    VALUATION_OVERFLOW: "Valuation overflow",
+    NO_PRICE_CACHED: "No price cached, call refresh_price first",
+    STALE_PRICE: "Cached price is stale, call refresh_price first",
+    TOKEN_NOT_WHITELISTED: "Token is not whitelisted",
+    NO_ORACLE_CONFIGURED: "No oracle configured",
+    // ownership
+    OWNER_AS_TRUSTEE: "The owner can't be a trustee",
+    // pause
+    CONTRACT_PAUSED: "Contract is paused",
+    CONTRACT_READ_ONLY: "Contract is read-only",
+    PAUSED: "Contract paused",
+    UNPAUSED: "Contract unpaused",
+    // pos
+    UNKNOWN_POS_SESSION: "Unknown POS session",
+    POS_SESSION_CLOSED: "POS session is closed",
+    POS_SESSION_OPEN: "Close your open POS session first",
+    NO_OPEN_POS_SESSION: "No open POS session",
+    // purchase
+    CHANGE_WITHDRAWN: "Withdrew change of {} to {}",
+    NO_MERCHANT_BENEFICIARY_CONFIGURED: "No merchant beneficiary configured",
+    PRICE_MUST_POSITIVE: "Price must be positive",
+    PRICE_NOT_COVERED: "Attached deposit doesn't cover the price",
+    NOT_ENOUGH_CHANGE: "Not enough change",
+    // rebate
+    REBATE_PAID: "Paid rebate of {} to {}",
+    NO_REBATE: "No rebate to claim",
+    TREASURY_SHORT_FOR_REBATE: "Treasury can't cover the rebate right now",
+    // referral
+    REFERRAL_BONUS_PAID: "Referral bonus of {} to {}",
+    SELF_REFERRAL: "Can't refer yourself",
+    CIRCULAR_REFERRAL: "Can't refer the account that referred you",
+    // relay
+    NONCE_ALREADY_USED: "Nonce already used",
+    NONCE_OUT_OF_ORDER: "Nonce out of order",
+    INVALID_RELAY_KEY: "Relay key must be an ed25519 key",
+    NO_RELAY_KEY: "Account {} has no relay key",
+    INVALID_RELAY_SIGNATURE: "Invalid relay signature",
+    // remote_transfer
+    REMOTE_TRANSFER_RECEIVED: "Received {} from {} on {}",
+    SAME_INSTANCE: "Target must be another instance",
+    NOT_ENOUGH_CHANGE_TO_SEND: "Not enough Change to send",
+    // savings
+    NOT_ENOUGH_SAVINGS: "Not enough savings",
+    // series
+    BUCKET_NOT_POSITIVE: "Bucket must be positive",
+    EMPTY_RANGE: "Range must end after it starts",
+    BUCKET_TOO_LONG: "Bucket is too long",
+    TOO_MANY_BUCKETS: "At most {} buckets per series",
+    // settlement
+    SETTLER_ONLY: "Only the merchant or the owner can settle",
+    REVENUE_BELOW_MINIMUM: "Revenue is below the minimum settlement amount",
+    UNKNOWN_SETTLEMENT: "Unknown settlement",
+    // sharding
+    ACCOUNT_ON_OTHER_SHARD: "Account {} is served by {}",
+    INVALID_SHARD_COUNT: "Shard count must be between 1 and {}",
+    INVALID_SHARD_ACCOUNT: "Invalid shard account {}",
+    SHARD_COUNT_FIXED: "The shard count can't change",
+    SHARDS_DEPLOYED: "Every shard is deployed",
+    SHARD_DEPOSIT_TOO_SMALL: "Attach at least {} yoctoNEAR per shard",
+    UNKNOWN_SHARD: "Unknown shard",
+    // snapshot
+    NO_SUCH_SNAPSHOT: "No such snapshot",
+    ROLLBACK_NEEDS_APPROVAL: "Rollbacks need trustee approval",
+    ROLLBACK_IN_PROGRESS: "A rollback is in progress, call continue_rollback",
+    NO_ROLLBACK_IN_PROGRESS: "No rollback in progress",
+    ROLLBACK_CONTINUES: "Restored {} so far, call continue_rollback for the rest",
+    // solvency
+    INSOLVENT_TRANSFER: "Transfer would leave the contract insolvent",
+    // split
+    INVALID_SPLIT_COUNT: "Between 1 and 10 splits are needed",
+    SPLITS_NOT_WHOLE: "Splits must add up to 100%",
+    DUPLICATE_SPLIT_RECIPIENT: "Split recipients must be distinct",
+    // state_export
+    EXPORT_WHILE_ACTIVE: "Pause the contract before exporting its state",
+    IMPORT_WHILE_ACTIVE: "Pause the contract before importing state",
+    MALFORMED_STATE_CHUNK: "Malformed state chunk",
+    STATE_CHUNK_IMPORTED: "State chunk imported",
+    // storage_management
+    ACCOUNT_NOT_REGISTERED: "Account {} is not registered",
+    STORAGE_DEPOSIT_TOO_SMALL: "The attached deposit is less than the minimum storage balance",
+    STORAGE_WITHDRAW_TOO_LARGE: "The amount is greater than the available storage balance",
+    CHANGE_BALANCE_LEFT: "Withdraw the change balance before unregistering",
+    UNREGISTER_NEEDS_FORCE: "Can't unregister an account holding Change without force",
+    // swap
+    NO_AMM_CONFIGURED: "No AMM configured",
+    NO_BASE_TOKEN_CONFIGURED: "No base token configured",
+    TOKEN_IS_BASE_TOKEN: "Token is already the base token",
+    BASE_TOKEN_NOT_WHITELISTED: "Base token is not whitelisted",
+    NO_SWAP_POOL: "No swap pool set for this token",
+    SURPLUS_ONLY: "Only the contract's own surplus can be swapped",
+    HOLDINGS_BELOW_OWED: "Holds {} of the token but owes {}, there is no surplus",
+    // throttle
+    RATE_LIMITED: "Rate limit exceeded, try again in {} ns",
+    // timelock
+    EXECUTION_TIME_IN_PAST: "Execution time must be in the future",
+    NO_SUCH_OPERATION: "No such operation",
+    TIMELOCKED: "Timelock hasn't passed yet",
+    // token
+    TOKEN_NOT_ACCEPTED: "Token {} is not accepted, refunding",
+    DEPOSIT_BELOW_MINIMUM: "Deposit below the minimum of {} for {}, refunding",
+    TOKEN_ALREADY_WHITELISTED: "Token is already whitelisted",
+    TOKEN_BALANCES_OWED: "Token still has balances owed to users",
+    PAUSED_REFUND: "Contract is paused, refunding",
+    NOT_ENOUGH_TOKENS: "Not enough tokens",
+    TOKEN_HELD_SHORT: "Only {} of the token is held here right now",
+    TOKEN_CURRENCY_IS_NEAR: "NEAR Change can only be backed by NEAR",
+    CHANGE_ROOM_REFUND: "Change can only take {} more, refunding {}",
+    // treasury
+    TREASURY_FUNDED: "Treasury funded with {}, now {}",
+    TREASURY_SHORT: "Treasury can't cover the withdrawal",
+    TREASURY_DEPOSIT_REQUIRED: "Attach a deposit to fund the treasury",
+    TRUSTEE_APPROVAL_REQUIRED: "Withdrawals above the threshold need trustee approval",
+    TREASURY_LIMITS_NEED_APPROVAL: "Treasury limits only change by trustee proposal",
+    // trustee
+    TRUSTEES_SET: "Trustees set: {} of {}",
+    INVALID_THRESHOLD: "Threshold must be between 1 and the number of trustees",
+    COLD_TREASURY_SHORT: "Cold treasury can't cover the refill",
+    RESET_NEEDS_APPROVAL: "Resets need trustee approval",
+    TRUSTEES_ALREADY_SET: "Trustees are already set",
+    UNKNOWN_PROPOSAL: "Unknown proposal",
+    PROPOSAL_ALREADY_EXECUTED: "Proposal was already executed",
+    ALREADY_CONFIRMED: "Already confirmed",
+    PROPOSAL_CANCELLED: "Proposal was cancelled",
+    PROPOSAL_EXPIRED: "Proposal expired",
+    NOT_PROPOSER: "Only the proposer can cancel a proposal",
+    TOO_MANY_PENDING_PROPOSALS: "At most {} proposals can be pending at once",
+    // velocity
+    WITHDRAWALS_PAUSED: "Withdrawals are paused",
+    WITHDRAWALS_RESUMED: "Withdrawals resumed",
+    // voucher
+    NO_VOUCHER_KEY_SET: "No voucher key set",
+    INVALID_VOUCHER_SIGNATURE: "Invalid voucher signature",
+    INVALID_VOUCHER_KEY: "Voucher key must be an ed25519 key",
+    VOUCHER_EXPIRED: "Voucher has expired",
+    VOUCHER_ALREADY_REDEEMED: "Voucher was already redeemed",
+    TREASURY_SHORT_FOR_VOUCHER: "Treasury can't cover the voucher",
+    // webhook
+    WEBHOOK_SETTER_ONLY: "Only the merchant or the owner can set the webhook",
+    INVALID_WEBHOOK_CONTRACT: "Invalid webhook contract",
+    EMPTY_WEBHOOK_METHOD: "Webhook method is empty",
+    INVALID_WEBHOOK_GAS: "Webhook gas must be positive and at most {}",
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns up to `limit` messages of the catalog starting at `from_index`,
+    /// as many as the gas allows, see [`pagination`](crate::pagination).
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_messages '{"from_index": "0", "limit": 100}'
+    /// ```
+    pub fn get_messages(&self, from_index: U64, limit: u64) -> Page<Message> {
+        paginate(limit, |n| {
+            let index = usize::try_from(from_index.0.checked_add(n)?).ok()?;
+            MESSAGES.get(index).copied()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::collections::HashSet;
+
+    #[test]
+    fn messages_carry_their_code() {
+        assert_eq!(
+            "[OWNER_ONLY] Only the owner can call this method",
+            OWNER_ONLY.to_string()
+        );
+        assert_eq!(
+            "[CHANGE_OVERFLOW] Change overflow: current 5, delta -7",
+            CHANGE_OVERFLOW.format(&[&5, &-7])
+        );
+        assert_eq!(
+            "[TOO_MANY_DECIMALS] At most {} decimals",
+            TOO_MANY_DECIMALS.format(&[])
+        );
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let codes: HashSet<&str> = MESSAGES.iter().map(|message| message.code).collect();
+        assert_eq!(MESSAGES.len(), codes.len());
+    }
+
+    #[test]
+    fn the_catalog_is_listed() {
+        testing_env!(context_for("alice.testnet", 0));
+        let contract = Change::default();
+        let page = contract.get_messages(U64(1), 2);
+        assert_eq!(vec![MESSAGES[1], MESSAGES[2]], page.items);
+        assert!(contract
+            .get_messages(U64(MESSAGES.len() as u64), 10)
+            .items
+            .is_empty());
+        assert!(contract.get_messages(U64(u64::MAX), 10).items.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "[AMOUNT_NOT_POSITIVE] Amount must be positive")]
+    fn panics_start_with_the_code() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(0)), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Upgrades of the contract state to a new layout.
+//!
+//! The root state is read as a [`VersionedChange`], so deploying code that
+//! changes the layout of [`Change`] doesn't brick the deployed state: the
+//! layout being replaced becomes a variant holding a copy of the old struct,
+//! and [`migrate`](Change::migrate), called by the owner right after the
+//! deploy, reads the state in the layout it was stored in, upgrades it and
+//! writes it back in the current one.
+//!
+//! The layout version is stored next to the state; states written before
+//! versioning have none and count as version 1, except for the single global
+//! `i32` the contract started out as, version 0, whose value is handed to the
+//! contract account, its owner, as Change in [`DEFAULT_CURRENCY`].
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// Storage key of the version of the layout the root state is stored in.
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+
+/// Layout version of [`Change`] as this code stores it.
+const STATE_VERSION: u32 = 1;
+
+/// Storage key near-sdk keeps the root state under.
+const STATE_KEY: &[u8] = b"STATE";
+
+/// The contract before Change was kept per account: one global counter.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ChangeV0 {
+    pub val: i32,
+}
+
+/// Every layout the root state has been stored in.
+// only ever read to be upgraded right away
+#[allow(clippy::large_enum_variant)]
+pub enum VersionedChange {
+    V0(ChangeV0),
+    V1(Change),
+}
+
+impl VersionedChange {
+    /// Reads the root state in the layout it was stored in.
+    fn read() -> Self {
+        match stored_state_version() {
+            0 => VersionedChange::V0(env::state_read().expect("Contract is not initialized")),
+            1 => VersionedChange::V1(env::state_read().expect("Contract is not initialized")),
+            _ => fail!(messages::UNKNOWN_STATE_VERSION),
+        }
+    }
+}
+
+impl From<VersionedChange> for Change {
+    /// Upgrades a stored state to the current layout.
+    fn from(state: VersionedChange) -> Self {
+        match state {
+            VersionedChange::V0(state) => {
+                let mut upgraded = Change::default();
+                let owner_id = upgraded.owner_id.clone();
+                upgraded.internal_set_num(&owner_id, DEFAULT_CURRENCY, i128::from(state.val));
+                upgraded
+            }
+            VersionedChange::V1(state) => state,
+        }
+    }
+}
+
+fn stored_state_version() -> u32 {
+    match env::storage_read(STATE_VERSION_KEY) {
+        Some(bytes) => {
+            u32::try_from_slice(&bytes).unwrap_or_else(|_| fail!(messages::INVALID_STATE_VERSION))
+        }
+        // an i32 and nothing else
+        None if env::storage_read(STATE_KEY).is_some_and(|state| state.len() == 4) => 0,
+        None => 1,
+    }
+}
+
+/// Records that the root state is stored in the current layout.
+pub(crate) fn internal_write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.try_to_vec().unwrap());
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct StateMigratedEvent {
+    from_version: u32,
+    to_version: u32,
+}
+
+#[near_bindgen]
+impl Change {
+    /// Upgrades the state to the layout of the deployed code. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet migrate '{}' --accountId YOU.testnet
+    /// ```
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let from_version = stored_state_version();
+        let state = Change::from(VersionedChange::read());
+        state.assert_access("migrate");
+        internal_write_state_version();
+        state.emit_event(
+            "state_migrated",
+            &StateMigratedEvent {
+                from_version,
+                to_version: STATE_VERSION,
+            },
+        );
+        state
+    }
+
+    /// Returns the layout version the state is stored in.
+    pub fn get_state_version(&self) -> u32 {
+        stored_state_version()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn deployed() -> Change {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        env::state_write(&contract);
+        contract
+    }
+
+    #[test]
+    fn owner_migrates_unversioned_state() {
+        let contract = deployed();
+        assert_eq!(1, contract.get_state_version());
+        testing_env!(context_for("alice.testnet", 0));
+        let contract = Change::migrate();
+        assert_eq!(
+            1000,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        assert_eq!(STATE_VERSION, contract.get_state_version());
+        assert_eq!(
+            vec!["EVENT_JSON:{\"data\":[{\"from_version\":1,\"to_version\":1}],\"event\":\"state_migrated\",\"standard\":\"changeback\",\"version\":\"1.0.0\"}"],
+            near_sdk::test_utils::get_logs()
+        );
+    }
+
+    #[test]
+    fn owner_migrates_the_global_i32() {
+        testing_env!(context_for("alice.testnet", 0));
+        env::state_write(&ChangeV0 {
+            val: -2_000_000_000,
+        });
+        assert_eq!(0, stored_state_version());
+        let contract = Change::migrate();
+        assert_eq!(STATE_VERSION, contract.get_state_version());
+        assert_eq!(
+            -2_000_000_000,
+            contract.get_num_for("alice.testnet".to_string(), None).0
+        );
+    }
+
+    #[test]
+    fn migrated_values_round_trip() {
+        let mut contract = deployed();
+        let jane = "jane.testnet".to_string();
+        contract.internal_set_num(&jane, DEFAULT_CURRENCY, i128::MIN);
+        env::state_write(&contract);
+        testing_env!(context_for("alice.testnet", 0));
+        let contract = Change::migrate();
+        let value = contract.get_num_for(jane, None);
+        assert_eq!(i128::MIN, value.0);
+        let json = near_sdk::serde_json::to_string(&value).unwrap();
+        assert_eq!("\"-170141183460469231731687303715884105728\"", json);
+        assert_eq!(value, near_sdk::serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_migrates() {
+        deployed();
+        testing_env!(context_for("jane.testnet", 0));
+        Change::migrate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown state version")]
+    fn unknown_versions_are_refused() {
+        deployed();
+        testing_env!(context_for("alice.testnet", 0));
+        env::storage_write(STATE_VERSION_KEY, &2u32.try_to_vec().unwrap());
+        Change::migrate();
+    }
+}
@@ -0,0 +1,128 @@
+//! Per-account notification preferences.
+//!
+//! Accounts opt out of categories of notifications addressed to them, kept
+//! as one bit per category in their record; every category is on until they
+//! do. Alerts are the [listener](crate::listener) calls about the account's
+//! Change moving. Receipts and marketing have no on-chain notification yet:
+//! receipt senders and campaign tools read them from
+//! [`get_notification_preferences`](Change::get_notification_preferences).
+//! Merchant [webhooks](crate::webhook) aren't addressed to the buyer, so its
+//! preferences don't touch them. Events aren't notifications either, they are
+//! the audit trail and always logged.
+
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum NotificationCategory {
+    Alerts,
+    Marketing,
+    Receipts,
+}
+
+impl NotificationCategory {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NotificationPreferences {
+    pub alerts: bool,
+    pub marketing: bool,
+    pub receipts: bool,
+}
+
+impl Change {
+    /// Whether `account_id` takes notifications of `category`.
+    pub(crate) fn internal_wants_notification(
+        &self,
+        account_id: &AccountId,
+        category: NotificationCategory,
+    ) -> bool {
+        self.internal_get_account(account_id).muted_notifications & category.bit() == 0
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sets which categories of notifications the caller takes.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_notification_preferences '{"preferences": {"alerts": true, "marketing": false, "receipts": true}}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn set_notification_preferences(&mut self, preferences: NotificationPreferences) {
+        self.assert_access("set_notification_preferences");
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_get_account(&account_id);
+        account.muted_notifications = [
+            (NotificationCategory::Alerts, preferences.alerts),
+            (NotificationCategory::Marketing, preferences.marketing),
+            (NotificationCategory::Receipts, preferences.receipts),
+        ]
+        .iter()
+        .filter(|(_, enabled)| !enabled)
+        .fold(0, |muted, (category, _)| muted | category.bit());
+        self.internal_set_account(&account_id, &account);
+    }
+
+    pub fn get_notification_preferences(&self, account_id: AccountId) -> NotificationPreferences {
+        NotificationPreferences {
+            alerts: self.internal_wants_notification(&account_id, NotificationCategory::Alerts),
+            marketing: self
+                .internal_wants_notification(&account_id, NotificationCategory::Marketing),
+            receipts: self.internal_wants_notification(&account_id, NotificationCategory::Receipts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn categories_are_on_until_muted() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        let jane = "jane.testnet".to_string();
+        let all = NotificationPreferences {
+            alerts: true,
+            marketing: true,
+            receipts: true,
+        };
+        assert_eq!(all, contract.get_notification_preferences(jane.clone()));
+        let preferences = NotificationPreferences {
+            marketing: false,
+            ..all
+        };
+        contract.set_notification_preferences(preferences.clone());
+        assert_eq!(preferences, contract.get_notification_preferences(jane));
+    }
+
+    #[test]
+    fn muted_alerts_skip_the_listener() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_listener(Some(ValidAccountId::try_from("listener.testnet").unwrap()));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_notification_preferences(NotificationPreferences {
+            alerts: false,
+            marketing: true,
+            receipts: true,
+        });
+        contract.add(None, None, None, None);
+        assert!(get_created_receipts().is_empty());
+        testing_env!(context_for("bob.testnet", 0));
+        contract.add(None, None, None, None);
+        assert!(!get_created_receipts().is_empty());
+    }
+}
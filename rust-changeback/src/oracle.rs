@@ -0,0 +1,443 @@
+//! USD prices fetched from an external oracle.
+//!
+//! Prices are cached with the time they were fetched. Any valuation made from
+//! a cached price older than `price_max_age` is refused, so callers have to
+//! [refresh](Change::refresh_price) it first instead of silently using stale data.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance, Promise, Timestamp};
+
+use crate::amount::{div_rounded, RoundingMode};
+use crate::*;
+
+/// Valuations are expressed in millionths of a USD.
+pub(crate) const USD_DECIMALS: u8 = 6;
+
+/// Price of one indivisible unit of a token: `multiplier / 10^decimals` USD.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CachedPrice {
+    pub price: Price,
+    /// Block timestamp at which the price was received.
+    pub fetched_at: U64,
+}
+
+/// USD value of one token amount, from its cached price.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenUsdValue {
+    pub token_id: AccountId,
+    pub amount: U128,
+    /// Value in millionths of a USD, if a price was ever fetched and the value fits in a `u128`.
+    pub value_usd: Option<U128>,
+    /// Nanoseconds since the price was fetched.
+    pub price_age: Option<U64>,
+    /// Whether the price is older than `price_max_age`.
+    pub stale: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UsdValuation {
+    /// Sum of the values with a fresh price, in millionths of a USD.
+    pub total_usd: U128,
+    /// False when some token had no price or a stale one, or its value would
+    /// overflow the total, so `total_usd` leaves it out.
+    pub complete: bool,
+    pub tokens: Vec<TokenUsdValue>,
+}
+
+impl Price {
+    /// Returns the value of `amount` units in millionths of a USD, rounded with `mode`,
+    /// or `None` if the value or a step of its computation doesn't fit in a `u128`.
+    pub(crate) fn value_usd(&self, amount: Balance, mode: RoundingMode) -> Option<Balance> {
+        let value = amount.checked_mul(self.multiplier.0)?;
+        if self.decimals >= USD_DECIMALS {
+            match 10u128.checked_pow(u32::from(self.decimals - USD_DECIMALS)) {
+                Some(scale) => Some(div_rounded(value, scale, mode)),
+                // the scale alone exceeds a u128, so the value is below a millionth of a USD
+                None => Some(u128::from(mode == RoundingMode::Ceil && value > 0)),
+            }
+        } else {
+            value.checked_mul(10u128.checked_pow(u32::from(USD_DECIMALS - self.decimals))?)
+        }
+    }
+}
+
+impl Change {
+    /// Returns the cached price of `token_id`, panicking if it is missing or stale.
+    pub(crate) fn internal_fresh_price(&self, token_id: &AccountId) -> Price {
+        let cached = self
+            .prices
+            .get(token_id)
+            .unwrap_or_else(|| fail!(messages::NO_PRICE_CACHED));
+        if self.internal_price_age(&cached) > self.config().price_max_age.0 {
+            fail!(messages::STALE_PRICE);
+        }
+        cached.price
+    }
+
+    pub(crate) fn internal_price_age(&self, cached: &CachedPrice) -> Timestamp {
+        env::block_timestamp().saturating_sub(cached.fetched_at.0)
+    }
+
+    /// Values every whitelisted token with the amount `amount_of` returns for it.
+    fn internal_valuation<F: Fn(&AccountId) -> Balance>(&self, amount_of: F) -> UsdValuation {
+        let mut valuation = UsdValuation {
+            total_usd: U128(0),
+            complete: true,
+            tokens: vec![],
+        };
+        for token_id in self.tokens.keys() {
+            let amount = amount_of(&token_id);
+            if amount == 0 {
+                continue;
+            }
+            let cached = self.prices.get(&token_id);
+            let price_age = cached.map(|cached| self.internal_price_age(&cached));
+            let stale = price_age.is_none_or(|age| age > self.config().price_max_age.0);
+            let value_usd = cached
+                .and_then(|cached| cached.price.value_usd(amount, self.config().rounding_mode));
+            match value_usd
+                .filter(|_| !stale)
+                .and_then(|value| valuation.total_usd.0.checked_add(value))
+            {
+                Some(total) => valuation.total_usd.0 = total,
+                None => valuation.complete = false,
+            }
+            valuation.tokens.push(TokenUsdValue {
+                token_id,
+                amount: U128(amount),
+                value_usd: value_usd.map(U128),
+                price_age: price_age.map(U64),
+                stale,
+            });
+        }
+        valuation
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Fetches the current price of `token_id` from the oracle. Anyone can call this.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet refresh_price '{"token_id": "usdc.testnet"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn refresh_price(&mut self, token_id: ValidAccountId) -> Promise {
+        self.assert_access("refresh_price");
+        let token_id: AccountId = token_id.into();
+        require!(
+            self.tokens.get(&token_id).is_some(),
+            messages::TOKEN_NOT_WHITELISTED
+        );
+        let oracle = self
+            .config
+            .oracle_contract
+            .clone()
+            .unwrap_or_else(|| fail!(messages::NO_ORACLE_CONFIGURED));
+        ext_oracle::get_price(token_id.clone(), &oracle, 0, self.config().gas.get_price.0).then(
+            ext_self::on_price_fetched(
+                token_id,
+                &env::current_account_id(),
+                0,
+                self.config().gas.callback.0,
+            ),
+        )
+    }
+
+    /// Caches the price returned by the oracle.
+    pub fn on_price_fetched(&mut self, token_id: AccountId) -> Option<Price> {
+        self.assert_access("on_price_fetched");
+        match promise_result::<Price>() {
+            Ok(price) => {
+                let cached = CachedPrice {
+                    price,
+                    fetched_at: U64(env::block_timestamp()),
+                };
+                self.prices.insert(&token_id, &cached);
+                Some(price)
+            }
+            Err(error) => {
+                self.log(
+                    LogLevel::Info,
+                    messages::PRICE_FETCH_FAILED,
+                    &[&token_id, &error],
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns the cached price of `token_id` and when it was fetched, stale or not.
+    pub fn get_price(&self, token_id: AccountId) -> Option<CachedPrice> {
+        self.prices.get(&token_id)
+    }
+
+    /// Returns the value of `amount` of `token_id` in millionths of a USD.
+    ///
+    /// Fails if the cached price is older than `price_max_age`, or if the value
+    /// doesn't fit in a `u128`.
+    pub fn get_value_usd(&self, token_id: AccountId, amount: U128) -> U128 {
+        U128(
+            self.internal_fresh_price(&token_id)
+                .value_usd(amount.0, self.config().rounding_mode)
+                .unwrap_or_else(|| fail!(messages::VALUATION_OVERFLOW)),
+        )
+    }
+
+    /// Returns the USD value of every token balance of `account_id`.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_balance_usd '{"account_id": "donation.YOU.testnet"}'
+    /// ```
+    pub fn get_balance_usd(&self, account_id: AccountId) -> UsdValuation {
+        self.internal_valuation(|token_id| self.internal_token_balance(&account_id, token_id))
+    }
+
+    /// Returns the USD value of all tokens held by the contract.
+    pub fn get_total_usd(&self) -> UsdValuation {
+        self.internal_valuation(|token_id| {
+            self.tokens
+                .get(token_id)
+                .map_or(0, |token| token.total_held)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MINUTE;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::PromiseResult;
+    use std::convert::TryFrom;
+
+    fn oracle_contract() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                oracle_contract: Some("oracle.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        contract.add_token(
+            ValidAccountId::try_from("usdc.testnet").unwrap(),
+            6,
+            U128(0),
+        );
+        contract
+    }
+
+    /// Delivers `price` to the callback at `timestamp`.
+    fn fetch_price(contract: &mut Change, price: &str, timestamp: u64) {
+        fetch_price_of(contract, "usdc.testnet", price, timestamp);
+    }
+
+    fn fetch_price_of(contract: &mut Change, token_id: &str, price: &str, timestamp: u64) {
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = timestamp;
+        testing_env!(
+            context,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(price.as_bytes().to_vec())]
+        );
+        contract.on_price_fetched(token_id.to_string());
+    }
+
+    fn at(timestamp: u64) {
+        let mut context = context_for("jane.testnet", 0);
+        context.block_timestamp = timestamp;
+        testing_env!(context);
+    }
+
+    #[test]
+    fn fresh_price_values_amounts() {
+        let mut contract = oracle_contract();
+        contract.refresh_price(ValidAccountId::try_from("usdc.testnet").unwrap());
+        // 1 USDC (10^6 units) is worth 0.99 USD
+        fetch_price(
+            &mut contract,
+            r#"{"multiplier": "9900", "decimals": 10}"#,
+            0,
+        );
+        at(5 * MINUTE);
+        let value = contract.get_value_usd("usdc.testnet".to_string(), U128(2_000_000));
+        assert_eq!(1_980_000, value.0);
+    }
+
+    #[test]
+    fn values_round_by_mode() {
+        let mut contract = oracle_contract();
+        // one unit is worth 0.5 millionths of a USD
+        fetch_price(&mut contract, r#"{"multiplier": "5", "decimals": 7}"#, 0);
+        at(0);
+        assert_eq!(
+            1,
+            contract
+                .get_value_usd("usdc.testnet".to_string(), U128(3))
+                .0
+        );
+        testing_env!(context_for("alice.testnet", 0));
+        contract.set_config(
+            Config {
+                oracle_contract: Some("oracle.testnet".to_string()),
+                rounding_mode: RoundingMode::Ceil,
+                ..Config::default()
+            },
+            None,
+        );
+        assert_eq!(
+            2,
+            contract
+                .get_value_usd("usdc.testnet".to_string(), U128(3))
+                .0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Valuation overflow")]
+    fn scaling_up_a_huge_value_overflows() {
+        let mut contract = oracle_contract();
+        // a whole USD per unit, scaled up by 10^6 after multiplying
+        fetch_price(&mut contract, r#"{"multiplier": "1", "decimals": 0}"#, 0);
+        at(0);
+        contract.get_value_usd("usdc.testnet".to_string(), U128(u128::MAX / 1_000));
+    }
+
+    #[test]
+    fn valuations_leave_out_values_that_overflow() {
+        let mut contract = oracle_contract();
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(
+            ValidAccountId::try_from("jane.testnet").unwrap(),
+            U128(u128::MAX / 1_000),
+            String::new(),
+        );
+        fetch_price(&mut contract, r#"{"multiplier": "1", "decimals": 0}"#, 0);
+        at(0);
+        let jane = contract.get_balance_usd("jane.testnet".to_string());
+        assert_eq!(0, jane.total_usd.0);
+        assert!(!jane.complete);
+        assert_eq!(None, jane.tokens[0].value_usd);
+    }
+
+    #[test]
+    fn total_leaves_out_values_that_overflow_it() {
+        let mut contract = oracle_contract();
+        contract.add_token(ValidAccountId::try_from("dai.testnet").unwrap(), 6, U128(0));
+        for token_id in ["usdc.testnet", "dai.testnet"] {
+            testing_env!(context_for(token_id, 0));
+            contract.ft_on_transfer(
+                ValidAccountId::try_from("jane.testnet").unwrap(),
+                U128(u128::MAX / 10),
+                String::new(),
+            );
+            fetch_price_of(
+                &mut contract,
+                token_id,
+                r#"{"multiplier": "8", "decimals": 6}"#,
+                0,
+            );
+        }
+        at(0);
+        let total = contract.get_total_usd();
+        assert_eq!(u128::MAX / 10 * 8, total.total_usd.0);
+        assert!(!total.complete);
+        assert_eq!(2, total.tokens.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Cached price is stale, call refresh_price first")]
+    fn stale_price_is_refused() {
+        let mut contract = oracle_contract();
+        fetch_price(
+            &mut contract,
+            r#"{"multiplier": "9900", "decimals": 10}"#,
+            0,
+        );
+        at(5 * MINUTE + 1);
+        contract.get_value_usd("usdc.testnet".to_string(), U128(1));
+    }
+
+    #[test]
+    fn failed_fetch_keeps_the_previous_price() {
+        let mut contract = oracle_contract();
+        fetch_price(
+            &mut contract,
+            r#"{"multiplier": "9900", "decimals": 10}"#,
+            7,
+        );
+        fetch_price(&mut contract, "not a price", 9);
+        let cached = contract.get_price("usdc.testnet".to_string()).unwrap();
+        assert_eq!(9900, cached.price.multiplier.0);
+        assert_eq!(7, cached.fetched_at.0);
+    }
+
+    #[test]
+    fn usd_views_leave_out_stale_prices() {
+        let mut contract = oracle_contract();
+        contract.add_token(
+            ValidAccountId::try_from("dai.testnet").unwrap(),
+            18,
+            U128(0),
+        );
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(
+            ValidAccountId::try_from("jane.testnet").unwrap(),
+            U128(3_000_000),
+            String::new(),
+        );
+        testing_env!(context_for("dai.testnet", 0));
+        contract.ft_on_transfer(
+            ValidAccountId::try_from("bob.testnet").unwrap(),
+            U128(10u128.pow(18)),
+            String::new(),
+        );
+        fetch_price(
+            &mut contract,
+            r#"{"multiplier": "10000", "decimals": 10}"#,
+            0,
+        );
+        fetch_price_of(
+            &mut contract,
+            "dai.testnet",
+            r#"{"multiplier": "1", "decimals": 18}"#,
+            0,
+        );
+        at(MINUTE);
+        let jane = contract.get_balance_usd("jane.testnet".to_string());
+        assert_eq!(3_000_000, jane.total_usd.0);
+        assert!(jane.complete);
+        assert_eq!(1, jane.tokens.len());
+        assert_eq!(Some(MINUTE), jane.tokens[0].price_age.map(|age| age.0));
+        assert_eq!(4_000_000, contract.get_total_usd().total_usd.0);
+
+        fetch_price(
+            &mut contract,
+            r#"{"multiplier": "10000", "decimals": 10}"#,
+            10 * MINUTE,
+        );
+        let total = contract.get_total_usd();
+        assert_eq!(3_000_000, total.total_usd.0);
+        assert!(!total.complete);
+        assert!(total.tokens.iter().any(|token| token.stale));
+    }
+}
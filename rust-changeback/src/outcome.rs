@@ -0,0 +1,90 @@
+//! What a counter call did, returned so frontends can update optimistically
+//! without a follow-up view call.
+//!
+//! `add`, `change`, `reset`, `batch_apply` and `relay` all return an
+//! [`OperationOutcome`]. A replayed call (see
+//! [`idempotency`](crate::idempotency)) returns the outcome of the original
+//! one, except that it emitted no events this time.
+
+use near_sdk::json_types::{I128, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OperationOutcome {
+    /// Id of the last [history](crate::history) record the call wrote.
+    pub receipt_id: U64,
+    /// Change the account was left with.
+    pub new_balance: I128,
+    /// `new_balance` formatted for display, see [`display`](crate::display).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_balance_formatted: Option<String>,
+    /// Fee taken by the call, in yoctoNEAR; the counter calls charge none.
+    pub fee_charged: U128,
+    /// Events the call emitted.
+    pub events_emitted: u32,
+}
+
+impl Change {
+    /// Returns the outcome of the current call, which left `new_balance`.
+    pub(crate) fn internal_outcome(&self, new_balance: i128) -> OperationOutcome {
+        OperationOutcome {
+            receipt_id: U64(self.history.len() - 1),
+            new_balance: I128(new_balance),
+            new_balance_formatted: self.internal_format(new_balance),
+            fee_charged: U128(0),
+            events_emitted: self.events_emitted.get(),
+        }
+    }
+
+    /// Returns the outcome of a replayed call whose original wrote history
+    /// record `record_id` and left `new_balance`.
+    pub(crate) fn internal_replayed_outcome(
+        &self,
+        record_id: u64,
+        new_balance: i128,
+    ) -> OperationOutcome {
+        OperationOutcome {
+            receipt_id: U64(record_id),
+            new_balance: I128(new_balance),
+            new_balance_formatted: self.internal_format(new_balance),
+            fee_charged: U128(0),
+            events_emitted: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn counter_calls_report_their_outcome() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.events_emitted.set(0);
+        assert_eq!(
+            OperationOutcome {
+                receipt_id: U64(1),
+                new_balance: I128(990),
+                new_balance_formatted: None,
+                fee_charged: U128(0),
+                events_emitted: 1,
+            },
+            contract.change(Some("tx-1".to_string()), None, None)
+        );
+        testing_env!(context_for("jane.testnet", 0));
+        contract.events_emitted.set(0);
+        let replayed = contract.change(Some("tx-1".to_string()), None, None);
+        assert_eq!(U64(1), replayed.receipt_id);
+        assert_eq!(I128(990), replayed.new_balance);
+        assert_eq!(0, replayed.events_emitted);
+    }
+}
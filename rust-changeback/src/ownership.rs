@@ -0,0 +1,90 @@
+//! The contract owner, who calls the administrative methods.
+//!
+//! The owner is set by [`new`](Change::new), or is the contract account itself
+//! when it was deployed without calling it, and can hand ownership over to
+//! another account with [`transfer_ownership`](Change::transfer_ownership).
+
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct OwnershipTransferredEvent<'a> {
+    old_owner_id: &'a AccountId,
+    new_owner_id: &'a AccountId,
+}
+
+#[near_bindgen]
+impl Change {
+    /// Makes `new_owner` the owner. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet transfer_ownership '{"new_owner": "admin.testnet"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn transfer_ownership(&mut self, new_owner: ValidAccountId) {
+        self.assert_access("transfer_ownership");
+        let new_owner_id: AccountId = new_owner.into();
+        require!(
+            !self.trustees.contains(&new_owner_id),
+            messages::OWNER_AS_TRUSTEE
+        );
+        self.emit_event(
+            "ownership_transferred",
+            &OwnershipTransferredEvent {
+                old_owner_id: &self.owner_id,
+                new_owner_id: &new_owner_id,
+            },
+        );
+        self.owner_id = new_owner_id;
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    #[test]
+    fn ownership_is_handed_over() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::new(account("admin.testnet"));
+        assert_eq!("admin.testnet", contract.get_owner());
+        testing_env!(context_for("admin.testnet", 0));
+        contract.transfer_ownership(account("jane.testnet"));
+        assert_eq!("jane.testnet", contract.get_owner());
+        testing_env!(context_for("jane.testnet", 0));
+        contract.pause();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn former_owners_lose_access() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.transfer_ownership(account("jane.testnet"));
+        contract.reset(account("jane.testnet"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The owner can't be a trustee")]
+    fn trustees_cannot_become_owner() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.init_trustees(vec![account("t1.testnet")], 1);
+        contract.transfer_ownership(account("t1.testnet"));
+    }
+}
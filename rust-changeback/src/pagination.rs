@@ -0,0 +1,68 @@
+//! Page sizes bounded by what the entries cost to return, rather than a fixed
+//! maximum.
+//!
+//! Views can't read the gas they use, so paginated views measure the next
+//! best thing: they serialize their first entry and, taking its size as that
+//! of every entry, clamp the requested `limit` to what fits in
+//! `PAGE_BYTES_BUDGET`. Larger entries make for shorter pages. The page
+//! reports the limit it was built with, so a client asking for too much learns
+//! what to ask for next time.
+
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+
+/// Serialized bytes the entries of one page may add up to, keeping the gas of
+/// loading and returning them well within a view call's.
+const PAGE_BYTES_BUDGET: u64 = 32 * 1024;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// `limit` after clamping it to the budget; the requested one when there
+    /// was nothing to measure.
+    pub limit: u64,
+}
+
+/// Builds a page of up to `limit` entries, `entry(n)` returning the n-th
+/// one, or `None` past the last.
+pub(crate) fn paginate<T: Serialize>(
+    limit: u64,
+    mut entry: impl FnMut(u64) -> Option<T>,
+) -> Page<T> {
+    let first = match (limit > 0).then(|| entry(0)).flatten() {
+        Some(first) => first,
+        None => {
+            return Page {
+                items: vec![],
+                limit,
+            }
+        }
+    };
+    let entry_bytes = serde_json::to_vec(&first)
+        .expect("Entry doesn't serialize")
+        .len() as u64;
+    let limit = limit.min(PAGE_BYTES_BUDGET / entry_bytes.max(1)).max(1);
+    let mut items = vec![first];
+    items.extend((1..limit).map_while(&mut entry));
+    Page { items, limit }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_fit_the_budget() {
+        let small = paginate(30, |n| (n < 100).then_some(n));
+        assert_eq!(30, small.limit);
+        assert_eq!((0..30).collect::<Vec<_>>(), small.items);
+        assert_eq!(100, paginate(1000, |n| (n < 100).then_some(n)).items.len());
+        assert!(paginate(0, Some).items.is_empty());
+
+        // 1 KiB entries once quoted
+        let large = paginate(1000, |_| Some("x".repeat(1022)));
+        assert_eq!(32, large.limit);
+        assert_eq!(32, large.items.len());
+    }
+}
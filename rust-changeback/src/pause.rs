@@ -0,0 +1,227 @@
+//! Pausing the contract.
+//!
+//! While paused, every method that changes state is refused with "Contract is
+//! paused" (`add` / `change` / `reset`, purchases, settlements, withdrawals,
+//! settings) and token deposits are refunded. [`assert_access`](Change::assert_access)
+//! refuses them from the [method registry](crate::interface), so a new method
+//! is covered as soon as it is registered. Only the methods registered to run
+//! while paused get through: pausing and unpausing, read-only mode, roles,
+//! ownership and key rotation, trustee proposals, rollbacks, migrations, state
+//! imports and the callbacks of promises sent before the pause. The owner
+//! pauses the contract in an emergency or for maintenance such as
+//! [exporting its state](crate::state_export).
+//!
+//! Read-only mode is the emergency brake for a security incident: accounts
+//! with the `Guardian` role can switch it on to refuse every state-changing call
+//! (counter updates, purchases, deposits, gift cards, coupons, points) while
+//! users keep withdrawing what they already hold: their change, rebates and
+//! savings, and merchants their settlements.
+
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+impl Change {
+    /// Panics while the contract is paused.
+    pub(crate) fn assert_not_paused(&self) {
+        require!(!self.paused, messages::CONTRACT_PAUSED);
+    }
+
+    /// Panics while the contract is paused or read-only.
+    pub(crate) fn assert_writable(&self) {
+        self.assert_not_paused();
+        require!(!self.read_only, messages::CONTRACT_READ_ONLY);
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Refuses balance-moving calls until [`unpause`](Change::unpause). Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet pause --accountId Change.YOU.testnet
+    /// ```
+    pub fn pause(&mut self) {
+        self.assert_access("pause");
+        self.paused = true;
+        self.log(LogLevel::Info, messages::PAUSED, &[]);
+    }
+
+    /// Lets balance-moving calls through again. Owner only.
+    pub fn unpause(&mut self) {
+        self.assert_access("unpause");
+        self.paused = false;
+        self.log(LogLevel::Info, messages::UNPAUSED, &[]);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Refuses every call but withdrawals until [`exit_read_only`](Change::exit_read_only).
+    /// Guardians only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet enter_read_only --accountId guardian.testnet
+    /// ```
+    pub fn enter_read_only(&mut self) {
+        self.assert_access("enter_read_only");
+        self.read_only = true;
+        self.emit_event("read_only_entered", &env::predecessor_account_id());
+    }
+
+    /// Lets every call through again. Guardians only.
+    pub fn exit_read_only(&mut self) {
+        self.assert_access("exit_read_only");
+        self.read_only = false;
+        self.emit_event("read_only_exited", &env::predecessor_account_id());
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::METHODS;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+    use std::panic::AssertUnwindSafe;
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_mutations() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        contract.add(None, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_change() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        contract.change(None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_reset() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+    }
+
+    #[test]
+    fn every_mutating_method_refuses_while_paused() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        let refused = METHODS
+            .iter()
+            .filter(|method| method.mutable && !method.while_paused);
+        for method in refused {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                contract.assert_access(method.name);
+            }));
+            let message = *result.unwrap_err().downcast::<String>().unwrap();
+            assert!(message.contains("Contract is paused"), "{}", method.name);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_settings() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_beneficiary(
+            Some(ValidAccountId::try_from("bob.testnet").unwrap()),
+            near_sdk::json_types::U64(0),
+        );
+    }
+
+    #[test]
+    fn emergency_controls_run_while_paused() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        contract.grant_role(
+            Role::Guardian,
+            ValidAccountId::try_from("guardian.testnet").unwrap(),
+        );
+        testing_env!(context_for("guardian.testnet", 0));
+        contract.enter_read_only();
+        assert!(contract.is_read_only());
+    }
+
+    #[test]
+    fn unpaused_contract_works_again() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        assert!(contract.is_paused());
+        contract.unpause();
+        contract.add(None, None, None, None);
+        assert_eq!(
+            1000,
+            contract.get_num_for("alice.testnet".to_string(), None).0
+        );
+    }
+
+    fn read_only_contract() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.grant_role(
+            Role::Guardian,
+            ValidAccountId::try_from("guardian.testnet").unwrap(),
+        );
+        testing_env!(context_for("guardian.testnet", 0));
+        contract.enter_read_only();
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is read-only")]
+    fn read_only_contract_refuses_mutations() {
+        let mut contract = read_only_contract();
+        assert!(contract.is_read_only());
+        testing_env!(context_for("jane.testnet", 100));
+        contract.purchase(U128(30), None, None, None, None, None);
+    }
+
+    #[test]
+    fn read_only_contract_still_pays_out_balances() {
+        let mut contract = read_only_contract();
+        contract.internal_credit_balance(&"jane.testnet".to_string(), 70, Book::Custody);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.withdraw_change(U128(70));
+        assert_eq!(0, contract.get_balance("jane.testnet".to_string()).0);
+        testing_env!(context_for("guardian.testnet", 0));
+        contract.exit_read_only();
+        assert!(!contract.is_read_only());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only guardians can change the read-only mode")]
+    fn only_guardians_switch_read_only() {
+        testing_env!(context_for("alice.testnet", 0));
+        Change::default().enter_read_only();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_owner_pauses() {
+        testing_env!(context_for("jane.testnet", 0));
+        Change::default().pause();
+    }
+}
@@ -0,0 +1,194 @@
+//! Point-of-sale sessions.
+//!
+//! A cashier opens a session for the register (device) they work at, passes
+//! its id with every purchase rung up there, and closes it at the end of the
+//! day. The session keeps the totals needed for end-of-day closing. Each
+//! cashier has at most one open session.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PosSession {
+    pub cashier_id: AccountId,
+    pub register_id: String,
+    pub opened_at: Timestamp,
+    pub closed_at: Option<Timestamp>,
+    pub purchases: u64,
+    /// Sum of the prices of the session's purchases.
+    pub sales: Balance,
+    /// Sum of the change credited during the session.
+    pub change: Balance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PosSessionView {
+    pub id: U64,
+    pub cashier_id: AccountId,
+    pub register_id: String,
+    pub opened_at: U64,
+    pub closed_at: Option<U64>,
+    pub purchases: U64,
+    pub sales: U128,
+    pub change: U128,
+}
+
+impl PosSessionView {
+    fn new(id: u64, session: PosSession) -> Self {
+        Self {
+            id: U64(id),
+            cashier_id: session.cashier_id,
+            register_id: session.register_id,
+            opened_at: U64(session.opened_at),
+            closed_at: session.closed_at.map(U64),
+            purchases: U64(session.purchases),
+            sales: U128(session.sales),
+            change: U128(session.change),
+        }
+    }
+}
+
+impl Change {
+    /// Adds a purchase to the totals of the open session `session_id`.
+    pub(crate) fn internal_record_session_sale(
+        &mut self,
+        session_id: u64,
+        price: Balance,
+        change: Balance,
+    ) {
+        let mut session = self
+            .pos_sessions
+            .get(session_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_POS_SESSION));
+        require!(session.closed_at.is_none(), messages::POS_SESSION_CLOSED);
+        session.purchases += 1;
+        session.sales += price;
+        session.change += change;
+        self.pos_sessions.replace(session_id, &session);
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Opens a session for the caller at `register_id` and returns its id.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet open_pos_session '{"register_id": "front-1"}' --accountId cashier.YOU.testnet
+    /// ```
+    pub fn open_pos_session(&mut self, register_id: String) -> U64 {
+        self.assert_access("open_pos_session");
+        self.assert_writable();
+        let cashier_id = env::predecessor_account_id();
+        require!(
+            self.open_pos_sessions.get(&cashier_id).is_none(),
+            messages::POS_SESSION_OPEN
+        );
+        let session = PosSession {
+            cashier_id: cashier_id.clone(),
+            register_id,
+            opened_at: env::block_timestamp(),
+            closed_at: None,
+            purchases: 0,
+            sales: 0,
+            change: 0,
+        };
+        let id = self.pos_sessions.len();
+        self.pos_sessions.push(&session);
+        self.open_pos_sessions.insert(&cashier_id, &id);
+        self.emit_event("pos_session_opened", &PosSessionView::new(id, session));
+        U64(id)
+    }
+
+    /// Closes the caller's open session and returns its summary.
+    pub fn close_pos_session(&mut self) -> PosSessionView {
+        self.assert_access("close_pos_session");
+        let cashier_id = env::predecessor_account_id();
+        let id = self
+            .open_pos_sessions
+            .remove(&cashier_id)
+            .unwrap_or_else(|| fail!(messages::NO_OPEN_POS_SESSION));
+        let mut session = self.pos_sessions.get(id).unwrap();
+        session.closed_at = Some(env::block_timestamp());
+        self.pos_sessions.replace(id, &session);
+        let summary = PosSessionView::new(id, session);
+        self.emit_event("pos_session_closed", &summary);
+        summary
+    }
+
+    /// Returns the summary of session `session_id`, open or closed.
+    pub fn get_pos_session(&self, session_id: U64) -> Option<PosSessionView> {
+        self.pos_sessions
+            .get(session_id.0)
+            .map(|session| PosSessionView::new(session_id.0, session))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn shop() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        contract
+    }
+
+    #[test]
+    fn session_summarizes_its_purchases() {
+        let mut contract = shop();
+        testing_env!(context_for("cashier.testnet", 0));
+        let session_id = contract.open_pos_session("front-1".to_string());
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(U128(430), None, None, Some(session_id), None, None);
+        testing_env!(context_for("bob.testnet", 100));
+        contract.purchase(U128(100), None, None, Some(session_id), None, None);
+        contract.purchase(U128(10), Some(true), None, None, None, None);
+        let receipt = contract.get_receipt(U64(0)).unwrap();
+        assert_eq!(Some(session_id.0), receipt.session_id.map(|id| id.0));
+
+        let mut context = context_for("cashier.testnet", 0);
+        context.block_timestamp = 42;
+        testing_env!(context);
+        let summary = contract.close_pos_session();
+        assert_eq!("front-1", summary.register_id);
+        assert_eq!(2, summary.purchases.0);
+        assert_eq!(530, summary.sales.0);
+        assert_eq!(70, summary.change.0);
+        assert_eq!(Some(42), summary.closed_at.map(|at| at.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "POS session is closed")]
+    fn closed_session_takes_no_purchases() {
+        let mut contract = shop();
+        testing_env!(context_for("cashier.testnet", 0));
+        let session_id = contract.open_pos_session("front-1".to_string());
+        contract.close_pos_session();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(U128(430), None, None, Some(session_id), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Close your open POS session first")]
+    fn one_open_session_per_cashier() {
+        let mut contract = shop();
+        testing_env!(context_for("cashier.testnet", 0));
+        contract.open_pos_session("front-1".to_string());
+        contract.open_pos_session("front-2".to_string());
+    }
+}
@@ -0,0 +1,490 @@
+//! Paying for a purchase in NEAR and keeping the change.
+//!
+//! The buyer attaches at least the price; the price goes to the merchant
+//! beneficiary and whatever is left over is credited to the buyer's balance,
+//! instead of having to be handed back in cash. With `use_balance` the
+//! stored change is spent first and only the shortfall has to be attached.
+//!
+//! A purchase made at a registered [merchant](crate::merchant) is not forwarded
+//! right away; the price is added to the merchant's revenue instead, which
+//! becomes settleable once the merchant's dispute window has passed.
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Promise};
+
+use crate::receipt::Receipt;
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PurchaseEvent<'a> {
+    receipt_id: U64,
+    buyer_id: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merchant_id: Option<&'a AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    beneficiary_id: Option<&'a AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<U64>,
+    price: U128,
+    from_balance: U128,
+    change: U128,
+}
+
+/// How a purchase would be paid, see [`internal_quote_purchase`](Change::internal_quote_purchase).
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseQuote {
+    /// Part of the price taken from the buyer's stored change.
+    pub from_balance: U128,
+    /// Part of the price the deposit has to cover.
+    pub due: U128,
+    /// What is left of the deposit and credited back as change.
+    pub change: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RefundEvent<'a> {
+    account_id: &'a AccountId,
+    amount: U128,
+}
+
+/// Returns who pays `price`: the `prepaid` payers with their amounts, and
+/// `buyer_id` with the rest.
+fn payers_of(
+    buyer_id: &AccountId,
+    price: Balance,
+    prepaid: &[(AccountId, Balance)],
+) -> Vec<(AccountId, Balance)> {
+    let rest = price - prepaid.iter().map(|(_, amount)| amount).sum::<Balance>();
+    let mut payers = prepaid.to_vec();
+    match payers.iter_mut().find(|(payer_id, _)| payer_id == buyer_id) {
+        Some((_, amount)) => *amount += rest,
+        None => payers.push((buyer_id.clone(), rest)),
+    }
+    payers
+}
+
+impl Change {
+    /// Adds `amount` yoctoNEAR held in `from` to the change balance of `account_id`.
+    pub(crate) fn internal_credit_balance(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+        from: Book,
+    ) {
+        let mut account = self.internal_get_account(account_id);
+        account.balance += amount;
+        self.internal_set_account(account_id, &account);
+        self.internal_post(Posting::transfer(from, Book::UserChange, amount));
+    }
+
+    /// Checks a purchase the way [`internal_purchase`](Change::internal_purchase)
+    /// does and works out how it is paid, returning the error it would panic with otherwise.
+    pub(crate) fn internal_quote_purchase(
+        &self,
+        buyer_id: &AccountId,
+        price: Balance,
+        prepaid: Balance,
+        deposit: Balance,
+        use_balance: bool,
+        merchant_id: Option<&AccountId>,
+    ) -> Result<PurchaseQuote, Message> {
+        let quote = self.internal_quote_payment(buyer_id, price, prepaid, deposit, use_balance)?;
+        match merchant_id {
+            Some(merchant_id) if self.merchants.get(merchant_id).is_none() => {
+                return Err(messages::UNKNOWN_MERCHANT)
+            }
+            None if self.config().beneficiary_id.is_none() => {
+                return Err(messages::NO_MERCHANT_BENEFICIARY_CONFIGURED)
+            }
+            _ => {}
+        }
+        Ok(quote)
+    }
+
+    /// Works out how `buyer_id` pays `price`, whoever it goes to.
+    pub(crate) fn internal_quote_payment(
+        &self,
+        buyer_id: &AccountId,
+        price: Balance,
+        prepaid: Balance,
+        deposit: Balance,
+        use_balance: bool,
+    ) -> Result<PurchaseQuote, Message> {
+        if price == 0 {
+            return Err(messages::PRICE_MUST_POSITIVE);
+        }
+        if use_balance && self.internal_is_locked(buyer_id) {
+            return Err(messages::ACCOUNT_LOCKED);
+        }
+        let from_balance = if use_balance {
+            self.internal_get_account(buyer_id)
+                .balance
+                .min(price - prepaid)
+        } else {
+            0
+        };
+        let due = price - prepaid - from_balance;
+        if deposit < due {
+            return Err(messages::PRICE_NOT_COVERED);
+        }
+        Ok(PurchaseQuote {
+            from_balance: U128(from_balance),
+            due: U128(due),
+            change: U128(deposit - due),
+        })
+    }
+
+    /// Books a purchase of `price` by `buyer_id`, of which `prepaid` is already
+    /// held by the contract, paid by the accounts listed with their amounts.
+    /// The rest is paid with `deposit` (and their balance if `use_balance`),
+    /// crediting the change and recording a receipt. Every payer earns the
+    /// points of its share.
+    /// `discount` is what a coupon already took off the price.
+    /// Returns the receipt id and the change.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn internal_purchase(
+        &mut self,
+        buyer_id: &AccountId,
+        price: Balance,
+        discount: Balance,
+        prepaid: &[(AccountId, Balance)],
+        deposit: Balance,
+        use_balance: bool,
+        merchant_id: Option<AccountId>,
+        session_id: Option<u64>,
+    ) -> (u64, Balance) {
+        let payers = payers_of(buyer_id, price, prepaid);
+        let prepaid: Balance = prepaid.iter().map(|(_, amount)| amount).sum();
+        let quote = self
+            .internal_quote_purchase(
+                buyer_id,
+                price,
+                prepaid,
+                deposit,
+                use_balance,
+                merchant_id.as_ref(),
+            )
+            .unwrap_or_else(|error| fail!(error));
+        let (from_balance, change) = (quote.from_balance.0, quote.change.0);
+        // the price is held for the merchant, or leaves right away for the beneficiary
+        let price_book = match merchant_id {
+            Some(_) => Book::Merchants,
+            None => Book::Custody,
+        };
+        self.internal_post(
+            Posting::default()
+                .debit(Book::UserChange, from_balance)
+                .debit(Book::Escrow, prepaid)
+                .debit(Book::Custody, quote.due.0)
+                .credit(price_book, price),
+        );
+        let mut buyer = self.internal_get_account(buyer_id);
+        buyer.balance -= from_balance;
+        self.internal_keep_change(buyer_id, &mut buyer, change);
+        self.internal_set_account(buyer_id, &buyer);
+        if let Some(session_id) = session_id {
+            self.internal_record_session_sale(session_id, price, change);
+        }
+
+        let (beneficiary_id, dispute_until) = match &merchant_id {
+            Some(merchant_id) => {
+                let receipt_id = self.receipts.len();
+                let dispute_until = self.internal_record_revenue(merchant_id, receipt_id, price);
+                let multiplier = self.internal_apply_campaigns(buyer_id, merchant_id, price);
+                for (payer_id, share) in &payers {
+                    self.internal_award_points(payer_id, merchant_id, *share, multiplier);
+                }
+                (None, Some(dispute_until))
+            }
+            None => (
+                Some(
+                    self.config()
+                        .beneficiary_id
+                        .clone()
+                        .unwrap_or_else(|| fail!(messages::NO_MERCHANT_BENEFICIARY_CONFIGURED)),
+                ),
+                None,
+            ),
+        };
+        let receipt = Receipt {
+            buyer: self.internal_intern(buyer_id),
+            merchant: merchant_id
+                .as_ref()
+                .map(|merchant_id| self.internal_intern(merchant_id)),
+            price,
+            discount,
+            from_balance,
+            change,
+            session_id,
+            timestamp: env::block_timestamp(),
+            dispute_until,
+        };
+        let receipt_id = self.internal_add_receipt(&receipt);
+        if payers.len() > 1 {
+            self.internal_set_receipt_payers(receipt_id, &payers);
+        }
+        if let Some(merchant_id) = &merchant_id {
+            self.internal_notify_merchant(merchant_id, "purchase", receipt_id, price);
+        }
+        self.emit_event(
+            "purchase",
+            &PurchaseEvent {
+                receipt_id: U64(receipt_id),
+                buyer_id,
+                merchant_id: merchant_id.as_ref(),
+                beneficiary_id: beneficiary_id.as_ref(),
+                session_id: session_id.map(U64),
+                price: U128(price),
+                from_balance: U128(from_balance),
+                change: U128(change),
+            },
+        );
+        if let Some(beneficiary_id) = beneficiary_id {
+            self.internal_forward_price(buyer_id, beneficiary_id, price);
+        }
+        (receipt_id, change)
+    }
+
+    /// Sends `amount` of the stored change of `account_id`, the caller, back to it.
+    pub(crate) fn internal_withdraw_change(
+        &mut self,
+        account_id: AccountId,
+        amount: Balance,
+    ) -> Promise {
+        self.assert_unlocked(&account_id);
+        let mut account = self.internal_get_account(&account_id);
+        require!(amount > 0, messages::AMOUNT_NOT_POSITIVE);
+        require!(amount <= account.balance, messages::NOT_ENOUGH_CHANGE);
+        self.internal_record_outflow(amount);
+        account.balance -= amount;
+        self.internal_set_account(&account_id, &account);
+        self.internal_post(Posting::pay_out(Book::UserChange, amount));
+        self.log(
+            LogLevel::Info,
+            messages::CHANGE_WITHDRAWN,
+            &[&amount, &account_id],
+        );
+        // the caller signed this very transaction, so the receiving account exists
+        self.internal_transfer(account_id, amount)
+    }
+
+    /// Sends `amount` of the price paid by `buyer_id` to `receiver_id`,
+    /// crediting it back to the buyer if the transfer fails.
+    pub(crate) fn internal_forward_price(
+        &self,
+        buyer_id: &AccountId,
+        receiver_id: AccountId,
+        amount: Balance,
+    ) {
+        self.internal_transfer(receiver_id, amount)
+            .then(ext_self::on_purchase_forwarded(
+                buyer_id.clone(),
+                U128(amount),
+                &env::current_account_id(),
+                0,
+                self.config().gas.callback.0,
+            ));
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Pays `price` to the merchant out of the attached deposit and keeps the
+    /// rest as the caller's change. Returns the change credited.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet purchase '{"price": "4300000000000000000000000"}' --accountId donation.YOU.testnet --deposit 5
+    /// ```
+    ///
+    /// With `use_balance` (false by default) the caller's stored change pays first:
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet purchase '{"price": "4300000000000000000000000", "use_balance": true}' --accountId donation.YOU.testnet --deposit 4
+    /// ```
+    ///
+    /// `merchant_id` books the price as revenue of that registered merchant;
+    /// without it the price goes straight to the configured beneficiary.
+    /// `session_id` ties the purchase to an open [POS session](crate::pos), and
+    /// `coupon` is the code of a [coupon](crate::coupon) to discount the price with.
+    /// A retry with the same `idempotency_key` refunds its deposit and returns the
+    /// change of the first purchase, see [`idempotency`](crate::idempotency).
+    #[payable]
+    pub fn purchase(
+        &mut self,
+        price: U128,
+        use_balance: Option<bool>,
+        merchant_id: Option<AccountId>,
+        session_id: Option<U64>,
+        coupon: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> U128 {
+        self.assert_access("purchase");
+        self.assert_writable();
+        if let Some(IdempotentOutcome::Purchase { change, .. }) =
+            self.internal_replay(&idempotency_key, "purchase")
+        {
+            let deposit = env::attached_deposit();
+            if deposit > 0 {
+                self.internal_transfer(env::predecessor_account_id(), deposit);
+            }
+            return U128(change);
+        }
+        let discount = match &coupon {
+            Some(code) => self.internal_use_coupon(code, merchant_id.as_ref(), price.0),
+            None => 0,
+        };
+        let (receipt_id, change) = self.internal_purchase(
+            &env::predecessor_account_id(),
+            price.0 - discount,
+            discount,
+            &[],
+            env::attached_deposit(),
+            use_balance.unwrap_or(false),
+            merchant_id,
+            session_id.map(|id| id.0),
+        );
+        self.internal_remember(
+            idempotency_key,
+            "purchase",
+            IdempotentOutcome::Purchase { receipt_id, change },
+        );
+        U128(change)
+    }
+
+    /// Credits the price back to the buyer if it couldn't reach the merchant.
+    pub fn on_purchase_forwarded(&mut self, buyer_id: AccountId, amount: U128) {
+        self.assert_access("on_purchase_forwarded");
+        if promise_succeeded().is_ok() {
+            return;
+        }
+        self.internal_credit_balance(&buyer_id, amount.0, Book::Custody);
+        self.emit_event(
+            "purchase_refunded",
+            &RefundEvent {
+                account_id: &buyer_id,
+                amount,
+            },
+        );
+    }
+
+    /// Returns the change balance of `account_id` in yoctoNEAR.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_balance '{"account_id": "donation.YOU.testnet"}'
+    /// ```
+    pub fn get_balance(&self, account_id: AccountId) -> U128 {
+        U128(self.internal_get_account(&account_id).balance)
+    }
+
+    /// Sends `amount` of the caller's stored change back to them. Still works
+    /// while the contract is [read-only](crate::pause).
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet withdraw_change '{"amount": "700000000000000000000000"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn withdraw_change(&mut self, amount: U128) -> Promise {
+        self.assert_access("withdraw_change");
+        self.internal_withdraw_change(env::predecessor_account_id(), amount.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::PromiseResult;
+
+    fn shop() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        contract
+    }
+
+    #[test]
+    fn purchase_keeps_the_change() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 500));
+        assert_eq!(
+            70,
+            contract.purchase(U128(430), None, None, None, None, None).0
+        );
+        testing_env!(context_for("jane.testnet", 430));
+        assert_eq!(
+            0,
+            contract.purchase(U128(430), None, None, None, None, None).0
+        );
+        assert_eq!(70, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit doesn't cover the price")]
+    fn purchase_needs_enough_deposit() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 429));
+        contract.purchase(U128(430), None, None, None, None, None);
+    }
+
+    #[test]
+    fn price_is_credited_back_if_forwarding_fails() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(U128(430), None, None, None, None, None);
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_purchase_forwarded("jane.testnet".to_string(), U128(430));
+        assert_eq!(500, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn stored_change_pays_first() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(U128(430), None, None, None, None, None);
+        // 70 from the balance, 360 of the deposit, 40 back as change
+        testing_env!(context_for("jane.testnet", 400));
+        assert_eq!(
+            40,
+            contract
+                .purchase(U128(430), Some(true), None, None, None, None)
+                .0
+        );
+        assert_eq!(40, contract.get_balance("jane.testnet".to_string()).0);
+        // the balance alone covers this one
+        testing_env!(context_for("jane.testnet", 0));
+        assert_eq!(
+            0,
+            contract
+                .purchase(U128(30), Some(true), None, None, None, None)
+                .0
+        );
+        assert_eq!(10, contract.get_balance("jane.testnet".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit doesn't cover the price")]
+    fn balance_is_not_used_unless_asked() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 500));
+        contract.purchase(U128(430), None, None, None, None, None);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.purchase(U128(30), Some(false), None, None, None, None);
+    }
+}
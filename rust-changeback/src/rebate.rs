@@ -0,0 +1,252 @@
+//! Fee rebates for high-frequency users.
+//!
+//! Every `add` / `change` counts as an operation of its caller. Once an account
+//! goes over `rebate_threshold` operations within one `rebate_period`, each
+//! further operation earns it `rebate_per_op`, which it can later collect with
+//! [`claim_rebate`](Change::claim_rebate). Rebates are paid out of the treasury.
+//!
+//! A rebate gives back fees, so it never goes past them: an account earns at
+//! most the platform fees kept on its matured purchases that no rebate has
+//! paid back yet, and at most `rebate_cap_per_period` per period. Counter
+//! operations are free, so an account that never paid a fee earns nothing.
+
+use near_sdk::json_types::U128;
+use near_sdk::{near_bindgen, AccountId, Balance, Promise};
+
+use crate::*;
+
+impl Change {
+    /// Counts one operation for `account_id`, accruing a rebate once it is over the threshold.
+    pub(crate) fn internal_record_operation(&mut self, account_id: &AccountId) {
+        let now = env::block_timestamp();
+        let mut account = self.internal_get_account(account_id);
+        if now.saturating_sub(account.period_start) >= self.config().rebate_period.0 {
+            account.period_start = now;
+            account.period_ops = 0;
+            account.period_rebate = 0;
+        }
+        account.period_ops = account.period_ops.saturating_add(1);
+        if account.period_ops > self.config().rebate_threshold {
+            let rebate = self
+                .config()
+                .rebate_per_op
+                .0
+                .min(account.rebatable_fees)
+                .min(
+                    self.config()
+                        .rebate_cap_per_period
+                        .0
+                        .saturating_sub(account.period_rebate),
+                );
+            account.rebate += rebate;
+            account.rebatable_fees -= rebate;
+            account.period_rebate += rebate;
+        }
+        self.internal_set_account(account_id, &account);
+    }
+
+    /// Adds `fee`, kept on a matured purchase of `account_id`, to what its rebates can pay back.
+    pub(crate) fn internal_credit_rebatable_fee(&mut self, account_id: &AccountId, fee: Balance) {
+        if fee > 0 {
+            let mut account = self.internal_get_account(account_id);
+            account.rebatable_fees += fee;
+            self.internal_set_account(account_id, &account);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the unclaimed rebate of `account_id` in yoctoNEAR.
+    pub fn get_rebate(&self, account_id: AccountId) -> U128 {
+        U128(self.internal_get_account(&account_id).rebate)
+    }
+
+    /// Transfers the caller's accrued rebate to them, paid from the treasury.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet claim_rebate --accountId donation.YOU.testnet
+    /// ```
+    pub fn claim_rebate(&mut self) -> Promise {
+        self.assert_access("claim_rebate");
+        let account_id = env::predecessor_account_id();
+        self.assert_unlocked(&account_id);
+        let mut account = self.internal_get_account(&account_id);
+        let amount = account.rebate;
+        require!(amount > 0, messages::NO_REBATE);
+        require!(
+            self.book(Book::Treasury) >= amount,
+            messages::TREASURY_SHORT_FOR_REBATE
+        );
+        self.internal_record_outflow(amount);
+        self.internal_post(Posting::pay_out(Book::Treasury, amount));
+        account.rebate = 0;
+        self.internal_set_account(&account_id, &account);
+        self.log(
+            LogLevel::Info,
+            messages::REBATE_PAID,
+            &[&amount, &account_id],
+        );
+        // the caller signed this very transaction, so the receiving account exists
+        self.internal_transfer(account_id, amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U64};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    const PER_OP: u128 = 10u128.pow(21);
+
+    /// A contract where jane.testnet paid `fees` in platform fees.
+    fn fee_payer(fees: Balance) -> Change {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.internal_credit_rebatable_fee(&"jane.testnet".to_string(), fees);
+        contract
+    }
+
+    fn rebate_of(contract: &Change) -> Balance {
+        contract.get_rebate("jane.testnet".to_string()).0
+    }
+
+    #[test]
+    fn rebate_accrues_above_threshold() {
+        let mut contract = fee_payer(100 * PER_OP);
+        for _ in 0..12 {
+            contract.add(None, None, None, None);
+        }
+        assert_eq!(2 * PER_OP, rebate_of(&contract));
+        assert_eq!(0, contract.get_rebate("bob.testnet".to_string()).0);
+    }
+
+    #[test]
+    fn free_operations_earn_nothing() {
+        let mut contract = fee_payer(0);
+        for _ in 0..20 {
+            contract.add(None, None, None, None);
+        }
+        assert_eq!(0, rebate_of(&contract));
+    }
+
+    #[test]
+    fn rebates_never_pass_the_fees_paid() {
+        let mut contract = fee_payer(PER_OP + PER_OP / 2);
+        for _ in 0..15 {
+            contract.add(None, None, None, None);
+        }
+        assert_eq!(PER_OP + PER_OP / 2, rebate_of(&contract));
+    }
+
+    #[test]
+    fn rebates_are_capped_per_period() {
+        let mut contract = fee_payer(100 * PER_OP);
+        testing_env!(context_for("alice.testnet", 0));
+        contract.set_config(
+            Config {
+                rebate_cap_per_period: U128(3 * PER_OP),
+                ..Config::default()
+            },
+            None,
+        );
+        let mut context = context_for("jane.testnet", 0);
+        testing_env!(context.clone());
+        for _ in 0..15 {
+            contract.add(None, None, None, None);
+        }
+        assert_eq!(3 * PER_OP, rebate_of(&contract));
+        context.block_timestamp = contract.get_config().rebate_period.0;
+        testing_env!(context);
+        for _ in 0..11 {
+            contract.add(None, None, None, None);
+        }
+        assert_eq!(4 * PER_OP, rebate_of(&contract));
+    }
+
+    #[test]
+    fn matured_purchases_fund_rebates() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(
+            ValidAccountId::try_from("shop.testnet").unwrap(),
+            ValidAccountId::try_from("shop.testnet").unwrap(),
+            1_000,
+        );
+        contract.set_merchant_dispute_window("shop.testnet".to_string(), U64(10));
+        contract.set_config(
+            Config {
+                min_settlement: U128(0),
+                ..Config::default()
+            },
+            None,
+        );
+        testing_env!(context_for("jane.testnet", 10 * PER_OP));
+        contract.purchase(
+            U128(10 * PER_OP),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = 11;
+        testing_env!(context.clone());
+        contract.settle("shop.testnet".to_string());
+        // 10% of the price
+        assert_eq!(
+            PER_OP,
+            contract
+                .internal_get_account(&"jane.testnet".to_string())
+                .rebatable_fees
+        );
+        context.predecessor_account_id = "jane.testnet".to_string();
+        testing_env!(context);
+        for _ in 0..12 {
+            contract.add(None, None, None, None);
+        }
+        assert_eq!(PER_OP, rebate_of(&contract));
+    }
+
+    #[test]
+    fn rebate_counter_restarts_each_period() {
+        let mut context = context_for("jane.testnet", 0);
+        let mut contract = fee_payer(100 * PER_OP);
+        for _ in 0..10 {
+            contract.change(None, None, None);
+        }
+        context.block_timestamp = contract.get_config().rebate_period.0;
+        testing_env!(context);
+        contract.change(None, None, None);
+        assert_eq!(0, rebate_of(&contract));
+    }
+
+    #[test]
+    fn claim_rebate_is_paid_from_treasury() {
+        let mut contract = fee_payer(100 * PER_OP);
+        testing_env!(context_for("jane.testnet", 5 * PER_OP));
+        contract.fund_treasury();
+        testing_env!(context_for("jane.testnet", 0));
+        for _ in 0..11 {
+            contract.add(None, None, None, None);
+        }
+        contract.claim_rebate();
+        assert_eq!(4 * PER_OP, contract.get_treasury().0);
+        assert_eq!(0, rebate_of(&contract));
+    }
+
+    #[test]
+    #[should_panic(expected = "Treasury can't cover the rebate right now")]
+    fn claim_rebate_needs_treasury_funds() {
+        let mut contract = fee_payer(100 * PER_OP);
+        for _ in 0..11 {
+            contract.add(None, None, None, None);
+        }
+        contract.claim_rebate();
+    }
+}
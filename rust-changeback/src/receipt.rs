@@ -0,0 +1,327 @@
+//! Receipts: the permanent record of every purchase.
+//!
+//! Besides the global history, the ids of each payer's receipts are indexed
+//! so customers can page through their own purchases, and those of each
+//! merchant so its purchases within a time range can be reconciled against
+//! off-chain POS records. Receipts are stored in time order, so both indexes
+//! are sorted by timestamp too. Receipts and both indexes refer to accounts by
+//! their [interned](crate::interning) id. A purchase paid by several accounts,
+//! like a layaway [intent](crate::intent), records each payer's share of the
+//! price and is indexed under every one of them.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Receipt {
+    /// Interned id of the buyer.
+    pub buyer: u32,
+    /// Interned id of the registered merchant the purchase was booked to, `None`
+    /// when it went to the beneficiary.
+    pub merchant: Option<u32>,
+    /// Price paid, after any coupon discount.
+    pub price: Balance,
+    /// Taken off the price by a coupon.
+    pub discount: Balance,
+    /// Part of the price paid from the buyer's stored change.
+    pub from_balance: Balance,
+    /// Change credited to the buyer.
+    pub change: Balance,
+    pub session_id: Option<u64>,
+    pub timestamp: Timestamp,
+    /// Last moment the buyer can dispute the purchase; `None` if it wasn't made at a merchant.
+    pub dispute_until: Option<Timestamp>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReceiptView {
+    pub id: U64,
+    pub buyer_id: AccountId,
+    pub merchant_id: Option<AccountId>,
+    pub price: U128,
+    pub discount: U128,
+    pub from_balance: U128,
+    pub change: U128,
+    pub session_id: Option<U64>,
+    pub timestamp: U64,
+    pub dispute_until: Option<U64>,
+    /// Each payer's share of the price, when several accounts paid for it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub payers: Vec<(AccountId, U128)>,
+}
+
+impl ReceiptView {
+    fn new(contract: &Change, id: u64, receipt: Receipt) -> Self {
+        Self {
+            id: U64(id),
+            buyer_id: contract.internal_account_id(receipt.buyer),
+            merchant_id: receipt
+                .merchant
+                .map(|merchant| contract.internal_account_id(merchant)),
+            price: U128(receipt.price),
+            discount: U128(receipt.discount),
+            from_balance: U128(receipt.from_balance),
+            change: U128(receipt.change),
+            session_id: receipt.session_id.map(U64),
+            timestamp: U64(receipt.timestamp),
+            dispute_until: receipt.dispute_until.map(U64),
+            payers: contract
+                .receipt_payers
+                .get(&id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(payer, share)| (contract.internal_account_id(payer), U128(share)))
+                .collect(),
+        }
+    }
+}
+
+/// Appends `receipt_id` to the receipts of the interned account `id` in
+/// `index`, creating its list under `prefix` on first use.
+fn push_indexed(
+    index: &mut LookupMap<u32, Vector<u64>>,
+    id: u32,
+    prefix: StorageKey,
+    receipt_id: u64,
+) {
+    let mut receipt_ids = index.get(&id).unwrap_or_else(|| Vector::new(prefix));
+    receipt_ids.push(&receipt_id);
+    index.insert(&id, &receipt_ids);
+}
+
+impl Change {
+    /// Stores `receipt`, indexing it under its buyer and merchant, and returns its id.
+    pub(crate) fn internal_add_receipt(&mut self, receipt: &Receipt) -> u64 {
+        self.receipts.push(receipt);
+        let receipt_id = self.receipts.len() - 1;
+        push_indexed(
+            &mut self.receipts_by_payer,
+            receipt.buyer,
+            StorageKey::PayerReceipts {
+                payer: receipt.buyer,
+            },
+            receipt_id,
+        );
+        if let Some(merchant) = receipt.merchant {
+            push_indexed(
+                &mut self.receipts_by_merchant,
+                merchant,
+                StorageKey::MerchantReceipts { merchant },
+                receipt_id,
+            );
+        }
+        receipt_id
+    }
+
+    /// Records that the receipt `receipt_id` was paid by `payers`, each with
+    /// its share of the price, and lists it among the receipts of each of them.
+    pub(crate) fn internal_set_receipt_payers(
+        &mut self,
+        receipt_id: u64,
+        payers: &[(AccountId, Balance)],
+    ) {
+        let buyer = self.receipts.get(receipt_id).unwrap().buyer;
+        let mut shares = Vec::with_capacity(payers.len());
+        for (payer_id, share) in payers {
+            let payer = self.internal_intern(payer_id);
+            if payer != buyer {
+                push_indexed(
+                    &mut self.receipts_by_payer,
+                    payer,
+                    StorageKey::PayerReceipts { payer },
+                    receipt_id,
+                );
+            }
+            shares.push((payer, *share));
+        }
+        self.receipt_payers.insert(&receipt_id, &shares);
+    }
+
+    /// Returns who paid the receipt `receipt_id` and their shares of its
+    /// price: the buyer alone, unless several accounts paid for it.
+    pub(crate) fn internal_receipt_payers(
+        &self,
+        receipt_id: u64,
+        receipt: &Receipt,
+    ) -> Vec<(AccountId, Balance)> {
+        match self.receipt_payers.get(&receipt_id) {
+            Some(shares) => shares
+                .into_iter()
+                .map(|(payer, share)| (self.internal_account_id(payer), share))
+                .collect(),
+            None => vec![(self.internal_account_id(receipt.buyer), receipt.price)],
+        }
+    }
+
+    /// Returns the position of the first receipt in `receipt_ids` made at or after `timestamp`.
+    fn internal_first_receipt_from(&self, receipt_ids: &Vector<u64>, timestamp: Timestamp) -> u64 {
+        let (mut low, mut high) = (0, receipt_ids.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let receipt_id = receipt_ids.get(middle).unwrap();
+            if self.receipts.get(receipt_id).unwrap().timestamp < timestamp {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the receipt `receipt_id`.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_receipt '{"receipt_id": "0"}'
+    /// ```
+    pub fn get_receipt(&self, receipt_id: U64) -> Option<ReceiptView> {
+        self.receipts
+            .get(receipt_id.0)
+            .map(|receipt| ReceiptView::new(self, receipt_id.0, receipt))
+    }
+
+    /// Returns up to `limit` receipts paid by `account_id`, oldest first, starting
+    /// at its `from_index`-th purchase, as many as the gas allows, see
+    /// [`pagination`](crate::pagination).
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_receipts_by_payer '{"account_id": "donation.YOU.testnet", "from_index": "0", "limit": 10}'
+    /// ```
+    pub fn get_receipts_by_payer(
+        &self,
+        account_id: AccountId,
+        from_index: U64,
+        limit: u64,
+    ) -> Page<ReceiptView> {
+        let payer_receipts = self
+            .internal_interned_id(&account_id)
+            .and_then(|payer| self.receipts_by_payer.get(&payer));
+        paginate(limit, |n| {
+            let receipt_id = payer_receipts.as_ref()?.get(from_index.0.checked_add(n)?)?;
+            Some(ReceiptView::new(
+                self,
+                receipt_id,
+                self.receipts.get(receipt_id).unwrap(),
+            ))
+        })
+    }
+
+    /// Returns up to `limit` receipts of purchases at `merchant_id` made from
+    /// `start_ts` (inclusive) to `end_ts` (exclusive), oldest first, as many as
+    /// the gas allows.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_merchant_receipts '{"merchant_id": "shop.testnet", "start_ts": "1700000000000000000", "end_ts": "1700086400000000000", "limit": 100}'
+    /// ```
+    pub fn get_merchant_receipts(
+        &self,
+        merchant_id: AccountId,
+        start_ts: U64,
+        end_ts: U64,
+        limit: u64,
+    ) -> Page<ReceiptView> {
+        let merchant_receipts = self
+            .internal_interned_id(&merchant_id)
+            .and_then(|merchant| self.receipts_by_merchant.get(&merchant));
+        let start = merchant_receipts.as_ref().map_or(0, |receipt_ids| {
+            self.internal_first_receipt_from(receipt_ids, start_ts.0)
+        });
+        paginate(limit, |n| {
+            let receipt_id = merchant_receipts.as_ref()?.get(start + n)?;
+            let receipt = self.receipts.get(receipt_id).unwrap();
+            (receipt.timestamp < end_ts.0).then(|| ReceiptView::new(self, receipt_id, receipt))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn receipts_are_listed_by_payer() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        for (buyer, price) in [
+            ("jane.testnet", 10),
+            ("bob.testnet", 20),
+            ("jane.testnet", 30),
+        ] {
+            testing_env!(context_for(buyer, 100));
+            contract.purchase(U128(price), None, None, None, None, None);
+        }
+        let jane = |from_index, limit| {
+            contract
+                .get_receipts_by_payer("jane.testnet".to_string(), U64(from_index), limit)
+                .items
+                .iter()
+                .map(|receipt| (receipt.id.0, receipt.price.0))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![(0, 10), (2, 30)], jane(0, 10));
+        assert_eq!(vec![(2, 30)], jane(1, 10));
+        assert_eq!(vec![(0, 10)], jane(0, 1));
+        assert!(contract
+            .get_receipts_by_payer("nobody.testnet".to_string(), U64(0), 10)
+            .items
+            .is_empty());
+    }
+
+    #[test]
+    fn merchant_receipts_are_listed_by_time() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let shop = ValidAccountId::try_from("shop.testnet").unwrap();
+        contract.register_merchant(shop.clone(), shop, 0);
+        for timestamp in [10, 20, 20, 30] {
+            let mut context = context_for("jane.testnet", 100);
+            context.block_timestamp = timestamp;
+            testing_env!(context);
+            contract.purchase(
+                U128(1),
+                None,
+                Some("shop.testnet".to_string()),
+                None,
+                None,
+                None,
+            );
+        }
+        let shop = |start_ts, end_ts, limit| {
+            contract
+                .get_merchant_receipts(
+                    "shop.testnet".to_string(),
+                    U64(start_ts),
+                    U64(end_ts),
+                    limit,
+                )
+                .items
+                .iter()
+                .map(|receipt| receipt.id.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![1, 2], shop(20, 30, 10));
+        assert_eq!(vec![0, 1, 2, 3], shop(0, 100, 10));
+        assert_eq!(vec![1], shop(11, 100, 1));
+        assert!(shop(31, 100, 10).is_empty());
+    }
+}
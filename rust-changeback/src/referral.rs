@@ -0,0 +1,425 @@
+//! One-level referral tracking.
+//!
+//! The first `add` that names a `referrer` binds the caller to that account for
+//! good. From then on the referrer earns `referral_bonus_bps` of the platform
+//! fee kept on every purchase the caller makes at a merchant, once the
+//! purchase is past its dispute window and its revenue matures: only NEAR the
+//! contract keeps funds a bonus, never deposits that can be withdrawn again or
+//! purchases still open to a refund. Disputed purchases earn nothing. Only the
+//! direct referrer earns a bonus; the referrer's own referrer gets nothing.
+//!
+//! Once a referrer's unpaid bonus reaches `referral_payout_threshold` it is
+//! queued and paid out of the treasury by NEAR transfer. The queue is drained
+//! in the order referrers joined it, at most `payout_batch_size` entries at a
+//! time, on every `add` and on [`process_payouts`](Change::process_payouts).
+//! A payout whose transfer fails goes back to the end of the queue.
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::account::Account;
+use crate::amount::bps_of;
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralView {
+    pub referrer: Option<AccountId>,
+    pub referrals: u32,
+    pub referral_bonus: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PayoutEvent {
+    account_id: AccountId,
+    amount: U128,
+}
+
+impl Change {
+    /// Binds `account_id` to `referrer` if it has none yet.
+    pub(crate) fn internal_bind_referrer(
+        &mut self,
+        account_id: &AccountId,
+        referrer: Option<AccountId>,
+    ) {
+        let mut account = self.internal_get_account(account_id);
+        if account.referrer.is_some() {
+            return;
+        }
+        if let Some(referrer) = referrer {
+            require!(&referrer != account_id, messages::SELF_REFERRAL);
+            let mut referrer_account = self.internal_get_account(&referrer);
+            require!(
+                referrer_account.referrer.as_ref() != Some(account_id),
+                messages::CIRCULAR_REFERRAL
+            );
+            referrer_account.referrals += 1;
+            self.internal_set_account(&referrer, &referrer_account);
+            account.referrer = Some(referrer);
+            self.internal_set_account(account_id, &account);
+        }
+    }
+
+    /// Credits the referrer of `account_id`, if any, its bonus on `fee`, the
+    /// platform fee kept on a matured purchase of the account.
+    pub(crate) fn internal_credit_referrer(&mut self, account_id: &AccountId, fee: Balance) {
+        if let Some(referrer) = self.internal_get_account(account_id).referrer {
+            let bonus = bps_of(
+                fee,
+                self.config().referral_bonus_bps,
+                self.config().rounding_mode,
+            );
+            if bonus > 0 {
+                let mut referrer_account = self.internal_get_account(&referrer);
+                referrer_account.referral_bonus += bonus;
+                self.internal_queue_payout(&referrer, &mut referrer_account);
+                self.internal_set_account(&referrer, &referrer_account);
+                self.log(
+                    LogLevel::Info,
+                    messages::REFERRAL_BONUS_PAID,
+                    &[&bonus, &referrer],
+                );
+            }
+        }
+    }
+}
+
+impl Change {
+    /// Queues `account` for a payout once its bonus reaches the threshold,
+    /// unless it is queued already. The caller saves the account.
+    fn internal_queue_payout(&mut self, account_id: &AccountId, account: &mut Account) {
+        if !account.payout_queued
+            && account.referral_bonus >= self.config().referral_payout_threshold.0
+        {
+            account.payout_queued = true;
+            self.payout_queue.push(account_id);
+        }
+    }
+
+    /// Pays out up to `payout_batch_size` queued referrers and returns how many were paid.
+    ///
+    /// Stops early when the treasury can't cover the next payout, leaving it queued,
+    /// and pays nothing while the contract or its withdrawals are paused.
+    pub(crate) fn internal_process_payouts(&mut self) -> u32 {
+        let mut paid = 0;
+        if self.paused || self.withdrawals_paused {
+            return paid;
+        }
+        let mut events = EventBuffer::new("referral_payout");
+        while paid < self.config().payout_batch_size {
+            let account_id = match self.payout_queue.get(self.payout_cursor) {
+                Some(account_id) => account_id,
+                None => break,
+            };
+            let mut account = self.internal_get_account(&account_id);
+            let amount = account.referral_bonus;
+            if amount > self.book(Book::Treasury) {
+                break;
+            }
+            self.payout_cursor += 1;
+            account.referral_bonus = 0;
+            account.payout_queued = false;
+            self.internal_set_account(&account_id, &account);
+            self.internal_record_outflow(amount);
+            self.internal_post(Posting::pay_out(Book::Treasury, amount));
+            events.push(
+                PayoutEvent {
+                    account_id: account_id.clone(),
+                    amount: U128(amount),
+                },
+                amount,
+            );
+            self.internal_transfer(account_id.clone(), amount)
+                .then(ext_self::on_referral_payout(
+                    account_id,
+                    U128(amount),
+                    &env::current_account_id(),
+                    0,
+                    self.config().gas.callback.0,
+                ));
+            paid += 1;
+        }
+        self.internal_flush_events(events);
+        paid
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Pays out the next batch of queued referral bonuses. Anyone can call this
+    /// to push payouts along; returns how many were sent.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet process_payouts --accountId donation.YOU.testnet
+    /// ```
+    pub fn process_payouts(&mut self) -> u32 {
+        self.assert_access("process_payouts");
+        self.internal_process_payouts()
+    }
+
+    /// Puts the bonus of a failed payout back on the referrer and the funds back
+    /// in the treasury, and queues the referrer again to retry the payout.
+    pub fn on_referral_payout(&mut self, account_id: AccountId, amount: U128) {
+        self.assert_access("on_referral_payout");
+        if promise_succeeded().is_ok() {
+            return;
+        }
+        let mut account = self.internal_get_account(&account_id);
+        account.referral_bonus += amount.0;
+        self.internal_queue_payout(&account_id, &mut account);
+        self.internal_set_account(&account_id, &account);
+        self.internal_credit_treasury(amount.0, Book::Custody);
+        self.emit_event(
+            "referral_payout_failed",
+            &PayoutEvent { account_id, amount },
+        );
+    }
+
+    /// Returns who referred `account_id`, how many accounts it referred and its unpaid bonus.
+    pub fn get_referral_info(&self, account_id: AccountId) -> ReferralView {
+        let account = self.internal_get_account(&account_id);
+        ReferralView {
+            referrer: account.referrer,
+            referrals: account.referrals,
+            referral_bonus: U128(account.referral_bonus),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U64};
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::PromiseResult;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    /// shop.testnet keeping a 10% fee and disputable for 10ns, with jane.testnet
+    /// referred by bob.testnet.
+    fn referred_contract(config: Config) -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                min_settlement: U128(0),
+                ..config
+            },
+            None,
+        );
+        contract.register_merchant(account("shop.testnet"), account("shop.testnet"), 1_000);
+        contract.set_merchant_dispute_window("shop.testnet".to_string(), U64(10));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(Some("bob.testnet".to_string()), None, None, None);
+        contract
+    }
+
+    fn bonus_contract() -> Change {
+        referred_contract(Config {
+            referral_bonus_bps: 1_000,
+            ..Config::default()
+        })
+    }
+
+    fn buy(contract: &mut Change, buyer_id: &str, price: Balance) -> u64 {
+        testing_env!(context_for(buyer_id, price));
+        contract.purchase(
+            U128(price),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        contract.receipts.len() - 1
+    }
+
+    /// Settles the shop once every purchase so far is past its dispute window.
+    fn settle_matured(contract: &mut Change) {
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = 11;
+        testing_env!(context);
+        contract.settle("shop.testnet".to_string());
+    }
+
+    fn bonus_of(contract: &Change, account_id: &str) -> Balance {
+        contract
+            .get_referral_info(account_id.to_string())
+            .referral_bonus
+            .0
+    }
+
+    #[test]
+    fn referrer_earns_bonus_on_matured_purchases() {
+        let mut contract = bonus_contract();
+        // later adds keep the first referrer, whatever they pass
+        contract.add(Some("carol.testnet".to_string()), None, None, None);
+        buy(&mut contract, "jane.testnet", 10_000);
+        buy(&mut contract, "jane.testnet", 20_000);
+        // still disputable
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+        settle_matured(&mut contract);
+        // 10% of the 3_000 kept as fees
+        assert_eq!(300, bonus_of(&contract, "bob.testnet"));
+        let bob = contract.get_referral_info("bob.testnet".to_string());
+        assert_eq!(1, bob.referrals);
+        let jane = contract.get_referral_info("jane.testnet".to_string());
+        assert_eq!(Some("bob.testnet".to_string()), jane.referrer);
+        assert_eq!(
+            0,
+            contract
+                .get_referral_info("carol.testnet".to_string())
+                .referrals
+        );
+    }
+
+    #[test]
+    fn bonus_is_one_level_deep() {
+        let mut contract = bonus_contract();
+        testing_env!(context_for("dave.testnet", 0));
+        contract.add(Some("jane.testnet".to_string()), None, None, None);
+        buy(&mut contract, "dave.testnet", 10_000);
+        settle_matured(&mut contract);
+        assert_eq!(100, bonus_of(&contract, "jane.testnet"));
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+    }
+
+    #[test]
+    fn deposits_earn_no_bonus() {
+        let mut contract = bonus_contract();
+        testing_env!(context_for("jane.testnet", 10u128.pow(24)));
+        contract.add(None, None, None, None);
+        contract.withdraw_change(U128(10u128.pow(24)));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, Some(U128(10u128.pow(26))), None);
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+    }
+
+    #[test]
+    fn disputed_purchases_earn_no_bonus() {
+        let mut contract = bonus_contract();
+        let receipt_id = buy(&mut contract, "jane.testnet", 10_000);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.open_dispute(U64(receipt_id), "never delivered".to_string());
+        settle_matured(&mut contract);
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't refer yourself")]
+    fn self_referral_is_rejected() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(Some("jane.testnet".to_string()), None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't refer the account that referred you")]
+    fn mutual_referral_is_rejected() {
+        let mut contract = bonus_contract();
+        testing_env!(context_for("bob.testnet", 0));
+        contract.add(Some("jane.testnet".to_string()), None, None, None);
+    }
+
+    /// The whole fee goes to the referrer, paid out from 2_000 on, one payout per call.
+    fn payout_contract() -> Change {
+        referred_contract(Config {
+            referral_bonus_bps: 10_000,
+            referral_payout_threshold: U128(2_000),
+            payout_batch_size: 1,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn bonus_is_paid_once_threshold_is_reached() {
+        let mut contract = payout_contract();
+        buy(&mut contract, "jane.testnet", 10_000);
+        settle_matured(&mut contract);
+        assert_eq!(0, contract.process_payouts());
+        buy(&mut contract, "jane.testnet", 10_000);
+        settle_matured(&mut contract);
+        // the fees of both purchases are in the treasury
+        assert_eq!(2_000, contract.get_treasury().0);
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(0, contract.get_treasury().0);
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.starts_with("EVENT_JSON:")
+                && log.contains("\"event\":\"referral_payout\"")));
+    }
+
+    #[test]
+    fn payout_waits_for_treasury_funds() {
+        let mut contract = payout_contract();
+        buy(&mut contract, "jane.testnet", 20_000);
+        settle_matured(&mut contract);
+        contract.withdraw_treasury(account("ops.testnet"), U128(1_000));
+        assert_eq!(0, contract.process_payouts());
+        assert_eq!(2_000, bonus_of(&contract, "bob.testnet"));
+        testing_env!(context_for("alice.testnet", 5_000));
+        contract.fund_treasury();
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(4_000, contract.get_treasury().0);
+    }
+
+    #[test]
+    fn payouts_are_batched() {
+        let mut contract = payout_contract();
+        testing_env!(context_for("dave.testnet", 0));
+        contract.add(Some("carol.testnet".to_string()), None, None, None);
+        buy(&mut contract, "jane.testnet", 20_000);
+        buy(&mut contract, "dave.testnet", 20_000);
+        settle_matured(&mut contract);
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(0, contract.process_payouts());
+    }
+
+    #[test]
+    fn first_queued_is_paid_first() {
+        let mut contract = payout_contract();
+        testing_env!(context_for("dave.testnet", 0));
+        contract.add(Some("carol.testnet".to_string()), None, None, None);
+        buy(&mut contract, "jane.testnet", 20_000);
+        settle_matured(&mut contract);
+        buy(&mut contract, "dave.testnet", 20_000);
+        settle_matured(&mut contract);
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+        assert_eq!(2_000, bonus_of(&contract, "carol.testnet"));
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(0, bonus_of(&contract, "carol.testnet"));
+    }
+
+    #[test]
+    fn failed_payout_is_restored() {
+        let mut contract = payout_contract();
+        buy(&mut contract, "jane.testnet", 20_000);
+        settle_matured(&mut contract);
+        contract.process_payouts();
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_referral_payout("bob.testnet".to_string(), U128(2_000));
+        assert_eq!(2_000, contract.get_treasury().0);
+        assert_eq!(2_000, bonus_of(&contract, "bob.testnet"));
+        // queued again, so the next batch retries it
+        testing_env!(context_for("alice.testnet", 0));
+        assert_eq!(1, contract.process_payouts());
+        assert_eq!(0, bonus_of(&contract, "bob.testnet"));
+    }
+}
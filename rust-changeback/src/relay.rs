@@ -0,0 +1,319 @@
+//! Operations relayed on behalf of other accounts.
+//!
+//! An account with the `Relayer` role can submit `add` / `change` for a user
+//! who has no NEAR for gas. Every relayed operation carries the user's next
+//! nonce, one more than the last one used, so a relayer retrying or replaying
+//! a payload can't apply it twice or out of order.
+//!
+//! The user signs each operation with the ed25519 key it registered with
+//! [`set_relay_key`](Change::set_relay_key), so a relayer can only apply
+//! what the user asked for. The key signs the borsh serialization of
+//! `(contract_id, account_id, nonce, operation)`, so a payload signed for one
+//! contract or account can't be applied to another. Changing the key voids
+//! every payload signed with the old one.
+
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RelayedOperation {
+    Add { referrer: Option<AccountId> },
+    Change,
+}
+
+/// What the user's relay key signs.
+#[derive(BorshSerialize)]
+struct RelayPayload {
+    contract_id: AccountId,
+    account_id: AccountId,
+    nonce: u64,
+    operation: RelayedOperation,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RelayKeyEvent<'a> {
+    account_id: &'a AccountId,
+    public_key: &'a Option<Base58PublicKey>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RelayedEvent<'a> {
+    relayer_id: &'a AccountId,
+    account_id: &'a AccountId,
+    nonce: U64,
+    operation: &'a RelayedOperation,
+}
+
+impl Change {
+    /// Consumes `nonce` for `account_id`, which has to be one more than the last one used.
+    pub(crate) fn internal_use_nonce(&mut self, account_id: &AccountId, nonce: u64) {
+        let last = self.nonces.get(account_id).unwrap_or(0);
+        require!(nonce > last, messages::NONCE_ALREADY_USED);
+        require!(nonce == last + 1, messages::NONCE_OUT_OF_ORDER);
+        self.nonces.insert(account_id, &nonce);
+    }
+
+    /// Panics unless `signature` is `account_id`'s relay key signature of
+    /// `operation` with `nonce`.
+    fn assert_signed_by(
+        &self,
+        account_id: &AccountId,
+        nonce: u64,
+        operation: &RelayedOperation,
+        signature: &[u8],
+    ) {
+        let key = self
+            .relay_keys
+            .get(account_id)
+            .unwrap_or_else(|| fail!(messages::NO_RELAY_KEY, account_id));
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(&key.0[1..]);
+        let mut sig = [0u8; 64];
+        require!(signature.len() == 64, messages::INVALID_RELAY_SIGNATURE);
+        sig.copy_from_slice(signature);
+        let message = RelayPayload {
+            contract_id: env::current_account_id(),
+            account_id: account_id.clone(),
+            nonce,
+            operation: operation.clone(),
+        }
+        .try_to_vec()
+        .expect("Failed to serialize the relayed operation");
+        require!(
+            ed25519::verify(&public_key, &message, &sig),
+            messages::INVALID_RELAY_SIGNATURE
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sets the ed25519 key the caller signs its relayed operations with;
+    /// `None` stops relaying for it.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_relay_key '{"public_key": "ed25519:<base58 public key>"}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn set_relay_key(&mut self, public_key: Option<Base58PublicKey>) {
+        self.assert_access("set_relay_key");
+        self.assert_writable();
+        let account_id = env::predecessor_account_id();
+        match &public_key {
+            Some(key) => {
+                require!(
+                    key.0.len() == 33 && key.0[0] == 0,
+                    messages::INVALID_RELAY_KEY
+                );
+                self.relay_keys.insert(&account_id, key);
+            }
+            None => {
+                self.relay_keys.remove(&account_id);
+            }
+        }
+        self.emit_event(
+            "relay_key_set",
+            &RelayKeyEvent {
+                account_id: &account_id,
+                public_key: &public_key,
+            },
+        );
+    }
+
+    pub fn get_relay_key(&self, account_id: AccountId) -> Option<Base58PublicKey> {
+        self.relay_keys.get(&account_id)
+    }
+
+    /// Applies `operation` for `account_id` with its next `nonce`, as the
+    /// account signed it with its relay key. Relayers only. Returns the
+    /// account's new value, see [`outcome`](crate::outcome).
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet relay '{"account_id": "donation.YOU.testnet", "nonce": "1", "operation": "Change", "signature": "<base64>"}' --accountId relayer.testnet
+    /// ```
+    pub fn relay(
+        &mut self,
+        account_id: ValidAccountId,
+        nonce: U64,
+        operation: RelayedOperation,
+        signature: Base64VecU8,
+    ) -> OperationOutcome {
+        self.assert_writable();
+        self.assert_access("relay");
+        let relayer_id = env::predecessor_account_id();
+        let account_id: AccountId = account_id.into();
+        self.assert_signed_by(&account_id, nonce.0, &operation, &signature.0);
+        self.internal_use_nonce(&account_id, nonce.0);
+        let val = match operation.clone() {
+            RelayedOperation::Add { referrer } => {
+                let amount = self.config().add_step.0 as i128;
+                self.internal_add(&account_id, DEFAULT_CURRENCY, referrer, amount)
+            }
+            RelayedOperation::Change => {
+                let amount = self.config().sub_step.0 as i128;
+                self.internal_change(&account_id, DEFAULT_CURRENCY, amount)
+            }
+        };
+        self.emit_event(
+            "relayed_operation",
+            &RelayedEvent {
+                relayer_id: &relayer_id,
+                account_id: &account_id,
+                nonce,
+                operation: &operation,
+            },
+        );
+        self.internal_outcome(val)
+    }
+
+    /// Returns the last nonce used for `account_id`; its next relayed operation carries one more.
+    pub fn get_nonce(&self, account_id: AccountId) -> U64 {
+        U64(self.nonces.get(&account_id).unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use ed25519_dalek::{Signer, SigningKey};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn public_key(seed: u8) -> Base58PublicKey {
+        let mut public_key = vec![0];
+        public_key.extend_from_slice(&key(seed).verifying_key().to_bytes());
+        Base58PublicKey(public_key)
+    }
+
+    /// `operation` with `nonce` for `account_id`, signed with the key of `seed`.
+    fn signed(seed: u8, account_id: &str, nonce: u64, operation: &RelayedOperation) -> Base64VecU8 {
+        let message = RelayPayload {
+            contract_id: "alice.testnet".to_string(),
+            account_id: account_id.to_string(),
+            nonce,
+            operation: operation.clone(),
+        }
+        .try_to_vec()
+        .unwrap();
+        Base64VecU8(key(seed).sign(&message).to_bytes().to_vec())
+    }
+
+    /// Relays `operation` for jane.testnet, signed with her key.
+    fn relay(contract: &mut Change, nonce: u64, operation: RelayedOperation) -> OperationOutcome {
+        let signature = signed(1, "jane.testnet", nonce, &operation);
+        contract.relay(account("jane.testnet"), U64(nonce), operation, signature)
+    }
+
+    fn relaying_contract() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.grant_role(Role::Relayer, account("relayer.testnet"));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_relay_key(Some(public_key(1)));
+        testing_env!(context_for("relayer.testnet", 0));
+        contract
+    }
+
+    #[test]
+    fn relayed_operations_count_for_the_account() {
+        let mut contract = relaying_contract();
+        let add = RelayedOperation::Add { referrer: None };
+        assert_eq!(1000, relay(&mut contract, 1, add).new_balance.0);
+        assert_eq!(
+            990,
+            relay(&mut contract, 2, RelayedOperation::Change)
+                .new_balance
+                .0
+        );
+        assert_eq!(
+            990,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+        assert_eq!(
+            0,
+            contract.get_num_for("relayer.testnet".to_string(), None).0
+        );
+        assert_eq!(2, contract.get_nonce("jane.testnet".to_string()).0);
+        assert_eq!(0, contract.get_nonce("bob.testnet".to_string()).0);
+        let jane = contract.internal_get_account(&"jane.testnet".to_string());
+        assert_eq!(2, jane.period_ops);
+    }
+
+    #[test]
+    #[should_panic(expected = "Nonce already used")]
+    fn replayed_nonces_are_rejected() {
+        let mut contract = relaying_contract();
+        relay(&mut contract, 1, RelayedOperation::Change);
+        relay(&mut contract, 1, RelayedOperation::Change);
+    }
+
+    #[test]
+    #[should_panic(expected = "Nonce out of order")]
+    fn skipped_nonces_are_rejected() {
+        let mut contract = relaying_contract();
+        relay(&mut contract, 2, RelayedOperation::Change);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid relay signature")]
+    fn operations_need_the_account_signature() {
+        let mut contract = relaying_contract();
+        let signature = signed(2, "jane.testnet", 1, &RelayedOperation::Change);
+        contract.relay(
+            account("jane.testnet"),
+            U64(1),
+            RelayedOperation::Change,
+            signature,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid relay signature")]
+    fn signed_operations_cant_be_altered() {
+        let mut contract = relaying_contract();
+        let signature = signed(1, "jane.testnet", 1, &RelayedOperation::Change);
+        contract.relay(
+            account("jane.testnet"),
+            U64(1),
+            RelayedOperation::Add { referrer: None },
+            signature,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Account bob.testnet has no relay key")]
+    fn accounts_without_a_key_cant_be_relayed_for() {
+        let mut contract = relaying_contract();
+        let signature = signed(1, "bob.testnet", 1, &RelayedOperation::Change);
+        contract.relay(
+            account("bob.testnet"),
+            U64(1),
+            RelayedOperation::Change,
+            signature,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only relayers can relay operations")]
+    fn only_relayers_relay() {
+        let mut contract = relaying_contract();
+        testing_env!(context_for("jane.testnet", 0));
+        relay(&mut contract, 1, RelayedOperation::Change);
+    }
+}
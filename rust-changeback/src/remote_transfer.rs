@@ -0,0 +1,247 @@
+//! Moving Change between separate deployments of this contract.
+//!
+//! [`send_to_instance`](Change::send_to_instance) burns the amount from the
+//! sender's Change right away and calls
+//! [`receive_remote_transfer`](Change::receive_remote_transfer) on the target
+//! instance, which credits the receiver. If the target refuses or fails, the
+//! callback puts the amount back on the sender. An instance only takes
+//! transfers from the instances its owner granted the `Instance`
+//! [role](crate::roles), so nobody else can credit Change out of thin air.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// A transfer on its way to another instance, handed to the callback.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RemoteTransfer {
+    pub target_id: AccountId,
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub currency: String,
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sends `amount` of the caller's Change in `currency`, `"NEAR"` when
+    /// omitted, to `receiver` on the instance deployed at `target`.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet send_to_instance '{"target": "shop.Change.YOU.testnet", "receiver": "jane.testnet", "amount": "100"}' --accountId jane.testnet
+    /// ```
+    pub fn send_to_instance(
+        &mut self,
+        target: ValidAccountId,
+        receiver: ValidAccountId,
+        amount: U128,
+        currency: Option<String>,
+    ) -> OperationOutcome {
+        self.assert_access("send_to_instance");
+        self.assert_writable();
+        let target_id: AccountId = target.into();
+        require!(
+            target_id != env::current_account_id(),
+            messages::SAME_INSTANCE
+        );
+        let sender_id = env::predecessor_account_id();
+        let amount = counter_amount(amount).unwrap_or_else(|error| fail!(error));
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        require!(
+            self.internal_get_num(&sender_id, &currency) >= amount,
+            messages::NOT_ENOUGH_CHANGE_TO_SEND
+        );
+        let val = self.internal_move_num(
+            CounterOperation::TransferOut,
+            &sender_id,
+            &currency,
+            -amount,
+        );
+        let gas = &self.config().gas;
+        ext_instance::receive_remote_transfer(
+            sender_id.clone(),
+            receiver.clone(),
+            U128(amount as Balance),
+            currency.clone(),
+            &target_id,
+            0,
+            gas.remote_transfer.0,
+        )
+        .then(ext_self::on_remote_transfer(
+            RemoteTransfer {
+                target_id,
+                sender_id,
+                receiver_id: receiver.into(),
+                amount: U128(amount as Balance),
+                currency,
+            },
+            &env::current_account_id(),
+            0,
+            gas.callback.0,
+        ));
+        self.internal_outcome(val)
+    }
+
+    /// Credits `amount` in `currency` to `receiver_id`, sent by `sender_id`
+    /// on the calling instance. Instances only.
+    pub fn receive_remote_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        currency: String,
+    ) -> U128 {
+        self.assert_access("receive_remote_transfer");
+        self.assert_writable();
+        let amount = counter_amount(amount).unwrap_or_else(|error| fail!(error));
+        let currency = currency_symbol(Some(currency)).unwrap_or_else(|error| fail!(error));
+        self.log(
+            LogLevel::Info,
+            messages::REMOTE_TRANSFER_RECEIVED,
+            &[&amount, &sender_id, &env::predecessor_account_id()],
+        );
+        self.internal_move_num(
+            CounterOperation::TransferIn,
+            receiver_id.as_ref(),
+            &currency,
+            amount,
+        );
+        U128(amount as Balance)
+    }
+
+    /// Puts a transfer the target instance didn't credit back on the sender.
+    pub fn on_remote_transfer(&mut self, transfer: RemoteTransfer) {
+        self.assert_access("on_remote_transfer");
+        if promise_succeeded().is_ok() {
+            return;
+        }
+        let RemoteTransfer {
+            sender_id,
+            amount,
+            currency,
+            ..
+        } = &transfer;
+        // limits set since the send don't apply, the Change was there before
+        let old_value = self.internal_get_num(sender_id, currency);
+        let delta = amount.0 as i128;
+        let new_value = old_value.saturating_add(delta);
+        self.internal_set_num(sender_id, currency, new_value);
+        self.internal_record_counter(
+            CounterOperation::TransferIn,
+            sender_id,
+            currency,
+            delta,
+            old_value,
+            new_value,
+        );
+        self.emit_event("remote_transfer_failed", &transfer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roles::Role;
+    use crate::test_utils::context_for;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult};
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn num_of(contract: &Change, account_id: &str) -> i128 {
+        contract.get_num_for(account_id.to_string(), None).0
+    }
+
+    #[test]
+    fn sending_burns_and_calls_the_target() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        let outcome = contract.send_to_instance(
+            account("other.testnet"),
+            account("bob.testnet"),
+            U128(400),
+            None,
+        );
+        assert_eq!(600, outcome.new_balance.0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("receive_remote_transfer"));
+        assert!(receipts.contains("other.testnet"));
+    }
+
+    #[test]
+    fn failed_transfers_are_rolled_back() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        contract.send_to_instance(
+            account("other.testnet"),
+            account("bob.testnet"),
+            U128(400),
+            None,
+        );
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_remote_transfer(RemoteTransfer {
+            target_id: "other.testnet".to_string(),
+            sender_id: "jane.testnet".to_string(),
+            receiver_id: "bob.testnet".to_string(),
+            amount: U128(400),
+            currency: DEFAULT_CURRENCY.to_string(),
+        });
+        assert_eq!(1000, num_of(&contract, "jane.testnet"));
+    }
+
+    #[test]
+    fn trusted_instances_credit_the_receiver() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.grant_role(Role::Instance, account("other.testnet"));
+        testing_env!(context_for("other.testnet", 0));
+        contract.receive_remote_transfer(
+            "jane.testnet".to_string(),
+            account("bob.testnet"),
+            U128(400),
+            DEFAULT_CURRENCY.to_string(),
+        );
+        assert_eq!(400, num_of(&contract, "bob.testnet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only trusted instances can send transfers")]
+    fn other_accounts_cannot_credit() {
+        testing_env!(context_for("mallory.testnet", 0));
+        let mut contract = Change::default();
+        contract.receive_remote_transfer(
+            "jane.testnet".to_string(),
+            account("mallory.testnet"),
+            U128(400),
+            DEFAULT_CURRENCY.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough Change to send")]
+    fn only_held_change_is_sent() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.send_to_instance(
+            account("other.testnet"),
+            account("bob.testnet"),
+            U128(1),
+            None,
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Roles the owner grants to other accounts for specific duties.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId};
+
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+    /// Resolves purchase disputes assigned to it, see [`dispute`](crate::dispute).
+    Arbiter,
+    /// Submits operations on behalf of other accounts, see [`relay`](crate::relay).
+    Relayer,
+    /// Switches the contract into and out of read-only mode, see [`pause`](crate::pause).
+    Guardian,
+    /// Another instance of this contract sending it transfers, see
+    /// [`remote_transfer`](crate::remote_transfer).
+    Instance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RoleEvent<'a> {
+    role: Role,
+    account_id: &'a AccountId,
+}
+
+impl Change {
+    pub(crate) fn internal_has_role(&self, role: Role, account_id: &AccountId) -> bool {
+        self.roles.contains(&(role, account_id.clone()))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Grants `role` to `account_id`. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet grant_role '{"role": "Arbiter", "account_id": "arbiter.testnet"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn grant_role(&mut self, role: Role, account_id: ValidAccountId) {
+        self.assert_access("grant_role");
+        let account_id: AccountId = account_id.into();
+        if self.roles.insert(&(role, account_id.clone())) {
+            self.emit_event(
+                "role_granted",
+                &RoleEvent {
+                    role,
+                    account_id: &account_id,
+                },
+            );
+        }
+    }
+
+    /// Takes `role` away from `account_id`. Owner only.
+    pub fn revoke_role(&mut self, role: Role, account_id: ValidAccountId) {
+        self.assert_access("revoke_role");
+        let account_id: AccountId = account_id.into();
+        if self.roles.remove(&(role, account_id.clone())) {
+            self.emit_event(
+                "role_revoked",
+                &RoleEvent {
+                    role,
+                    account_id: &account_id,
+                },
+            );
+        }
+    }
+
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        self.internal_has_role(role, &account_id)
+    }
+
+    /// Returns every account holding `role`.
+    pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+        self.roles
+            .iter()
+            .filter(|(member_role, _)| *member_role == role)
+            .map(|(_, account_id)| account_id)
+            .collect()
+    }
+}
@@ -0,0 +1,273 @@
+//! Round-up savings.
+//!
+//! Accounts that opt in have every token payment rounded up to the token's
+//! `round_up_unit`: the difference moves from their spendable balance into a
+//! separate savings balance. A 4.30 USDC deposit with a 1 USDC unit puts 0.70
+//! into savings. Savings can only leave through
+//! [`withdraw_savings`](Change::withdraw_savings).
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Promise};
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SavingsEvent<'a> {
+    account_id: &'a AccountId,
+    token_id: &'a AccountId,
+    amount: U128,
+}
+
+impl Change {
+    pub(crate) fn internal_savings(&self, account_id: &AccountId, token_id: &AccountId) -> Balance {
+        self.savings
+            .get(&(account_id.clone(), token_id.clone()))
+            .unwrap_or(0)
+    }
+
+    /// Moves the round-up of a `payment` of `token_id` from the spendable balance of
+    /// `account_id` into its savings, if it opted in and can cover it.
+    pub(crate) fn internal_round_up(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        payment: Balance,
+    ) {
+        if !self.internal_get_account(account_id).round_up {
+            return;
+        }
+        let unit = self
+            .tokens
+            .get(token_id)
+            .map_or(0, |token| token.round_up_unit);
+        if unit == 0 || payment.is_multiple_of(unit) {
+            return;
+        }
+        let round_up = unit - payment % unit;
+        let key = (account_id.clone(), token_id.clone());
+        let balance = self.internal_token_balance(account_id, token_id);
+        if balance < round_up {
+            return;
+        }
+        self.token_balances.insert(&key, &(balance - round_up));
+        let saved = self.internal_savings(account_id, token_id) + round_up;
+        self.savings.insert(&key, &saved);
+        self.emit_event(
+            "round_up_saved",
+            &SavingsEvent {
+                account_id,
+                token_id,
+                amount: U128(round_up),
+            },
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sets the round-up unit of `token_id`, 0 to disable. Owner only.
+    pub fn set_round_up_unit(&mut self, token_id: ValidAccountId, unit: U128) {
+        self.assert_access("set_round_up_unit");
+        let token_id: AccountId = token_id.into();
+        let mut token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        token.round_up_unit = unit.0;
+        self.tokens.insert(&token_id, &token);
+    }
+
+    /// Opts the caller in or out of round-up savings.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_round_up '{"enabled": true}' --accountId donation.YOU.testnet
+    /// ```
+    pub fn set_round_up(&mut self, enabled: bool) {
+        self.assert_access("set_round_up");
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_get_account(&account_id);
+        account.round_up = enabled;
+        self.internal_set_account(&account_id, &account);
+    }
+
+    /// Returns the savings of `account_id` in `token_id`.
+    pub fn get_savings(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(self.internal_savings(&account_id, &token_id))
+    }
+
+    /// Sends `amount` of the caller's `token_id` savings back to them.
+    /// Requires exactly 1 yoctoNEAR attached, as token withdrawals do.
+    #[payable]
+    pub fn withdraw_savings(&mut self, token_id: ValidAccountId, amount: U128) -> Promise {
+        self.assert_access("withdraw_savings");
+        let account_id = env::predecessor_account_id();
+        self.assert_unlocked(&account_id);
+        let token_id: AccountId = token_id.into();
+        let saved = self.internal_savings(&account_id, &token_id);
+        require!(
+            amount.0 > 0 && amount.0 <= saved,
+            messages::NOT_ENOUGH_SAVINGS
+        );
+        self.savings
+            .insert(&(account_id.clone(), token_id.clone()), &(saved - amount.0));
+        let mut token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        token.total_held -= amount.0;
+        token.total_owed -= amount.0;
+        self.tokens.insert(&token_id, &token);
+
+        ext_ft::ft_transfer(
+            account_id.clone(),
+            amount,
+            None,
+            &token_id,
+            1,
+            self.config().gas.ft_transfer.0,
+        )
+        .then(ext_self::on_savings_withdrawn(
+            account_id,
+            token_id,
+            amount,
+            &env::current_account_id(),
+            0,
+            self.config().gas.callback.0,
+        ))
+    }
+
+    /// Puts the savings back if the token transfer failed.
+    pub fn on_savings_withdrawn(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+    ) {
+        self.assert_access("on_savings_withdrawn");
+        let event = SavingsEvent {
+            account_id: &account_id,
+            token_id: &token_id,
+            amount,
+        };
+        if promise_succeeded().is_ok() {
+            self.emit_event("savings_withdrawn", &event);
+            return;
+        }
+        let saved = self.internal_savings(&account_id, &token_id);
+        self.savings
+            .insert(&(account_id.clone(), token_id.clone()), &(saved + amount.0));
+        if let Some(mut token) = self.tokens.get(&token_id) {
+            token.total_held += amount.0;
+            token.total_owed += amount.0;
+            self.tokens.insert(&token_id, &token);
+        }
+        self.emit_event("savings_withdraw_failed", &event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::PromiseResult;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    /// USDC with a round-up unit of 1 USDC, and jane opted in.
+    fn savings_contract() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.add_token(account("usdc.testnet"), 6, U128(0));
+        contract.set_round_up_unit(account("usdc.testnet"), U128(1_000_000));
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_round_up(true);
+        contract
+    }
+
+    fn deposit(contract: &mut Change, sender: &str, amount: u128) {
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account(sender), U128(amount), String::new());
+    }
+
+    fn balance(contract: &Change, account_id: &str) -> (u128, u128) {
+        (
+            contract
+                .get_token_balance(account_id.to_string(), "usdc.testnet".to_string())
+                .0,
+            contract
+                .get_savings(account_id.to_string(), "usdc.testnet".to_string())
+                .0,
+        )
+    }
+
+    #[test]
+    fn payments_are_rounded_up_into_savings() {
+        let mut contract = savings_contract();
+        deposit(&mut contract, "jane.testnet", 4_300_000);
+        assert_eq!((3_600_000, 700_000), balance(&contract, "jane.testnet"));
+        deposit(&mut contract, "jane.testnet", 2_000_000);
+        assert_eq!((5_600_000, 700_000), balance(&contract, "jane.testnet"));
+        // bob never opted in
+        deposit(&mut contract, "bob.testnet", 4_300_000);
+        assert_eq!((4_300_000, 0), balance(&contract, "bob.testnet"));
+    }
+
+    #[test]
+    fn round_up_is_skipped_when_balance_is_short() {
+        let mut contract = savings_contract();
+        deposit(&mut contract, "jane.testnet", 200_000);
+        assert_eq!((200_000, 0), balance(&contract, "jane.testnet"));
+    }
+
+    #[test]
+    fn savings_withdrawal_is_restored_on_failure() {
+        let mut contract = savings_contract();
+        deposit(&mut contract, "jane.testnet", 4_300_000);
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_savings(account("usdc.testnet"), U128(500_000));
+        assert_eq!((3_600_000, 200_000), balance(&contract, "jane.testnet"));
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_savings_withdrawn(
+            "jane.testnet".to_string(),
+            "usdc.testnet".to_string(),
+            U128(500_000),
+        );
+        assert_eq!((3_600_000, 700_000), balance(&contract, "jane.testnet"));
+        assert_eq!(4_300_000, contract.get_token_totals()[0].owed.0);
+    }
+
+    #[test]
+    fn savings_are_withdrawn_with_ft_transfer() {
+        let mut contract = savings_contract();
+        deposit(&mut contract, "jane.testnet", 4_300_000);
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_savings(account("usdc.testnet"), U128(500_000));
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("usdc.testnet"));
+        assert!(receipts.contains("ft_transfer"));
+        assert!(receipts.contains("on_savings_withdrawn"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough savings")]
+    fn cannot_withdraw_more_than_saved() {
+        let mut contract = savings_contract();
+        deposit(&mut contract, "jane.testnet", 4_300_000);
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_savings(account("usdc.testnet"), U128(700_001));
+    }
+}
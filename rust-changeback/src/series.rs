@@ -0,0 +1,187 @@
+//! Net change over time in buckets, for charting without an indexer.
+//!
+//! Every move recorded in the [history](crate::history) is also added to the
+//! checkpoint of its minute and currency: one stored net change per minute
+//! that saw activity. [`get_series`](Change::get_series) sums them into
+//! buckets of any length, every bucket of the range included, so the result
+//! can be handed to a chart as it is. Buckets are as exact as the checkpoints:
+//! a checkpoint counts towards the bucket its minute starts in.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{I128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, Timestamp};
+
+use crate::config::MINUTE;
+use crate::*;
+
+/// Length of the period each checkpoint covers.
+const CHECKPOINT_INTERVAL: u64 = MINUTE;
+/// Most buckets one series can have, bounding its size.
+const MAX_SERIES_BUCKETS: u64 = 500;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Checkpoint {
+    /// Start of the minute the checkpoint covers.
+    pub period_start: Timestamp,
+    /// Sum of the deltas recorded in that minute.
+    pub net_change: i128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SeriesPoint {
+    /// Start of the bucket.
+    pub timestamp: U64,
+    pub net_change: I128,
+    /// `net_change` formatted for display, see [`display`](crate::display).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_change_formatted: Option<String>,
+}
+
+/// Index of the first of `checkpoints` starting at or after `timestamp`.
+fn first_checkpoint_from(checkpoints: &Vector<Checkpoint>, timestamp: Timestamp) -> u64 {
+    let (mut low, mut high) = (0, checkpoints.len());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let checkpoint = checkpoints.get(middle).expect("Checkpoint is missing");
+        if checkpoint.period_start < timestamp {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+impl Change {
+    /// Adds `delta` to the checkpoint of the current minute in `currency`.
+    pub(crate) fn internal_checkpoint(&mut self, currency: &str, delta: i128) {
+        let now = env::block_timestamp();
+        let period_start = now - now % CHECKPOINT_INTERVAL;
+        let currency = currency.to_string();
+        let mut checkpoints = self.checkpoints.get(&currency).unwrap_or_else(|| {
+            Vector::new(StorageKey::CurrencyCheckpoints {
+                currency: currency.clone(),
+            })
+        });
+        match checkpoints.len().checked_sub(1) {
+            Some(last) if checkpoints.get(last).unwrap().period_start == period_start => {
+                let mut checkpoint = checkpoints.get(last).unwrap();
+                checkpoint.net_change = checkpoint.net_change.saturating_add(delta);
+                checkpoints.replace(last, &checkpoint);
+            }
+            _ => checkpoints.push(&Checkpoint {
+                period_start,
+                net_change: delta,
+            }),
+        }
+        self.checkpoints.insert(&currency, &checkpoints);
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the net change of `currency` in every `bucket_seconds` long
+    /// bucket from `from_ts` up to `to_ts`, in nanoseconds, oldest first.
+    /// Buckets without activity are included with 0.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_series '{"bucket_seconds": 3600, "from_ts": "1700000000000000000", "to_ts": "1700086400000000000"}'
+    /// ```
+    pub fn get_series(
+        &self,
+        bucket_seconds: u64,
+        from_ts: U64,
+        to_ts: U64,
+        currency: Option<String>,
+    ) -> Vec<SeriesPoint> {
+        let currency = currency_symbol(currency).unwrap_or_else(|error| fail!(error));
+        require!(bucket_seconds > 0, messages::BUCKET_NOT_POSITIVE);
+        require!(from_ts.0 < to_ts.0, messages::EMPTY_RANGE);
+        let bucket = bucket_seconds
+            .checked_mul(1_000_000_000)
+            .unwrap_or_else(|| fail!(messages::BUCKET_TOO_LONG));
+        let range = to_ts.0 - from_ts.0;
+        let buckets = range.div_ceil(bucket);
+        require!(
+            buckets <= MAX_SERIES_BUCKETS,
+            messages::TOO_MANY_BUCKETS,
+            MAX_SERIES_BUCKETS
+        );
+        let mut series: Vec<SeriesPoint> = (0..buckets)
+            .map(|index| SeriesPoint {
+                timestamp: U64(from_ts.0 + index * bucket),
+                net_change: I128(0),
+                net_change_formatted: None,
+            })
+            .collect();
+        if let Some(checkpoints) = self.checkpoints.get(&currency) {
+            let first = first_checkpoint_from(&checkpoints, from_ts.0);
+            for index in first..checkpoints.len() {
+                let checkpoint = checkpoints.get(index).expect("Checkpoint is missing");
+                if checkpoint.period_start >= to_ts.0 {
+                    break;
+                }
+                let point = &mut series[((checkpoint.period_start - from_ts.0) / bucket) as usize];
+                point.net_change = I128(point.net_change.0.saturating_add(checkpoint.net_change));
+            }
+        }
+        for point in &mut series {
+            point.net_change_formatted = self.internal_format(point.net_change.0);
+        }
+        series
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HOUR;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn at(predecessor: &str, timestamp: u64) {
+        let mut context = context_for(predecessor, 0);
+        context.block_timestamp = timestamp;
+        testing_env!(context);
+    }
+
+    fn values(series: &[SeriesPoint]) -> Vec<i128> {
+        series.iter().map(|point| point.net_change.0).collect()
+    }
+
+    #[test]
+    fn moves_are_summed_into_buckets() {
+        at("jane.testnet", 0);
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(250)), None);
+        at("bob.testnet", 30 * 1_000_000_000);
+        contract.add(None, None, Some(U128(100)), None);
+        at("jane.testnet", HOUR + MINUTE);
+        contract.change(None, None, None);
+        at("bob.testnet", 3 * HOUR);
+        contract.add(None, None, Some(U128(5)), None);
+
+        let series = contract.get_series(3600, U64(0), U64(3 * HOUR), None);
+        assert_eq!(vec![350, -10, 0], values(&series));
+        assert_eq!(HOUR, series[1].timestamp.0);
+        let series = contract.get_series(7200, U64(HOUR), U64(4 * HOUR), None);
+        assert_eq!(vec![-10, 5], values(&series));
+        assert!(contract
+            .get_series(3600, U64(0), U64(HOUR), Some("USD".to_string()))
+            .iter()
+            .all(|point| point.net_change.0 == 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "At most 500 buckets per series")]
+    fn series_are_bounded() {
+        at("jane.testnet", 0);
+        let contract = Change::default();
+        contract.get_series(60, U64(0), U64(501 * MINUTE), None);
+    }
+}
@@ -0,0 +1,270 @@
+//! Paying merchants their revenue.
+//!
+//! [`settle`](Change::settle) sends a merchant's accumulated revenue, minus the
+//! platform fee, to its payout account. The fee goes to the treasury. Every
+//! settlement is recorded, and reverted if the transfer fails.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Timestamp};
+
+use crate::amount::bps_of;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SettlementStatus {
+    /// The transfer to the payout account is in flight.
+    Pending,
+    Settled,
+    /// The transfer failed and the revenue went back to the merchant.
+    Failed,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Settlement {
+    pub merchant_id: AccountId,
+    pub payout_account: AccountId,
+    /// Revenue settled, before the fee.
+    pub gross: Balance,
+    /// Platform fee kept in the treasury.
+    pub fee: Balance,
+    /// Fee waived by campaigns, see [`campaign`](crate::campaign).
+    pub fee_waived: Balance,
+    pub timestamp: Timestamp,
+    pub status: SettlementStatus,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SettlementView {
+    pub id: U64,
+    pub merchant_id: AccountId,
+    pub payout_account: AccountId,
+    pub gross: U128,
+    pub fee: U128,
+    pub fee_waived: U128,
+    pub net: U128,
+    pub timestamp: U64,
+    pub status: SettlementStatus,
+}
+
+impl SettlementView {
+    fn new(id: u64, settlement: Settlement) -> Self {
+        Self {
+            id: U64(id),
+            merchant_id: settlement.merchant_id,
+            payout_account: settlement.payout_account,
+            gross: U128(settlement.gross),
+            fee: U128(settlement.fee),
+            fee_waived: U128(settlement.fee_waived),
+            net: U128(settlement.gross - settlement.fee),
+            timestamp: U64(settlement.timestamp),
+            status: settlement.status,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Pays the revenue of `merchant_id`, minus the platform fee, to its payout account.
+    /// Callable by the merchant or the owner. Returns the settlement id.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet settle '{"merchant_id": "shop.testnet"}' --accountId shop.testnet
+    /// ```
+    pub fn settle(&mut self, merchant_id: AccountId) -> U64 {
+        self.assert_access("settle");
+        self.assert_not_paused();
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == merchant_id || caller == self.owner_id,
+            messages::SETTLER_ONLY
+        );
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        self.internal_mature_revenue(&mut merchant);
+        if merchant.maturing_head == merchant.maturing.len() {
+            merchant.maturing.clear();
+            merchant.maturing_head = 0;
+            merchant.maturing_cursor = 0;
+        }
+        let gross = merchant.revenue;
+        require!(
+            gross >= self.config().min_settlement.0,
+            messages::REVENUE_BELOW_MINIMUM
+        );
+        let full_fee = bps_of(gross, merchant.fee_bps, self.config().rounding_mode);
+        let fee_waived = full_fee.min(merchant.fee_credit);
+        let fee = full_fee - fee_waived;
+        self.internal_record_outflow(gross - fee);
+        merchant.fee_credit -= fee_waived;
+        merchant.revenue = 0;
+        self.merchants.insert(&merchant_id, &merchant);
+        self.internal_post(Posting::pay_out(Book::Merchants, gross - fee));
+        self.internal_credit_treasury(fee, Book::Merchants);
+
+        let id = self.settlements.len();
+        let settlement = Settlement {
+            merchant_id,
+            payout_account: merchant.payout_account.clone(),
+            gross,
+            fee,
+            fee_waived,
+            timestamp: env::block_timestamp(),
+            status: SettlementStatus::Pending,
+        };
+        self.settlements.push(&settlement);
+        self.emit_event("settlement", &SettlementView::new(id, settlement));
+
+        self.internal_transfer(merchant.payout_account, gross - fee)
+            .then(ext_self::on_settled(
+                U64(id),
+                &env::current_account_id(),
+                0,
+                self.config().gas.callback.0,
+            ));
+        U64(id)
+    }
+
+    /// Marks the settlement done, or gives the revenue back to the merchant if the transfer failed.
+    pub fn on_settled(&mut self, settlement_id: U64) {
+        self.assert_access("on_settled");
+        let mut settlement = self
+            .settlements
+            .get(settlement_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_SETTLEMENT));
+        if promise_succeeded().is_ok() {
+            settlement.status = SettlementStatus::Settled;
+        } else {
+            settlement.status = SettlementStatus::Failed;
+            self.internal_post(Posting::receive(
+                Book::Merchants,
+                settlement.gross - settlement.fee,
+            ));
+            self.internal_revert_treasury_credit(settlement.fee, Book::Merchants);
+            if let Some(mut merchant) = self.merchants.get(&settlement.merchant_id) {
+                merchant.revenue += settlement.gross;
+                merchant.fee_credit += settlement.fee_waived;
+                self.merchants.insert(&settlement.merchant_id, &merchant);
+            }
+        }
+        self.settlements.replace(settlement_id.0, &settlement);
+        if settlement.status == SettlementStatus::Failed {
+            self.emit_event(
+                "settlement_failed",
+                &SettlementView::new(settlement_id.0, settlement),
+            );
+        }
+    }
+
+    /// Returns up to `limit` settlements starting at `from_index`, as many as
+    /// the gas allows, see [`pagination`](crate::pagination).
+    pub fn get_settlements(&self, from_index: U64, limit: u64) -> Page<SettlementView> {
+        paginate(limit, |n| {
+            let id = from_index.0.checked_add(n)?;
+            Some(SettlementView::new(id, self.settlements.get(id)?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult, VMContext};
+    use std::convert::TryFrom;
+
+    const NEAR: u128 = 10u128.pow(24);
+
+    fn market_with_sales(amount: u128) -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(
+            ValidAccountId::try_from("shop.testnet").unwrap(),
+            ValidAccountId::try_from("shop-payouts.testnet").unwrap(),
+            250,
+        );
+        testing_env!(context_for("jane.testnet", amount));
+        contract.purchase(
+            U128(amount),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        contract
+    }
+
+    fn past_dispute_window(predecessor: &str) -> VMContext {
+        let mut context = context_for(predecessor, 0);
+        context.block_timestamp = Config::default().dispute_window.0 + 1;
+        context
+    }
+
+    #[test]
+    fn settlement_pays_revenue_minus_fee() {
+        let mut contract = market_with_sales(2 * NEAR);
+        testing_env!(past_dispute_window("shop.testnet"));
+        let id = contract.settle("shop.testnet".to_string());
+        let settlement = &contract.get_settlements(id, 1).items[0];
+        assert_eq!(2 * NEAR / 40, settlement.fee.0);
+        assert_eq!(2 * NEAR - 2 * NEAR / 40, settlement.net.0);
+        assert_eq!(SettlementStatus::Pending, settlement.status);
+        assert_eq!(2 * NEAR / 40, contract.get_treasury().0);
+        let shop = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(0, shop.revenue.0);
+    }
+
+    #[test]
+    fn failed_settlement_returns_the_revenue() {
+        let mut contract = market_with_sales(2 * NEAR);
+        testing_env!(past_dispute_window("alice.testnet"));
+        let id = contract.settle("shop.testnet".to_string());
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_settled(id);
+        assert_eq!(
+            SettlementStatus::Failed,
+            contract.get_settlements(id, 1).items[0].status
+        );
+        assert_eq!(0, contract.get_treasury().0);
+        let shop = contract.get_merchant("shop.testnet".to_string()).unwrap();
+        assert_eq!(2 * NEAR, shop.revenue.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Revenue is below the minimum settlement amount")]
+    fn small_revenue_waits_for_the_minimum() {
+        let mut contract = market_with_sales(NEAR - 1);
+        testing_env!(past_dispute_window("shop.testnet"));
+        contract.settle("shop.testnet".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Revenue is below the minimum settlement amount")]
+    fn revenue_in_the_dispute_window_is_not_settled() {
+        let mut contract = market_with_sales(2 * NEAR);
+        testing_env!(context_for("shop.testnet", 0));
+        contract.settle("shop.testnet".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the merchant or the owner can settle")]
+    fn others_cannot_settle() {
+        let mut contract = market_with_sales(2 * NEAR);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.settle("shop.testnet".to_string());
+    }
+}
@@ -0,0 +1,300 @@
+//! Spreading the Change ledger over shard contracts, for user bases too large
+//! for one contract's storage.
+//!
+//! [`deploy_shards`](Change::deploy_shards) deploys the code the owner uploaded
+//! to the [factory](crate::factory) to `shard<i>.<this contract>` subaccounts,
+//! this contract owning each of them. The number of shards is fixed by the
+//! first deployment; shards that failed to deploy are retried by calling it
+//! again with the same count.
+//!
+//! Every account belongs to one shard, picked by the hash of its id, and
+//! [`get_shard_for`](Change::get_shard_for) tells clients which. Once sharded,
+//! this contract only routes: `add`, `change` and `batch_apply` refuse the
+//! accounts, which are served by their shard. Shards don't deploy shards of
+//! their own, so they serve every account they're sent. Changes already
+//! stored here aren't moved, so a contract is meant to be sharded before it
+//! takes on accounts.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::factory::MIN_CHILD_DEPOSIT;
+use crate::*;
+
+/// Most shards a contract can be split into.
+const MAX_SHARDS: u32 = 16;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ShardStatus {
+    Deploying,
+    Active,
+    /// The deployment failed; the next `deploy_shards` retries it.
+    Failed,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Shard {
+    pub account_id: AccountId,
+    pub status: ShardStatus,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ShardView {
+    pub account_id: AccountId,
+    pub status: ShardStatus,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ShardEvent<'a> {
+    account_id: &'a AccountId,
+    status: ShardStatus,
+}
+
+impl Change {
+    /// Returns the index of the shard `account_id` belongs to, if the
+    /// contract is sharded.
+    fn internal_shard_index(&self, account_id: &AccountId) -> Option<u64> {
+        let count = self.shards.len();
+        if count == 0 {
+            return None;
+        }
+        let hash = env::sha256(account_id.as_bytes());
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash[..8]);
+        Some(u64::from_le_bytes(prefix) % count)
+    }
+
+    /// Panics if `account_id` belongs to a shard rather than this contract.
+    pub(crate) fn assert_served_here(&self, account_id: &AccountId) {
+        if let Some(index) = self.internal_shard_index(account_id) {
+            let shard = self.shards.get(index).expect("Shard is missing");
+            fail!(
+                messages::ACCOUNT_ON_OTHER_SHARD,
+                account_id,
+                shard.account_id
+            );
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Deploys the uploaded code to every one of the `count` shards that isn't
+    /// deployed or deploying yet, splitting the attached deposit between them.
+    /// Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet deploy_shards '{"count": 4}' --accountId Change.YOU.testnet --amount 20 --gas 300000000000000
+    /// ```
+    #[payable]
+    pub fn deploy_shards(&mut self, count: u32) {
+        self.assert_access("deploy_shards");
+        require!(
+            count > 0 && count <= MAX_SHARDS,
+            messages::INVALID_SHARD_COUNT,
+            MAX_SHARDS
+        );
+        if self.shards.is_empty() {
+            for index in 0..count {
+                let account_id = format!("shard{}.{}", index, env::current_account_id());
+                require!(
+                    env::is_valid_account_id(account_id.as_bytes()),
+                    messages::INVALID_SHARD_ACCOUNT,
+                    account_id
+                );
+                self.shards.push(&Shard {
+                    account_id,
+                    status: ShardStatus::Failed,
+                });
+            }
+        } else {
+            require!(
+                u64::from(count) == self.shards.len(),
+                messages::SHARD_COUNT_FIXED
+            );
+        }
+        let pending: Vec<u64> = (0..self.shards.len())
+            .filter(|&index| self.shards.get(index).unwrap().status == ShardStatus::Failed)
+            .collect();
+        require!(!pending.is_empty(), messages::SHARDS_DEPLOYED);
+        let deposit = env::attached_deposit() / pending.len() as Balance;
+        require!(
+            deposit >= MIN_CHILD_DEPOSIT,
+            messages::SHARD_DEPOSIT_TOO_SMALL,
+            MIN_CHILD_DEPOSIT
+        );
+        for index in pending {
+            let mut shard = self.shards.get(index).unwrap();
+            shard.status = ShardStatus::Deploying;
+            self.shards.replace(index, &shard);
+            self.internal_deploy_child(shard.account_id, &env::current_account_id(), deposit)
+                .then(ext_self::on_shard_deployed(
+                    index,
+                    U128(deposit),
+                    &env::current_account_id(),
+                    0,
+                    self.config().gas.callback.0,
+                ));
+        }
+    }
+
+    /// Marks shard `index` active, or failed with its deposit, which came
+    /// back, credited to the treasury.
+    pub fn on_shard_deployed(&mut self, index: u64, deposit: U128) {
+        self.assert_access("on_shard_deployed");
+        let mut shard = self
+            .shards
+            .get(index)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_SHARD));
+        shard.status = if promise_succeeded().is_ok() {
+            ShardStatus::Active
+        } else {
+            self.internal_credit_treasury(deposit.0, Book::Custody);
+            ShardStatus::Failed
+        };
+        self.shards.replace(index, &shard);
+        self.emit_event(
+            "shard_deployed",
+            &ShardEvent {
+                account_id: &shard.account_id,
+                status: shard.status,
+            },
+        );
+    }
+
+    /// Returns the contract serving `account_id`: its shard, or this contract
+    /// when it isn't sharded.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_shard_for '{"account_id": "jane.testnet"}'
+    /// ```
+    pub fn get_shard_for(&self, account_id: AccountId) -> AccountId {
+        match self.internal_shard_index(&account_id) {
+            Some(index) => self.shards.get(index).expect("Shard is missing").account_id,
+            None => env::current_account_id(),
+        }
+    }
+
+    pub fn get_shards(&self) -> Vec<ShardView> {
+        self.shards
+            .iter()
+            .map(|shard| ShardView {
+                account_id: shard.account_id,
+                status: shard.status,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::Base64VecU8;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult};
+
+    fn sharded(count: u32) -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_contract_code(Base64VecU8(vec![0, 97, 115, 109]));
+        testing_env!(context_for(
+            "alice.testnet",
+            u128::from(count) * MIN_CHILD_DEPOSIT
+        ));
+        contract.deploy_shards(count);
+        contract
+    }
+
+    #[test]
+    fn shards_are_deployed_to_subaccounts() {
+        let contract = sharded(2);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("shard0.alice.testnet"));
+        assert!(receipts.contains("shard1.alice.testnet"));
+        let shards = contract.get_shards();
+        assert_eq!(2, shards.len());
+        assert_eq!(ShardStatus::Deploying, shards[1].status);
+    }
+
+    #[test]
+    fn accounts_are_routed_to_their_shard() {
+        testing_env!(context_for("alice.testnet", 0));
+        let contract = Change::default();
+        assert_eq!(
+            "alice.testnet",
+            contract.get_shard_for("jane.testnet".to_string())
+        );
+
+        let contract = sharded(4);
+        let shards: Vec<_> = [
+            "jane.testnet",
+            "bob.testnet",
+            "carol.testnet",
+            "dave.testnet",
+        ]
+        .iter()
+        .map(|account_id| contract.get_shard_for(account_id.to_string()))
+        .collect();
+        assert!(shards
+            .iter()
+            .all(|shard| shard.starts_with("shard") && shard.ends_with(".alice.testnet")));
+        assert_eq!(
+            shards[0],
+            contract.get_shard_for("jane.testnet".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Account jane.testnet is served by shard0.alice.testnet")]
+    fn sharded_accounts_are_refused() {
+        let mut contract = sharded(1);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, None, None);
+    }
+
+    #[test]
+    fn failed_shards_are_redeployed() {
+        let mut contract = sharded(2);
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_shard_deployed(1, U128(MIN_CHILD_DEPOSIT));
+        assert_eq!(ShardStatus::Failed, contract.get_shards()[1].status);
+        assert_eq!(MIN_CHILD_DEPOSIT, contract.book(Book::Treasury));
+
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_shard_deployed(0, U128(MIN_CHILD_DEPOSIT));
+        assert_eq!(ShardStatus::Active, contract.get_shards()[0].status);
+
+        testing_env!(context_for("alice.testnet", MIN_CHILD_DEPOSIT));
+        contract.deploy_shards(2);
+        assert_eq!(ShardStatus::Deploying, contract.get_shards()[1].status);
+    }
+
+    #[test]
+    #[should_panic(expected = "The shard count can't change")]
+    fn the_shard_count_is_fixed() {
+        let mut contract = sharded(2);
+        testing_env!(context_for("alice.testnet", 2 * MIN_CHILD_DEPOSIT));
+        contract.deploy_shards(3);
+    }
+}
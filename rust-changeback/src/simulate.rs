@@ -0,0 +1,227 @@
+//! Dry runs of mutating calls, so frontends can check a call before sending
+//! the transaction.
+//!
+//! Each view runs the same checks and arithmetic as the call it stands for and
+//! returns either the projected result or the exact message the call would
+//! panic with.
+
+use near_sdk::json_types::{I128, U128};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::purchase::PurchaseQuote;
+use crate::*;
+
+/// Outcome of a dry run: `result` if the call would go through, `error` otherwise.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Simulation<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<T, E: ToString> From<Result<T, E>> for Simulation<T> {
+    fn from(outcome: Result<T, E>) -> Self {
+        match outcome {
+            Ok(result) => Self {
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => Self {
+                result: None,
+                error: Some(error.to_string()),
+            },
+        }
+    }
+}
+
+impl Change {
+    fn internal_check_not_paused(&self) -> Result<(), Message> {
+        if self.paused {
+            return Err(messages::CONTRACT_PAUSED);
+        }
+        if self.read_only {
+            return Err(messages::CONTRACT_READ_ONLY);
+        }
+        Ok(())
+    }
+}
+
+impl Change {
+    /// Returns the currency and amount of a counter call, or why it would be refused.
+    fn internal_check_counter(
+        &self,
+        amount: Option<U128>,
+        step: U128,
+        currency: Option<String>,
+    ) -> Result<(String, i128), String> {
+        self.internal_check_not_paused()?;
+        let amount = counter_amount(amount.unwrap_or(step))?;
+        self.internal_check_not_dust(amount)?;
+        Ok((currency_symbol(currency)?, amount))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the value `add` by `account_id` would leave, `amount` defaulting
+    /// to the `add_step` it adds and `currency` to `"NEAR"`.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet simulate_add '{"account_id": "donation.YOU.testnet", "amount": "1000"}'
+    /// ```
+    pub fn simulate_add(
+        &self,
+        account_id: AccountId,
+        amount: Option<U128>,
+        currency: Option<String>,
+    ) -> Simulation<I128> {
+        self.internal_check_counter(amount, self.config().add_step, currency)
+            .and_then(|(currency, amount)| {
+                self.internal_apply_delta(self.internal_get_num(&account_id, &currency), amount)
+            })
+            .map(I128)
+            .into()
+    }
+
+    /// Returns the value `change` by `account_id` would leave, `amount`
+    /// defaulting to the `sub_step` it subtracts.
+    pub fn simulate_change(
+        &self,
+        account_id: AccountId,
+        amount: Option<U128>,
+        currency: Option<String>,
+    ) -> Simulation<I128> {
+        self.internal_check_counter(amount, self.config().sub_step, currency)
+            .and_then(|(currency, amount)| {
+                self.internal_apply_delta(self.internal_get_num(&account_id, &currency), -amount)
+            })
+            .map(I128)
+            .into()
+    }
+
+    /// Returns how a `purchase` of `price` by `account_id` attaching `deposit`
+    /// would be paid.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet simulate_purchase '{"account_id": "donation.YOU.testnet", "price": "4300000000000000000000000", "deposit": "5000000000000000000000000"}'
+    /// ```
+    pub fn simulate_purchase(
+        &self,
+        account_id: AccountId,
+        price: U128,
+        deposit: U128,
+        use_balance: Option<bool>,
+        merchant_id: Option<AccountId>,
+    ) -> Simulation<PurchaseQuote> {
+        self.internal_check_not_paused()
+            .and_then(|_| {
+                self.internal_quote_purchase(
+                    &account_id,
+                    price.0,
+                    0,
+                    deposit.0,
+                    use_balance.unwrap_or(false),
+                    merchant_id.as_ref(),
+                )
+            })
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn simulations_project_the_counter() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let jane = "jane.testnet".to_string();
+        let result = |simulation: Simulation<I128>| simulation.result.map(|val| val.0);
+        assert_eq!(
+            Some(1000),
+            result(contract.simulate_add(jane.clone(), None, None))
+        );
+        assert_eq!(
+            Some(-10),
+            result(contract.simulate_change(jane.clone(), None, None))
+        );
+        contract.internal_set_num(&jane, DEFAULT_CURRENCY, i128::MAX);
+        assert_eq!(
+            Some(format!(
+                "[CHANGE_OVERFLOW] Change overflow: current {}, delta 1",
+                i128::MAX
+            )),
+            contract
+                .simulate_add(jane.clone(), Some(U128(1)), None)
+                .error
+        );
+        assert_eq!(
+            Some(1),
+            result(contract.simulate_add("bob.testnet".to_string(), Some(U128(1)), None))
+        );
+        contract.pause();
+        assert_eq!(
+            Some("[CONTRACT_PAUSED] Contract is paused".to_string()),
+            contract.simulate_change(jane, None, None).error
+        );
+    }
+
+    #[test]
+    fn purchase_simulation_matches_the_purchase() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.register_merchant(
+            ValidAccountId::try_from("shop.testnet").unwrap(),
+            ValidAccountId::try_from("shop.testnet").unwrap(),
+            0,
+        );
+        let shop = Some("shop.testnet".to_string());
+        let quote = contract
+            .simulate_purchase(
+                "jane.testnet".to_string(),
+                U128(30),
+                U128(100),
+                None,
+                shop.clone(),
+            )
+            .result
+            .unwrap();
+        testing_env!(context_for("jane.testnet", 100));
+        let change = contract.purchase(U128(30), None, shop.clone(), None, None, None);
+        assert_eq!(change.0, quote.change.0);
+        assert_eq!(30, quote.due.0);
+
+        let short = contract.simulate_purchase(
+            "jane.testnet".to_string(),
+            U128(100),
+            U128(10),
+            Some(true),
+            shop,
+        );
+        // 70 of stored change and 10 attached don't cover 100
+        assert_eq!(
+            Some("[PRICE_NOT_COVERED] Attached deposit doesn't cover the price".to_string()),
+            short.error
+        );
+        let unknown = contract.simulate_purchase(
+            "jane.testnet".to_string(),
+            U128(30),
+            U128(100),
+            None,
+            Some("nobody.testnet".to_string()),
+        );
+        assert_eq!(
+            Some("[UNKNOWN_MERCHANT] Unknown merchant".to_string()),
+            unknown.error
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Snapshots of the Change ledger the owner can roll back to.
+//!
+//! The ledger can't be iterated, so a snapshot doesn't copy it when taken.
+//! Instead it saves the value a Change had at the time the first time that
+//! Change moves afterwards, so it holds exactly the values rolling back needs.
+//! Only the latest snapshot saves values: rolling back to an older one
+//! restores its values and those of every snapshot taken since, newest first,
+//! so the oldest saved value of each Change is the one left.
+//!
+//! Each call restores up to `MAX_RESTORES_PER_CALL` values and
+//! [`continue_rollback`](Change::continue_rollback) picks up where the last
+//! one stopped; the ledger refuses changes until the rollback is done. The
+//! restores are recorded in the history as `rollback` operations of their
+//! own, so rolling back is audited like any other move, and a later snapshot
+//! can still be rolled back to. Configured limits don't apply to restores.
+//!
+//! Once there are [trustees](crate::trustee), rolling back is up to them, as
+//! resets are. Only the Change ledger is restored. NEAR balances, receipts and
+//! the rest of the state stay as they are.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::U64;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, BlockHeight, Timestamp};
+
+use crate::*;
+
+/// Saved values one call restores, bounding its gas.
+const MAX_RESTORES_PER_CALL: u64 = 50;
+
+/// A Change saved by a snapshot: interned account id and currency.
+type SavedKey = (u32, String);
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Snapshot {
+    /// History records written before the snapshot was taken.
+    pub history_len: u64,
+    pub block_height: BlockHeight,
+    pub timestamp: Timestamp,
+    /// Value of each Change moved since, as it was when the snapshot was taken.
+    values: LookupMap<SavedKey, i128>,
+    /// Keys of `values`, in the order they were saved.
+    keys: Vector<SavedKey>,
+}
+
+/// A rollback restoring the ledger over several calls.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Rollback {
+    /// Snapshot rolled back to.
+    snapshot_id: u64,
+    /// Snapshot whose values are being restored, counting down to `snapshot_id`.
+    current: u64,
+    /// Next saved value of `current` to restore.
+    index: u64,
+    /// Changes restored so far.
+    restored: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SnapshotView {
+    pub snapshot_id: U64,
+    pub history_len: U64,
+    pub block_height: U64,
+    pub timestamp: U64,
+    /// Values saved, one per Change moved while it was the latest snapshot.
+    pub values: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RollbackView {
+    pub snapshot_id: U64,
+    pub restored: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SnapshotCreatedEvent {
+    snapshot_id: U64,
+    block_height: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct RolledBackEvent {
+    snapshot_id: U64,
+    /// Changes restored.
+    restored: U64,
+}
+
+impl Change {
+    /// Panics while a rollback is restoring the ledger.
+    pub(crate) fn assert_not_rolling_back(&self) {
+        require!(self.rollback.is_none(), messages::ROLLBACK_IN_PROGRESS);
+    }
+
+    /// Saves the Change of `account_id` in `currency` in the latest snapshot,
+    /// unless it already holds it. Called before every write to the ledger.
+    pub(crate) fn internal_save_for_snapshot(&mut self, account_id: &AccountId, currency: &str) {
+        let snapshot_id = match self.snapshots.len().checked_sub(1) {
+            Some(snapshot_id) => snapshot_id,
+            None => return,
+        };
+        let mut snapshot = self
+            .snapshots
+            .get(&snapshot_id)
+            .expect("Snapshot is missing");
+        let key = (self.internal_intern(account_id), currency.to_string());
+        if snapshot.values.get(&key).is_none() {
+            snapshot
+                .values
+                .insert(&key, &self.internal_get_num(account_id, currency));
+            snapshot.keys.push(&key);
+            self.snapshots.insert(&snapshot_id, &snapshot);
+        }
+    }
+
+    /// Starts rolling back to snapshot `snapshot_id`, see [`rollback_to`](Change::rollback_to).
+    pub(crate) fn internal_start_rollback(&mut self, snapshot_id: u64) -> bool {
+        self.assert_not_rolling_back();
+        require!(
+            self.snapshots.get(&snapshot_id).is_some(),
+            messages::NO_SUCH_SNAPSHOT
+        );
+        self.rollback = Some(Rollback {
+            snapshot_id,
+            current: self.snapshots.len() - 1,
+            index: 0,
+            restored: 0,
+        });
+        self.internal_continue_rollback()
+    }
+
+    /// Restores up to `MAX_RESTORES_PER_CALL` saved values of the rollback in
+    /// progress. Returns whether it is done.
+    fn internal_continue_rollback(&mut self) -> bool {
+        let mut rollback = self
+            .rollback
+            .take()
+            .unwrap_or_else(|| fail!(messages::NO_ROLLBACK_IN_PROGRESS));
+        let mut steps = 0;
+        let done = loop {
+            let snapshot = self
+                .snapshots
+                .get(&rollback.current)
+                .expect("Snapshot is missing");
+            while rollback.index < snapshot.keys.len() && steps < MAX_RESTORES_PER_CALL {
+                let key = snapshot.keys.get(rollback.index).expect("Key is missing");
+                let value = snapshot.values.get(&key).expect("Value is missing");
+                if self.internal_restore_num(key, value) {
+                    rollback.restored += 1;
+                }
+                rollback.index += 1;
+                steps += 1;
+            }
+            if rollback.index < snapshot.keys.len() {
+                break false;
+            }
+            if rollback.current == rollback.snapshot_id {
+                break true;
+            }
+            rollback.current -= 1;
+            rollback.index = 0;
+            // reading a snapshot counts too, or empty ones could exhaust the gas
+            steps += 1;
+            if steps >= MAX_RESTORES_PER_CALL {
+                break false;
+            }
+        };
+        if done {
+            self.emit_event(
+                "rolled_back",
+                &RolledBackEvent {
+                    snapshot_id: U64(rollback.snapshot_id),
+                    restored: U64(rollback.restored),
+                },
+            );
+        } else {
+            self.log(
+                LogLevel::Info,
+                messages::ROLLBACK_CONTINUES,
+                &[&rollback.restored],
+            );
+            self.rollback = Some(rollback);
+        }
+        done
+    }
+
+    /// Puts the Change saved under `key` back to `value`. Returns whether it moved.
+    fn internal_restore_num(&mut self, (account, currency): SavedKey, value: i128) -> bool {
+        let account_id = self.internal_account_id(account);
+        let old_value = self.internal_get_num(&account_id, &currency);
+        if old_value == value {
+            return false;
+        }
+        if value == 0 {
+            self.internal_drop_num(&account_id, &currency);
+        } else {
+            self.internal_write_num(&account_id, &currency, value);
+        }
+        self.internal_record_counter(
+            CounterOperation::Rollback,
+            &account_id,
+            &currency,
+            value - old_value,
+            old_value,
+            value,
+        );
+        true
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Takes a snapshot of the Change ledger. Returns its id. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet create_snapshot --accountId YOU.testnet
+    /// ```
+    pub fn create_snapshot(&mut self) -> U64 {
+        self.assert_access("create_snapshot");
+        self.assert_not_rolling_back();
+        let snapshot_id = self.snapshots.len();
+        let snapshot = Snapshot {
+            history_len: self.history.len(),
+            block_height: env::block_index(),
+            timestamp: env::block_timestamp(),
+            values: LookupMap::new(StorageKey::SnapshotValues { snapshot_id }),
+            keys: Vector::new(StorageKey::SnapshotKeys { snapshot_id }),
+        };
+        self.snapshots.insert(&snapshot_id, &snapshot);
+        self.emit_event(
+            "snapshot_created",
+            &SnapshotCreatedEvent {
+                snapshot_id: U64(snapshot_id),
+                block_height: U64(snapshot.block_height),
+            },
+        );
+        U64(snapshot_id)
+    }
+
+    /// Puts every Change moved since snapshot `snapshot_id` back to its value
+    /// at the time. Returns whether it is done; if not, the rest takes
+    /// [`continue_rollback`](Change::continue_rollback). Owner only, and only
+    /// while there are no trustees: from then on it takes a trustee proposal.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet rollback_to '{"snapshot_id": "0"}' --accountId YOU.testnet
+    /// ```
+    pub fn rollback_to(&mut self, snapshot_id: U64) -> bool {
+        self.assert_access("rollback_to");
+        require!(self.trustees.is_empty(), messages::ROLLBACK_NEEDS_APPROVAL);
+        self.internal_start_rollback(snapshot_id.0)
+    }
+
+    /// Restores the next values of the rollback in progress. Returns whether
+    /// it is done. Anyone can call it.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet continue_rollback --accountId YOU.testnet
+    /// ```
+    pub fn continue_rollback(&mut self) -> bool {
+        self.assert_access("continue_rollback");
+        self.internal_continue_rollback()
+    }
+
+    /// Returns the rollback in progress, if any.
+    pub fn get_rollback(&self) -> Option<RollbackView> {
+        self.rollback.as_ref().map(|rollback| RollbackView {
+            snapshot_id: U64(rollback.snapshot_id),
+            restored: U64(rollback.restored),
+        })
+    }
+
+    /// Returns up to `limit` snapshots starting at `from_index`, oldest first,
+    /// as many as the gas allows, see [`pagination`](crate::pagination).
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_snapshots '{"from_index": "0", "limit": 50}'
+    /// ```
+    pub fn get_snapshots(&self, from_index: U64, limit: u64) -> Page<SnapshotView> {
+        paginate(limit, |n| {
+            let snapshot_id = from_index.0.checked_add(n)?;
+            let snapshot = self.snapshots.get(&snapshot_id)?;
+            Some(SnapshotView {
+                snapshot_id: U64(snapshot_id),
+                history_len: U64(snapshot.history_len),
+                block_height: U64(snapshot.block_height),
+                timestamp: U64(snapshot.timestamp),
+                values: U64(snapshot.keys.len()),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn num_of(contract: &Change, account_id: &str) -> i128 {
+        contract.get_num_for(account_id.to_string(), None).0
+    }
+
+    #[test]
+    fn rollback_restores_the_ledger_at_the_snapshot() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, Some(U128(250)), None);
+        testing_env!(context_for("alice.testnet", 0));
+        let snapshot_id = contract.create_snapshot();
+
+        testing_env!(context_for("jane.testnet", 0));
+        contract.change(None, None, None);
+        contract.add(None, None, Some(U128(40)), None);
+        testing_env!(context_for("bob.testnet", 0));
+        contract.add(None, None, Some(U128(70)), None);
+        testing_env!(context_for("alice.testnet", 0));
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+
+        assert!(contract.rollback_to(snapshot_id));
+        assert_eq!(250, num_of(&contract, "jane.testnet"));
+        assert_eq!(0, num_of(&contract, "bob.testnet"));
+        assert!(contract.get_rollback().is_none());
+        assert_eq!(2, contract.get_snapshots(U64(0), 10).items[0].values.0);
+
+        let history = contract.get_history(U64(5), 10).items;
+        let summary: Vec<_> = history
+            .iter()
+            .map(|record| {
+                (
+                    record.account_id.as_str(),
+                    record.operation,
+                    record.delta.0,
+                    record.value.0,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("jane.testnet", CounterOperation::Rollback, 250, 250),
+                ("bob.testnet", CounterOperation::Rollback, -70, 0),
+            ],
+            summary
+        );
+    }
+
+    #[test]
+    fn later_snapshots_survive_a_rollback() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let first = contract.create_snapshot();
+        contract.add(None, None, Some(U128(100)), None);
+        let mut context = context_for("alice.testnet", 0);
+        context.block_index = 12;
+        context.block_timestamp = 34;
+        testing_env!(context);
+        let second = contract.create_snapshot();
+        contract.add(None, None, Some(U128(5)), None);
+
+        contract.rollback_to(first);
+        assert_eq!(0, num_of(&contract, "alice.testnet"));
+        contract.rollback_to(second);
+        assert_eq!(100, num_of(&contract, "alice.testnet"));
+
+        let snapshots = contract.get_snapshots(U64(0), 10).items;
+        assert_eq!(2, snapshots.len());
+        assert_eq!(1, snapshots[1].history_len.0);
+        assert_eq!(12, snapshots[1].block_height.0);
+        assert_eq!(34, snapshots[1].timestamp.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_rolls_back() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let snapshot_id = contract.create_snapshot();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.rollback_to(snapshot_id);
+    }
+
+    fn moved_since_snapshot(accounts: u64) -> (Change, U64) {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let snapshot_id = contract.create_snapshot();
+        for n in 0..accounts {
+            let account_id = format!("user{}.testnet", n);
+            contract.internal_set_num(&account_id, DEFAULT_CURRENCY, 1);
+        }
+        (contract, snapshot_id)
+    }
+
+    #[test]
+    fn long_rollbacks_continue_over_several_calls() {
+        let (mut contract, snapshot_id) = moved_since_snapshot(MAX_RESTORES_PER_CALL + 10);
+        assert!(!contract.rollback_to(snapshot_id));
+        assert_eq!(
+            MAX_RESTORES_PER_CALL,
+            contract.get_rollback().unwrap().restored.0
+        );
+        assert_eq!(1, num_of(&contract, "user59.testnet"));
+
+        testing_env!(context_for("jane.testnet", 0));
+        assert!(contract.continue_rollback());
+        assert_eq!(0, num_of(&contract, "user249.testnet"));
+        assert!(contract.get_rollback().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "A rollback is in progress")]
+    fn the_ledger_waits_for_the_rollback() {
+        let (mut contract, snapshot_id) = moved_since_snapshot(MAX_RESTORES_PER_CALL + 1);
+        contract.rollback_to(snapshot_id);
+        contract.add(None, None, Some(U128(1)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rollbacks need trustee approval")]
+    fn trustees_guard_rollbacks() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let snapshot_id = contract.create_snapshot();
+        contract.init_trustees(
+            vec![
+                ValidAccountId::try_from("t1.testnet").unwrap(),
+                ValidAccountId::try_from("t2.testnet").unwrap(),
+            ],
+            2,
+        );
+        contract.rollback_to(snapshot_id);
+    }
+}
@@ -0,0 +1,109 @@
+//! Solvency check made before any NEAR leaves the contract.
+//!
+//! Everything the contract holds on behalf of others (stored change, partial
+//! intent payments, outstanding gift cards, merchant revenue and rewards pools)
+//! must stay covered by its balance net of the [storage reserve](crate::storage). Every
+//! outbound transfer goes through [`internal_transfer`](Change::internal_transfer),
+//! which fails the call rather than dip into those funds.
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, Promise};
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SolvencyView {
+    /// Balance of the contract account.
+    pub balance: U128,
+    /// Part of the balance kept for storage.
+    pub storage_reserve: U128,
+    /// NEAR held on behalf of users and merchants.
+    pub liabilities: U128,
+}
+
+impl Change {
+    /// Returns the NEAR held on behalf of users and merchants.
+    pub(crate) fn internal_liabilities(&self) -> Balance {
+        [
+            Book::UserChange,
+            Book::Escrow,
+            Book::GiftCards,
+            Book::Merchants,
+        ]
+        .iter()
+        .map(|&book| self.book(book))
+        .sum()
+    }
+
+    /// Sends `amount` to `receiver_id`, panicking if the contract couldn't
+    /// cover its liabilities afterwards. Bookkeeping for the transfer has to be
+    /// done before, so the liabilities no longer count what is being sent.
+    pub(crate) fn internal_transfer(&self, receiver_id: AccountId, amount: Balance) -> Promise {
+        let available = env::account_balance().saturating_sub(self.internal_storage_reserve());
+        require!(
+            available >= self.internal_liabilities() + amount,
+            messages::INSOLVENT_TRANSFER
+        );
+        Promise::new(receiver_id).transfer(amount)
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the contract's balance next to what it owes.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_solvency
+    /// ```
+    pub fn get_solvency(&self) -> SolvencyView {
+        SolvencyView {
+            balance: U128(env::account_balance()),
+            storage_reserve: U128(self.internal_storage_reserve()),
+            liabilities: U128(self.internal_liabilities()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn liabilities_track_stored_change() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                beneficiary_id: Some("shop.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        testing_env!(context_for("jane.testnet", 100));
+        contract.purchase(U128(30), None, None, None, None, None);
+        assert_eq!(70, contract.get_solvency().liabilities.0);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.withdraw_change(U128(50));
+        assert_eq!(20, contract.get_solvency().liabilities.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Transfer would leave the contract insolvent")]
+    fn transfers_never_dip_into_user_funds() {
+        let mut context = context_for("jane.testnet", 0);
+        // just the storage reserve and 100 yoctoNEAR on top
+        context.account_balance = Balance::from(context.storage_usage) * 10u128.pow(19)
+            + Config::default().storage_reserve_margin.0
+            + 100;
+        testing_env!(context);
+        let mut contract = Change::default();
+        contract.internal_credit_balance(&"bob.testnet".to_string(), 80, Book::Custody);
+        contract.internal_credit_balance(&"jane.testnet".to_string(), 30, Book::Custody);
+        contract.withdraw_change(U128(30));
+    }
+}
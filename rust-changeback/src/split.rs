@@ -0,0 +1,254 @@
+//! Purchases whose price is shared among several payout accounts.
+//!
+//! A marketplace sale can pay the marketplace, the seller and a charity in one
+//! call: each recipient gets its share of the price in basis points, the
+//! shares adding up to exactly 100%. The bookkeeping (change, receipt) is done
+//! in one go; a share that can't be delivered is credited back to the buyer,
+//! like the price of a plain [purchase](crate::purchase).
+
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::amount::{bps_of, RoundingMode, BPS_DENOMINATOR};
+use crate::receipt::Receipt;
+use crate::*;
+
+/// Most recipients one payment can be split among, bounding its gas.
+pub(crate) const MAX_SPLITS: usize = 10;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SplitPurchaseEvent<'a> {
+    receipt_id: U64,
+    buyer_id: &'a AccountId,
+    price: U128,
+    shares: Vec<(&'a AccountId, U128)>,
+    from_balance: U128,
+    change: U128,
+}
+
+/// Works out each recipient's share of `price`, settling whatever rounding
+/// leaves over with the first recipient (or, when rounding up overshoots, the
+/// last ones) so the shares always add up to the price.
+fn shares_of(
+    price: Balance,
+    splits: &[(AccountId, u16)],
+    mode: RoundingMode,
+) -> Result<Vec<Balance>, Message> {
+    if splits.is_empty() || splits.len() > MAX_SPLITS {
+        return Err(messages::INVALID_SPLIT_COUNT);
+    }
+    let total_bps: u128 = splits.iter().map(|(_, bps)| u128::from(*bps)).sum();
+    if total_bps != BPS_DENOMINATOR {
+        return Err(messages::SPLITS_NOT_WHOLE);
+    }
+    for (index, (recipient, _)) in splits.iter().enumerate() {
+        if splits[..index].iter().any(|(other, _)| other == recipient) {
+            return Err(messages::DUPLICATE_SPLIT_RECIPIENT);
+        }
+    }
+    let mut shares: Vec<Balance> = splits
+        .iter()
+        .map(|(_, bps)| bps_of(price, *bps, mode))
+        .collect();
+    let allotted: Balance = shares.iter().sum();
+    if allotted > price {
+        // rounding up overshoots by at most one yoctoNEAR per split,
+        // taken back from the last recipients
+        let mut excess = allotted - price;
+        for share in shares.iter_mut().rev() {
+            let taken = excess.min(*share);
+            *share -= taken;
+            excess -= taken;
+        }
+    } else {
+        shares[0] += price - allotted;
+    }
+    Ok(shares)
+}
+
+/// Splits `amount` among `payers` in proportion to the shares they paid, to
+/// the basis point. An `amount` equal to the shares' total, like a full
+/// refund, gives each payer exactly its share.
+pub(crate) fn pro_rata(
+    amount: Balance,
+    payers: &[(AccountId, Balance)],
+    mode: RoundingMode,
+) -> Vec<Balance> {
+    let total: Balance = payers.iter().map(|(_, share)| share).sum();
+    if amount == total {
+        return payers.iter().map(|(_, share)| *share).collect();
+    }
+    let mut splits: Vec<(AccountId, u16)> = payers
+        .iter()
+        .map(|(payer_id, share)| (payer_id.clone(), (share * BPS_DENOMINATOR / total) as u16))
+        .collect();
+    // what the floored shares leave over goes to the first payer
+    let allotted: u128 = splits.iter().map(|(_, bps)| u128::from(*bps)).sum();
+    splits[0].1 += (BPS_DENOMINATOR - allotted) as u16;
+    shares_of(amount, &splits, mode).unwrap_or_else(|error| fail!(error))
+}
+
+#[near_bindgen]
+impl Change {
+    /// Pays `price` out of the attached deposit (and stored change with
+    /// `use_balance`) to the recipients of `splits`, each taking its share in
+    /// basis points. Returns the change credited.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet purchase_split '{"price": "10000000000000000000000000", "splits": [["market.testnet", 1000], ["seller.testnet", 8500], ["charity.testnet", 500]]}' --accountId donation.YOU.testnet --deposit 10
+    /// ```
+    #[payable]
+    pub fn purchase_split(
+        &mut self,
+        price: U128,
+        splits: Vec<(ValidAccountId, u16)>,
+        use_balance: Option<bool>,
+    ) -> U128 {
+        self.assert_access("purchase_split");
+        self.assert_writable();
+        let buyer_id = env::predecessor_account_id();
+        let splits: Vec<(AccountId, u16)> = splits
+            .into_iter()
+            .map(|(recipient, bps)| (recipient.into(), bps))
+            .collect();
+        let shares = shares_of(price.0, &splits, self.config().rounding_mode)
+            .unwrap_or_else(|error| fail!(error));
+        let quote = self
+            .internal_quote_payment(
+                &buyer_id,
+                price.0,
+                0,
+                env::attached_deposit(),
+                use_balance.unwrap_or(false),
+            )
+            .unwrap_or_else(|error| fail!(error));
+        let (from_balance, change) = (quote.from_balance.0, quote.change.0);
+        // the shares leave right away
+        self.internal_post(Posting::pay_out(Book::UserChange, from_balance));
+        let mut buyer = self.internal_get_account(&buyer_id);
+        buyer.balance -= from_balance;
+        self.internal_keep_change(&buyer_id, &mut buyer, change);
+        self.internal_set_account(&buyer_id, &buyer);
+
+        let receipt = Receipt {
+            buyer: self.internal_intern(&buyer_id),
+            merchant: None,
+            price: price.0,
+            discount: 0,
+            from_balance,
+            change,
+            session_id: None,
+            timestamp: env::block_timestamp(),
+            dispute_until: None,
+        };
+        let receipt_id = self.internal_add_receipt(&receipt);
+        self.emit_event(
+            "purchase_split",
+            &SplitPurchaseEvent {
+                receipt_id: U64(receipt_id),
+                buyer_id: &buyer_id,
+                price,
+                shares: splits
+                    .iter()
+                    .zip(&shares)
+                    .map(|((recipient, _), share)| (recipient, U128(*share)))
+                    .collect(),
+                from_balance: U128(from_balance),
+                change: U128(change),
+            },
+        );
+        for ((recipient, _), share) in splits.into_iter().zip(shares) {
+            if share > 0 {
+                self.internal_forward_price(&buyer_id, recipient, share);
+            }
+        }
+        U128(change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn splits(splits: &[(&str, u16)]) -> Vec<(AccountId, u16)> {
+        splits
+            .iter()
+            .map(|(recipient, bps)| (recipient.to_string(), *bps))
+            .collect()
+    }
+
+    #[test]
+    fn shares_always_add_up_to_the_price() {
+        let three_ways = splits(&[("a", 3333), ("b", 3333), ("c", 3334)]);
+        for mode in [
+            RoundingMode::Floor,
+            RoundingMode::Ceil,
+            RoundingMode::Bankers,
+        ] {
+            for price in [1, 2, 10, 101, 9_999, 10u128.pow(24) + 7] {
+                let shares = shares_of(price, &three_ways, mode).unwrap();
+                assert_eq!(price, shares.iter().sum::<u128>(), "{} {:?}", price, mode);
+            }
+        }
+        assert_eq!(
+            vec![34, 33, 33],
+            shares_of(100, &three_ways, RoundingMode::Floor).unwrap()
+        );
+    }
+
+    #[test]
+    fn pro_rata_shares_follow_what_each_payer_paid() {
+        let payers = vec![("a".to_string(), 300), ("b".to_string(), 100)];
+        assert_eq!(vec![300, 100], pro_rata(400, &payers, RoundingMode::Floor));
+        assert_eq!(vec![75, 25], pro_rata(100, &payers, RoundingMode::Floor));
+        let thirds = vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 1),
+            ("c".to_string(), 1),
+        ];
+        assert_eq!(vec![4, 3, 3], pro_rata(10, &thirds, RoundingMode::Floor));
+    }
+
+    #[test]
+    fn invalid_splits_are_rejected() {
+        let mode = RoundingMode::Floor;
+        assert_eq!(
+            Err(messages::SPLITS_NOT_WHOLE),
+            shares_of(100, &splits(&[("a", 5000), ("b", 4000)]), mode)
+        );
+        assert_eq!(
+            Err(messages::DUPLICATE_SPLIT_RECIPIENT),
+            shares_of(100, &splits(&[("a", 5000), ("a", 5000)]), mode)
+        );
+        assert_eq!(
+            Err(messages::INVALID_SPLIT_COUNT),
+            shares_of(100, &[], mode)
+        );
+    }
+
+    #[test]
+    fn split_purchases_keep_the_change_and_a_receipt() {
+        testing_env!(context_for("jane.testnet", 100));
+        let mut contract = Change::default();
+        let recipient = |id| ValidAccountId::try_from(id).unwrap();
+        let change = contract.purchase_split(
+            U128(60),
+            vec![
+                (recipient("market.testnet"), 1000),
+                (recipient("seller.testnet"), 9000),
+            ],
+            None,
+        );
+        assert_eq!(40, change.0);
+        assert_eq!(40, contract.get_balance("jane.testnet".to_string()).0);
+        let receipt = contract.get_receipt(U64(0)).unwrap();
+        assert_eq!(60, receipt.price.0);
+        assert_eq!(None, receipt.merchant_id);
+    }
+}
@@ -0,0 +1,367 @@
+//! Cloning the contract state into a staging deployment, to rehearse upgrades
+//! against real data.
+//!
+//! The owner of a [paused](crate::pause) contract reads its state chunk by
+//! chunk with [`export_full_state`](Change::export_full_state) and hands each
+//! chunk, in order, to [`import_state`](Change::import_state) on the paused
+//! staging contract. Chunk 0 carries the config and the [books](crate::accounting); the others
+//! carry up to `EXPORT_CHUNK_SIZE` records of one collection each. The
+//! [interned](crate::interning) accounts come first, so imported receipts
+//! keep naming the same accounts.
+//!
+//! Only collections that can be iterated are exported. Per-account records
+//! (the Change ledger, balances, token balances, savings, points) and the lookup indexes live in
+//! maps without a key list, so the staging contract starts without them. The
+//! owner and the pause flags are never copied.
+//!
+//! Meant for testnet staging: importing overwrites the state it touches.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+/// Records per exported chunk, bounding the gas of one call.
+pub(crate) const EXPORT_CHUNK_SIZE: u64 = 50;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RootExport {
+    pub config: Config,
+    pub scheduled_config: Option<ScheduledConfig>,
+    pub books: Books,
+    pub roles: Vec<(Role, AccountId)>,
+    pub trustees: Vec<AccountId>,
+    pub trustee_threshold: u32,
+    pub active_campaigns: Vec<u64>,
+    pub pending_proposals: Vec<u64>,
+    pub donation_leaderboard: Vec<(AccountId, Balance)>,
+}
+
+/// Merchant with the receipts its revenue is still maturing on, from the head.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct MerchantExport {
+    pub merchant: Merchant,
+    pub maturing: Vec<u64>,
+}
+
+/// Contents of one chunk. Records of the indexed collections come with the
+/// index of the first one, so chunks can't be imported out of order.
+// only ever built to be encoded or applied right away
+#[allow(clippy::large_enum_variant)]
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum StateChunk {
+    Root(RootExport),
+    InternedAccounts(u64, Vec<AccountId>),
+    Tokens(Vec<(AccountId, TokenInfo)>),
+    Merchants(Vec<(AccountId, MerchantExport)>),
+    GiftCards(Vec<(CryptoHash, GiftCard)>),
+    Settlements(u64, Vec<Settlement>),
+    Receipts(u64, Vec<Receipt>),
+    History(u64, Vec<ChangeRecord>),
+    PosSessions(u64, Vec<PosSession>),
+    Intents(u64, Vec<Intent>),
+    Disputes(u64, Vec<Dispute>),
+    Campaigns(u64, Vec<Campaign>),
+    TrusteeProposals(u64, Vec<TrusteeProposal>),
+}
+
+/// Up to `EXPORT_CHUNK_SIZE` records of `vector`, starting at `from_index`.
+fn page<T: BorshSerialize + BorshDeserialize>(vector: &Vector<T>, from_index: u64) -> Vec<T> {
+    (from_index..vector.len().min(from_index + EXPORT_CHUNK_SIZE))
+        .map(|index| vector.get(index).unwrap())
+        .collect()
+}
+
+/// Appends `records` to `vector`, which must hold exactly `from_index` records so far.
+fn append<T: BorshSerialize + BorshDeserialize>(
+    vector: &mut Vector<T>,
+    from_index: u64,
+    records: Vec<T>,
+) {
+    require!(from_index == vector.len(), messages::CHUNK_OUT_OF_ORDER);
+    for record in records {
+        vector.push(&record);
+    }
+}
+
+/// Number of chunks `len` records take.
+fn pages(len: u64) -> u64 {
+    len.div_ceil(EXPORT_CHUNK_SIZE)
+}
+
+impl Change {
+    fn internal_export_root(&self) -> RootExport {
+        RootExport {
+            config: self.config.clone(),
+            scheduled_config: self.scheduled_config.clone(),
+            books: self.books.clone(),
+            roles: self.roles.to_vec(),
+            trustees: self.trustees.to_vec(),
+            trustee_threshold: self.trustee_threshold,
+            active_campaigns: self.active_campaigns.to_vec(),
+            pending_proposals: self.pending_proposals.to_vec(),
+            donation_leaderboard: self.donation_leaderboard.clone(),
+        }
+    }
+
+    /// Builds chunk `page_index` of the collection at position `section` in export order.
+    fn internal_export_section(&self, section: usize, page_index: u64) -> StateChunk {
+        let from_index = page_index * EXPORT_CHUNK_SIZE;
+        match section {
+            0 => {
+                StateChunk::InternedAccounts(from_index, page(&self.interned_accounts, from_index))
+            }
+            1 => StateChunk::Tokens(
+                self.tokens
+                    .iter()
+                    .skip(from_index as usize)
+                    .take(EXPORT_CHUNK_SIZE as usize)
+                    .collect(),
+            ),
+            2 => StateChunk::Merchants(
+                self.merchants
+                    .iter()
+                    .skip(from_index as usize)
+                    .take(EXPORT_CHUNK_SIZE as usize)
+                    .map(|(merchant_id, merchant)| {
+                        let maturing = (merchant.maturing_head..merchant.maturing.len())
+                            .map(|index| merchant.maturing.get(index).unwrap())
+                            .collect();
+                        (merchant_id, MerchantExport { merchant, maturing })
+                    })
+                    .collect(),
+            ),
+            3 => StateChunk::GiftCards(
+                self.gift_cards
+                    .iter()
+                    .skip(from_index as usize)
+                    .take(EXPORT_CHUNK_SIZE as usize)
+                    .collect(),
+            ),
+            4 => StateChunk::Settlements(from_index, page(&self.settlements, from_index)),
+            5 => StateChunk::Receipts(from_index, page(&self.receipts, from_index)),
+            6 => StateChunk::History(from_index, page(&self.history, from_index)),
+            7 => StateChunk::PosSessions(from_index, page(&self.pos_sessions, from_index)),
+            8 => StateChunk::Intents(from_index, page(&self.intents, from_index)),
+            9 => StateChunk::Disputes(from_index, page(&self.disputes, from_index)),
+            10 => StateChunk::Campaigns(from_index, page(&self.campaigns, from_index)),
+            _ => {
+                StateChunk::TrusteeProposals(from_index, page(&self.trustee_proposals, from_index))
+            }
+        }
+    }
+
+    /// Record counts of the exported collections, in export order.
+    fn internal_section_lengths(&self) -> [u64; 12] {
+        [
+            self.interned_accounts.len(),
+            self.tokens.len(),
+            self.merchants.len(),
+            self.gift_cards.len(),
+            self.settlements.len(),
+            self.receipts.len(),
+            self.history.len(),
+            self.pos_sessions.len(),
+            self.intents.len(),
+            self.disputes.len(),
+            self.campaigns.len(),
+            self.trustee_proposals.len(),
+        ]
+    }
+
+    fn internal_import_root(&mut self, root: RootExport) {
+        self.config = root.config;
+        self.scheduled_config = root.scheduled_config;
+        self.books = root.books;
+        self.roles.clear();
+        for role in root.roles.iter() {
+            self.roles.insert(role);
+        }
+        self.trustees.clear();
+        for trustee_id in root.trustees.iter() {
+            self.trustees.insert(trustee_id);
+        }
+        self.trustee_threshold = root.trustee_threshold;
+        self.active_campaigns.clear();
+        for campaign_id in root.active_campaigns.iter() {
+            self.active_campaigns.insert(campaign_id);
+        }
+        self.pending_proposals.clear();
+        for proposal_id in root.pending_proposals.iter() {
+            self.pending_proposals.insert(proposal_id);
+        }
+        self.donation_leaderboard = root.donation_leaderboard;
+    }
+
+    fn internal_import_merchant(&mut self, merchant_id: &AccountId, export: MerchantExport) {
+        let mut merchant = export.merchant;
+        // the maturing list shares its storage prefix with the exported one,
+        // so start it over from the head
+        merchant.maturing.clear();
+        for receipt_id in export.maturing.iter() {
+            merchant.maturing.push(receipt_id);
+        }
+        merchant.maturing_head = 0;
+        merchant.maturing_cursor = 0;
+        self.merchants.insert(merchant_id, &merchant);
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns chunk `chunk` of the state, borsh-encoded, or `None` past the last one.
+    /// Owner only, while paused.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet export_full_state '{"chunk": 0}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn export_full_state(&self, chunk: u64) -> Option<Base64VecU8> {
+        self.assert_access("export_full_state");
+        require!(self.paused, messages::EXPORT_WHILE_ACTIVE);
+        let state_chunk = if chunk == 0 {
+            StateChunk::Root(self.internal_export_root())
+        } else {
+            let mut page_index = chunk - 1;
+            let section = self.internal_section_lengths().iter().position(|len| {
+                if page_index < pages(*len) {
+                    return true;
+                }
+                page_index -= pages(*len);
+                false
+            })?;
+            self.internal_export_section(section, page_index)
+        };
+        Some(state_chunk.try_to_vec().unwrap().into())
+    }
+
+    /// Writes a chunk produced by [`export_full_state`](Change::export_full_state)
+    /// into this contract. Owner only, while paused.
+    ///
+    /// ```bash
+    /// near call Change-staging.YOU.testnet import_state '{"chunk": "<base64>"}' --accountId Change-staging.YOU.testnet
+    /// ```
+    pub fn import_state(&mut self, chunk: Base64VecU8) {
+        self.assert_access("import_state");
+        require!(self.paused, messages::IMPORT_WHILE_ACTIVE);
+        let state_chunk = StateChunk::try_from_slice(&Vec::<u8>::from(chunk))
+            .unwrap_or_else(|_| fail!(messages::MALFORMED_STATE_CHUNK));
+        match state_chunk {
+            StateChunk::Root(root) => self.internal_import_root(root),
+            StateChunk::InternedAccounts(from_index, account_ids) => {
+                self.internal_import_interned(from_index, account_ids)
+            }
+            StateChunk::Tokens(tokens) => {
+                for (token_id, token) in tokens {
+                    self.tokens.insert(&token_id, &token);
+                }
+            }
+            StateChunk::Merchants(merchants) => {
+                for (merchant_id, export) in merchants {
+                    self.internal_import_merchant(&merchant_id, export);
+                }
+            }
+            StateChunk::GiftCards(gift_cards) => {
+                for (code_hash, gift_card) in gift_cards {
+                    self.gift_cards.insert(&code_hash, &gift_card);
+                }
+            }
+            StateChunk::Settlements(from_index, records) => {
+                append(&mut self.settlements, from_index, records)
+            }
+            StateChunk::Receipts(from_index, records) => {
+                // through internal_add_receipt, which rebuilds the payer and merchant indexes
+                require!(
+                    from_index == self.receipts.len(),
+                    messages::CHUNK_OUT_OF_ORDER
+                );
+                for record in records {
+                    self.internal_add_receipt(&record);
+                }
+            }
+            StateChunk::History(from_index, records) => {
+                append(&mut self.history, from_index, records)
+            }
+            StateChunk::PosSessions(from_index, records) => {
+                append(&mut self.pos_sessions, from_index, records)
+            }
+            StateChunk::Intents(from_index, records) => {
+                append(&mut self.intents, from_index, records)
+            }
+            StateChunk::Disputes(from_index, records) => {
+                append(&mut self.disputes, from_index, records)
+            }
+            StateChunk::Campaigns(from_index, records) => {
+                append(&mut self.campaigns, from_index, records)
+            }
+            StateChunk::TrusteeProposals(from_index, records) => {
+                append(&mut self.trustee_proposals, from_index, records)
+            }
+        }
+        self.log(LogLevel::Info, messages::STATE_CHUNK_IMPORTED, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn export_all(contract: &Change) -> Vec<Base64VecU8> {
+        (0..)
+            .map_while(|chunk| contract.export_full_state(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn exported_state_imports_into_a_fresh_contract() {
+        testing_env!(context_for("alice.testnet", 3 * 10u128.pow(24)));
+        let mut contract = Change::default();
+        contract.fund_treasury();
+        contract.register_merchant(account("shop.testnet"), account("shop.testnet"), 100);
+        for token in 0..60 {
+            contract.add_token(account(&format!("token{}.testnet", token)), 6, U128(0));
+        }
+        contract.pause();
+        let chunks = export_all(&contract);
+        // root, two chunks of tokens, one merchant
+        assert_eq!(4, chunks.len());
+        let hash = String::from(&contract.get_state_hash());
+
+        // drop the mocked storage so the staging contract starts empty
+        near_sdk::env::take_blockchain_interface();
+        testing_env!(context_for("alice.testnet", 0));
+        let mut staging = Change::default();
+        staging.pause();
+        for chunk in chunks {
+            staging.import_state(chunk);
+        }
+        assert_eq!(hash, String::from(&staging.get_state_hash()));
+        assert_eq!(3 * 10u128.pow(24), staging.get_treasury().0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pause the contract before exporting its state")]
+    fn export_needs_a_paused_contract() {
+        testing_env!(context_for("alice.testnet", 0));
+        Change::default().export_full_state(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunks must be imported in order")]
+    fn indexed_chunks_are_imported_in_order() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.pause();
+        let chunk = StateChunk::Receipts(5, vec![]);
+        contract.import_state(chunk.try_to_vec().unwrap().into());
+    }
+}
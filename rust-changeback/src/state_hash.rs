@@ -0,0 +1,157 @@
+//! A digest of the contract state, for replicas, indexers and test harnesses
+//! to check they agree with the chain without replaying it.
+//!
+//! The hash covers the config, the balance of every [book](crate::accounting)
+//! and the totals of every token and merchant, in account id order. Per-account
+//! records, the Change ledger among them, live in maps that can't be iterated and are left out;
+//! they show up through the token totals they add up to.
+
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+#[derive(BorshSerialize)]
+struct TokenDigest {
+    token_id: AccountId,
+    total_held: Balance,
+    total_owed: Balance,
+}
+
+#[derive(BorshSerialize)]
+struct MerchantDigest {
+    merchant_id: AccountId,
+    revenue: Balance,
+    pending: Balance,
+    frozen: Balance,
+    total_revenue: Balance,
+    purchases: u64,
+    rewards_pool: Balance,
+    fee_credit: Balance,
+}
+
+#[derive(BorshSerialize)]
+struct StateDigest {
+    owner_id: AccountId,
+    config: Config,
+    pending_config: Option<ScheduledConfig>,
+    books: Vec<(Book, Balance)>,
+    withdrawals_paused: bool,
+    tokens: Vec<TokenDigest>,
+    merchants: Vec<MerchantDigest>,
+    trustees: Vec<AccountId>,
+    trustee_threshold: u32,
+    /// Lengths of the append-only logs: payout queue, settlements, receipts,
+    /// POS sessions, intents, disputes, campaigns, trustee proposals.
+    counters: [u64; 8],
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the sha256 hash of the canonical borsh encoding of the state digest.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_state_hash
+    /// ```
+    pub fn get_state_hash(&self) -> Base58CryptoHash {
+        let mut tokens: Vec<TokenDigest> = self
+            .tokens
+            .iter()
+            .map(|(token_id, token)| TokenDigest {
+                token_id,
+                total_held: token.total_held,
+                total_owed: token.total_owed,
+            })
+            .collect();
+        tokens.sort_by(|a, b| a.token_id.cmp(&b.token_id));
+        let mut merchants: Vec<MerchantDigest> = self
+            .merchants
+            .iter()
+            .map(|(merchant_id, merchant)| MerchantDigest {
+                merchant_id,
+                revenue: merchant.revenue,
+                pending: merchant.pending,
+                frozen: merchant.frozen,
+                total_revenue: merchant.total_revenue,
+                purchases: merchant.purchases,
+                rewards_pool: merchant.rewards_pool,
+                fee_credit: merchant.fee_credit,
+            })
+            .collect();
+        merchants.sort_by(|a, b| a.merchant_id.cmp(&b.merchant_id));
+        let mut trustees = self.trustees.to_vec();
+        trustees.sort();
+
+        let digest = StateDigest {
+            owner_id: self.owner_id.clone(),
+            config: self.config().clone(),
+            pending_config: self
+                .scheduled_config
+                .clone()
+                .filter(|scheduled| env::block_timestamp() < scheduled.effective_at),
+            books: self.books.balances(),
+            withdrawals_paused: self.withdrawals_paused,
+            tokens,
+            merchants,
+            trustees,
+            trustee_threshold: self.trustee_threshold,
+            counters: [
+                self.payout_queue.len(),
+                self.settlements.len(),
+                self.receipts.len(),
+                self.pos_sessions.len(),
+                self.intents.len(),
+                self.disputes.len(),
+                self.campaigns.len(),
+                self.trustee_proposals.len(),
+            ],
+        };
+        let mut hash = CryptoHash::default();
+        hash.copy_from_slice(&env::sha256(&digest.try_to_vec().unwrap()));
+        hash.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn contract_with_tokens(token_ids: &[&str]) -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        for token_id in token_ids {
+            contract.add_token(account(token_id), 6, U128(0));
+        }
+        contract
+    }
+
+    #[test]
+    fn hash_does_not_depend_on_insertion_order() {
+        let mut contract = contract_with_tokens(&["dai.testnet", "usdc.testnet"]);
+        let before = String::from(&contract.get_state_hash());
+        // re-adding dai puts it after usdc in the map
+        contract.remove_token(account("dai.testnet"));
+        contract.add_token(account("dai.testnet"), 6, U128(0));
+        assert_eq!("usdc.testnet", contract.list_tokens()[0].token_id);
+        assert_eq!(before, String::from(&contract.get_state_hash()));
+    }
+
+    #[test]
+    fn hash_follows_balance_changes() {
+        let mut contract = contract_with_tokens(&["usdc.testnet"]);
+        let before = String::from(&contract.get_state_hash());
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(10), String::new());
+        assert_ne!(before, String::from(&contract.get_state_hash()));
+    }
+}
@@ -0,0 +1,138 @@
+//! Usage statistics of the counter, kept as it moves.
+//!
+//! Every `add`, `change` and `reset`, batched ones included, counts towards
+//! [`get_stats`](Change::get_stats). Distinct callers are flagged by their
+//! [interned](crate::interning) id, so counting them doesn't take a set of
+//! account ids. Restores and transfers between instances aren't usage and
+//! aren't counted.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{I128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct UsageStats {
+    pub total_adds: u64,
+    pub total_subtracts: u64,
+    pub total_resets: u64,
+    pub unique_callers: u64,
+    /// Delta of the biggest single move, by size.
+    pub largest_delta: i128,
+    /// When the counter was last moved, 0 if never.
+    pub last_modified_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StatsView {
+    pub total_adds: U64,
+    pub total_subtracts: U64,
+    pub total_resets: U64,
+    pub unique_callers: U64,
+    pub largest_delta: I128,
+    /// `largest_delta` formatted for display, see [`display`](crate::display).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub largest_delta_formatted: Option<String>,
+    pub last_modified_at: U64,
+}
+
+impl Change {
+    /// Counts the caller's `operation` moving a counter by `delta`.
+    pub(crate) fn internal_record_stats(&mut self, operation: CounterOperation, delta: i128) {
+        match operation {
+            CounterOperation::Add => self.stats.total_adds += 1,
+            CounterOperation::Change => self.stats.total_subtracts += 1,
+            CounterOperation::Reset => self.stats.total_resets += 1,
+            _ => return,
+        }
+        let caller = self.internal_intern(&env::predecessor_account_id());
+        if self.stats_callers.insert(u64::from(caller)) {
+            self.stats.unique_callers += 1;
+        }
+        if delta.unsigned_abs() > self.stats.largest_delta.unsigned_abs() {
+            self.stats.largest_delta = delta;
+        }
+        self.stats.last_modified_at = env::block_timestamp();
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns how the counter has been used.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_stats
+    /// ```
+    pub fn get_stats(&self) -> StatsView {
+        StatsView {
+            total_adds: U64(self.stats.total_adds),
+            total_subtracts: U64(self.stats.total_subtracts),
+            total_resets: U64(self.stats.total_resets),
+            unique_callers: U64(self.stats.unique_callers),
+            largest_delta: I128(self.stats.largest_delta),
+            largest_delta_formatted: self.internal_format(self.stats.largest_delta),
+            last_modified_at: U64(self.stats.last_modified_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::Operation;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{ValidAccountId, U128};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn counter_moves_are_counted() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        assert_eq!(0, contract.get_stats().last_modified_at.0);
+        contract.add(None, None, Some(U128(250)), None);
+        contract.change(None, None, None);
+        let mut context = context_for("bob.testnet", 0);
+        context.block_timestamp = 42;
+        testing_env!(context);
+        contract.add(None, None, None, None);
+        contract.change(None, Some(U128(1500)), None);
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = 99;
+        testing_env!(context);
+        contract.reset(ValidAccountId::try_from("jane.testnet").unwrap(), None);
+
+        let stats = contract.get_stats();
+        assert_eq!(2, stats.total_adds.0);
+        assert_eq!(2, stats.total_subtracts.0);
+        assert_eq!(1, stats.total_resets.0);
+        assert_eq!(3, stats.unique_callers.0);
+        assert_eq!(-1500, stats.largest_delta.0);
+        assert_eq!(99, stats.last_modified_at.0);
+    }
+
+    #[test]
+    fn batched_moves_are_counted() {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.batch_apply(
+            vec![
+                Operation::Add { amount: U128(250) },
+                Operation::Sub { amount: U128(10) },
+                Operation::Reset,
+            ],
+            None,
+        );
+
+        let stats = contract.get_stats();
+        assert_eq!(1, stats.total_adds.0);
+        assert_eq!(1, stats.total_subtracts.0);
+        assert_eq!(1, stats.total_resets.0);
+        assert_eq!(1, stats.unique_callers.0);
+        assert_eq!(250, stats.largest_delta.0);
+    }
+}
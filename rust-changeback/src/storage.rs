@@ -0,0 +1,79 @@
+//! Storage reserve: NEAR the contract keeps to pay for its own state.
+//!
+//! The reserve is what the bytes the contract uses lock at the current
+//! storage price, plus `storage_reserve_margin` so the next writes don't push
+//! it under. It is recomputed from the storage usage on every check, so it
+//! grows with the state, and no withdrawal, payout or sweep can spend it (see
+//! [`solvency`](crate::solvency)).
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageReserveView {
+    /// Bytes of state the contract account uses.
+    pub storage_usage: U64,
+    /// What those bytes lock, in yoctoNEAR.
+    pub storage_cost: U128,
+    pub margin: U128,
+    /// `storage_cost` plus `margin`.
+    pub reserve: U128,
+}
+
+impl Change {
+    /// Returns the NEAR kept for storage, margin included.
+    pub(crate) fn internal_storage_reserve(&self) -> Balance {
+        Balance::from(env::storage_usage()) * env::storage_byte_cost()
+            + self.config().storage_reserve_margin.0
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Returns the storage reserve and what it is made of.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_storage_reserve
+    /// ```
+    pub fn get_storage_reserve(&self) -> StorageReserveView {
+        let storage_usage = env::storage_usage();
+        let storage_cost = Balance::from(storage_usage) * env::storage_byte_cost();
+        let margin = self.config().storage_reserve_margin.0;
+        StorageReserveView {
+            storage_usage: U64(storage_usage),
+            storage_cost: U128(storage_cost),
+            margin: U128(margin),
+            reserve: U128(storage_cost + margin),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    #[test]
+    fn reserve_covers_storage_and_margin() {
+        let context = context_for("jane.testnet", 0);
+        let storage_usage = context.storage_usage;
+        testing_env!(context);
+        let contract = Change::default();
+        let reserve = contract.get_storage_reserve();
+        assert_eq!(storage_usage, reserve.storage_usage.0);
+        assert_eq!(
+            Balance::from(storage_usage) * 10u128.pow(19),
+            reserve.storage_cost.0
+        );
+        assert_eq!(
+            reserve.storage_cost.0 + Config::default().storage_reserve_margin.0,
+            reserve.reserve.0
+        );
+    }
+}
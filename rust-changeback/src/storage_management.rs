@@ -0,0 +1,279 @@
+//! [NEP-145](https://nomicon.io/Standards/StorageManagement) storage
+//! management, so accounts cover the storage their Change takes.
+//!
+//! Registering an account locks a fixed deposit, enough for its ledger entry,
+//! account record and a few currencies; there is nothing above it to
+//! withdraw, so `storage_withdraw` only ever returns the balance. With
+//! `storage_registration_required` set, `add`, `change` and `batch_apply` refuse accounts
+//! that haven't registered. `storage_unregister` refunds the deposit; an
+//! account still holding Change needs `force`, which resets it in every
+//! currency, and one holding change in NEAR has to withdraw it first.
+//!
+//! The deposits pay for the state the [storage reserve](crate::storage)
+//! keeps NEAR for, so they aren't counted as liabilities again.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// Bytes of state an account is charged for on registration.
+const ACCOUNT_STORAGE_BYTES: u64 = 1_000;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct StorageEvent<'a> {
+    account_id: &'a AccountId,
+    amount: U128,
+}
+
+/// Deposit each registered account locks.
+fn storage_deposit_amount() -> Balance {
+    Balance::from(ACCOUNT_STORAGE_BYTES) * env::storage_byte_cost()
+}
+
+impl Change {
+    /// Panics if registration is required and `account_id` hasn't registered.
+    pub(crate) fn assert_registered(&self, account_id: &AccountId) {
+        if self.config().storage_registration_required
+            && !self.storage_deposits.contains_key(account_id)
+        {
+            fail!(messages::ACCOUNT_NOT_REGISTERED, account_id);
+        }
+    }
+
+    fn internal_storage_balance(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.storage_deposits
+            .get(account_id)
+            .map(|total| StorageBalance {
+                total: U128(total),
+                available: U128(0),
+            })
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Registers `account_id`, the caller by default, with the storage
+    /// deposit, refunding whatever is attached above it, or all of it if the
+    /// account is registered already.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet storage_deposit '{"account_id": "jane.testnet"}' --accountId jane.testnet --deposit 0.01
+    /// ```
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        self.assert_access("storage_deposit");
+        // the deposit is fixed, so every deposit is registration only
+        let _ = registration_only;
+        self.assert_writable();
+        let account_id: AccountId = account_id
+            .map(Into::into)
+            .unwrap_or_else(env::predecessor_account_id);
+        let deposit = env::attached_deposit();
+        let refund = if self.storage_deposits.contains_key(&account_id) {
+            deposit
+        } else {
+            let amount = storage_deposit_amount();
+            require!(deposit >= amount, messages::STORAGE_DEPOSIT_TOO_SMALL);
+            self.storage_deposits.insert(&account_id, &amount);
+            self.internal_post(Posting::receive(Book::StorageDeposits, amount));
+            self.emit_event(
+                "storage_registered",
+                &StorageEvent {
+                    account_id: &account_id,
+                    amount: U128(amount),
+                },
+            );
+            deposit - amount
+        };
+        if refund > 0 {
+            self.internal_transfer(env::predecessor_account_id(), refund);
+        }
+        self.internal_storage_balance(&account_id).unwrap()
+    }
+
+    /// Withdraws from the caller's storage balance above the minimum, which is
+    /// always nothing: the deposit is fixed.
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        self.assert_access("storage_withdraw");
+        let account_id = env::predecessor_account_id();
+        let balance = self
+            .internal_storage_balance(&account_id)
+            .unwrap_or_else(|| fail!(messages::ACCOUNT_NOT_REGISTERED, account_id));
+        require!(
+            amount.map_or(0, |amount| amount.0) <= balance.available.0,
+            messages::STORAGE_WITHDRAW_TOO_LARGE
+        );
+        balance
+    }
+
+    /// Unregisters the caller, refunding the storage deposit. Returns whether
+    /// it was registered. With Change in any currency the caller has to
+    /// `force` it, which resets it.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet storage_unregister '{"force": true}' --accountId jane.testnet --depositYocto 1
+    /// ```
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        self.assert_access("storage_unregister");
+        self.assert_writable();
+        let account_id = env::predecessor_account_id();
+        let amount = match self.storage_deposits.get(&account_id) {
+            Some(amount) => amount,
+            None => return false,
+        };
+        require!(
+            self.internal_get_account(&account_id).balance == 0,
+            messages::CHANGE_BALANCE_LEFT
+        );
+        let currencies: Vec<String> = self
+            .get_all_balances(account_id.clone())
+            .into_keys()
+            .collect();
+        if !currencies.is_empty() {
+            require!(force.unwrap_or(false), messages::UNREGISTER_NEEDS_FORCE);
+        }
+        for currency in currencies {
+            let old_value = self.internal_remove_num(&account_id, &currency);
+            self.internal_record_counter(
+                CounterOperation::Reset,
+                &account_id,
+                &currency,
+                -old_value,
+                old_value,
+                0,
+            );
+        }
+        self.ledger.remove(&account_id);
+        self.storage_deposits.remove(&account_id);
+        self.internal_post(Posting::pay_out(Book::StorageDeposits, amount));
+        self.emit_event(
+            "storage_unregistered",
+            &StorageEvent {
+                account_id: &account_id,
+                amount: U128(amount),
+            },
+        );
+        self.internal_transfer(account_id, amount);
+        true
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let amount = storage_deposit_amount();
+        StorageBalanceBounds {
+            min: U128(amount),
+            max: Some(U128(amount)),
+        }
+    }
+
+    pub fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance(account_id.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn required() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                storage_registration_required: true,
+                ..Config::default()
+            },
+            None,
+        );
+        contract
+    }
+
+    #[test]
+    fn registered_accounts_can_use_the_counter() {
+        let mut contract = required();
+        let amount = contract.storage_balance_bounds().min.0;
+        testing_env!(context_for("bob.testnet", amount + 5));
+        let balance = contract.storage_deposit(Some(account("jane.testnet")), None);
+        assert_eq!(amount, balance.total.0);
+        assert_eq!(0, balance.available.0);
+        assert_eq!(amount, contract.book(Book::StorageDeposits));
+        assert!(contract
+            .storage_balance_of(account("bob.testnet"))
+            .is_none());
+
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, None, None);
+        testing_env!(context_for("jane.testnet", 1));
+        contract.storage_withdraw(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account jane.testnet is not registered")]
+    fn unregistered_accounts_are_refused() {
+        let mut contract = required();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.change(None, None, None);
+    }
+
+    #[test]
+    fn unregistering_refunds_the_deposit() {
+        let mut contract = required();
+        let amount = contract.storage_balance_bounds().min.0;
+        testing_env!(context_for("jane.testnet", amount));
+        contract.storage_deposit(None, None);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, None, None);
+
+        testing_env!(context_for("jane.testnet", 1));
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract
+            .storage_balance_of(account("jane.testnet"))
+            .is_none());
+        assert_eq!(0, contract.get_num_for("jane.testnet".to_string(), None).0);
+        assert_eq!(0, contract.book(Book::StorageDeposits));
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister an account holding Change without force")]
+    fn unregistering_with_change_takes_force() {
+        let mut contract = required();
+        let amount = contract.storage_balance_bounds().min.0;
+        testing_env!(context_for("jane.testnet", amount));
+        contract.storage_deposit(None, None);
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, None, None);
+        testing_env!(context_for("jane.testnet", 1));
+        contract.storage_unregister(None);
+    }
+}
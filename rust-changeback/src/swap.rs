@@ -0,0 +1,489 @@
+//! Consolidating token holdings into the base token through an AMM.
+//!
+//! The AMM is expected to follow ref.finance: tokens are deposited with
+//! `ft_transfer_call` and then swapped with `swap(actions)`, which returns the
+//! amount received. Only the contract's own surplus of a token (what it holds
+//! beyond what it owes users) can be swapped, so user balances stay covered.
+//!
+//! Both the deposit and the proceeds sit in the contract's balance on the
+//! AMM, where they can't be sent to anyone, so each swap ends with a
+//! `withdraw` of the proceeds, or of the input if the swap failed. Tokens
+//! count as held again only once that withdraw lands; if it fails they stay
+//! on the AMM and the owner retries with
+//! [`withdraw_from_amm`](Change::withdraw_from_amm).
+//!
+//! The AMM is asked for at least `min_amount_out` and should refuse the swap
+//! otherwise. If it goes through for less anyway, the proceeds are swapped
+//! straight back and what returns of the input is withdrawn instead, so the
+//! base token's books don't count a swap the contract refused.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Gas, Promise};
+
+use crate::*;
+
+/// `ft_transfer_call` requires exactly one yoctoNEAR attached.
+const ONE_YOCTO: Balance = 1;
+
+/// A single hop of a ref.finance swap.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapAction {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub amount_in: Option<U128>,
+    pub token_out: AccountId,
+    pub min_amount_out: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SwapEvent<'a> {
+    token_id: &'a AccountId,
+    amount_in: U128,
+    amount_out: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AmmWithdrawEvent<'a> {
+    token_id: &'a AccountId,
+    amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SlippageEvent<'a> {
+    token_id: &'a AccountId,
+    amount_in: U128,
+    amount_out: U128,
+    min_amount_out: U128,
+}
+
+impl Change {
+    fn internal_amm(&self) -> AccountId {
+        self.config()
+            .amm_contract
+            .clone()
+            .unwrap_or_else(|| fail!(messages::NO_AMM_CONFIGURED))
+    }
+
+    /// Gas for withdrawing from the AMM and checking the outcome.
+    fn internal_amm_withdraw_gas(&self) -> Gas {
+        self.config().gas.amm_withdraw.0 + self.config().gas.callback.0
+    }
+
+    /// Gas for swapping proceeds back, checking it and withdrawing the result.
+    fn internal_amm_revert_gas(&self) -> Gas {
+        self.config().gas.amm_swap.0
+            + self.config().gas.callback.0
+            + self.internal_amm_withdraw_gas()
+    }
+
+    /// Withdraws `amount` of `token_id` from the contract's balance on the AMM
+    /// back to the contract, counting it as held once it lands.
+    fn internal_withdraw_from_amm(&self, token_id: AccountId, amount: U128) -> Promise {
+        ext_amm::withdraw(
+            token_id.clone(),
+            amount,
+            None,
+            &self.internal_amm(),
+            ONE_YOCTO,
+            self.config().gas.amm_withdraw.0,
+        )
+        .then(ext_self::on_amm_withdrawn(
+            token_id,
+            amount,
+            &env::current_account_id(),
+            0,
+            self.config().gas.callback.0,
+        ))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sets the AMM pool used to swap `token_id` into the base token. Owner only.
+    pub fn set_swap_pool(&mut self, token_id: ValidAccountId, pool_id: u64) {
+        self.assert_access("set_swap_pool");
+        let token_id: AccountId = token_id.into();
+        let mut token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        token.swap_pool_id = Some(pool_id);
+        self.tokens.insert(&token_id, &token);
+    }
+
+    /// Swaps `amount` of the contract's surplus of `token_id` into the base token,
+    /// failing if less than `min_amount_out` would come back. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet swap_to_base '{"token_id": "dai.testnet", "amount": "1000", "min_amount_out": "990"}' --accountId Change.YOU.testnet --gas 200000000000000
+    /// ```
+    pub fn swap_to_base(
+        &mut self,
+        token_id: ValidAccountId,
+        amount: U128,
+        min_amount_out: U128,
+    ) -> Promise {
+        self.assert_access("swap_to_base");
+        let token_id: AccountId = token_id.into();
+        let amm = self.internal_amm();
+        let base_token = self
+            .config
+            .base_token
+            .clone()
+            .unwrap_or_else(|| fail!(messages::NO_BASE_TOKEN_CONFIGURED));
+        require!(token_id != base_token, messages::TOKEN_IS_BASE_TOKEN);
+        require!(
+            self.tokens.get(&base_token).is_some(),
+            messages::BASE_TOKEN_NOT_WHITELISTED
+        );
+        let mut token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        let pool_id = token
+            .swap_pool_id
+            .unwrap_or_else(|| fail!(messages::NO_SWAP_POOL));
+        let surplus = token
+            .total_held
+            .checked_sub(token.total_owed)
+            .unwrap_or_else(|| {
+                fail!(
+                    messages::HOLDINGS_BELOW_OWED,
+                    token.total_held,
+                    token.total_owed
+                )
+            });
+        require!(surplus >= amount.0, messages::SURPLUS_ONLY);
+        token.total_held -= amount.0;
+        self.tokens.insert(&token_id, &token);
+
+        let actions = vec![SwapAction {
+            pool_id,
+            token_in: token_id.clone(),
+            amount_in: Some(amount),
+            token_out: base_token,
+            min_amount_out,
+        }];
+        ext_ft::ft_transfer_call(
+            amm.clone(),
+            amount,
+            None,
+            String::new(),
+            &token_id,
+            ONE_YOCTO,
+            self.config().gas.ft_transfer_call.0,
+        )
+        .then(ext_amm::swap(
+            actions,
+            &amm,
+            0,
+            self.config().gas.amm_swap.0,
+        ))
+        .then(ext_self::on_swap_to_base(
+            token_id,
+            amount,
+            min_amount_out,
+            &env::current_account_id(),
+            0,
+            self.config().gas.callback.0 + self.internal_amm_revert_gas(),
+        ))
+    }
+
+    /// Withdraws the swap proceeds from the AMM, or the input if the swap failed
+    /// or gave less than `min_amount_out`.
+    pub fn on_swap_to_base(
+        &mut self,
+        token_id: AccountId,
+        amount_in: U128,
+        min_amount_out: U128,
+    ) -> Promise {
+        self.assert_access("on_swap_to_base");
+        let base_token = self
+            .config
+            .base_token
+            .clone()
+            .unwrap_or_else(|| fail!(messages::NO_BASE_TOKEN_CONFIGURED));
+        match promise_result::<U128>() {
+            Ok(amount_out) if amount_out.0 >= min_amount_out.0 => {
+                self.emit_event(
+                    "swap_to_base",
+                    &SwapEvent {
+                        token_id: &token_id,
+                        amount_in,
+                        amount_out,
+                    },
+                );
+                self.internal_withdraw_from_amm(base_token, amount_out)
+            }
+            // the AMM should have refused such a swap; as it went through,
+            // it is undone by swapping back on the same pool
+            Ok(amount_out) => {
+                self.emit_event(
+                    "slippage_rejected",
+                    &SlippageEvent {
+                        token_id: &token_id,
+                        amount_in,
+                        amount_out,
+                        min_amount_out,
+                    },
+                );
+                let pool_id = self
+                    .tokens
+                    .get(&token_id)
+                    .and_then(|token| token.swap_pool_id)
+                    .unwrap_or_else(|| fail!(messages::NO_SWAP_POOL));
+                let actions = vec![SwapAction {
+                    pool_id,
+                    token_in: base_token,
+                    amount_in: Some(amount_out),
+                    token_out: token_id.clone(),
+                    min_amount_out: U128(0),
+                }];
+                ext_amm::swap(
+                    actions,
+                    &self.internal_amm(),
+                    0,
+                    self.config().gas.amm_swap.0,
+                )
+                .then(ext_self::on_swap_reverted(
+                    token_id,
+                    amount_out,
+                    &env::current_account_id(),
+                    0,
+                    self.config().gas.callback.0 + self.internal_amm_withdraw_gas(),
+                ))
+            }
+            Err(_) => {
+                self.emit_event(
+                    "swap_to_base_failed",
+                    &SwapEvent {
+                        token_id: &token_id,
+                        amount_in,
+                        amount_out: U128(0),
+                    },
+                );
+                self.internal_withdraw_from_amm(token_id, amount_in)
+            }
+        }
+    }
+
+    /// Withdraws what swapping the `amount_out` proceeds of a refused swap back
+    /// into `token_id` returned, or the proceeds if that swap failed too.
+    pub fn on_swap_reverted(&mut self, token_id: AccountId, amount_out: U128) -> Promise {
+        self.assert_access("on_swap_reverted");
+        match promise_result::<U128>() {
+            Ok(returned) => {
+                self.emit_event(
+                    "swap_reverted",
+                    &AmmWithdrawEvent {
+                        token_id: &token_id,
+                        amount: returned,
+                    },
+                );
+                self.internal_withdraw_from_amm(token_id, returned)
+            }
+            Err(_) => {
+                let base_token = self
+                    .config
+                    .base_token
+                    .clone()
+                    .unwrap_or_else(|| fail!(messages::NO_BASE_TOKEN_CONFIGURED));
+                self.emit_event(
+                    "swap_revert_failed",
+                    &AmmWithdrawEvent {
+                        token_id: &base_token,
+                        amount: amount_out,
+                    },
+                );
+                self.internal_withdraw_from_amm(base_token, amount_out)
+            }
+        }
+    }
+
+    /// Withdraws `amount` of `token_id` left on the contract's balance on the
+    /// AMM, such as after a failed withdraw. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet withdraw_from_amm '{"token_id": "wrap.testnet", "amount": "95"}' --accountId Change.YOU.testnet --gas 50000000000000
+    /// ```
+    pub fn withdraw_from_amm(&mut self, token_id: ValidAccountId, amount: U128) -> Promise {
+        self.assert_access("withdraw_from_amm");
+        let token_id: AccountId = token_id.into();
+        require!(
+            self.tokens.get(&token_id).is_some(),
+            messages::TOKEN_NOT_WHITELISTED
+        );
+        self.internal_withdraw_from_amm(token_id, amount)
+    }
+
+    /// Counts `amount` of `token_id` as held once its withdraw from the AMM
+    /// landed. Returns whether it did.
+    pub fn on_amm_withdrawn(&mut self, token_id: AccountId, amount: U128) -> bool {
+        self.assert_access("on_amm_withdrawn");
+        if promise_succeeded().is_err() {
+            self.emit_event(
+                "amm_withdraw_failed",
+                &AmmWithdrawEvent {
+                    token_id: &token_id,
+                    amount,
+                },
+            );
+            return false;
+        }
+        if let Some(mut token) = self.tokens.get(&token_id) {
+            token.total_held += amount.0;
+            self.tokens.insert(&token_id, &token);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::test_utils::{get_created_receipts, get_logs};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::PromiseResult;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn held(contract: &Change, token_id: &str) -> u128 {
+        contract
+            .tokens
+            .get(&token_id.to_string())
+            .unwrap()
+            .total_held
+    }
+
+    /// A contract with a `dai.testnet` surplus of 100 and `wrap.testnet` as base token.
+    fn swap_contract() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_config(
+            Config {
+                amm_contract: Some("amm.testnet".to_string()),
+                base_token: Some("wrap.testnet".to_string()),
+                ..Config::default()
+            },
+            None,
+        );
+        contract.add_token(account("wrap.testnet"), 24, U128(0));
+        contract.add_token(account("dai.testnet"), 18, U128(0));
+        contract.set_swap_pool(account("dai.testnet"), 7);
+        testing_env!(context_for("dai.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(50), String::new());
+        let mut dai = contract.tokens.get(&"dai.testnet".to_string()).unwrap();
+        dai.total_held += 100;
+        contract.tokens.insert(&"dai.testnet".to_string(), &dai);
+        testing_env!(context_for("alice.testnet", 0));
+        contract
+    }
+
+    fn callback_env(result: PromiseResult) {
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![result]
+        );
+    }
+
+    #[test]
+    fn proceeds_are_held_once_withdrawn() {
+        let mut contract = swap_contract();
+        contract.swap_to_base(account("dai.testnet"), U128(100), U128(90));
+        assert_eq!(50, held(&contract, "dai.testnet"));
+        callback_env(PromiseResult::Successful(b"\"95\"".to_vec()));
+        contract.on_swap_to_base("dai.testnet".to_string(), U128(100), U128(90));
+        assert_eq!(0, held(&contract, "wrap.testnet"));
+        callback_env(PromiseResult::Successful(vec![]));
+        assert!(contract.on_amm_withdrawn("wrap.testnet".to_string(), U128(95)));
+        assert_eq!(95, held(&contract, "wrap.testnet"));
+    }
+
+    #[test]
+    fn failed_swap_withdraws_the_input() {
+        let mut contract = swap_contract();
+        contract.swap_to_base(account("dai.testnet"), U128(100), U128(90));
+        callback_env(PromiseResult::Failed);
+        contract.on_swap_to_base("dai.testnet".to_string(), U128(100), U128(90));
+        assert_eq!(50, held(&contract, "dai.testnet"));
+        callback_env(PromiseResult::Successful(vec![]));
+        contract.on_amm_withdrawn("dai.testnet".to_string(), U128(100));
+        assert_eq!(150, held(&contract, "dai.testnet"));
+        assert_eq!(0, held(&contract, "wrap.testnet"));
+    }
+
+    #[test]
+    fn tokens_left_on_the_amm_are_not_held() {
+        let mut contract = swap_contract();
+        contract.swap_to_base(account("dai.testnet"), U128(100), U128(90));
+        callback_env(PromiseResult::Failed);
+        assert!(!contract.on_amm_withdrawn("dai.testnet".to_string(), U128(100)));
+        assert_eq!(50, held(&contract, "dai.testnet"));
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.contains("\"event\":\"amm_withdraw_failed\"")));
+        testing_env!(context_for("alice.testnet", 0));
+        contract.withdraw_from_amm(account("dai.testnet"), U128(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract's own surplus can be swapped")]
+    fn user_balances_cannot_be_swapped() {
+        let mut contract = swap_contract();
+        contract.swap_to_base(account("dai.testnet"), U128(101), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Holds 40 of the token but owes 50, there is no surplus")]
+    fn nothing_is_swapped_while_holdings_are_short() {
+        let mut contract = swap_contract();
+        let mut dai = contract.tokens.get(&"dai.testnet".to_string()).unwrap();
+        dai.total_held = 40;
+        contract.tokens.insert(&"dai.testnet".to_string(), &dai);
+        contract.swap_to_base(account("dai.testnet"), U128(1), U128(0));
+    }
+
+    #[test]
+    fn worse_rate_than_asked_is_swapped_back() {
+        let mut contract = swap_contract();
+        contract.swap_to_base(account("dai.testnet"), U128(100), U128(90));
+        callback_env(PromiseResult::Successful(b"\"89\"".to_vec()));
+        contract.on_swap_to_base("dai.testnet".to_string(), U128(100), U128(90));
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.contains("\"event\":\"slippage_rejected\"")));
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("on_swap_reverted"));
+
+        callback_env(PromiseResult::Successful(b"\"98\"".to_vec()));
+        contract.on_swap_reverted("dai.testnet".to_string(), U128(89));
+        callback_env(PromiseResult::Successful(vec![]));
+        contract.on_amm_withdrawn("dai.testnet".to_string(), U128(98));
+        assert_eq!(148, held(&contract, "dai.testnet"));
+        assert_eq!(0, held(&contract, "wrap.testnet"));
+    }
+
+    #[test]
+    fn proceeds_are_withdrawn_if_swapping_back_fails() {
+        let mut contract = swap_contract();
+        contract.swap_to_base(account("dai.testnet"), U128(100), U128(90));
+        callback_env(PromiseResult::Failed);
+        contract.on_swap_reverted("dai.testnet".to_string(), U128(89));
+        callback_env(PromiseResult::Successful(vec![]));
+        contract.on_amm_withdrawn("wrap.testnet".to_string(), U128(89));
+        assert_eq!(89, held(&contract, "wrap.testnet"));
+    }
+}
@@ -0,0 +1,41 @@
+//! Helpers shared by the unit tests of every module.
+
+use near_sdk::VMContext;
+
+// part of writing unit tests is setting up a mock context
+// in this example, this is only needed for env::log in the contract
+// this is also a useful list to peek at when wondering what's available in env::*
+pub(crate) fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
+    VMContext {
+        current_account_id: "alice.testnet".to_string(),
+        signer_account_id: "robert.testnet".to_string(),
+        signer_account_pk: vec![0, 1, 2],
+        predecessor_account_id: "jane.testnet".to_string(),
+        input,
+        block_index: 0,
+        block_timestamp: 0,
+        account_balance: 0,
+        account_locked_balance: 0,
+        storage_usage: 0,
+        attached_deposit: 0,
+        prepaid_gas: 10u64.pow(18),
+        random_seed: vec![0, 1, 2],
+        is_view,
+        output_data_receivers: vec![],
+        epoch_height: 19,
+    }
+}
+
+/// A mutating call made by `predecessor` with `deposit` attached.
+///
+/// The contract account is given a generous balance so promises that
+/// transfer NEAR out can be created, and some storage usage so the mocked
+/// runtime doesn't underflow when a test re-sets the context over existing state.
+pub(crate) fn context_for(predecessor: &str, deposit: u128) -> VMContext {
+    let mut context = get_context(vec![], false);
+    context.predecessor_account_id = predecessor.to_string();
+    context.attached_deposit = deposit;
+    context.account_balance = 10u128.pow(27);
+    context.storage_usage = 1024 * 300;
+    context
+}
@@ -0,0 +1,108 @@
+//! Rate limiting of `add`, `change` and `batch_apply`, so an account can't
+//! spam them.
+//!
+//! With a `rate_limit` of `max_calls` per `window`, each account gets a fixed
+//! window starting with its first call; calls past `max_calls` within it are
+//! refused until the window ends and the next call starts a new one. Relayed
+//! calls count against the account relayed for, and a batch counts as one
+//! call. `max_calls` 0 disables it.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::Timestamp;
+
+use crate::*;
+
+/// Calls an account made in its current window.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CallWindow {
+    pub started_at: Timestamp,
+    pub calls: u32,
+}
+
+impl Change {
+    /// Counts a call by `account_id`, panicking if it goes over the rate limit.
+    pub(crate) fn internal_throttle(&mut self, account_id: &AccountId) {
+        let rate_limit = self.config().rate_limit.clone();
+        if rate_limit.max_calls == 0 {
+            return;
+        }
+        let now = env::block_timestamp();
+        let mut window = self
+            .call_windows
+            .get(account_id)
+            .filter(|window| now < window.started_at + rate_limit.window.0)
+            .unwrap_or(CallWindow {
+                started_at: now,
+                calls: 0,
+            });
+        require!(
+            window.calls < rate_limit.max_calls,
+            messages::RATE_LIMITED,
+            window.started_at + rate_limit.window.0 - now
+        );
+        window.calls += 1;
+        self.call_windows.insert(account_id, &window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::Operation;
+    use crate::config::MINUTE;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::{U128, U64};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    fn throttled() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.set_rate_limit(2, U64(MINUTE));
+        contract
+    }
+
+    fn at(predecessor: &str, timestamp: u64) {
+        let mut context = context_for(predecessor, 0);
+        context.block_timestamp = timestamp;
+        testing_env!(context);
+    }
+
+    #[test]
+    fn calls_are_allowed_again_in_the_next_window() {
+        let mut contract = throttled();
+        at("jane.testnet", 0);
+        contract.add(None, None, None, None);
+        contract.change(None, None, None);
+        at("bob.testnet", 0);
+        contract.add(None, None, None, None);
+        at("jane.testnet", MINUTE);
+        contract.add(None, None, None, None);
+        assert_eq!(
+            1990,
+            contract.get_num_for("jane.testnet".to_string(), None).0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Rate limit exceeded, try again in 30000000000 ns")]
+    fn calls_over_the_limit_are_refused() {
+        let mut contract = throttled();
+        at("jane.testnet", 0);
+        contract.add(None, None, None, None);
+        at("jane.testnet", MINUTE / 2);
+        contract.change(None, None, None);
+        contract.change(None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rate limit exceeded")]
+    fn batches_count_against_the_limit() {
+        let mut contract = throttled();
+        at("jane.testnet", 0);
+        contract.add(None, None, None, None);
+        let ops = vec![Operation::Add { amount: U128(1) }; 50];
+        contract.batch_apply(ops.clone(), None);
+        contract.batch_apply(ops, None);
+    }
+}
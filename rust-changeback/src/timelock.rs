@@ -0,0 +1,218 @@
+//! Administrative operations that only run once a timelock has passed.
+//!
+//! The owner [`schedule`](Change::schedule)s a reset or a configuration
+//! change to run after a given time, which gives everyone watching the
+//! `operation_scheduled` event the time in between to react. Once the time
+//! has come anyone can [`execute`](Change::execute) it; until then the owner
+//! can [`cancel`](Change::cancel) it. Executed and cancelled operations are
+//! dropped from the queue.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, Timestamp};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TimelockOperation {
+    /// Resets the Change of `account_id` in `currency`, see [`reset`](Change::reset).
+    Reset {
+        account_id: ValidAccountId,
+        currency: Option<String>,
+    },
+    /// Replaces the whole configuration, see [`set_config`](Change::set_config).
+    SetConfig { config: Box<Config> },
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ScheduledOperation {
+    pub operation: TimelockOperation,
+    pub scheduled_at: Timestamp,
+    pub execute_after: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ScheduledOperationView {
+    pub op_id: U64,
+    pub operation: TimelockOperation,
+    pub scheduled_at: U64,
+    pub execute_after: U64,
+}
+
+impl ScheduledOperationView {
+    fn new(op_id: u64, scheduled: ScheduledOperation) -> Self {
+        Self {
+            op_id: U64(op_id),
+            operation: scheduled.operation,
+            scheduled_at: U64(scheduled.scheduled_at),
+            execute_after: U64(scheduled.execute_after),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct OperationEvent {
+    op_id: U64,
+}
+
+#[near_bindgen]
+impl Change {
+    /// Queues `op` to run once `execute_after` has passed. Owner only.
+    /// Returns the operation id.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet schedule '{"op": {"Reset": {"account_id": "jane.testnet"}}, "execute_after": "1700000000000000000"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn schedule(&mut self, op: TimelockOperation, execute_after: U64) -> U64 {
+        self.assert_access("schedule");
+        require!(
+            execute_after.0 > env::block_timestamp(),
+            messages::EXECUTION_TIME_IN_PAST
+        );
+        if let TimelockOperation::Reset { .. } = op {
+            self.assert_reset_unguarded();
+        }
+        let op_id = self.next_timelock_id;
+        self.next_timelock_id += 1;
+        let scheduled = ScheduledOperation {
+            operation: op,
+            scheduled_at: env::block_timestamp(),
+            execute_after: execute_after.0,
+        };
+        self.timelock.insert(&op_id, &scheduled);
+        self.emit_event(
+            "operation_scheduled",
+            &ScheduledOperationView::new(op_id, scheduled),
+        );
+        U64(op_id)
+    }
+
+    /// Runs the operation `op_id` once its time has come. Anyone can call this.
+    pub fn execute(&mut self, op_id: U64) {
+        self.assert_access("execute");
+        let scheduled = self
+            .timelock
+            .remove(&op_id.0)
+            .unwrap_or_else(|| fail!(messages::NO_SUCH_OPERATION));
+        require!(
+            env::block_timestamp() >= scheduled.execute_after,
+            messages::TIMELOCKED
+        );
+        match scheduled.operation {
+            TimelockOperation::Reset {
+                account_id,
+                currency,
+            } => {
+                self.assert_reset_unguarded();
+                self.internal_reset(account_id.into(), currency);
+            }
+            TimelockOperation::SetConfig { config } => self.internal_set_config(*config, None),
+        }
+        self.emit_event("operation_executed", &OperationEvent { op_id });
+    }
+
+    /// Drops the operation `op_id` before it runs. Owner only.
+    pub fn cancel(&mut self, op_id: U64) {
+        self.assert_access("cancel");
+        self.timelock
+            .remove(&op_id.0)
+            .unwrap_or_else(|| fail!(messages::NO_SUCH_OPERATION));
+        self.emit_event("operation_cancelled", &OperationEvent { op_id });
+    }
+
+    pub fn get_scheduled_operation(&self, op_id: U64) -> Option<ScheduledOperationView> {
+        self.timelock
+            .get(&op_id.0)
+            .map(|scheduled| ScheduledOperationView::new(op_id.0, scheduled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DAY;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    fn at(predecessor: &str, timestamp: u64) {
+        let mut context = context_for(predecessor, 0);
+        context.block_timestamp = timestamp;
+        testing_env!(context);
+    }
+
+    fn reset_jane() -> TimelockOperation {
+        TimelockOperation::Reset {
+            account_id: ValidAccountId::try_from("jane.testnet").unwrap(),
+            currency: None,
+        }
+    }
+
+    fn scheduled_reset() -> (Change, U64) {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::default();
+        contract.add(None, None, None, None);
+        testing_env!(context_for("alice.testnet", 0));
+        let op_id = contract.schedule(reset_jane(), U64(DAY));
+        (contract, op_id)
+    }
+
+    #[test]
+    fn operations_run_after_the_timelock() {
+        let (mut contract, op_id) = scheduled_reset();
+        assert_eq!(
+            reset_jane(),
+            contract.get_scheduled_operation(op_id).unwrap().operation
+        );
+        at("bob.testnet", DAY);
+        contract.execute(op_id);
+        assert_eq!(0, contract.get_num_for("jane.testnet".to_string(), None).0);
+        assert!(contract.get_scheduled_operation(op_id).is_none());
+
+        at("alice.testnet", DAY);
+        let config = Config {
+            rebate_threshold: 3,
+            ..Config::default()
+        };
+        let op_id = contract.schedule(
+            TimelockOperation::SetConfig {
+                config: Box::new(config.clone()),
+            },
+            U64(2 * DAY),
+        );
+        assert_eq!(1, op_id.0);
+        at("bob.testnet", 2 * DAY);
+        contract.execute(op_id);
+        assert_eq!(config, contract.get_config());
+    }
+
+    #[test]
+    #[should_panic(expected = "Timelock hasn't passed yet")]
+    fn operations_wait_for_their_time() {
+        let (mut contract, op_id) = scheduled_reset();
+        at("bob.testnet", DAY - 1);
+        contract.execute(op_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "No such operation")]
+    fn cancelled_operations_never_run() {
+        let (mut contract, op_id) = scheduled_reset();
+        contract.cancel(op_id);
+        at("bob.testnet", DAY);
+        contract.execute(op_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_schedules() {
+        testing_env!(context_for("jane.testnet", 0));
+        let mut contract = Change::new(ValidAccountId::try_from("alice.testnet").unwrap());
+        contract.schedule(reset_jane(), U64(DAY));
+    }
+}
@@ -0,0 +1,722 @@
+//! NEP-141 fungible token deposits.
+//!
+//! Any token on the owner-managed whitelist can be sent here with
+//! `ft_transfer_call`; the amount is credited to the sender's balance of that
+//! token. Tokens that are not listed are refunded in full per NEP-141.
+//! Balances leave with [`withdraw_token`](Change::withdraw_token), which
+//! puts them back if the token transfer fails.
+//!
+//! A token the owner gives a [currency](crate::currency) with
+//! [`set_token_currency`](Change::set_token_currency) also backs Change:
+//! a deposit is added to the sender's Change in that currency, like a NEAR
+//! deposit to [`add`](Change::add), and a withdrawal takes it off again. What
+//! the Change can't take under the `max_value` limit is unused and refunded.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Balance, Promise, PromiseOrValue};
+
+use crate::*;
+
+/// Bookkeeping for one whitelisted token.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TokenInfo {
+    /// Decimals of the token, for display.
+    pub decimals: u8,
+    /// Smallest transfer accepted; smaller ones are refunded.
+    pub min_deposit: Balance,
+    /// Amount of this token in the contract's own balance, what it can send
+    /// out; tokens left on the AMM after a [swap](crate::swap) don't count.
+    pub total_held: Balance,
+    /// Sum of all account balances of this token, i.e. what the contract owes.
+    pub total_owed: Balance,
+    /// AMM pool swapping this token into the base token.
+    pub swap_pool_id: Option<u64>,
+    /// Unit deposits are rounded up to for savings; 0 disables round-ups.
+    pub round_up_unit: Balance,
+    /// Currency of the Change deposits of this token are added to, if any.
+    pub currency: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenView {
+    pub token_id: AccountId,
+    pub decimals: u8,
+    pub min_deposit: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+}
+
+/// Holdings of one token against what is owed to users.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenTotalsView {
+    pub token_id: AccountId,
+    pub held: U128,
+    pub owed: U128,
+    /// Whether `held` covers `owed`.
+    pub solvent: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TokenListedEvent<'a> {
+    token_id: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decimals: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_deposit: Option<U128>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TokenDepositEvent<'a> {
+    account_id: &'a AccountId,
+    token_id: &'a AccountId,
+    amount: U128,
+}
+
+impl Change {
+    /// Returns the balance of `token_id` held for `account_id`.
+    pub(crate) fn internal_token_balance(
+        &self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+    ) -> Balance {
+        self.token_balances
+            .get(&(account_id.clone(), token_id.clone()))
+            .unwrap_or(0)
+    }
+
+    /// Credits `amount` of the whitelisted `token_id` to `account_id`.
+    pub(crate) fn internal_deposit_token(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut token = self
+            .tokens
+            .get(token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        let balance = self.internal_token_balance(account_id, token_id);
+        self.token_balances
+            .insert(&(account_id.clone(), token_id.clone()), &(balance + amount));
+        token.total_held += amount;
+        token.total_owed += amount;
+        self.tokens.insert(token_id, &token);
+    }
+
+    /// Returns how much of `amount` the Change of `account_id` in `currency`
+    /// can take before reaching the `max_value` limit.
+    fn internal_change_room(
+        &self,
+        account_id: &AccountId,
+        currency: &str,
+        amount: Balance,
+    ) -> Balance {
+        let current = self.internal_get_num(account_id, currency);
+        let room = self
+            .config()
+            .limits
+            .max_value
+            .0
+            .checked_sub(current)
+            .unwrap_or(i128::MAX);
+        amount.min(u128::try_from(room).unwrap_or(0))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Accepts `token_id` for deposits of at least `min_deposit`. Owner only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet add_token '{"token_id": "usdc.testnet", "decimals": 6, "min_deposit": "10000"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn add_token(&mut self, token_id: ValidAccountId, decimals: u8, min_deposit: U128) {
+        self.assert_access("add_token");
+        let token_id: AccountId = token_id.into();
+        require!(
+            self.tokens.get(&token_id).is_none(),
+            messages::TOKEN_ALREADY_WHITELISTED
+        );
+        self.tokens.insert(
+            &token_id,
+            &TokenInfo {
+                decimals,
+                min_deposit: min_deposit.0,
+                total_held: 0,
+                total_owed: 0,
+                swap_pool_id: None,
+                round_up_unit: 0,
+                currency: None,
+            },
+        );
+        self.emit_event(
+            "token_added",
+            &TokenListedEvent {
+                token_id: &token_id,
+                decimals: Some(decimals),
+                min_deposit: Some(min_deposit),
+            },
+        );
+    }
+
+    /// Stops accepting `token_id`. Owner only, and only once nothing of it is owed to users.
+    pub fn remove_token(&mut self, token_id: ValidAccountId) {
+        self.assert_access("remove_token");
+        let token_id: AccountId = token_id.into();
+        let token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        require!(token.total_owed == 0, messages::TOKEN_BALANCES_OWED);
+        self.tokens.remove(&token_id);
+        self.emit_event(
+            "token_removed",
+            &TokenListedEvent {
+                token_id: &token_id,
+                decimals: None,
+                min_deposit: None,
+            },
+        );
+    }
+
+    /// Returns every whitelisted token.
+    pub fn list_tokens(&self) -> Vec<TokenView> {
+        self.tokens
+            .iter()
+            .map(|(token_id, token)| TokenView {
+                token_id,
+                decimals: token.decimals,
+                min_deposit: U128(token.min_deposit),
+                currency: token.currency,
+            })
+            .collect()
+    }
+
+    /// Adds deposits of `token_id` to the depositor's Change in `currency` from
+    /// now on, or stops if `None`. Owner only. NEAR Change is only backed by NEAR.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_token_currency '{"token_id": "usdc.testnet", "currency": "USDC"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn set_token_currency(&mut self, token_id: ValidAccountId, currency: Option<String>) {
+        self.assert_access("set_token_currency");
+        let token_id: AccountId = token_id.into();
+        let mut token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        if let Some(currency) = &currency {
+            currency_symbol(Some(currency.clone())).unwrap_or_else(|error| fail!(error));
+            require!(
+                currency != DEFAULT_CURRENCY,
+                messages::TOKEN_CURRENCY_IS_NEAR
+            );
+        }
+        token.currency = currency;
+        self.tokens.insert(&token_id, &token);
+    }
+
+    /// Sends `amount` of the caller's balance of `token_id` back to them.
+    /// Requires exactly 1 yoctoNEAR attached, as token withdrawals do.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet withdraw_token '{"token_id": "usdc.testnet", "amount": "1000000"}' --accountId donation.YOU.testnet --depositYocto 1
+    /// ```
+    #[payable]
+    pub fn withdraw_token(&mut self, token_id: ValidAccountId, amount: U128) -> Promise {
+        self.assert_access("withdraw_token");
+        let account_id = env::predecessor_account_id();
+        self.assert_unlocked(&account_id);
+        let token_id: AccountId = token_id.into();
+        let mut token = self
+            .tokens
+            .get(&token_id)
+            .unwrap_or_else(|| fail!(messages::TOKEN_NOT_WHITELISTED));
+        let balance = self.internal_token_balance(&account_id, &token_id);
+        require!(
+            amount.0 > 0 && amount.0 <= balance,
+            messages::NOT_ENOUGH_TOKENS
+        );
+        // tokens out on a swap are owed but can't be sent yet
+        require!(
+            amount.0 <= token.total_held,
+            messages::TOKEN_HELD_SHORT,
+            token.total_held
+        );
+        self.token_balances.insert(
+            &(account_id.clone(), token_id.clone()),
+            &(balance - amount.0),
+        );
+        token.total_held -= amount.0;
+        token.total_owed -= amount.0;
+        self.tokens.insert(&token_id, &token);
+        if let Some(currency) = &token.currency {
+            let delta = counter_amount(amount).unwrap_or_else(|error| fail!(error));
+            self.internal_change(&account_id, currency, delta);
+        }
+
+        ext_ft::ft_transfer(
+            account_id.clone(),
+            amount,
+            None,
+            &token_id,
+            1,
+            self.config().gas.ft_transfer.0,
+        )
+        .then(ext_self::on_token_withdrawn(
+            account_id,
+            token_id,
+            amount,
+            token.currency,
+            &env::current_account_id(),
+            0,
+            self.config().gas.callback.0,
+        ))
+    }
+
+    /// Puts the balance back if the token transfer failed, and the Change in
+    /// `currency` it was taken off.
+    pub fn on_token_withdrawn(
+        &mut self,
+        account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        currency: Option<String>,
+    ) {
+        self.assert_access("on_token_withdrawn");
+        let event = TokenDepositEvent {
+            account_id: &account_id,
+            token_id: &token_id,
+            amount,
+        };
+        if promise_succeeded().is_ok() {
+            self.emit_event("ft_withdraw", &event);
+            return;
+        }
+        let balance = self.internal_token_balance(&account_id, &token_id);
+        self.token_balances.insert(
+            &(account_id.clone(), token_id.clone()),
+            &(balance + amount.0),
+        );
+        if let Some(mut token) = self.tokens.get(&token_id) {
+            token.total_held += amount.0;
+            token.total_owed += amount.0;
+            self.tokens.insert(&token_id, &token);
+        }
+        if let Some(currency) = currency {
+            self.internal_move_num(
+                CounterOperation::TransferIn,
+                &account_id,
+                &currency,
+                amount.0 as i128,
+            );
+        }
+        self.emit_event("ft_withdraw_failed", &event);
+    }
+
+    /// Returns the balance of `token_id` held for `account_id`.
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_token_balance '{"account_id": "donation.YOU.testnet", "token_id": "usdc.testnet"}'
+    /// ```
+    pub fn get_token_balance(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(self.internal_token_balance(&account_id, &token_id))
+    }
+
+    /// Returns, for every whitelisted token, how much the contract holds and how much it owes.
+    pub fn get_token_totals(&self) -> Vec<TokenTotalsView> {
+        self.tokens
+            .iter()
+            .map(|(token_id, token)| TokenTotalsView {
+                token_id,
+                held: U128(token.total_held),
+                owed: U128(token.total_owed),
+                solvent: token.total_held >= token.total_owed,
+            })
+            .collect()
+    }
+
+    /// NEP-141 receiver, called by a token contract on `ft_transfer_call`.
+    ///
+    /// Returns the amount to refund: everything for tokens that are not
+    /// whitelisted, otherwise what the sender's Change in the token's currency
+    /// can't take, if it has one. `msg` is not used yet.
+    #[allow(unused_variables)]
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: ValidAccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_access("ft_on_transfer");
+        let token_id = env::predecessor_account_id();
+        let token = match self.tokens.get(&token_id) {
+            Some(token) => token,
+            None => {
+                self.log(LogLevel::Info, messages::TOKEN_NOT_ACCEPTED, &[&token_id]);
+                return PromiseOrValue::Value(amount);
+            }
+        };
+        if self.paused || self.read_only {
+            self.log(LogLevel::Info, messages::PAUSED_REFUND, &[]);
+            return PromiseOrValue::Value(amount);
+        }
+        if amount.0 < token.min_deposit {
+            self.log(
+                LogLevel::Info,
+                messages::DEPOSIT_BELOW_MINIMUM,
+                &[&token.min_deposit, &token_id],
+            );
+            return PromiseOrValue::Value(amount);
+        }
+        let sender_id: AccountId = sender_id.into();
+        let used = match &token.currency {
+            Some(currency) => self.internal_change_room(&sender_id, currency, amount.0),
+            None => amount.0,
+        };
+        let unused = amount.0 - used;
+        if unused > 0 {
+            self.log(
+                LogLevel::Info,
+                messages::CHANGE_ROOM_REFUND,
+                &[&used, &unused],
+            );
+        }
+        if used == 0 {
+            return PromiseOrValue::Value(amount);
+        }
+        self.internal_deposit_token(&sender_id, &token_id, used);
+        if let Some(currency) = &token.currency {
+            self.internal_add(&sender_id, currency, None, used as i128);
+        }
+        self.internal_round_up(&sender_id, &token_id, used);
+        self.emit_event(
+            "ft_deposit",
+            &TokenDepositEvent {
+                account_id: &sender_id,
+                token_id: &token_id,
+                amount: U128(used),
+            },
+        );
+        PromiseOrValue::Value(U128(unused))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::I128;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::PromiseResult;
+    use std::convert::TryFrom;
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    fn refund(result: PromiseOrValue<U128>) -> u128 {
+        match result {
+            PromiseOrValue::Value(amount) => amount.0,
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    fn contract_with_token(token_id: &str) -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        contract.add_token(account(token_id), 6, U128(5));
+        contract
+    }
+
+    #[test]
+    fn whitelisted_tokens_are_credited_per_account() {
+        let mut contract = contract_with_token("usdc.testnet");
+        contract.add_token(account("dai.testnet"), 18, U128(0));
+        testing_env!(context_for("usdc.testnet", 0));
+        assert_eq!(
+            0,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(50), String::new()))
+        );
+        assert_eq!(
+            0,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(25), String::new()))
+        );
+        testing_env!(context_for("dai.testnet", 0));
+        assert_eq!(
+            0,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(7), String::new()))
+        );
+        let jane = "jane.testnet".to_string();
+        assert_eq!(
+            75,
+            contract.internal_token_balance(&jane, &"usdc.testnet".to_string())
+        );
+        assert_eq!(
+            7,
+            contract.internal_token_balance(&jane, &"dai.testnet".to_string())
+        );
+        assert_eq!(
+            75,
+            contract
+                .tokens
+                .get(&"usdc.testnet".to_string())
+                .unwrap()
+                .total_owed
+        );
+    }
+
+    #[test]
+    fn unlisted_tokens_are_refunded_in_full() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("scam.testnet", 0));
+        assert_eq!(
+            100,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(100), String::new()))
+        );
+        assert_eq!(
+            0,
+            contract
+                .internal_token_balance(&"jane.testnet".to_string(), &"scam.testnet".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_owner_whitelists_tokens() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add_token(account("dai.testnet"), 18, U128(0));
+    }
+
+    #[test]
+    fn deposits_below_minimum_are_refunded() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("usdc.testnet", 0));
+        assert_eq!(
+            4,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(4), String::new()))
+        );
+        assert_eq!(
+            0,
+            contract
+                .tokens
+                .get(&"usdc.testnet".to_string())
+                .unwrap()
+                .total_owed
+        );
+    }
+
+    #[test]
+    fn deposits_while_paused_are_refunded() {
+        let mut contract = contract_with_token("usdc.testnet");
+        contract.pause();
+        testing_env!(context_for("usdc.testnet", 0));
+        assert_eq!(
+            100,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(100), String::new()))
+        );
+        assert_eq!(
+            0,
+            contract
+                .internal_token_balance(&"jane.testnet".to_string(), &"usdc.testnet".to_string())
+        );
+    }
+
+    #[test]
+    fn tokens_are_listed_and_removed() {
+        let mut contract = contract_with_token("usdc.testnet");
+        contract.add_token(account("dai.testnet"), 18, U128(0));
+        let tokens = contract.list_tokens();
+        assert_eq!(2, tokens.len());
+        assert_eq!("usdc.testnet", tokens[0].token_id);
+        assert_eq!(6, tokens[0].decimals);
+        assert_eq!(5, tokens[0].min_deposit.0);
+        contract.remove_token(account("usdc.testnet"));
+        assert_eq!(1, contract.list_tokens().len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Token still has balances owed to users")]
+    fn tokens_with_balances_cannot_be_removed() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(10), String::new());
+        testing_env!(context_for("alice.testnet", 0));
+        contract.remove_token(account("usdc.testnet"));
+    }
+
+    #[test]
+    fn balances_are_withdrawn_with_ft_transfer() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(30), String::new());
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_token(account("usdc.testnet"), U128(20));
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("ft_transfer"));
+        assert!(receipts.contains("on_token_withdrawn"));
+        assert_eq!(
+            10,
+            contract
+                .get_token_balance("jane.testnet".to_string(), "usdc.testnet".to_string())
+                .0
+        );
+        assert_eq!(10, contract.get_token_totals()[0].owed.0);
+
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_token(account("usdc.testnet"), U128(10));
+        testing_env!(context_for("alice.testnet", 0));
+        contract.remove_token(account("usdc.testnet"));
+        assert!(contract.list_tokens().is_empty());
+    }
+
+    #[test]
+    fn failed_withdrawals_are_restored() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(30), String::new());
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_token(account("usdc.testnet"), U128(30));
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_token_withdrawn(
+            "jane.testnet".to_string(),
+            "usdc.testnet".to_string(),
+            U128(30),
+            None,
+        );
+        let totals = contract.get_token_totals();
+        assert_eq!((30, 30), (totals[0].held.0, totals[0].owed.0));
+        assert_eq!(
+            30,
+            contract
+                .get_token_balance("jane.testnet".to_string(), "usdc.testnet".to_string())
+                .0
+        );
+    }
+
+    /// usdc.testnet backing Change in USDC, which can't go above 100.
+    fn change_backed_token() -> Change {
+        let mut contract = contract_with_token("usdc.testnet");
+        contract.set_token_currency(account("usdc.testnet"), Some("USDC".to_string()));
+        contract.set_limits(I128(i128::MIN), I128(100));
+        contract
+    }
+
+    fn usdc_change(contract: &Change) -> i128 {
+        contract
+            .get_num_for("jane.testnet".to_string(), Some("USDC".to_string()))
+            .0
+    }
+
+    #[test]
+    fn deposits_are_added_to_the_change_in_the_token_currency() {
+        let mut contract = change_backed_token();
+        assert_eq!(Some("USDC".to_string()), contract.list_tokens()[0].currency);
+        testing_env!(context_for("usdc.testnet", 0));
+        assert_eq!(
+            0,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(30), String::new()))
+        );
+        assert_eq!(30, usdc_change(&contract));
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_token(account("usdc.testnet"), U128(20));
+        assert_eq!(10, usdc_change(&contract));
+    }
+
+    #[test]
+    fn what_the_change_cannot_take_is_refunded() {
+        let mut contract = change_backed_token();
+        testing_env!(context_for("usdc.testnet", 0));
+        assert_eq!(
+            20,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(120), String::new()))
+        );
+        assert_eq!(100, usdc_change(&contract));
+        assert_eq!(
+            100,
+            contract
+                .get_token_balance("jane.testnet".to_string(), "usdc.testnet".to_string())
+                .0
+        );
+        assert_eq!(
+            40,
+            refund(contract.ft_on_transfer(account("jane.testnet"), U128(40), String::new()))
+        );
+        assert_eq!(100, contract.get_token_totals()[0].owed.0);
+    }
+
+    #[test]
+    fn failed_withdrawals_restore_the_change() {
+        let mut contract = change_backed_token();
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(30), String::new());
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_token(account("usdc.testnet"), U128(30));
+        assert_eq!(0, usdc_change(&contract));
+        testing_env!(
+            context_for("alice.testnet", 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_token_withdrawn(
+            "jane.testnet".to_string(),
+            "usdc.testnet".to_string(),
+            U128(30),
+            Some("USDC".to_string()),
+        );
+        assert_eq!(30, usdc_change(&contract));
+    }
+
+    #[test]
+    #[should_panic(expected = "NEAR Change can only be backed by NEAR")]
+    fn tokens_cannot_back_near_change() {
+        let mut contract = contract_with_token("usdc.testnet");
+        contract.set_token_currency(account("usdc.testnet"), Some("NEAR".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough tokens")]
+    fn cannot_withdraw_more_than_the_balance() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(30), String::new());
+        testing_env!(context_for("jane.testnet", 1));
+        contract.withdraw_token(account("usdc.testnet"), U128(31));
+    }
+
+    #[test]
+    fn totals_compare_holdings_with_balances_owed() {
+        let mut contract = contract_with_token("usdc.testnet");
+        testing_env!(context_for("usdc.testnet", 0));
+        contract.ft_on_transfer(account("jane.testnet"), U128(30), String::new());
+        contract.ft_on_transfer(account("bob.testnet"), U128(12), String::new());
+        assert_eq!(
+            30,
+            contract
+                .get_token_balance("jane.testnet".to_string(), "usdc.testnet".to_string())
+                .0
+        );
+        let totals = contract.get_token_totals();
+        assert_eq!(1, totals.len());
+        assert_eq!(42, totals[0].held.0);
+        assert_eq!(42, totals[0].owed.0);
+        assert!(totals[0].solvent);
+    }
+}
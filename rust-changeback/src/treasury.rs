@@ -0,0 +1,173 @@
+//! The treasury holds NEAR owned by the contract itself, used to fund payouts
+//! such as [rebates](crate::rebate).
+//!
+//! The owner can withdraw up to `treasury_multisig_threshold` a day, however
+//! the amount is split across calls; more needs the approval of the
+//! [trustees](crate::trustee). Once there are trustees, that threshold and the
+//! hot limit below only change by a trustee proposal too.
+//!
+//! With `hot_balance_limit` set, the treasury is split in two. The hot part
+//! pays every operational outflow (rebates, referral payouts, owner
+//! withdrawals) and never holds more than the limit: whatever comes in above
+//! it goes to the cold part, which only trustee proposals can move.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, AccountId, Promise};
+
+use crate::config::DAY;
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TreasuryWithdrawalEvent<'a> {
+    receiver_id: &'a AccountId,
+    amount: U128,
+}
+
+impl Change {
+    /// Adds `amount` held in `from` to the hot treasury, moving anything above
+    /// the hot limit to cold.
+    pub(crate) fn internal_credit_treasury(&mut self, amount: Balance, from: Book) {
+        self.internal_post(Posting::transfer(from, Book::Treasury, amount));
+        self.internal_rebalance_treasury();
+    }
+
+    /// Moves whatever the hot treasury holds above `hot_balance_limit` to cold.
+    pub(crate) fn internal_rebalance_treasury(&mut self) {
+        if let Some(limit) = self.config().hot_balance_limit {
+            let hot = self.book(Book::Treasury);
+            if hot > limit.0 {
+                self.internal_post(Posting::transfer(
+                    Book::Treasury,
+                    Book::ColdTreasury,
+                    hot - limit.0,
+                ));
+            }
+        }
+    }
+
+    /// Takes back `amount` credited earlier into `to`, from the hot treasury
+    /// first and then from cold, where part of it may have spilled over to.
+    pub(crate) fn internal_revert_treasury_credit(&mut self, amount: Balance, to: Book) {
+        let from_hot = amount.min(self.book(Book::Treasury));
+        self.internal_post(
+            Posting::default()
+                .debit(Book::Treasury, from_hot)
+                .debit(Book::ColdTreasury, amount - from_hot)
+                .credit(to, amount),
+        );
+    }
+
+    /// Sends `amount` from the treasury to `receiver_id`, putting it back if the
+    /// transfer fails. Only trustee proposals pass `from_cold`, spending the cold
+    /// treasury before the hot one.
+    pub(crate) fn internal_withdraw_treasury(
+        &mut self,
+        receiver_id: AccountId,
+        amount: Balance,
+        from_cold: bool,
+    ) -> Promise {
+        require!(amount > 0, messages::AMOUNT_NOT_POSITIVE);
+        let cold = if from_cold {
+            amount.min(self.book(Book::ColdTreasury))
+        } else {
+            0
+        };
+        require!(
+            self.book(Book::Treasury) >= amount - cold,
+            messages::TREASURY_SHORT
+        );
+        self.internal_record_outflow(amount);
+        self.internal_post(
+            Posting::default()
+                .debit(Book::ColdTreasury, cold)
+                .debit(Book::Treasury, amount - cold)
+                .credit(Book::Custody, amount),
+        );
+        self.emit_event(
+            "treasury_withdrawal",
+            &TreasuryWithdrawalEvent {
+                receiver_id: &receiver_id,
+                amount: U128(amount),
+            },
+        );
+        self.internal_transfer(receiver_id.clone(), amount)
+            .then(ext_self::on_treasury_withdrawn(
+                receiver_id,
+                U128(amount),
+                &env::current_account_id(),
+                0,
+                self.config().gas.callback.0,
+            ))
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Adds the attached deposit to the treasury.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet fund_treasury --accountId donation.YOU.testnet --deposit 10
+    /// ```
+    #[payable]
+    pub fn fund_treasury(&mut self) {
+        self.assert_access("fund_treasury");
+        let amount = env::attached_deposit();
+        require!(amount > 0, messages::TREASURY_DEPOSIT_REQUIRED);
+        self.internal_credit_treasury(amount, Book::Custody);
+        self.log(
+            LogLevel::Info,
+            messages::TREASURY_FUNDED,
+            &[&amount, &self.book(Book::Treasury)],
+        );
+    }
+
+    /// Returns the hot treasury balance in yoctoNEAR.
+    pub fn get_treasury(&self) -> U128 {
+        U128(self.book(Book::Treasury))
+    }
+
+    /// Returns the cold treasury balance in yoctoNEAR.
+    pub fn get_cold_treasury(&self) -> U128 {
+        U128(self.book(Book::ColdTreasury))
+    }
+
+    /// Sends `amount` from the treasury to `receiver_id`. Owner only, and only up
+    /// to `treasury_multisig_threshold` a day, counting every withdrawal of the
+    /// day so far; larger amounts go through a trustee proposal.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet withdraw_treasury '{"receiver_id": "ops.testnet", "amount": "1000000000000000000000000"}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn withdraw_treasury(&mut self, receiver_id: ValidAccountId, amount: U128) -> Promise {
+        self.assert_access("withdraw_treasury");
+        let day = env::block_timestamp() / DAY;
+        let withdrawn = match self.owner_withdrawals {
+            (withdrawal_day, withdrawn) if withdrawal_day == day => withdrawn,
+            _ => 0,
+        };
+        let withdrawn = withdrawn
+            .checked_add(amount.0)
+            .filter(|withdrawn| *withdrawn <= self.config().treasury_multisig_threshold.0)
+            .unwrap_or_else(|| fail!(messages::TRUSTEE_APPROVAL_REQUIRED));
+        self.owner_withdrawals = (day, withdrawn);
+        self.internal_withdraw_treasury(receiver_id.into(), amount.0, false)
+    }
+
+    /// Puts a withdrawal that didn't reach `receiver_id` back into the treasury.
+    pub fn on_treasury_withdrawn(&mut self, receiver_id: AccountId, amount: U128) {
+        self.assert_access("on_treasury_withdrawn");
+        if promise_succeeded().is_ok() {
+            return;
+        }
+        self.internal_credit_treasury(amount.0, Book::Custody);
+        self.emit_event(
+            "treasury_withdrawal_failed",
+            &TreasuryWithdrawalEvent {
+                receiver_id: &receiver_id,
+                amount,
+            },
+        );
+    }
+}
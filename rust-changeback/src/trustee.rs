@@ -0,0 +1,663 @@
+//! Trustee approval for large treasury withdrawals and resets.
+//!
+//! Trustees are a set of accounts separate from the owner and the granted
+//! [roles](crate::roles). A trustee proposes an action, other trustees confirm
+//! it, and it runs as soon as `threshold` of them have. The owner names the
+//! first trustees; from then on the trustee set only changes by a proposal of
+//! its own. Once there are trustees, resetting an account's Change is up to
+//! them too: the owner can no longer reset, directly or through the
+//! [timelock](crate::timelock), nor roll back to a [snapshot](crate::snapshot).
+//!
+//! A proposal not run within [`PROPOSAL_TTL`] expires, and its proposer can
+//! cancel it before that, so one that can't run, say a refill the cold
+//! treasury can't cover, doesn't stay pending for good. At most
+//! [`MAX_PENDING_PROPOSALS`] wait for confirmations at once.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Timestamp};
+
+use crate::config::DAY;
+use crate::*;
+
+/// How long a proposal can wait for confirmations before it expires, in nanoseconds.
+pub(crate) const PROPOSAL_TTL: Timestamp = 7 * DAY;
+
+/// Most proposals waiting for confirmations at once.
+pub(crate) const MAX_PENDING_PROPOSALS: u64 = 20;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TrusteeAction {
+    /// Sends `amount` from the treasury to `receiver_id`.
+    WithdrawTreasury {
+        receiver_id: ValidAccountId,
+        amount: U128,
+    },
+    /// Moves `amount` from the cold treasury to the hot one, up to the hot limit.
+    RefillHot { amount: U128 },
+    /// Sets `treasury_multisig_threshold` and `hot_balance_limit` of the config.
+    SetTreasuryLimits {
+        multisig_threshold: U128,
+        hot_balance_limit: Option<U128>,
+    },
+    /// Replaces the trustee set and its approval threshold.
+    SetTrustees {
+        trustees: Vec<ValidAccountId>,
+        threshold: u32,
+    },
+    /// Resets the Change of `account_id` in `currency`, see [`reset`](Change::reset).
+    Reset {
+        account_id: ValidAccountId,
+        currency: Option<String>,
+    },
+    /// Rolls the ledger back to snapshot `snapshot_id`, see [`rollback_to`](Change::rollback_to).
+    Rollback { snapshot_id: U64 },
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TrusteeProposal {
+    pub action: TrusteeAction,
+    pub proposer_id: AccountId,
+    pub approvals: Vec<AccountId>,
+    pub created_at: Timestamp,
+    pub executed: bool,
+    pub cancelled: bool,
+}
+
+impl TrusteeProposal {
+    fn is_expired(&self) -> bool {
+        env::block_timestamp() >= self.created_at.saturating_add(PROPOSAL_TTL)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TrusteeProposalView {
+    pub id: U64,
+    pub action: TrusteeAction,
+    pub proposer_id: AccountId,
+    pub approvals: Vec<AccountId>,
+    pub created_at: U64,
+    pub expires_at: U64,
+    pub executed: bool,
+    pub cancelled: bool,
+}
+
+impl TrusteeProposalView {
+    fn new(id: u64, proposal: TrusteeProposal) -> Self {
+        Self {
+            id: U64(id),
+            action: proposal.action,
+            proposer_id: proposal.proposer_id,
+            approvals: proposal.approvals,
+            created_at: U64(proposal.created_at),
+            expires_at: U64(proposal.created_at.saturating_add(PROPOSAL_TTL)),
+            executed: proposal.executed,
+            cancelled: proposal.cancelled,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ProposalConfirmedEvent<'a> {
+    proposal_id: U64,
+    trustee_id: &'a AccountId,
+    approvals: usize,
+    threshold: u32,
+}
+
+impl Change {
+    /// Panics if resets are up to the trustees.
+    pub(crate) fn assert_reset_unguarded(&self) {
+        require!(self.trustees.is_empty(), messages::RESET_NEEDS_APPROVAL);
+    }
+
+    fn internal_set_trustees(&mut self, trustees: Vec<ValidAccountId>, threshold: u32) {
+        self.trustees.clear();
+        for trustee in trustees {
+            let trustee: AccountId = trustee.into();
+            require!(trustee != self.owner_id, messages::OWNER_AS_TRUSTEE);
+            self.trustees.insert(&trustee);
+        }
+        // counted after deduplication
+        require!(
+            threshold > 0 && u64::from(threshold) <= self.trustees.len(),
+            messages::INVALID_THRESHOLD
+        );
+        self.trustee_threshold = threshold;
+        self.log(
+            LogLevel::Info,
+            messages::TRUSTEES_SET,
+            &[&threshold, &self.trustees.len()],
+        );
+    }
+
+    /// Drops the expired proposals from the pending list.
+    fn internal_prune_expired_proposals(&mut self) {
+        for id in self.pending_proposals.to_vec() {
+            let proposal = self.trustee_proposals.get(id).unwrap();
+            if proposal.is_expired() {
+                self.pending_proposals.remove(&id);
+                self.emit_event("proposal_expired", &TrusteeProposalView::new(id, proposal));
+            }
+        }
+    }
+
+    fn internal_execute_proposal(&mut self, proposal: &TrusteeProposal) {
+        match proposal.action.clone() {
+            TrusteeAction::WithdrawTreasury {
+                receiver_id,
+                amount,
+            } => {
+                self.internal_withdraw_treasury(receiver_id.into(), amount.0, true);
+            }
+            TrusteeAction::RefillHot { amount } => {
+                require!(
+                    amount.0 <= self.book(Book::ColdTreasury),
+                    messages::COLD_TREASURY_SHORT
+                );
+                self.internal_credit_treasury(amount.0, Book::ColdTreasury);
+            }
+            TrusteeAction::SetTreasuryLimits {
+                multisig_threshold,
+                hot_balance_limit,
+            } => self.internal_set_treasury_limits(multisig_threshold, hot_balance_limit),
+            TrusteeAction::SetTrustees {
+                trustees,
+                threshold,
+            } => self.internal_set_trustees(trustees, threshold),
+            TrusteeAction::Reset {
+                account_id,
+                currency,
+            } => {
+                self.internal_reset(account_id.into(), currency);
+            }
+            TrusteeAction::Rollback { snapshot_id } => {
+                self.internal_start_rollback(snapshot_id.0);
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Names the first trustees and how many of them must confirm a proposal.
+    /// Owner only, and only while there are no trustees yet.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet init_trustees '{"trustees": ["t1.testnet", "t2.testnet", "t3.testnet"], "threshold": 2}' --accountId Change.YOU.testnet
+    /// ```
+    pub fn init_trustees(&mut self, trustees: Vec<ValidAccountId>, threshold: u32) {
+        self.assert_access("init_trustees");
+        require!(self.trustees.is_empty(), messages::TRUSTEES_ALREADY_SET);
+        self.internal_set_trustees(trustees, threshold);
+    }
+
+    /// Proposes `action`, counting as the caller's confirmation. Trustees only.
+    /// Returns the proposal id.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet propose_action '{"action": {"WithdrawTreasury": {"receiver_id": "ops.testnet", "amount": "50000000000000000000000000"}}}' --accountId t1.testnet
+    /// ```
+    pub fn propose_action(&mut self, action: TrusteeAction) -> U64 {
+        self.assert_access("propose_action");
+        self.internal_prune_expired_proposals();
+        require!(
+            self.pending_proposals.len() < MAX_PENDING_PROPOSALS,
+            messages::TOO_MANY_PENDING_PROPOSALS,
+            MAX_PENDING_PROPOSALS
+        );
+        let proposer_id = env::predecessor_account_id();
+        let proposal = TrusteeProposal {
+            action,
+            proposer_id: proposer_id.clone(),
+            approvals: Vec::new(),
+            created_at: env::block_timestamp(),
+            executed: false,
+            cancelled: false,
+        };
+        let id = self.trustee_proposals.len();
+        self.trustee_proposals.push(&proposal);
+        self.pending_proposals.insert(&id);
+        self.emit_event("proposal_created", &TrusteeProposalView::new(id, proposal));
+        self.confirm_action(U64(id));
+        U64(id)
+    }
+
+    /// Confirms the proposal `proposal_id`, running it once enough trustees have. Trustees only.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet confirm_action '{"proposal_id": "0"}' --accountId t2.testnet
+    /// ```
+    pub fn confirm_action(&mut self, proposal_id: U64) {
+        self.assert_access("confirm_action");
+        let trustee_id = env::predecessor_account_id();
+        let mut proposal = self
+            .trustee_proposals
+            .get(proposal_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_PROPOSAL));
+        require!(!proposal.executed, messages::PROPOSAL_ALREADY_EXECUTED);
+        require!(!proposal.cancelled, messages::PROPOSAL_CANCELLED);
+        require!(!proposal.is_expired(), messages::PROPOSAL_EXPIRED);
+        require!(
+            !proposal.approvals.contains(&trustee_id),
+            messages::ALREADY_CONFIRMED
+        );
+        proposal.approvals.push(trustee_id.clone());
+        // trustees replaced since confirming no longer count
+        let approvals = proposal
+            .approvals
+            .iter()
+            .filter(|approver| self.trustees.contains(approver))
+            .count();
+        self.emit_event(
+            "proposal_confirmed",
+            &ProposalConfirmedEvent {
+                proposal_id,
+                trustee_id: &trustee_id,
+                approvals,
+                threshold: self.trustee_threshold,
+            },
+        );
+        if approvals >= self.trustee_threshold as usize {
+            proposal.executed = true;
+            self.pending_proposals.remove(&proposal_id.0);
+            self.internal_execute_proposal(&proposal);
+        }
+        self.trustee_proposals.replace(proposal_id.0, &proposal);
+    }
+
+    /// Withdraws the proposal `proposal_id` before it runs. Only its proposer can.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet cancel_action '{"proposal_id": "0"}' --accountId t1.testnet
+    /// ```
+    pub fn cancel_action(&mut self, proposal_id: U64) {
+        self.assert_access("cancel_action");
+        let mut proposal = self
+            .trustee_proposals
+            .get(proposal_id.0)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_PROPOSAL));
+        require!(
+            proposal.proposer_id == env::predecessor_account_id(),
+            messages::NOT_PROPOSER
+        );
+        require!(!proposal.executed, messages::PROPOSAL_ALREADY_EXECUTED);
+        require!(!proposal.cancelled, messages::PROPOSAL_CANCELLED);
+        proposal.cancelled = true;
+        self.pending_proposals.remove(&proposal_id.0);
+        self.trustee_proposals.replace(proposal_id.0, &proposal);
+        self.emit_event(
+            "proposal_cancelled",
+            &TrusteeProposalView::new(proposal_id.0, proposal),
+        );
+    }
+
+    pub fn get_proposal(&self, proposal_id: U64) -> Option<TrusteeProposalView> {
+        self.trustee_proposals
+            .get(proposal_id.0)
+            .map(|proposal| TrusteeProposalView::new(proposal_id.0, proposal))
+    }
+
+    /// Returns the proposals still waiting for confirmations, oldest first. There
+    /// are never more than [`MAX_PENDING_PROPOSALS`].
+    ///
+    /// ```bash
+    /// near view Change.YOU.testnet get_pending_proposals
+    /// ```
+    pub fn get_pending_proposals(&self) -> Vec<TrusteeProposalView> {
+        let mut ids = self.pending_proposals.to_vec();
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|id| (id, self.trustee_proposals.get(id).unwrap()))
+            .filter(|(_, proposal)| !proposal.is_expired())
+            .map(|(id, proposal)| TrusteeProposalView::new(id, proposal))
+            .collect()
+    }
+
+    /// Returns the trustees and the number of approvals a proposal needs.
+    pub fn get_trustees(&self) -> (Vec<AccountId>, u32) {
+        (self.trustees.to_vec(), self.trustee_threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DAY;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    const NEAR: u128 = 10u128.pow(24);
+
+    fn account(id: &str) -> ValidAccountId {
+        ValidAccountId::try_from(id).unwrap()
+    }
+
+    /// 100 NEAR in the treasury and trustees t1, t2, t3 with a threshold of 2.
+    fn guarded_treasury() -> Change {
+        guarded_treasury_with(Config::default())
+    }
+
+    /// [`guarded_treasury`] with `config` set before the trustees were named.
+    fn guarded_treasury_with(config: Config) -> Change {
+        testing_env!(context_for("alice.testnet", 100 * NEAR));
+        let mut contract = Change::default();
+        contract.set_config(config, None);
+        contract.fund_treasury();
+        contract.init_trustees(
+            vec![
+                account("t1.testnet"),
+                account("t2.testnet"),
+                account("t3.testnet"),
+            ],
+            2,
+        );
+        contract
+    }
+
+    fn withdraw(amount: u128) -> TrusteeAction {
+        TrusteeAction::WithdrawTreasury {
+            receiver_id: account("ops.testnet"),
+            amount: U128(amount),
+        }
+    }
+
+    #[test]
+    fn owner_withdraws_small_amounts_directly() {
+        let mut contract = guarded_treasury();
+        contract.withdraw_treasury(account("ops.testnet"), U128(NEAR));
+        assert_eq!(99 * NEAR, contract.get_treasury().0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawals above the threshold need trustee approval")]
+    fn owner_cannot_withdraw_large_amounts() {
+        let mut contract = guarded_treasury();
+        contract.withdraw_treasury(account("ops.testnet"), U128(50 * NEAR));
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawals above the threshold need trustee approval")]
+    fn owner_withdrawals_add_up_over_the_day() {
+        let mut contract = guarded_treasury();
+        contract.withdraw_treasury(account("ops.testnet"), U128(6 * NEAR));
+        contract.withdraw_treasury(account("ops.testnet"), U128(4 * NEAR));
+        contract.withdraw_treasury(account("ops.testnet"), U128(1));
+    }
+
+    #[test]
+    fn owner_withdrawal_limit_renews_daily() {
+        let mut contract = guarded_treasury();
+        contract.withdraw_treasury(account("ops.testnet"), U128(10 * NEAR));
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = DAY;
+        testing_env!(context);
+        contract.withdraw_treasury(account("ops.testnet"), U128(10 * NEAR));
+        assert_eq!(80 * NEAR, contract.get_treasury().0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Treasury limits only change by trustee proposal")]
+    fn owner_cannot_raise_the_threshold_once_there_are_trustees() {
+        let mut contract = guarded_treasury();
+        contract.set_config(
+            Config {
+                treasury_multisig_threshold: U128(100 * NEAR),
+                ..Config::default()
+            },
+            Some(U64(DAY)),
+        );
+    }
+
+    #[test]
+    fn trustees_set_the_treasury_limits() {
+        let mut contract = guarded_treasury();
+        // scheduled before the proposal, it doesn't bring the old limits back
+        contract.set_config(
+            Config {
+                rebate_threshold: 3,
+                ..Config::default()
+            },
+            Some(U64(DAY)),
+        );
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(TrusteeAction::SetTreasuryLimits {
+            multisig_threshold: U128(NEAR),
+            hot_balance_limit: Some(U128(20 * NEAR)),
+        });
+        testing_env!(context_for("t2.testnet", 0));
+        contract.confirm_action(id);
+        assert_eq!(20 * NEAR, contract.get_treasury().0);
+        assert_eq!(80 * NEAR, contract.get_cold_treasury().0);
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = DAY;
+        testing_env!(context);
+        let config = contract.get_config();
+        assert_eq!(3, config.rebate_threshold);
+        assert_eq!(NEAR, config.treasury_multisig_threshold.0);
+        assert_eq!(Some(U128(20 * NEAR)), config.hot_balance_limit);
+    }
+
+    #[test]
+    fn large_withdrawal_runs_at_the_threshold() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(withdraw(50 * NEAR));
+        assert_eq!(100 * NEAR, contract.get_treasury().0);
+        testing_env!(context_for("t3.testnet", 0));
+        contract.confirm_action(id);
+        assert_eq!(50 * NEAR, contract.get_treasury().0);
+        let proposal = contract.get_proposal(id).unwrap();
+        assert!(proposal.executed);
+        assert_eq!(vec!["t1.testnet", "t3.testnet"], proposal.approvals);
+    }
+
+    #[test]
+    #[should_panic(expected = "Already confirmed")]
+    fn trustees_confirm_once() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(withdraw(50 * NEAR));
+        contract.confirm_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only trustees can call this method")]
+    fn owner_is_not_a_trustee() {
+        let mut contract = guarded_treasury();
+        contract.propose_action(withdraw(50 * NEAR));
+    }
+
+    #[test]
+    fn cold_treasury_only_moves_by_proposal() {
+        let mut contract = guarded_treasury_with(Config {
+            hot_balance_limit: Some(U128(5 * NEAR)),
+            ..Config::default()
+        });
+        assert_eq!(5 * NEAR, contract.get_treasury().0);
+        assert_eq!(95 * NEAR, contract.get_cold_treasury().0);
+
+        contract.withdraw_treasury(account("ops.testnet"), U128(4 * NEAR));
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(TrusteeAction::RefillHot {
+            amount: U128(20 * NEAR),
+        });
+        testing_env!(context_for("t2.testnet", 0));
+        contract.confirm_action(id);
+        // topped up to the limit, the rest of the refill spilled straight back
+        assert_eq!(5 * NEAR, contract.get_treasury().0);
+        assert_eq!(91 * NEAR, contract.get_cold_treasury().0);
+
+        let id = contract.propose_action(withdraw(30 * NEAR));
+        testing_env!(context_for("t1.testnet", 0));
+        contract.confirm_action(id);
+        assert_eq!(5 * NEAR, contract.get_treasury().0);
+        assert_eq!(61 * NEAR, contract.get_cold_treasury().0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Treasury can't cover the withdrawal")]
+    fn owner_cannot_reach_the_cold_treasury() {
+        let mut contract = guarded_treasury_with(Config {
+            hot_balance_limit: Some(U128(5 * NEAR)),
+            ..Config::default()
+        });
+        contract.withdraw_treasury(account("ops.testnet"), U128(6 * NEAR));
+    }
+
+    #[test]
+    fn trustees_replace_themselves_by_proposal() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(TrusteeAction::SetTrustees {
+            trustees: vec![account("t1.testnet"), account("t4.testnet")],
+            threshold: 2,
+        });
+        testing_env!(context_for("t2.testnet", 0));
+        contract.confirm_action(id);
+        let (trustees, threshold) = contract.get_trustees();
+        assert_eq!(vec!["t1.testnet", "t4.testnet"], trustees);
+        assert_eq!(2, threshold);
+    }
+
+    #[test]
+    fn resets_run_at_the_threshold() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, None, None);
+        testing_env!(context_for("t1.testnet", 0));
+        let withdrawal = contract.propose_action(withdraw(50 * NEAR));
+        let reset = contract.propose_action(TrusteeAction::Reset {
+            account_id: account("jane.testnet"),
+            currency: None,
+        });
+        let pending = contract.get_pending_proposals();
+        assert_eq!(
+            vec![withdrawal.0, reset.0],
+            pending
+                .iter()
+                .map(|proposal| proposal.id.0)
+                .collect::<Vec<_>>()
+        );
+
+        testing_env!(context_for("t2.testnet", 0));
+        contract.confirm_action(reset);
+        assert_eq!(0, contract.get_num_for("jane.testnet".to_string(), None).0);
+        let pending = contract.get_pending_proposals();
+        assert_eq!(1, pending.len());
+        assert_eq!(withdrawal.0, pending[0].id.0);
+    }
+
+    #[test]
+    fn trustees_roll_back_by_proposal() {
+        let mut contract = guarded_treasury();
+        let snapshot_id = contract.create_snapshot();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.add(None, None, Some(U128(1)), None);
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(TrusteeAction::Rollback { snapshot_id });
+        assert_eq!(1, contract.get_num_for("jane.testnet".to_string(), None).0);
+        testing_env!(context_for("t2.testnet", 0));
+        contract.confirm_action(id);
+        assert_eq!(0, contract.get_num_for("jane.testnet".to_string(), None).0);
+    }
+
+    #[test]
+    fn executed_proposals_leave_the_pending_list() {
+        let mut contract = guarded_treasury();
+        for _ in 0..3 {
+            testing_env!(context_for("t1.testnet", 0));
+            let id = contract.propose_action(withdraw(20 * NEAR));
+            testing_env!(context_for("t2.testnet", 0));
+            contract.confirm_action(id);
+        }
+        let pending = contract.propose_action(withdraw(20 * NEAR));
+        let proposals = contract.get_pending_proposals();
+        assert_eq!(1, proposals.len());
+        assert_eq!(pending.0, proposals[0].id.0);
+    }
+
+    #[test]
+    fn proposer_cancels_a_stuck_proposal() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        // there is no cold treasury to refill from, so confirming it would panic
+        let id = contract.propose_action(TrusteeAction::RefillHot { amount: U128(NEAR) });
+        contract.cancel_action(id);
+        assert!(contract.get_pending_proposals().is_empty());
+        assert!(contract.get_proposal(id).unwrap().cancelled);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal was cancelled")]
+    fn cancelled_proposals_cannot_be_confirmed() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(withdraw(50 * NEAR));
+        contract.cancel_action(id);
+        testing_env!(context_for("t2.testnet", 0));
+        contract.confirm_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the proposer can cancel a proposal")]
+    fn only_the_proposer_cancels() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(withdraw(50 * NEAR));
+        testing_env!(context_for("t2.testnet", 0));
+        contract.cancel_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal expired")]
+    fn expired_proposals_cannot_be_confirmed() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        let id = contract.propose_action(withdraw(50 * NEAR));
+        let mut context = context_for("t2.testnet", 0);
+        context.block_timestamp = PROPOSAL_TTL;
+        testing_env!(context);
+        assert!(contract.get_pending_proposals().is_empty());
+        contract.confirm_action(id);
+    }
+
+    #[test]
+    fn expired_proposals_free_their_slot() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        for _ in 0..MAX_PENDING_PROPOSALS {
+            contract.propose_action(withdraw(50 * NEAR));
+        }
+        let mut context = context_for("t1.testnet", 0);
+        context.block_timestamp = PROPOSAL_TTL;
+        testing_env!(context);
+        let id = contract.propose_action(withdraw(50 * NEAR));
+        let pending = contract.get_pending_proposals();
+        assert_eq!(1, pending.len());
+        assert_eq!(id.0, pending[0].id.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "At most 20 proposals can be pending at once")]
+    fn pending_proposals_are_capped() {
+        let mut contract = guarded_treasury();
+        testing_env!(context_for("t1.testnet", 0));
+        for _ in 0..=MAX_PENDING_PROPOSALS {
+            contract.propose_action(withdraw(50 * NEAR));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Resets need trustee approval")]
+    fn owner_cannot_reset_once_there_are_trustees() {
+        let mut contract = guarded_treasury();
+        contract.reset(account("jane.testnet"), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! Watching how fast NEAR leaves the contract.
+//!
+//! Every outbound transfer (treasury withdrawals, rebates, referral payouts,
+//! settlements) is summed per hour. When the current hour goes over
+//! `velocity_alert_multiple` times the average of the trailing
+//! `velocity_window_hours`, an `outflow_anomaly` event is emitted, once per
+//! hour. While that average is 0, a fresh or long quiet contract, the hour is
+//! reported once it goes over `velocity_quiet_floor` instead. With `velocity_auto_pause` on, withdrawals are also paused until the
+//! owner resumes them.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{near_bindgen, Balance};
+
+use crate::config::HOUR;
+use crate::*;
+
+/// Hourly outflow totals.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct OutflowVelocity {
+    /// Hour, counted from the epoch, `current` sums the outflow of.
+    pub hour: u64,
+    pub current: Balance,
+    /// Totals of the hours before, oldest first, at most `velocity_window_hours` of them.
+    pub history: Vec<Balance>,
+    /// Whether the current hour was already reported.
+    pub alerted: bool,
+}
+
+impl OutflowVelocity {
+    /// Average hourly outflow over the recorded history.
+    fn trailing_average(&self) -> Balance {
+        if self.history.is_empty() {
+            return 0;
+        }
+        self.history.iter().sum::<Balance>() / self.history.len() as Balance
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OutflowVelocityView {
+    pub hour_outflow: U128,
+    pub trailing_average: U128,
+    pub withdrawals_paused: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct OutflowAnomalyEvent {
+    severity: &'static str,
+    hour_outflow: U128,
+    trailing_average: U128,
+    multiple: u32,
+    withdrawals_paused: bool,
+}
+
+impl Change {
+    /// Starts summing a new hour if `hour` is past the current one, keeping the
+    /// quiet hours in between as zeros.
+    fn internal_roll_velocity(&mut self, hour: u64) {
+        let window = self.config().velocity_window_hours as usize;
+        let velocity = &mut self.outflow_velocity;
+        if hour <= velocity.hour {
+            return;
+        }
+        if velocity.current > 0 || !velocity.history.is_empty() {
+            let quiet = ((hour - velocity.hour - 1) as usize).min(window);
+            velocity.history.push(velocity.current);
+            velocity.history.extend(std::iter::repeat_n(0, quiet));
+            if velocity.history.len() > window {
+                let excess = velocity.history.len() - window;
+                velocity.history.drain(..excess);
+            }
+        }
+        velocity.hour = hour;
+        velocity.current = 0;
+        velocity.alerted = false;
+    }
+
+    /// Counts `amount` of NEAR leaving the contract, reporting it if the hour's
+    /// outflow is anomalous. Panics while withdrawals are paused.
+    pub(crate) fn internal_record_outflow(&mut self, amount: Balance) {
+        self.assert_not_paused();
+        require!(!self.withdrawals_paused, messages::WITHDRAWALS_PAUSED);
+        self.internal_roll_velocity(env::block_timestamp() / HOUR);
+        let multiple = self.config().velocity_alert_multiple;
+        let quiet_floor = self.config().velocity_quiet_floor.0;
+        let velocity = &mut self.outflow_velocity;
+        velocity.current += amount;
+        let average = velocity.trailing_average();
+        if multiple == 0 || velocity.alerted {
+            return;
+        }
+        let limit = if average == 0 {
+            quiet_floor
+        } else {
+            average.saturating_mul(multiple as Balance)
+        };
+        if velocity.current > limit {
+            velocity.alerted = true;
+            if self.config().velocity_auto_pause {
+                self.withdrawals_paused = true;
+            }
+            self.emit_event(
+                "outflow_anomaly",
+                &OutflowAnomalyEvent {
+                    severity: "high",
+                    hour_outflow: U128(self.outflow_velocity.current),
+                    trailing_average: U128(average),
+                    multiple,
+                    withdrawals_paused: self.withdrawals_paused,
+                },
+            );
+        }
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Lets withdrawals through again after an anomaly paused them. Owner only.
+    pub fn resume_withdrawals(&mut self) {
+        self.assert_access("resume_withdrawals");
+        self.withdrawals_paused = false;
+        self.log(LogLevel::Info, messages::WITHDRAWALS_RESUMED, &[]);
+    }
+
+    /// Returns the outflow of the current hour against the trailing average.
+    pub fn get_outflow_velocity(&self) -> OutflowVelocityView {
+        let velocity = &self.outflow_velocity;
+        let current_hour = env::block_timestamp() / HOUR == velocity.hour;
+        OutflowVelocityView {
+            hour_outflow: U128(if current_hour { velocity.current } else { 0 }),
+            trailing_average: U128(velocity.trailing_average()),
+            withdrawals_paused: self.withdrawals_paused,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, VMContext};
+    use std::convert::TryFrom;
+
+    const NEAR: u128 = 10u128.pow(24);
+
+    fn owner_at(hour: u64) -> VMContext {
+        let mut context = context_for("alice.testnet", 0);
+        context.block_timestamp = hour * HOUR;
+        context
+    }
+
+    fn withdraw(contract: &mut Change, amount: u128) {
+        contract.withdraw_treasury(
+            ValidAccountId::try_from("ops.testnet").unwrap(),
+            U128(amount),
+        );
+    }
+
+    /// 100 NEAR in the treasury, alerting at 3x the average, and 1 NEAR withdrawn
+    /// in each of hours 1 and 2.
+    fn watched_treasury(auto_pause: bool) -> Change {
+        testing_env!(context_for("alice.testnet", 100 * NEAR));
+        let mut contract = Change::default();
+        contract.fund_treasury();
+        contract.set_config(
+            Config {
+                velocity_alert_multiple: 3,
+                velocity_auto_pause: auto_pause,
+                ..Config::default()
+            },
+            None,
+        );
+        for hour in 1..3 {
+            testing_env!(owner_at(hour));
+            withdraw(&mut contract, NEAR);
+        }
+        contract
+    }
+
+    fn anomalies() -> usize {
+        get_logs()
+            .iter()
+            .filter(|log| log.contains("\"event\":\"outflow_anomaly\""))
+            .count()
+    }
+
+    #[test]
+    fn outflow_above_the_multiple_is_reported_once() {
+        let mut contract = watched_treasury(false);
+        // hours 1 and 2 average out at 1 NEAR
+        testing_env!(owner_at(3));
+        withdraw(&mut contract, 3 * NEAR);
+        assert_eq!(0, anomalies());
+        withdraw(&mut contract, NEAR);
+        withdraw(&mut contract, NEAR);
+        assert_eq!(1, anomalies());
+        let view = contract.get_outflow_velocity();
+        assert_eq!(5 * NEAR, view.hour_outflow.0);
+        assert_eq!(NEAR, view.trailing_average.0);
+        assert!(!view.withdrawals_paused);
+    }
+
+    #[test]
+    fn quiet_hours_lower_the_average() {
+        let mut contract = watched_treasury(false);
+        // hour 3 is quiet: (1 + 1 + 0) / 3
+        testing_env!(owner_at(4));
+        withdraw(&mut contract, 3 * NEAR);
+        assert_eq!(1, anomalies());
+    }
+
+    #[test]
+    fn quiet_contract_reports_outflow_above_the_floor() {
+        testing_env!(context_for("alice.testnet", 100 * NEAR));
+        let mut contract = Change::default();
+        contract.fund_treasury();
+        contract.set_config(
+            Config {
+                velocity_alert_multiple: 3,
+                velocity_quiet_floor: U128(5 * NEAR),
+                ..Config::default()
+            },
+            None,
+        );
+        // hours 1 to 3 move nothing, so the average stays 0
+        testing_env!(owner_at(4));
+        withdraw(&mut contract, 5 * NEAR);
+        assert_eq!(0, anomalies());
+        withdraw(&mut contract, NEAR);
+        assert_eq!(1, anomalies());
+        assert_eq!(0, contract.get_outflow_velocity().trailing_average.0);
+    }
+
+    #[test]
+    fn anomaly_can_pause_withdrawals_until_resumed() {
+        let mut contract = watched_treasury(true);
+        testing_env!(owner_at(3));
+        withdraw(&mut contract, 4 * NEAR);
+        assert!(contract.get_outflow_velocity().withdrawals_paused);
+        contract.resume_withdrawals();
+        withdraw(&mut contract, NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawals are paused")]
+    fn paused_withdrawals_are_refused() {
+        let mut contract = watched_treasury(true);
+        testing_env!(owner_at(3));
+        withdraw(&mut contract, 4 * NEAR);
+        withdraw(&mut contract, NEAR);
+    }
+}
@@ -0,0 +1,247 @@
+//! Change vouchers signed off-chain, for paper and QR change.
+//!
+//! The owner signs a [`Voucher`] with the ed25519 key set as
+//! `voucher_public_key`, without touching the contract, and hands it over as
+//! a slip or QR code. Whoever holds it calls
+//! [`redeem_voucher`](Change::redeem_voucher): a voucher naming a recipient
+//! credits that account, a bearer voucher the caller. Either way the amount
+//! comes out of the hot treasury into the recipient's change balance.
+//!
+//! The key signs the borsh serialization of
+//! `(contract_id, amount, recipient, expires_at, nonce)`: a string, a u128,
+//! an optional string and two u64s, so a voucher signed for one contract
+//! can't be redeemed at another. Each nonce is redeemed once; the owner
+//! numbers vouchers sequentially, which keeps the flags compact.
+
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Voucher {
+    pub amount: U128,
+    /// Account credited; a voucher without one credits whoever redeems it.
+    pub recipient: Option<ValidAccountId>,
+    /// Time, in nanoseconds, from which the voucher can't be redeemed anymore.
+    pub expires_at: U64,
+    pub nonce: U64,
+}
+
+/// What the voucher key signs.
+#[derive(BorshSerialize)]
+struct VoucherPayload {
+    contract_id: AccountId,
+    amount: Balance,
+    recipient: Option<AccountId>,
+    expires_at: u64,
+    nonce: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct VoucherRedeemedEvent<'a> {
+    nonce: U64,
+    recipient_id: &'a AccountId,
+    amount: U128,
+}
+
+impl Change {
+    /// Panics unless `signature` is the voucher key's signature of `voucher`.
+    fn assert_signed_voucher(&self, voucher: &Voucher, signature: &[u8]) {
+        let key = self
+            .config()
+            .voucher_public_key
+            .as_ref()
+            .unwrap_or_else(|| fail!(messages::NO_VOUCHER_KEY_SET));
+        let payload = VoucherPayload {
+            contract_id: env::current_account_id(),
+            amount: voucher.amount.0,
+            recipient: voucher.recipient.clone().map(Into::into),
+            expires_at: voucher.expires_at.0,
+            nonce: voucher.nonce.0,
+        };
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(&key.0[1..]);
+        let mut sig = [0u8; 64];
+        require!(signature.len() == 64, messages::INVALID_VOUCHER_SIGNATURE);
+        sig.copy_from_slice(signature);
+        let message = payload
+            .try_to_vec()
+            .expect("Failed to serialize the voucher");
+        require!(
+            ed25519::verify(&public_key, &message, &sig),
+            messages::INVALID_VOUCHER_SIGNATURE
+        );
+    }
+}
+
+/// Panics unless `key` is an ed25519 public key.
+pub(crate) fn assert_valid_voucher_key(key: &Base58PublicKey) {
+    require!(
+        key.0.len() == 33 && key.0[0] == 0,
+        messages::INVALID_VOUCHER_KEY
+    );
+}
+
+#[near_bindgen]
+impl Change {
+    /// Redeems a voucher signed by the voucher key, crediting its amount to
+    /// its recipient, or the caller for a bearer voucher. Returns the amount
+    /// credited.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet redeem_voucher '{"voucher": {"amount": "500000000000000000000000", "recipient": null, "expires_at": "1700000000000000000", "nonce": "17"}, "signature": "<base64>"}' --accountId jane.testnet
+    /// ```
+    pub fn redeem_voucher(&mut self, voucher: Voucher, signature: Base64VecU8) -> U128 {
+        self.assert_access("redeem_voucher");
+        self.assert_writable();
+        require!(voucher.amount.0 > 0, messages::AMOUNT_NOT_POSITIVE);
+        require!(
+            env::block_timestamp() < voucher.expires_at.0,
+            messages::VOUCHER_EXPIRED
+        );
+        self.assert_signed_voucher(&voucher, &signature.0);
+        require!(
+            self.redeemed_vouchers.insert(voucher.nonce.0),
+            messages::VOUCHER_ALREADY_REDEEMED
+        );
+        require!(
+            self.book(Book::Treasury) >= voucher.amount.0,
+            messages::TREASURY_SHORT_FOR_VOUCHER
+        );
+        let recipient_id = voucher
+            .recipient
+            .map(Into::into)
+            .unwrap_or_else(env::predecessor_account_id);
+        self.internal_credit_balance(&recipient_id, voucher.amount.0, Book::Treasury);
+        self.emit_event(
+            "voucher_redeemed",
+            &VoucherRedeemedEvent {
+                nonce: voucher.nonce,
+                recipient_id: &recipient_id,
+                amount: voucher.amount,
+            },
+        );
+        voucher.amount
+    }
+
+    /// Whether the voucher with `nonce` was redeemed.
+    pub fn is_voucher_redeemed(&self, nonce: U64) -> bool {
+        self.redeemed_vouchers.contains(nonce.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DAY;
+    use crate::test_utils::context_for;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+    use std::convert::TryFrom;
+
+    /// Public key of the ed25519 seed 0, 1, ..., 31, which signed the vouchers below.
+    const KEY: [u8; 32] = [
+        3, 161, 7, 191, 243, 206, 16, 190, 29, 112, 221, 24, 231, 75, 192, 153, 103, 228, 214, 48,
+        155, 165, 13, 95, 29, 220, 134, 100, 18, 85, 49, 184,
+    ];
+    /// 500 for jane.testnet, nonce 1.
+    const JANE_SIG: &str = "a2c7af03e0e035d1e69f47278f171675c5ca4db2a930f74461de5935161ea92adee6d21a0d3ed0dd208aa66381b132a84b7d5cb955e90698264a335a54262506";
+    /// 300 for the bearer, nonce 2.
+    const BEARER_SIG: &str = "a4dcc3548736886b4614ae9d2420e832a8c2637083472210b4dc0f957240b23a881640b90074058dd48081313e82b1c5ea381197ab90eba6447ad5f52192f901";
+
+    fn signature(hex: &str) -> Base64VecU8 {
+        Base64VecU8(
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect(),
+        )
+    }
+
+    fn voucher(amount: u128, recipient: Option<&str>, nonce: u64) -> Voucher {
+        Voucher {
+            amount: U128(amount),
+            recipient: recipient.map(|id| ValidAccountId::try_from(id).unwrap()),
+            expires_at: U64(DAY),
+            nonce: U64(nonce),
+        }
+    }
+
+    fn funded(treasury: Balance) -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let mut key = vec![0];
+        key.extend_from_slice(&KEY);
+        contract.set_voucher_key(Some(Base58PublicKey(key)));
+        contract.internal_credit_treasury(treasury, Book::Custody);
+        contract
+    }
+
+    fn balance_of(contract: &Change, account_id: &str) -> Balance {
+        contract
+            .internal_get_account(&account_id.to_string())
+            .balance
+    }
+
+    #[test]
+    fn vouchers_credit_their_recipient() {
+        let mut contract = funded(1000);
+        testing_env!(context_for("bob.testnet", 0));
+        let amount =
+            contract.redeem_voucher(voucher(500, Some("jane.testnet"), 1), signature(JANE_SIG));
+        assert_eq!(500, amount.0);
+        assert_eq!(500, balance_of(&contract, "jane.testnet"));
+        assert_eq!(0, balance_of(&contract, "bob.testnet"));
+        assert_eq!(500, contract.book(Book::Treasury));
+        assert!(contract.is_voucher_redeemed(U64(1)));
+        assert!(!contract.is_voucher_redeemed(U64(2)));
+    }
+
+    #[test]
+    fn bearer_vouchers_credit_the_caller() {
+        let mut contract = funded(1000);
+        testing_env!(context_for("bob.testnet", 0));
+        contract.redeem_voucher(voucher(300, None, 2), signature(BEARER_SIG));
+        assert_eq!(300, balance_of(&contract, "bob.testnet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Voucher was already redeemed")]
+    fn vouchers_are_redeemed_once() {
+        let mut contract = funded(1000);
+        testing_env!(context_for("bob.testnet", 0));
+        contract.redeem_voucher(voucher(300, None, 2), signature(BEARER_SIG));
+        contract.redeem_voucher(voucher(300, None, 2), signature(BEARER_SIG));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid voucher signature")]
+    fn altered_vouchers_are_refused() {
+        let mut contract = funded(1000);
+        testing_env!(context_for("bob.testnet", 0));
+        contract.redeem_voucher(voucher(300, Some("bob.testnet"), 2), signature(BEARER_SIG));
+    }
+
+    #[test]
+    #[should_panic(expected = "Voucher has expired")]
+    fn expired_vouchers_are_refused() {
+        let mut contract = funded(1000);
+        let mut context = context_for("bob.testnet", 0);
+        context.block_timestamp = DAY;
+        testing_env!(context);
+        contract.redeem_voucher(voucher(300, None, 2), signature(BEARER_SIG));
+    }
+
+    #[test]
+    #[should_panic(expected = "Treasury can't cover the voucher")]
+    fn vouchers_need_a_funded_treasury() {
+        let mut contract = funded(200);
+        testing_env!(context_for("bob.testnet", 0));
+        contract.redeem_voucher(voucher(300, None, 2), signature(BEARER_SIG));
+    }
+}
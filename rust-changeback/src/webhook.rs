@@ -0,0 +1,192 @@
+//! Per-merchant notifications of purchases and refunds on their receipts.
+//!
+//! A merchant can point the contract at a method of its own contract, which
+//! gets called with `{"event", "receipt_id", "amount"}` whenever a purchase is
+//! booked on one of its receipts (`"purchase"`) or a dispute refunds part of
+//! one (`"refund"`). The call is fire-and-forget with the merchant's own gas
+//! budget: a failing or out-of-gas notification never affects the purchase.
+//! The webhook is the merchant's own subscription, so it hears of every
+//! purchase whatever the buyer's [notification](crate::notification)
+//! preferences.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{near_bindgen, AccountId, Promise};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Webhook {
+    /// Contract notified.
+    pub contract_id: AccountId,
+    /// Method called on `contract_id`.
+    pub method: String,
+    /// Gas attached to every notification.
+    pub gas: U64,
+}
+
+impl Change {
+    /// Notifies the webhook of `merchant_id`, if it has one, of `event` on
+    /// `receipt_id`.
+    pub(crate) fn internal_notify_merchant(
+        &self,
+        merchant_id: &AccountId,
+        event: &str,
+        receipt_id: u64,
+        amount: Balance,
+    ) {
+        let webhook = match self.merchants.get(merchant_id).and_then(|m| m.webhook) {
+            Some(webhook) => webhook,
+            None => return,
+        };
+        let args = json!({
+            "event": event,
+            "receipt_id": U64(receipt_id),
+            "amount": U128(amount),
+        });
+        Promise::new(webhook.contract_id).function_call(
+            webhook.method.into_bytes(),
+            args.to_string().into_bytes(),
+            0,
+            webhook.gas.0.min(self.config().gas.webhook.0),
+        );
+    }
+}
+
+#[near_bindgen]
+impl Change {
+    /// Sets the contract method notified of purchases and refunds at `merchant_id`,
+    /// or removes it with `null`. Callable by the merchant or the owner.
+    ///
+    /// ```bash
+    /// near call Change.YOU.testnet set_merchant_webhook '{"merchant_id": "shop.testnet", "webhook": {"contract_id": "hooks.shop.testnet", "method": "on_receipt", "gas": "10000000000000"}}' --accountId shop.testnet
+    /// ```
+    pub fn set_merchant_webhook(&mut self, merchant_id: AccountId, webhook: Option<Webhook>) {
+        self.assert_access("set_merchant_webhook");
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == merchant_id || caller == self.owner_id,
+            messages::WEBHOOK_SETTER_ONLY
+        );
+        let max_gas = self.config().gas.webhook.0;
+        if let Some(webhook) = &webhook {
+            require!(
+                env::is_valid_account_id(webhook.contract_id.as_bytes()),
+                messages::INVALID_WEBHOOK_CONTRACT
+            );
+            require!(!webhook.method.is_empty(), messages::EMPTY_WEBHOOK_METHOD);
+            require!(
+                webhook.gas.0 > 0 && webhook.gas.0 <= max_gas,
+                messages::INVALID_WEBHOOK_GAS,
+                max_gas
+            );
+        }
+        let mut merchant = self
+            .merchants
+            .get(&merchant_id)
+            .unwrap_or_else(|| fail!(messages::UNKNOWN_MERCHANT));
+        merchant.webhook = webhook;
+        self.merchants.insert(&merchant_id, &merchant);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TGAS;
+    use crate::notification::NotificationPreferences;
+    use crate::test_utils::context_for;
+    use near_sdk::json_types::ValidAccountId;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::{Gas, MockedBlockchain};
+    use std::convert::TryFrom;
+
+    const MAX_GAS: Gas = 50 * TGAS;
+
+    fn webhook(gas: Gas) -> Option<Webhook> {
+        Some(Webhook {
+            contract_id: "hooks.testnet".to_string(),
+            method: "on_receipt".to_string(),
+            gas: U64(gas),
+        })
+    }
+
+    fn shop() -> Change {
+        testing_env!(context_for("alice.testnet", 0));
+        let mut contract = Change::default();
+        let shop = ValidAccountId::try_from("shop.testnet").unwrap();
+        contract.register_merchant(shop.clone(), shop, 0);
+        contract
+    }
+
+    fn notified() -> bool {
+        near_sdk::serde_json::to_string(&get_created_receipts())
+            .unwrap()
+            .contains("hooks.testnet")
+    }
+
+    #[test]
+    fn purchases_notify_the_merchant_webhook() {
+        let mut contract = shop();
+        testing_env!(context_for("shop.testnet", 0));
+        contract.set_merchant_webhook("shop.testnet".to_string(), webhook(MAX_GAS));
+        assert_eq!(
+            webhook(MAX_GAS),
+            contract
+                .get_merchant("shop.testnet".to_string())
+                .unwrap()
+                .webhook
+        );
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_notification_preferences(NotificationPreferences {
+            alerts: true,
+            marketing: true,
+            receipts: false,
+        });
+        testing_env!(context_for("jane.testnet", 100));
+        contract.purchase(
+            U128(30),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        // muted receipts are the buyer's, the webhook is the merchant's
+        assert!(notified());
+    }
+
+    #[test]
+    fn merchants_without_a_webhook_are_not_notified() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 100));
+        contract.purchase(
+            U128(30),
+            None,
+            Some("shop.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        assert!(!notified());
+    }
+
+    #[test]
+    #[should_panic(expected = "Webhook gas must be positive")]
+    fn webhook_gas_is_capped() {
+        let mut contract = shop();
+        contract.set_merchant_webhook("shop.testnet".to_string(), webhook(MAX_GAS + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the merchant or the owner can set the webhook")]
+    fn strangers_cannot_set_the_webhook() {
+        let mut contract = shop();
+        testing_env!(context_for("jane.testnet", 0));
+        contract.set_merchant_webhook("shop.testnet".to_string(), None);
+    }
+}